@@ -33,6 +33,23 @@ impl KvCache {
         self.cache.reset()
     }
 
+    /// Shorten the cache, keeping the first `len` tokens and dropping the rest.
+    pub fn truncate(&mut self, len: usize) -> candle_core::Result<()> {
+        let (Some(k), Some(v)) = (self.cache.k()?, self.cache.v()?) else {
+            return Ok(());
+        };
+        if len >= k.dim(self.concat_dim)? {
+            return Ok(());
+        }
+        self.reset();
+        if len > 0 {
+            let k = k.narrow(self.concat_dim, 0, len)?;
+            let v = v.narrow(self.concat_dim, 0, len)?;
+            self.append(&k, &v)?;
+        }
+        Ok(())
+    }
+
     /// Append a new key/value pair to the cache.
     pub fn append(&mut self, k: &Tensor, v: &Tensor) -> candle_core::Result<(Tensor, Tensor)> {
         let k = k.contiguous()?;
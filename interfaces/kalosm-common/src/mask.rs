@@ -49,6 +49,23 @@ impl MaskCache {
             on_true: mask.on_true,
         })
     }
+
+    /// Get a mask for a batch of single token sequences with different lengths. The keys of shorter sequences are padded at the start to the length of the longest sequence, and the padding is masked out.
+    ///
+    /// Padding at the start lines up the newest token of every sequence in the last column, so the next token of every sequence can be appended to the batch at once.
+    pub fn get_padding_mask(&self, lengths: &[usize], device: &Device) -> Result<AttentionMask> {
+        let max_len = lengths.iter().copied().max().unwrap_or_default();
+        let mask: Vec<_> = lengths
+            .iter()
+            .flat_map(|&len| (0..max_len).map(move |j| u8::from(j < max_len - len)))
+            .collect();
+        let mask = Tensor::from_slice(&mask, (lengths.len(), 1, 1, max_len), device)?;
+
+        Ok(AttentionMask {
+            mask,
+            on_true: OnceCell::new(),
+        })
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -74,3 +91,34 @@ impl AttentionMask {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn padding_mask_hides_the_start_of_shorter_sequences() {
+        let mask = MaskCache::default()
+            .get_padding_mask(&[3, 1, 2], &Device::Cpu)
+            .unwrap();
+        assert_eq!(mask.mask.dims(), [3, 1, 1, 3]);
+        assert_eq!(
+            mask.mask.flatten_all().unwrap().to_vec1::<u8>().unwrap(),
+            [0, 0, 0, 1, 1, 0, 1, 0, 0]
+        );
+    }
+
+    #[test]
+    fn padding_mask_removes_padding_from_attention() {
+        let mask = MaskCache::default()
+            .get_padding_mask(&[2, 1], &Device::Cpu)
+            .unwrap();
+        let mut weights = Tensor::zeros((2, 1, 1, 2), DType::F32, &Device::Cpu).unwrap();
+        mask.forward(&mut weights).unwrap();
+        let weights = candle_nn::ops::softmax_last_dim(&weights).unwrap();
+        assert_eq!(
+            weights.flatten_all().unwrap().to_vec1::<f32>().unwrap(),
+            [0.5, 0.5, 0.0, 1.0]
+        );
+    }
+}
@@ -3,22 +3,35 @@
 use std::{
     fmt::Display,
     path::PathBuf,
+    pin::Pin,
     sync::{Arc, Mutex, RwLock},
 };
 
 use anyhow::Result;
 use futures_util::Future;
-use kalosm_language_model::ChatMarkers;
-use kalosm_language_model::Session;
+pub use kalosm_language_model::{ChatHistoryItem, MessageType};
+use kalosm_language_model::{ChatMarkers, ChatTemplate, Session};
+use kalosm_language_model::{
+    GenerationControl, Interrupt, ModelFeedback, StopReason, TextGeneration,
+};
 use kalosm_language_model::{GenerationParameters, Model, ModelExt, SyncModel, SyncModelExt};
 use kalosm_sample::{ArcParser, CreateParserState, ParserExt, SendCreateParserState};
 use kalosm_streams::text_stream::ChannelTextStream;
 use llm_samplers::types::Sampler;
+use tokenizers::Tokenizer;
 use tokio::sync::{mpsc::unbounded_channel, oneshot};
 
+use crate::search::Summarizer;
+
 type ResponseConstraintGenerator =
     Arc<Mutex<Box<dyn FnMut(&[ChatHistoryItem]) -> ArcParser<()> + Send + Sync>>>;
 
+type SummarizeHistory<M> = for<'a> fn(
+    &'a Summarizer,
+    &'a M,
+    String,
+) -> Pin<Box<dyn Future<Output = Result<String>> + Send + 'a>>;
+
 const DEFAULT_SYSTEM_PROMPT: &str = "Always assist with care, respect, and truth. Respond with utmost utility yet securely. Avoid harmful, unethical, prejudiced, or negative content. Ensure replies promote fairness and positivity.";
 
 /// A simple helper function for prompting the user for input.
@@ -32,58 +45,172 @@ pub fn prompt_input(prompt: impl Display) -> Result<String> {
     Ok(input)
 }
 
-/// The type of a chat message
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum MessageType {
-    /// A system prompt.
-    SystemPrompt,
-    /// A user message.
-    UserMessage,
-    /// A model answer.
-    ModelAnswer,
+/// The fraction of the context window kept free for the response of the model.
+const RESPONSE_RESERVE_FRACTION: usize = 4;
+
+/// What the chat does when the history no longer fits in the context window of the model.
+///
+/// The strategy only runs when a new message would overflow the context window with room left for the response. Old messages are removed from [`Chat::history`] and the model session is rebuilt from the remaining history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ContextOverflowStrategy {
+    /// Remove the oldest turns until the history fits. The system prompt is always kept.
+    #[default]
+    DropOldestTurns,
+    /// Only keep the newest messages that fit in a window of `tokens` tokens. The oldest kept message may be cut off at the start, and the kept history always starts with a user message. The system prompt is always kept. If the prompt still doesn't fit in the context window, the window shrinks until it does.
+    SlidingWindow {
+        /// The number of tokens of messages to keep, not counting the system prompt.
+        tokens: usize,
+    },
+    /// Remove the oldest turns like [`ContextOverflowStrategy::DropOldestTurns`], and add a summary of them to the system prompt. The summary is generated by the chat model with a [`Summarizer`].
+    Summarize,
 }
 
-/// A single item in the chat history.
-#[derive(Clone, Debug)]
-pub struct ChatHistoryItem {
-    ty: MessageType,
-    contents: String,
+/// How the chat history is turned into a prompt for the model.
+enum ChatFormat {
+    /// Render the whole history with the chat template the model ships with.
+    Template {
+        template: ChatTemplate,
+        end_assistant_marker: String,
+        /// The rendered text that has already been fed into the session.
+        fed: String,
+    },
+    /// Wrap every message in fixed markers.
+    Markers {
+        system_prompt_marker: String,
+        end_system_prompt_marker: String,
+        user_marker: String,
+        end_user_marker: String,
+        assistant_marker: String,
+        end_assistant_marker: String,
+    },
 }
 
-impl ChatHistoryItem {
-    /// Creates a new chat history item.
-    pub fn new(ty: MessageType, contents: impl Into<String>) -> Self {
-        Self {
-            ty,
-            contents: contents.into(),
+impl ChatFormat {
+    /// Use the chat template if it works and fall back to the chat markers. Returns an error if the model has neither.
+    fn new(chat_template: Option<ChatTemplate>, chat_markers: Option<ChatMarkers>) -> Result<Self> {
+        if let Some(template) = chat_template {
+            match template.end_assistant_marker() {
+                Ok(end_assistant_marker) => {
+                    return Ok(Self::Template {
+                        template,
+                        end_assistant_marker,
+                        fed: String::new(),
+                    })
+                }
+                Err(err) if chat_markers.is_some() => {
+                    tracing::error!(
+                        "Falling back to chat markers, failed to use the chat template: {err}"
+                    )
+                }
+                Err(err) => return Err(err),
+            }
         }
+        let chat_markers = chat_markers
+            .ok_or_else(|| anyhow::anyhow!("The model has no chat template or chat markers"))?;
+        Ok(Self::Markers {
+            system_prompt_marker: chat_markers.system_prompt_marker.to_string(),
+            end_system_prompt_marker: chat_markers.end_system_prompt_marker.to_string(),
+            user_marker: chat_markers.user_marker.to_string(),
+            end_user_marker: chat_markers.end_user_marker.to_string(),
+            assistant_marker: chat_markers.assistant_marker.to_string(),
+            end_assistant_marker: chat_markers.end_assistant_marker.to_string(),
+        })
     }
 
-    /// Returns the type of the item.
-    pub fn ty(&self) -> MessageType {
-        self.ty
+    /// Render the history into the text the model sees. If `add_generation_prompt` is true, the text ends with the start of a new assistant message.
+    fn render(&self, history: &[ChatHistoryItem], add_generation_prompt: bool) -> Result<String> {
+        match self {
+            Self::Template { template, .. } => template.render(history, add_generation_prompt),
+            Self::Markers {
+                system_prompt_marker,
+                end_system_prompt_marker,
+                user_marker,
+                end_user_marker,
+                assistant_marker,
+                end_assistant_marker,
+            } => {
+                let mut rendered = String::new();
+                for item in history {
+                    let (start, end) = match item.ty() {
+                        MessageType::SystemPrompt => {
+                            (system_prompt_marker, end_system_prompt_marker)
+                        }
+                        MessageType::UserMessage => (user_marker, end_user_marker),
+                        MessageType::ModelAnswer => (assistant_marker, end_assistant_marker),
+                    };
+                    rendered += start;
+                    rendered += item.contents();
+                    rendered += end;
+                }
+                if add_generation_prompt {
+                    rendered += assistant_marker;
+                }
+                Ok(rendered)
+            }
+        }
     }
 
-    /// Returns the contents of the item.
-    pub fn contents(&self) -> &str {
-        &self.contents
+    fn end_assistant_marker(&self) -> &str {
+        match self {
+            Self::Template {
+                end_assistant_marker,
+                ..
+            } => end_assistant_marker,
+            Self::Markers {
+                end_assistant_marker,
+                ..
+            } => end_assistant_marker,
+        }
     }
 }
 
+/// Find the longest run of tokens at the start of the session that decodes to a prefix of the text. Returns the number of tokens and the length of the text they decode to.
+///
+/// Only the last few tokens are checked because templates only change the end of the text that was already fed.
+fn matching_prefix(
+    tokenizer: &Tokenizer,
+    tokens: &[u32],
+    text: &str,
+) -> Result<Option<(usize, usize)>> {
+    const MAX_REWIND: usize = 32;
+    for kept in (tokens.len().saturating_sub(MAX_REWIND)..=tokens.len()).rev() {
+        let decoded = tokenizer
+            .decode(&tokens[..kept], false)
+            .map_err(anyhow::Error::msg)?;
+        if text.starts_with(&decoded) {
+            return Ok(Some((kept, decoded.len())));
+        }
+    }
+    Ok(None)
+}
+
+/// Count the tokens of some text.
+fn token_count(tokenizer: &Tokenizer, text: &str) -> Result<usize> {
+    Ok(tokenizer
+        .encode(text, false)
+        .map_err(anyhow::Error::msg)?
+        .len())
+}
+
 /// The history of a chat session.
 struct ChatSession<Model: SyncModel> {
     logits_scratch: Vec<f32>,
-    system_prompt_marker: String,
-    end_system_prompt_marker: String,
-    user_marker: String,
-    end_user_marker: String,
-    assistant_marker: String,
-    end_assistant_marker: String,
+    format: ChatFormat,
     history: Arc<RwLock<Vec<ChatHistoryItem>>>,
+    /// The number of tokens in each message of the history that was counted so far.
+    token_counts: Vec<usize>,
     session: Model::Session,
     unfed_text: String,
     bot_constraints: Option<ResponseConstraintGenerator>,
     sampler: Arc<Mutex<dyn Sampler + Send + Sync>>,
+    tokenizer: Arc<Tokenizer>,
+    context_length: Option<usize>,
+    /// The system prompt before any summary of removed messages was added to it.
+    system_prompt: Option<String>,
+    /// The summary of the messages that were removed from the history.
+    summary: Option<String>,
+    /// Set when the history was trimmed and no longer matches the text fed into the session.
+    history_trimmed: bool,
 }
 
 impl<Model: SyncModel> ChatSession<Model> {
@@ -91,12 +218,7 @@ impl<Model: SyncModel> ChatSession<Model> {
     /// Creates a new chat history.
     fn new(
         model: &mut Model,
-        system_prompt_marker: String,
-        end_system_prompt_marker: String,
-        user_marker: String,
-        end_user_marker: String,
-        assistant_marker: String,
-        end_assistant_marker: String,
+        format: ChatFormat,
         system_prompt: Option<String>,
         bot_constraints: Option<ResponseConstraintGenerator>,
         sampler: Arc<Mutex<dyn Sampler + Send + Sync>>,
@@ -111,17 +233,18 @@ impl<Model: SyncModel> ChatSession<Model> {
 
         let mut myself = Self {
             logits_scratch: Vec::new(),
-            system_prompt_marker,
-            end_system_prompt_marker,
-            user_marker,
-            end_user_marker,
-            assistant_marker,
-            end_assistant_marker,
+            format,
             session,
             unfed_text,
             history: shared_history,
+            token_counts: Vec::new(),
             bot_constraints,
             sampler,
+            tokenizer: model.tokenizer(),
+            context_length: model.context_length(),
+            system_prompt: None,
+            summary: None,
+            history_trimmed: false,
         };
 
         if feed_initial_messages {
@@ -135,15 +258,16 @@ impl<Model: SyncModel> ChatSession<Model> {
                 myself.add_system_message(system_prompt);
             }
             for item in initial_history {
+                let contents = item.contents().to_string();
                 match item.ty() {
                     MessageType::SystemPrompt => {
-                        myself.add_system_message(item.contents);
+                        myself.add_system_message(contents);
                     }
                     MessageType::UserMessage => {
-                        myself.add_user_message(item.contents);
+                        myself.add_user_message(contents);
                     }
                     MessageType::ModelAnswer => {
-                        myself.add_bot_message(item.contents);
+                        myself.add_bot_message(contents);
                     }
                 }
             }
@@ -152,28 +276,73 @@ impl<Model: SyncModel> ChatSession<Model> {
         myself
     }
 
+    /// Take the text that needs to be fed into the session before the model can respond.
+    fn take_prompt(&mut self, model: &mut Model) -> Result<String> {
+        match &mut self.format {
+            ChatFormat::Template { template, fed, .. } => {
+                let rendered = template.render(&self.history.read().unwrap(), true)?;
+                let prompt = match rendered.strip_prefix(fed.as_str()) {
+                    Some(new_text) => new_text.to_string(),
+                    // The template changed text that was already fed into the session (templates often trim messages). Rewind the session to the last token that still matches
+                    None => {
+                        let token_count = self.session.tokens().len();
+                        let matching =
+                            matching_prefix(&model.tokenizer(), self.session.tokens(), &rendered)?;
+                        match matching {
+                            Some((kept_tokens, kept_text))
+                                if self.session.rewind(token_count - kept_tokens).is_ok() =>
+                            {
+                                rendered[kept_text..].to_string()
+                            }
+                            _ => {
+                                self.session = model.new_session()?;
+                                rendered.clone()
+                            }
+                        }
+                    }
+                };
+                *fed = rendered;
+                Ok(prompt)
+            }
+            ChatFormat::Markers {
+                assistant_marker, ..
+            } => {
+                self.unfed_text += assistant_marker;
+                Ok(std::mem::take(&mut self.unfed_text))
+            }
+        }
+    }
+
     /// Adds a message to the history.
     fn add_message(
         &mut self,
         message: String,
         model: &mut Model,
         stream: tokio::sync::mpsc::UnboundedSender<String>,
+        control: &GenerationControl,
     ) -> Result<()> {
+        if std::mem::take(&mut self.history_trimmed) {
+            self.rebuild_session(model)?;
+        }
         self.add_user_message(message);
         let mut bot_response = String::new();
-        self.unfed_text += &self.assistant_marker;
-        let prompt = std::mem::take(&mut self.unfed_text);
+        let prompt = self.take_prompt(model)?;
+        let end_assistant_marker = self.format.end_assistant_marker().to_string();
         let bot_constraints = &self.bot_constraints;
 
-        let mut on_token = |tok: String| {
+        let mut on_token = |tok: String| -> Result<ModelFeedback> {
+            // Stop at the next token boundary once the response is interrupted
+            if control.interrupted().is_some() {
+                return Ok(ModelFeedback::Stop);
+            }
             let tok = tok
-                .strip_suffix(&self.end_assistant_marker)
+                .strip_suffix(&end_assistant_marker)
                 .unwrap_or(&tok)
                 .to_string();
             bot_response += &tok;
             // Send the new token to the stream
             stream.send(tok)?;
-            Ok(())
+            Ok(ModelFeedback::Continue)
         };
 
         match bot_constraints {
@@ -181,26 +350,23 @@ impl<Model: SyncModel> ChatSession<Model> {
                 let mut constraints = constraints.lock().unwrap();
                 let constraints = constraints(&self.history.read().unwrap());
                 let state = constraints.create_parser_state();
-                model.generate_structured(
+                let result = model.generate_structured(
                     &mut self.session,
                     &prompt,
                     constraints,
                     state,
                     self.sampler.clone(),
-                    on_token,
+                    |tok| match on_token(tok)? {
+                        ModelFeedback::Continue => Ok(()),
+                        ModelFeedback::Stop => Err(anyhow::anyhow!("The response was interrupted")),
+                    },
                     Some(4),
-                )?;
-                let end_assistant_token = model
-                    .tokenizer()
-                    .token_to_id(&self.end_assistant_marker)
-                    .unwrap();
-                // If it doesn't end with the end assistant marker, but the constraints are finished, add the end assistant marker
-                if self.session.tokens().last() != Some(&end_assistant_token) {
-                    model.feed_tokens(
-                        &mut self.session,
-                        &[end_assistant_token],
-                        &mut self.logits_scratch,
-                    )?;
+                );
+                // An interrupted response is kept in the history with the text generated so far
+                if let Err(err) = result {
+                    if control.interrupted().is_none() {
+                        return Err(err);
+                    }
                 }
             }
             None => {
@@ -208,86 +374,384 @@ impl<Model: SyncModel> ChatSession<Model> {
                     &mut self.session,
                     &prompt,
                     None,
-                    Some(&self.end_assistant_marker),
+                    Some(&end_assistant_marker),
                     self.sampler.clone(),
-                    |tok| {
-                        on_token(tok)?;
-                        Ok(kalosm_language_model::ModelFeedback::Continue)
-                    },
+                    on_token,
                 )?;
             }
         }
+        let interrupted = control.interrupted();
 
-        self.history.write().unwrap().push(ChatHistoryItem {
-            ty: MessageType::ModelAnswer,
-            contents: bot_response,
-        });
+        // The end assistant marker may be split across several tokens, so compare the tokens instead of looking up a single token id
+        let end_tokens = model
+            .tokenizer()
+            .encode(end_assistant_marker.as_str(), false)
+            .map_err(anyhow::Error::msg)?;
+        let end_tokens = end_tokens.get_ids();
+        match &mut self.format {
+            ChatFormat::Template { fed, .. } => {
+                // Close the response so the session matches the rendered history
+                if !self.session.tokens().ends_with(end_tokens) {
+                    model.feed_tokens(&mut self.session, end_tokens, &mut self.logits_scratch)?;
+                }
+                *fed += &bot_response;
+                *fed += &end_assistant_marker;
+            }
+            ChatFormat::Markers { .. } => {
+                // If it doesn't end with the end assistant marker, but the constraints are finished or the response was interrupted, add the end assistant marker
+                if (self.bot_constraints.is_some() || interrupted.is_some())
+                    && !self.session.tokens().ends_with(end_tokens)
+                {
+                    model.feed_tokens(&mut self.session, end_tokens, &mut self.logits_scratch)?;
+                }
+            }
+        }
+
+        self.history
+            .write()
+            .unwrap()
+            .push(ChatHistoryItem::new(MessageType::ModelAnswer, bot_response));
+        control.finish(interrupted.unwrap_or(StopReason::Finished));
 
         Ok(())
     }
 
+    /// Get the number of tokens in each message of the history. The counts are cached so each message is only tokenized once.
+    fn token_counts(&mut self) -> Result<Vec<usize>> {
+        let history = self.history.read().unwrap();
+        // Messages are only added to the end of the history outside of make_room and add_summary, which update the counts themselves
+        self.token_counts.truncate(history.len());
+        for item in &history[self.token_counts.len()..] {
+            self.token_counts
+                .push(token_count(&self.tokenizer, item.contents())?);
+        }
+        Ok(self.token_counts.clone())
+    }
+
+    /// Get the number of tokens the prompt can use with room left for the response.
+    fn prompt_budget(&self) -> Option<usize> {
+        self.context_length
+            .map(|context_length| context_length - context_length / RESPONSE_RESERVE_FRACTION)
+    }
+
+    /// Count the tokens of the prompt for the history.
+    fn prompt_tokens(&self, history: &[ChatHistoryItem]) -> Result<usize> {
+        token_count(&self.tokenizer, &self.format.render(history, true)?)
+    }
+
+    /// Remove old messages from the history until the new message fits in the context window. Returns the text of the removed messages (and any earlier summary) if anything was removed.
+    fn make_room(
+        &mut self,
+        message: &str,
+        strategy: ContextOverflowStrategy,
+    ) -> Result<Option<String>> {
+        let Some(budget) = self.prompt_budget() else {
+            return Ok(None);
+        };
+        let mut history = self.history.read().unwrap().clone();
+        history.push(ChatHistoryItem::new(MessageType::UserMessage, message));
+        let total = self.prompt_tokens(&history)?;
+        if total <= budget {
+            return Ok(None);
+        }
+        let mut counts = self.token_counts()?;
+        counts.push(token_count(&self.tokenizer, message)?);
+        // Spread the tokens of the chat format over the messages so removing messages can be estimated without rendering the history again
+        let format_tokens = total
+            .saturating_sub(counts.iter().sum())
+            .div_ceil(history.len());
+        let cost = |index: usize| counts[index] + format_tokens;
+
+        // The system prompt and the new message are never removed
+        let start = usize::from(
+            history
+                .first()
+                .is_some_and(|item| item.ty() == MessageType::SystemPrompt),
+        );
+        let last = history.len() - 1;
+        let (removed, cut_off) = match strategy {
+            ContextOverflowStrategy::DropOldestTurns | ContextOverflowStrategy::Summarize => {
+                let mut estimate = total;
+                let mut end = start;
+                loop {
+                    while end < last && estimate > budget {
+                        estimate = estimate.saturating_sub(cost(end));
+                        end += 1;
+                        // Remove whole turns so the kept history never starts with a model answer
+                        while end < last && history[end].ty() == MessageType::ModelAnswer {
+                            estimate = estimate.saturating_sub(cost(end));
+                            end += 1;
+                        }
+                    }
+                    // Chat templates can render messages differently depending on the rest of the history, so check the estimate against the real prompt
+                    if end == last
+                        || self.prompt_tokens(&[&history[..start], &history[end..]].concat())?
+                            <= budget
+                    {
+                        break;
+                    }
+                    estimate = budget + 1;
+                }
+                (start..end, None)
+            }
+            ContextOverflowStrategy::SlidingWindow { tokens } => {
+                // The window can't be larger than the room the prompt has
+                let mut window = tokens.min(budget);
+                loop {
+                    let mut remaining = window;
+                    let mut keep_from = last;
+                    let mut cut_off = None;
+                    for index in (start..=last).rev() {
+                        if counts[index] <= remaining || index == last {
+                            remaining = remaining.saturating_sub(counts[index]);
+                            keep_from = index;
+                        } else {
+                            // Keep the end of the message that still fits in the window
+                            if remaining > 0 {
+                                let encoding = self
+                                    .tokenizer
+                                    .encode(history[index].contents(), false)
+                                    .map_err(anyhow::Error::msg)?;
+                                let ids = encoding.get_ids();
+                                let tail = self
+                                    .tokenizer
+                                    .decode(&ids[ids.len().saturating_sub(remaining)..], false)
+                                    .map_err(anyhow::Error::msg)?;
+                                cut_off = Some(ChatHistoryItem::new(history[index].ty(), tail));
+                            }
+                            break;
+                        }
+                    }
+                    // Chat templates that alternate between the user and the model reject a history that starts with a model answer
+                    if cut_off
+                        .as_ref()
+                        .is_some_and(|item| item.ty() == MessageType::ModelAnswer)
+                    {
+                        cut_off = None;
+                    }
+                    if cut_off.is_none() {
+                        while keep_from < last
+                            && history[keep_from].ty() == MessageType::ModelAnswer
+                        {
+                            keep_from += 1;
+                        }
+                    }
+                    // The window only counts the text of the messages, so check the real prompt and shrink the window until it fits
+                    let kept =
+                        [&history[..start], cut_off.as_slice(), &history[keep_from..]].concat();
+                    let prompt_tokens = self.prompt_tokens(&kept)?;
+                    if prompt_tokens <= budget || window == 0 {
+                        break (start..keep_from, cut_off);
+                    }
+                    window = window.saturating_sub(prompt_tokens - budget);
+                }
+            }
+        };
+        if removed.is_empty() {
+            return Ok(None);
+        }
+
+        counts.drain(removed.clone());
+        let removed: Vec<_> = history.drain(removed).collect();
+        if let Some(cut_off) = cut_off {
+            counts.insert(start, token_count(&self.tokenizer, cut_off.contents())?);
+            history.insert(start, cut_off);
+        }
+        // The new message is added to the history when the model responds to it
+        history.pop();
+        counts.pop();
+        *self.history.write().unwrap() = history;
+        self.token_counts = counts;
+        self.history_trimmed = true;
+
+        let mut text = self
+            .summary
+            .as_ref()
+            .map(|summary| format!("{summary}\n"))
+            .unwrap_or_default();
+        for item in &removed {
+            text += &format!("{}: {}\n", item.ty().role(), item.contents());
+        }
+        Ok(Some(text))
+    }
+
+    /// Add a summary of the removed messages to the system prompt. If the longer system prompt no longer leaves room for the new message, more turns are removed and the summary is cut off if it is still too long.
+    fn add_summary(&mut self, summary: String, message: &str) -> Result<()> {
+        self.set_summary(summary.clone())?;
+        self.make_room(message, ContextOverflowStrategy::DropOldestTurns)?;
+
+        let Some(budget) = self.prompt_budget() else {
+            return Ok(());
+        };
+        let mut history = self.history.read().unwrap().clone();
+        history.push(ChatHistoryItem::new(MessageType::UserMessage, message));
+        let overflow = self.prompt_tokens(&history)?.saturating_sub(budget);
+        if overflow > 0 {
+            let encoding = self
+                .tokenizer
+                .encode(summary.as_str(), false)
+                .map_err(anyhow::Error::msg)?;
+            let ids = encoding.get_ids();
+            let summary = self
+                .tokenizer
+                .decode(&ids[..ids.len().saturating_sub(overflow)], false)
+                .map_err(anyhow::Error::msg)?;
+            self.set_summary(summary)?;
+        }
+        Ok(())
+    }
+
+    /// Replace the summary in the system prompt.
+    fn set_summary(&mut self, summary: String) -> Result<()> {
+        let mut history = self.history.write().unwrap();
+        let has_system_prompt = history
+            .first()
+            .is_some_and(|item| item.ty() == MessageType::SystemPrompt);
+        let system_prompt = self
+            .system_prompt
+            .get_or_insert_with(|| match history.first() {
+                Some(item) if has_system_prompt => item.contents().to_string(),
+                _ => DEFAULT_SYSTEM_PROMPT.to_string(),
+            })
+            .clone();
+        let item = ChatHistoryItem::new(
+            MessageType::SystemPrompt,
+            format!("{system_prompt}\n\nSummary of the earlier conversation: {summary}"),
+        );
+        let count = token_count(&self.tokenizer, item.contents())?;
+        if has_system_prompt {
+            history[0] = item;
+            if let Some(first) = self.token_counts.first_mut() {
+                *first = count;
+            }
+        } else {
+            history.insert(0, item);
+            self.token_counts.insert(0, count);
+        }
+        self.summary = Some(summary);
+        self.history_trimmed = true;
+        Ok(())
+    }
+
+    /// Rebuild the session after the history was trimmed.
+    fn rebuild_session(&mut self, model: &mut Model) -> Result<()> {
+        match &self.format {
+            // Rendering the trimmed history changes the text that was already fed, so the next prompt rewinds or recreates the session
+            ChatFormat::Template { .. } => {}
+            ChatFormat::Markers { .. } => {
+                self.session = model.new_session()?;
+                self.unfed_text = self.format.render(&self.history.read().unwrap(), false)?;
+            }
+        }
+        Ok(())
+    }
+
     fn add_system_message(&mut self, message: String) {
-        self.unfed_text += &self.system_prompt_marker;
-        self.unfed_text += &message;
-        self.unfed_text += &self.end_system_prompt_marker;
+        if let ChatFormat::Markers {
+            system_prompt_marker,
+            end_system_prompt_marker,
+            ..
+        } = &self.format
+        {
+            self.unfed_text += system_prompt_marker;
+            self.unfed_text += &message;
+            self.unfed_text += end_system_prompt_marker;
+        }
         let mut history = self.history.write().unwrap();
         if !history.is_empty() {
             tracing::error!("System prompt should be the first message in the history. System prompt was added to the end of the history: {history:?}");
         }
-        history.push(ChatHistoryItem {
-            ty: MessageType::SystemPrompt,
-            contents: message,
-        });
+        history.push(ChatHistoryItem::new(MessageType::SystemPrompt, message));
     }
 
     fn add_user_message(&mut self, message: String) {
-        self.unfed_text += &self.user_marker;
-        self.unfed_text += &message;
-        self.unfed_text += &self.end_user_marker;
-        self.history.write().unwrap().push(ChatHistoryItem {
-            ty: MessageType::UserMessage,
-            contents: message,
-        });
+        if let ChatFormat::Markers {
+            user_marker,
+            end_user_marker,
+            ..
+        } = &self.format
+        {
+            self.unfed_text += user_marker;
+            self.unfed_text += &message;
+            self.unfed_text += end_user_marker;
+        }
+        self.history
+            .write()
+            .unwrap()
+            .push(ChatHistoryItem::new(MessageType::UserMessage, message));
     }
 
     fn add_bot_message(&mut self, message: String) {
-        self.unfed_text += &self.assistant_marker;
-        self.unfed_text += &message;
-        self.unfed_text += &self.end_assistant_marker;
-        self.history.write().unwrap().push(ChatHistoryItem {
-            ty: MessageType::ModelAnswer,
-            contents: message,
-        });
+        if let ChatFormat::Markers {
+            assistant_marker,
+            end_assistant_marker,
+            ..
+        } = &self.format
+        {
+            self.unfed_text += assistant_marker;
+            self.unfed_text += &message;
+            self.unfed_text += end_assistant_marker;
+        }
+        self.history
+            .write()
+            .unwrap()
+            .push(ChatHistoryItem::new(MessageType::ModelAnswer, message));
     }
 }
 
 /// A builder for [`Chat`].
 pub struct ChatBuilder<M: Model> {
     model: M,
-    chat_markers: ChatMarkers,
+    chat_markers: Option<ChatMarkers>,
+    chat_template: Option<ChatTemplate>,
     session: Option<<M::SyncModel as kalosm_language_model::SyncModel>::Session>,
     system_prompt: Option<String>,
     sampler: Arc<Mutex<dyn Sampler + Send + Sync>>,
     bot_constraints: Option<ResponseConstraintGenerator>,
     initial_history: Vec<ChatHistoryItem>,
+    context_overflow: ContextOverflowStrategy,
+    summarizer: Option<(Summarizer, SummarizeHistory<M>)>,
 }
 
 impl<M: Model> ChatBuilder<M> {
     fn new(model: M) -> ChatBuilder<M> {
-        let chat_markers = model.chat_markers().expect("Model does not support chat");
+        let chat_markers = model.chat_markers();
+        let chat_template = model.chat_template();
+        assert!(
+            chat_markers.is_some() || chat_template.is_some(),
+            "Model does not support chat"
+        );
 
         ChatBuilder {
             model,
             chat_markers,
+            chat_template,
             session: None,
             system_prompt: None,
             sampler: Arc::new(Mutex::new(GenerationParameters::default().sampler())),
             bot_constraints: None,
             initial_history: Vec::new(),
+            context_overflow: ContextOverflowStrategy::default(),
+            summarizer: None,
         }
     }
 }
 
+fn summarize_history<'a, M>(
+    summarizer: &'a Summarizer,
+    model: &'a M,
+    text: String,
+) -> Pin<Box<dyn Future<Output = Result<String>> + Send + 'a>>
+where
+    M: Model,
+    <M::SyncModel as SyncModel>::Session: Sync + Send,
+{
+    Box::pin(async move {
+        let summary = summarizer.generate_summary(&text, model).await?;
+        Ok(summary.join(" "))
+    })
+}
+
 impl<M: Model> ChatBuilder<M> {
     /// Adds a system prompt to the chat. The system prompt guides the model to respond in a certain way.
     /// If no system prompt is added, the model will use a default system prompt that instructs the model to respond in a way that is safe and respectful.
@@ -352,6 +816,7 @@ impl<M: Model> ChatBuilder<M> {
         ChatBuilder {
             model: self.model,
             chat_markers: self.chat_markers,
+            chat_template: self.chat_template,
             session: self.session,
             system_prompt: self.system_prompt,
             sampler: self.sampler,
@@ -362,9 +827,38 @@ impl<M: Model> ChatBuilder<M> {
             )
                 as Box<dyn FnMut(&[ChatHistoryItem]) -> ArcParser + Send + Sync>))),
             initial_history: self.initial_history,
+            context_overflow: self.context_overflow,
+            summarizer: self.summarizer,
         }
     }
 
+    /// Sets what the chat does when the history no longer fits in the context window of the model. Defaults to [`ContextOverflowStrategy::DropOldestTurns`].
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::builder(Llama::new_chat().await.unwrap())
+    ///     // Summarize old messages instead of forgetting them
+    ///     .with_context_overflow(ContextOverflowStrategy::Summarize)
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn with_context_overflow(mut self, strategy: ContextOverflowStrategy) -> Self
+    where
+        <M::SyncModel as SyncModel>::Session: Sync + Send,
+    {
+        self.summarizer = match strategy {
+            ContextOverflowStrategy::Summarize => {
+                Some((Summarizer::new(None), summarize_history::<M>))
+            }
+            _ => None,
+        };
+        self.context_overflow = strategy;
+        self
+    }
+
     /// Starts the chat instance with the given model session. This can be useful for resuming a chat session with a long context that has already been processed.
     ///
     /// # Example
@@ -442,24 +936,43 @@ impl<M: Model> ChatBuilder<M> {
         let Self {
             model,
             chat_markers,
+            chat_template,
             system_prompt,
             sampler,
             bot_constraints,
             session,
             initial_history,
+            context_overflow,
+            summarizer,
         } = self;
-        let system_prompt_marker = chat_markers.system_prompt_marker.to_string();
-        let end_system_prompt_marker = chat_markers.end_system_prompt_marker.to_string();
-        let user_marker = chat_markers.user_marker.to_string();
-        let end_user_marker = chat_markers.end_user_marker.to_string();
-        let assistant_marker = chat_markers.assistant_marker.to_string();
-        let end_assistant_marker = chat_markers.end_assistant_marker.to_string();
+        // Prefer the chat template the model ships with and fall back to the chat markers
+        let format = ChatFormat::new(chat_template, chat_markers);
         let (sender_tx, mut sender_rx) = unbounded_channel();
         let shared_history = Arc::new(RwLock::new(Vec::new()));
         {
             let shared_history = shared_history.clone();
 
             tokio::spawn(async move {
+                let format = match format {
+                    Ok(format) => format,
+                    Err(err) => {
+                        tracing::error!("Model does not support chat: {err}");
+                        // Fail every message instead of panicking when the chat is built
+                        while let Some(message) = sender_rx.recv().await {
+                            match message {
+                                Message::AddMessage { control, .. } => {
+                                    control.finish(StopReason::Error)
+                                }
+                                Message::SaveSession { resolve, .. } => {
+                                    let _ = resolve.send(Err(anyhow::anyhow!(
+                                        "Model does not support chat: {err}"
+                                    )));
+                                }
+                            }
+                        }
+                        return;
+                    }
+                };
                 let (tx, rx) = oneshot::channel();
                 {
                     model
@@ -467,12 +980,7 @@ impl<M: Model> ChatBuilder<M> {
                             Box::pin(async move {
                                 let _ = tx.send(ChatSession::new(
                                     model,
-                                    system_prompt_marker,
-                                    end_system_prompt_marker,
-                                    user_marker,
-                                    end_user_marker,
-                                    assistant_marker,
-                                    end_assistant_marker,
+                                    format,
                                     system_prompt,
                                     bot_constraints,
                                     sampler,
@@ -496,20 +1004,62 @@ impl<M: Model> ChatBuilder<M> {
                         Message::AddMessage {
                             message,
                             response_tx,
+                            control,
                         } => {
+                            let removed = chat_session
+                                .lock()
+                                .unwrap()
+                                .make_room(&message, context_overflow);
+                            match removed {
+                                Ok(Some(removed)) => {
+                                    if let Some((summarizer, summarize)) = &summarizer {
+                                        match summarize(summarizer, &model, removed).await {
+                                            Ok(summary) => {
+                                                let result = chat_session
+                                                    .lock()
+                                                    .unwrap()
+                                                    .add_summary(summary, &message);
+                                                if let Err(err) = result {
+                                                    tracing::error!(
+                                                        "Error adding the summary to the chat history: {}",
+                                                        err
+                                                    )
+                                                }
+                                            }
+                                            Err(err) => tracing::error!(
+                                                "Error summarizing the chat history: {}",
+                                                err
+                                            ),
+                                        }
+                                    }
+                                }
+                                Ok(None) => {}
+                                Err(err) => {
+                                    tracing::error!("Error fitting the chat history: {}", err)
+                                }
+                            }
+
+                            let (done_tx, done_rx) = oneshot::channel();
                             let chat_session = chat_session.clone();
                             model
                                 .run_sync(move |model| {
                                     Box::pin(async move {
                                         let mut chat_session = chat_session.lock().unwrap();
-                                        if let Err(err) =
-                                            chat_session.add_message(message, model, response_tx)
-                                        {
+                                        if let Err(err) = chat_session.add_message(
+                                            message,
+                                            model,
+                                            response_tx,
+                                            &control,
+                                        ) {
                                             tracing::error!("Error adding message: {}", err);
+                                            control.finish(StopReason::Error);
                                         }
+                                        let _ = done_tx.send(());
                                     })
                                 })
                                 .unwrap();
+                            // Wait for the response so the next message sees the complete history
+                            let _ = done_rx.await;
                         }
                         Message::SaveSession { path, resolve } => {
                             let chat_session = chat_session.lock().unwrap();
@@ -531,6 +1081,7 @@ enum Message {
     AddMessage {
         message: String,
         response_tx: tokio::sync::mpsc::UnboundedSender<String>,
+        control: GenerationControl,
     },
     SaveSession {
         path: PathBuf,
@@ -688,14 +1239,56 @@ impl Chat {
     /// ```
     pub fn add_message(&mut self, message: impl ToString) -> ChannelTextStream {
         let (tx, rx) = unbounded_channel();
+        self.send_message(message, tx, GenerationControl::default());
+        ChannelTextStream::from(rx)
+    }
+
+    /// Adds a user message to the chat session and streams the bot response until it finishes or the interrupt fires. [`TextGeneration::result`] reports why the response stopped.
+    ///
+    /// Dropping the stream or calling [`TextGeneration::cancel`] stops the response at the next token. The partial response is kept in the history.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # use std::time::Duration;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::new(Llama::new_chat().await.unwrap());
+    /// let prompt = prompt_input("\n> ").unwrap();
+    ///
+    /// // Stop the response if it takes longer than 10 seconds
+    /// let interrupt = Interrupt::new().with_timeout(Duration::from_secs(10));
+    /// let mut response_stream = chat.add_message_with_interrupt(prompt, interrupt);
+    /// response_stream.to_std_out().await.unwrap();
+    /// if response_stream.result().await == StopReason::TimedOut {
+    ///     println!("\nThe response timed out");
+    /// }
+    /// # }
+    /// ```
+    pub fn add_message_with_interrupt(
+        &mut self,
+        message: impl ToString,
+        interrupt: Interrupt,
+    ) -> TextGeneration<ChannelTextStream> {
+        let (tx, rx) = unbounded_channel();
+        let control = GenerationControl::new(interrupt);
+        self.send_message(message, tx, control.clone());
+        TextGeneration::new(ChannelTextStream::from(rx), control)
+    }
 
+    fn send_message(
+        &mut self,
+        message: impl ToString,
+        response_tx: tokio::sync::mpsc::UnboundedSender<String>,
+        control: GenerationControl,
+    ) {
         let message = message.to_string();
         let message = message.trim().to_string();
         let _ = self.sender.send(Message::AddMessage {
             message,
-            response_tx: tx,
+            response_tx,
+            control,
         });
-        ChannelTextStream::from(rx)
     }
 
     /// Saves the session to the given path.
@@ -727,6 +1320,8 @@ impl Chat {
 
     /// Get the current chat history.
     ///
+    /// Messages removed to fit the history in the context window of the model are not included. See [`ChatBuilder::with_context_overflow`].
+    ///
     /// # Example
     /// ```rust, no_run
     /// # use kalosm::language::*;
@@ -744,3 +1339,285 @@ impl Chat {
         self.shared_history.read().unwrap().clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tokenizers::decoders::fuse::Fuse;
+    use tokenizers::models::bpe::BPE;
+
+    use super::*;
+
+    /// A model with one token for every printable ASCII character and newlines, and a context window of 100 tokens. Only the history management is tested, so the model never generates anything.
+    struct TestModel {
+        tokenizer: Arc<Tokenizer>,
+    }
+
+    impl TestModel {
+        fn new() -> Self {
+            let vocab = (b' '..=b'~')
+                .map(|byte| (byte as char).to_string())
+                .chain(["\n".to_string(), "</s>".to_string()])
+                .enumerate()
+                .map(|(id, text)| (text, id as u32))
+                .collect();
+            let model = BPE::builder()
+                .vocab_and_merges(vocab, Vec::new())
+                .build()
+                .unwrap();
+            let mut tokenizer = Tokenizer::new(model);
+            tokenizer.with_decoder(Fuse::new());
+            Self {
+                tokenizer: Arc::new(tokenizer),
+            }
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct TestSession {
+        tokens: Vec<u32>,
+    }
+
+    impl Session for TestSession {
+        fn tokens(&self) -> &[u32] {
+            &self.tokens
+        }
+
+        fn try_clone(&self) -> Result<Self> {
+            Ok(self.clone())
+        }
+    }
+
+    impl SyncModel for TestModel {
+        type Session = TestSession;
+
+        fn new_session(&self) -> Result<Self::Session> {
+            Ok(TestSession::default())
+        }
+
+        fn feed_text(
+            &self,
+            session: &mut Self::Session,
+            prompt: &str,
+            into: &mut Vec<f32>,
+        ) -> Result<()> {
+            let tokens = self
+                .tokenizer
+                .encode(prompt, false)
+                .map_err(anyhow::Error::msg)?;
+            self.feed_tokens(session, tokens.get_ids(), into)
+        }
+
+        fn feed_tokens(
+            &self,
+            session: &mut Self::Session,
+            tokens: &[u32],
+            into: &mut Vec<f32>,
+        ) -> Result<()> {
+            session.tokens.extend_from_slice(tokens);
+            *into = vec![0.0; self.tokenizer.get_vocab_size(true)];
+            Ok(())
+        }
+
+        fn stop_token(&self) -> Result<u32> {
+            Ok(self.tokenizer.token_to_id("</s>").unwrap())
+        }
+
+        fn tokenizer(&self) -> Arc<Tokenizer> {
+            self.tokenizer.clone()
+        }
+
+        fn context_length(&self) -> Option<usize> {
+            Some(100)
+        }
+    }
+
+    /// Start a chat with the system prompt "sys" and three turns of 18 tokens each. Every marker is one token and the prompt can use 75 tokens.
+    fn long_chat() -> ChatSession<TestModel> {
+        let format = ChatFormat::Markers {
+            system_prompt_marker: "[".to_string(),
+            end_system_prompt_marker: "]".to_string(),
+            user_marker: "<".to_string(),
+            end_user_marker: ">".to_string(),
+            assistant_marker: "{".to_string(),
+            end_assistant_marker: "}".to_string(),
+        };
+        let mut chat = ChatSession::new(
+            &mut TestModel::new(),
+            format,
+            Some("sys".to_string()),
+            None,
+            Arc::new(Mutex::new(GenerationParameters::default().sampler())),
+            None,
+            Vec::new(),
+            Arc::new(RwLock::new(Vec::new())),
+        );
+        for turn in 1..=3 {
+            chat.add_user_message(format!("query {turn}"));
+            chat.add_bot_message(format!("reply {turn}"));
+        }
+        chat
+    }
+
+    /// A message of 17 tokens that doesn't fit after the whole [`long_chat`].
+    const LONG_MESSAGE: &str = "query 4 is long";
+
+    fn contents(chat: &ChatSession<TestModel>) -> Vec<String> {
+        chat.history
+            .read()
+            .unwrap()
+            .iter()
+            .map(|item| item.contents().to_string())
+            .collect()
+    }
+
+    /// Check that the new message fits after the history and that the cached token counts match the history.
+    fn assert_fits(chat: &mut ChatSession<TestModel>, message: &str) {
+        let mut history = chat.history.read().unwrap().clone();
+        let counts: Vec<_> = history
+            .iter()
+            .map(|item| token_count(&chat.tokenizer, item.contents()).unwrap())
+            .collect();
+        assert_eq!(chat.token_counts().unwrap(), counts);
+        history.push(ChatHistoryItem::new(MessageType::UserMessage, message));
+        assert!(chat.prompt_tokens(&history).unwrap() <= chat.prompt_budget().unwrap());
+    }
+
+    #[test]
+    fn chat_format_requires_a_template_or_markers() {
+        assert!(ChatFormat::new(None, None).is_err());
+    }
+
+    #[test]
+    fn short_histories_are_kept() {
+        let mut chat = long_chat();
+        let removed = chat
+            .make_room("hi", ContextOverflowStrategy::DropOldestTurns)
+            .unwrap();
+        assert!(removed.is_none());
+        assert_eq!(contents(&chat).len(), 7);
+        assert!(!chat.history_trimmed);
+    }
+
+    #[test]
+    fn oldest_turns_are_dropped() {
+        let mut chat = long_chat();
+        let message = LONG_MESSAGE;
+        let removed = chat
+            .make_room(message, ContextOverflowStrategy::DropOldestTurns)
+            .unwrap();
+        assert_eq!(
+            removed.as_deref(),
+            Some("user: query 1\nassistant: reply 1\n")
+        );
+        assert_eq!(
+            contents(&chat),
+            ["sys", "query 2", "reply 2", "query 3", "reply 3"]
+        );
+        assert_fits(&mut chat, message);
+    }
+
+    #[test]
+    fn sliding_window_keeps_the_newest_tokens() {
+        let mut chat = long_chat();
+        let message = LONG_MESSAGE;
+        let removed = chat
+            .make_room(
+                message,
+                ContextOverflowStrategy::SlidingWindow { tokens: 20 },
+            )
+            .unwrap()
+            .unwrap();
+        assert!(removed.ends_with("assistant: reply 3\n"));
+        // The new message uses 15 tokens of the window. The end of the last answer would fill the rest, but the history can't start with an answer
+        assert_eq!(contents(&chat), ["sys"]);
+        assert_fits(&mut chat, message);
+
+        // The end of a question is kept if it fits in the window
+        let mut chat = long_chat();
+        chat.make_room(
+            message,
+            ContextOverflowStrategy::SlidingWindow { tokens: 23 },
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(contents(&chat), ["sys", "3", "reply 3"]);
+        assert_eq!(
+            chat.history.read().unwrap()[1].ty(),
+            MessageType::UserMessage
+        );
+        assert_fits(&mut chat, message);
+    }
+
+    #[test]
+    fn sliding_window_fits_the_prompt() {
+        let mut chat = long_chat();
+        let message = LONG_MESSAGE;
+        // The text of every message fits in the window, but the markers push the prompt over the budget
+        chat.make_room(
+            message,
+            ContextOverflowStrategy::SlidingWindow { tokens: 1000 },
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            contents(&chat),
+            ["sys", "ery 1", "reply 1", "query 2", "reply 2", "query 3", "reply 3"]
+        );
+        assert_eq!(
+            chat.history.read().unwrap()[1].ty(),
+            MessageType::UserMessage
+        );
+        assert_fits(&mut chat, message);
+    }
+
+    #[test]
+    fn summaries_replace_removed_turns() {
+        let mut chat = long_chat();
+        let removed = chat
+            .make_room(LONG_MESSAGE, ContextOverflowStrategy::Summarize)
+            .unwrap()
+            .unwrap();
+        assert_eq!(removed, "user: query 1\nassistant: reply 1\n");
+        chat.add_summary("short".to_string(), LONG_MESSAGE).unwrap();
+        let history = contents(&chat);
+        assert_eq!(
+            history[0],
+            "sys\n\nSummary of the earlier conversation: short"
+        );
+        // The summary makes the system prompt longer, so the turns that were kept before are removed too
+        assert_eq!(history.len(), 1);
+        assert_fits(&mut chat, LONG_MESSAGE);
+
+        // The next summary replaces the last one and includes it in the removed text
+        chat.add_user_message("query 5".to_string());
+        chat.add_bot_message("reply 5".to_string());
+        let removed = chat
+            .make_room(LONG_MESSAGE, ContextOverflowStrategy::Summarize)
+            .unwrap()
+            .unwrap();
+        assert!(removed.starts_with("short\n"));
+        chat.add_summary("shorter".to_string(), LONG_MESSAGE)
+            .unwrap();
+        assert_eq!(
+            contents(&chat)[0],
+            "sys\n\nSummary of the earlier conversation: shorter"
+        );
+        assert_fits(&mut chat, LONG_MESSAGE);
+    }
+
+    #[test]
+    fn long_summaries_are_cut_off() {
+        let mut chat = long_chat();
+        let message = LONG_MESSAGE;
+        chat.make_room(message, ContextOverflowStrategy::Summarize)
+            .unwrap()
+            .unwrap();
+        chat.add_summary("x".repeat(60), message).unwrap();
+        // Every turn is removed to make room for the summary, and the summary is cut off so the new message still fits
+        let history = contents(&chat);
+        assert_eq!(history.len(), 1);
+        assert!(history[0].starts_with("sys\n\nSummary of the earlier conversation: xxx"));
+        assert!(history[0].len() < 60);
+        assert_fits(&mut chat, message);
+    }
+}
@@ -28,7 +28,10 @@ pub mod prelude {
     pub use kalosm_llama::{Llama, LlamaBuilder, LlamaSession, LlamaSource};
     pub use kalosm_sample::*;
     pub use kalosm_streams::text_stream::*;
-    pub use rbert::{Bert, BertBuilder, BertSource, BertSpace};
+    pub use rbert::{
+        Bert, BertBuilder, BertReranker, BertRerankerBuilder, BertRerankerSource, BertSource,
+        BertSpace,
+    };
     pub use rphi::{Phi, PhiBuilder, PhiSource};
     pub use scraper::Html;
 }
@@ -0,0 +1,78 @@
+use std::{future::Future, pin::Pin};
+
+use rbert::BertReranker;
+
+/// The number of candidates a search fetches for each result before reranking if the number of candidates isn't set.
+pub const RERANK_CANDIDATES_PER_RESULT: usize = 4;
+
+/// A model that scores how relevant documents are to a query.
+///
+/// Vector search compares a query embedding to document embeddings that were created separately. A reranker reads the query and each document together, so it is much more accurate, but too slow to run on every document. Searches fetch extra candidates with vector search and then sort them with a reranker.
+///
+/// # Example
+///
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let reranker = BertReranker::new().await.unwrap();
+///     let documents = vec![
+///         "Cats are cool".to_string(),
+///         "Kalosm is a library for local AI".to_string(),
+///     ];
+///     let ranked = reranker
+///         .rerank("What is Kalosm?", documents, |document| document.clone())
+///         .await
+///         .unwrap();
+///     assert_eq!(ranked[0].1, "Kalosm is a library for local AI");
+/// }
+/// ```
+pub trait Reranker: Send + Sync {
+    /// Score how relevant each document is to the query. Returns one score for each document in the same order as the documents. Higher scores are more relevant.
+    fn score(
+        &self,
+        query: String,
+        documents: Vec<String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<Vec<f32>>> + Send + '_>>;
+}
+
+/// An extension trait for [`Reranker`] that sorts items by relevance.
+///
+/// This trait is automatically implemented for any item that implements [`Reranker`].
+pub trait RerankerExt: Reranker {
+    /// Sort items by how relevant their text is to the query. Returns the items with their scores, most relevant first.
+    fn rerank<T: Send>(
+        &self,
+        query: impl ToString,
+        items: Vec<T>,
+        text: impl Fn(&T) -> String,
+    ) -> impl Future<Output = anyhow::Result<Vec<(f32, T)>>> + Send {
+        let documents = items.iter().map(text).collect();
+        let scores = self.score(query.to_string(), documents);
+        async move {
+            let mut ranked = scores.await?.into_iter().zip(items).collect::<Vec<_>>();
+            ranked.sort_by(|(first, _), (second, _)| second.total_cmp(first));
+            Ok(ranked)
+        }
+    }
+}
+
+impl<R: Reranker + ?Sized> RerankerExt for R {}
+
+impl Reranker for BertReranker {
+    fn score(
+        &self,
+        query: String,
+        documents: Vec<String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<Vec<f32>>> + Send + '_>> {
+        let reranker = self.clone();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || {
+                let documents = documents.iter().map(|s| s.as_str()).collect();
+                reranker.score_batch(&query, documents)
+            })
+            .await?
+        })
+    }
+}
@@ -474,16 +474,17 @@ impl Task {
         TaskBuilder::new(description)
     }
 
-    /// Create a new task that generates json for the given [`Parse`] type.
+    /// Create a new task that generates the given [`Parse`] type in the format of its [`Schema`].
     pub fn builder_for<P: Parse + Schema + 'static>(
         description: impl ToString,
     ) -> TaskBuilder<
         impl kalosm_sample::SendCreateParserState + kalosm_sample::Parser<Output = P> + 'static,
     > {
         let description = description.to_string();
+        let schema = P::schema();
         Task::builder(format_args!(
-            "{description}\nYou respond with JSON that follows this schema:\n{}",
-            P::schema()
+            "{description}\nYou respond with {} that follows this schema:\n{schema}",
+            schema.format()
         ))
         .with_constraints(P::new_parser())
     }
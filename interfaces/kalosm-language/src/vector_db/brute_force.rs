@@ -0,0 +1,153 @@
+use std::sync::RwLock;
+
+use kalosm_language_model::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::memory::{distance, sort_by_distance, MemoryEmbeddings};
+use super::{
+    Candidates, EmbeddingId, VectorDBSearchResult, VectorDbError, VectorStore, VectorStoreQuery,
+};
+
+/// A [`VectorStore`] that keeps every embedding in memory and compares the query to each of them.
+///
+/// Searches are exact, but take time proportional to the number of embeddings. Unlike [`VectorDB`](super::VectorDB), the store doesn't create any files, so it is a good fit for tests and small collections.
+///
+/// The [`VectorDBSearchResult::distance`] of each result is one minus the dot product of the embeddings, which is the cosine distance for normalized embeddings.
+///
+/// # Example
+///
+/// ```rust
+/// use kalosm_language::prelude::*;
+///
+/// let store: BruteForceVectorStore = BruteForceVectorStore::new();
+/// let ids = store
+///     .add_embeddings(vec![
+///         Embedding::from([1.0, 0.0]),
+///         Embedding::from([0.0, 1.0]),
+///     ])
+///     .unwrap();
+/// let query = Embedding::from([0.9, 0.1]);
+/// let results = store
+///     .search(VectorStoreQuery::new(&query).with_results(1))
+///     .unwrap();
+/// assert_eq!(results[0].value, ids[0]);
+/// ```
+pub struct BruteForceVectorStore<S = UnknownVectorSpace, P = ()> {
+    embeddings: RwLock<MemoryEmbeddings>,
+    _phantom: std::marker::PhantomData<(S, P)>,
+}
+
+impl<S: VectorSpace, P> Default for BruteForceVectorStore<S, P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: VectorSpace, P> BruteForceVectorStore<S, P> {
+    /// Create a new empty store.
+    pub fn new() -> Self {
+        Self {
+            embeddings: RwLock::new(MemoryEmbeddings::default()),
+            _phantom: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<S, P> VectorStore<S, P> for BruteForceVectorStore<S, P>
+where
+    S: VectorSpace,
+    P: Serialize + DeserializeOwned + Send + Sync,
+{
+    fn add_embeddings(
+        &self,
+        embeddings: Vec<Embedding<S>>,
+    ) -> Result<Vec<EmbeddingId>, VectorDbError> {
+        let mut stored = self.embeddings.write().unwrap();
+        let vectors = stored.to_vectors(&embeddings)?;
+        Ok(vectors
+            .into_iter()
+            .map(|vector| stored.add(vector, None))
+            .collect())
+    }
+
+    fn add_embeddings_with_payloads(
+        &self,
+        embeddings: Vec<(Embedding<S>, P)>,
+    ) -> Result<Vec<EmbeddingId>, VectorDbError> {
+        let mut stored = self.embeddings.write().unwrap();
+        let vectors = stored.to_vectors(embeddings.iter().map(|(embedding, _)| embedding))?;
+        let payloads =
+            MemoryEmbeddings::to_payloads(embeddings.iter().map(|(_, payload)| payload))?;
+        Ok(vectors
+            .into_iter()
+            .zip(payloads)
+            .map(|(vector, payload)| stored.add(vector, Some(payload)))
+            .collect())
+    }
+
+    fn get_payload(&self, embedding_id: EmbeddingId) -> Result<Option<P>, VectorDbError> {
+        self.embeddings.read().unwrap().get_payload(embedding_id)
+    }
+
+    fn set_payload(&self, embedding_id: EmbeddingId, payload: &P) -> Result<(), VectorDbError> {
+        let payload = serde_json::to_value(payload)?;
+        self.embeddings
+            .write()
+            .unwrap()
+            .set_payload(embedding_id, payload)
+    }
+
+    fn add_texts(&self, texts: Vec<(EmbeddingId, String)>) -> Result<(), VectorDbError> {
+        self.embeddings.write().unwrap().add_texts(texts);
+        Ok(())
+    }
+
+    fn remove_embedding(&self, embedding_id: EmbeddingId) -> Result<(), VectorDbError> {
+        self.embeddings
+            .write()
+            .unwrap()
+            .remove(embedding_id)
+            .map(|_| ())
+            .ok_or(VectorDbError::EmbeddingNotFound(embedding_id))
+    }
+
+    fn get_embedding(&self, embedding_id: EmbeddingId) -> Result<Embedding<S>, VectorDbError> {
+        self.embeddings.read().unwrap().get(embedding_id)
+    }
+
+    fn clear(&self) -> Result<(), VectorDbError> {
+        self.embeddings.write().unwrap().clear();
+        Ok(())
+    }
+
+    fn filter(
+        &self,
+        filter: &mut dyn FnMut(Embedding<S>) -> bool,
+    ) -> Result<Candidates, VectorDbError> {
+        Ok(self.embeddings.read().unwrap().filter(filter))
+    }
+
+    fn search(
+        &self,
+        query: VectorStoreQuery<'_, S>,
+    ) -> Result<Vec<VectorDBSearchResult<P>>, VectorDbError> {
+        let stored = self.embeddings.read().unwrap();
+        let vector = stored.to_vectors([query.embedding])?.remove(0);
+        let candidates = stored.candidates(&query);
+
+        let mut vector_results = stored
+            .vectors
+            .iter()
+            .filter(|(id, _)| match &candidates {
+                Some(candidates) => candidates.contains(**id),
+                None => true,
+            })
+            .map(|(id, other)| (*id, distance(&vector, other)))
+            .collect::<Vec<_>>();
+        sort_by_distance(&mut vector_results);
+        vector_results.truncate(query.results);
+
+        stored.results(vector_results, &query, candidates.as_ref())
+    }
+}
@@ -0,0 +1,469 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::RwLock;
+
+use kalosm_language_model::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::memory::{distance, sort_by_distance, MemoryEmbeddings};
+use super::{
+    Candidates, EmbeddingId, VectorDBSearchResult, VectorDbError, VectorStore, VectorStoreQuery,
+};
+
+/// A [`VectorStore`] that keeps embeddings in memory in a [hierarchical navigable small world](https://arxiv.org/abs/1603.09320) graph.
+///
+/// Searches are approximate like [`VectorDB`](super::VectorDB), but the index lives in the process instead of in files on disk. Each embedding is linked to its closest neighbors on a stack of layers that get sparser towards the top. A search starts at the top layer and walks towards the query on each layer.
+///
+/// The [`VectorDBSearchResult::distance`] of each result is one minus the dot product of the embeddings, which is the cosine distance for normalized embeddings.
+///
+/// # Example
+///
+/// ```rust
+/// use kalosm_language::prelude::*;
+///
+/// let store: HnswVectorStore = HnswVectorStore::new().with_ef_search(32);
+/// let ids = store
+///     .add_embeddings(vec![
+///         Embedding::from([1.0, 0.0]),
+///         Embedding::from([0.0, 1.0]),
+///     ])
+///     .unwrap();
+/// let query = Embedding::from([0.9, 0.1]);
+/// let results = store
+///     .search(VectorStoreQuery::new(&query).with_results(1))
+///     .unwrap();
+/// assert_eq!(results[0].value, ids[0]);
+/// ```
+pub struct HnswVectorStore<S = UnknownVectorSpace, P = ()> {
+    graph: RwLock<HnswGraph>,
+    _phantom: std::marker::PhantomData<(S, P)>,
+}
+
+impl<S: VectorSpace, P> Default for HnswVectorStore<S, P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: VectorSpace, P> HnswVectorStore<S, P> {
+    /// Create a new empty store.
+    pub fn new() -> Self {
+        Self {
+            graph: RwLock::new(HnswGraph::new()),
+            _phantom: std::marker::PhantomData,
+        }
+    }
+
+    /// Set the number of neighbors each embedding is linked to on each layer. More connections improve recall, but use more memory and make adding embeddings slower. This should be set before any embeddings are added. Defaults to 16.
+    pub fn with_connections(mut self, connections: usize) -> Self {
+        self.graph.get_mut().unwrap().connections = connections.max(2);
+        self
+    }
+
+    /// Set the number of candidates to consider when linking a new embedding to its neighbors. Higher values build a better graph, but make adding embeddings slower. Defaults to 100.
+    pub fn with_ef_construction(mut self, ef_construction: usize) -> Self {
+        self.graph.get_mut().unwrap().ef_construction = ef_construction.max(1);
+        self
+    }
+
+    /// Set the number of candidates to consider when searching. Higher values improve recall, but make searches slower. Searches always consider at least as many candidates as the number of results. Defaults to 64.
+    pub fn with_ef_search(mut self, ef_search: usize) -> Self {
+        self.graph.get_mut().unwrap().ef_search = ef_search.max(1);
+        self
+    }
+
+    /// Set the seed for the random layers of new embeddings. Stores with the same seed build the same graph from the same embeddings, so searches return the same results. This should be set before any embeddings are added. Defaults to a random seed.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.graph.get_mut().unwrap().rng = StdRng::seed_from_u64(seed);
+        self
+    }
+}
+
+impl<S, P> VectorStore<S, P> for HnswVectorStore<S, P>
+where
+    S: VectorSpace,
+    P: Serialize + DeserializeOwned + Send + Sync,
+{
+    fn add_embeddings(
+        &self,
+        embeddings: Vec<Embedding<S>>,
+    ) -> Result<Vec<EmbeddingId>, VectorDbError> {
+        let mut graph = self.graph.write().unwrap();
+        let vectors = graph.embeddings.to_vectors(&embeddings)?;
+        Ok(vectors
+            .into_iter()
+            .map(|vector| graph.add(vector, None))
+            .collect())
+    }
+
+    fn add_embeddings_with_payloads(
+        &self,
+        embeddings: Vec<(Embedding<S>, P)>,
+    ) -> Result<Vec<EmbeddingId>, VectorDbError> {
+        let mut graph = self.graph.write().unwrap();
+        let vectors = graph
+            .embeddings
+            .to_vectors(embeddings.iter().map(|(embedding, _)| embedding))?;
+        let payloads =
+            MemoryEmbeddings::to_payloads(embeddings.iter().map(|(_, payload)| payload))?;
+        Ok(vectors
+            .into_iter()
+            .zip(payloads)
+            .map(|(vector, payload)| graph.add(vector, Some(payload)))
+            .collect())
+    }
+
+    fn get_payload(&self, embedding_id: EmbeddingId) -> Result<Option<P>, VectorDbError> {
+        self.graph
+            .read()
+            .unwrap()
+            .embeddings
+            .get_payload(embedding_id)
+    }
+
+    fn set_payload(&self, embedding_id: EmbeddingId, payload: &P) -> Result<(), VectorDbError> {
+        let payload = serde_json::to_value(payload)?;
+        self.graph
+            .write()
+            .unwrap()
+            .embeddings
+            .set_payload(embedding_id, payload)
+    }
+
+    fn add_texts(&self, texts: Vec<(EmbeddingId, String)>) -> Result<(), VectorDbError> {
+        self.graph.write().unwrap().embeddings.add_texts(texts);
+        Ok(())
+    }
+
+    fn remove_embedding(&self, embedding_id: EmbeddingId) -> Result<(), VectorDbError> {
+        if self.graph.write().unwrap().remove(embedding_id.0) {
+            Ok(())
+        } else {
+            Err(VectorDbError::EmbeddingNotFound(embedding_id))
+        }
+    }
+
+    fn get_embedding(&self, embedding_id: EmbeddingId) -> Result<Embedding<S>, VectorDbError> {
+        self.graph.read().unwrap().embeddings.get(embedding_id)
+    }
+
+    fn clear(&self) -> Result<(), VectorDbError> {
+        let mut graph = self.graph.write().unwrap();
+        graph.embeddings.clear();
+        graph.neighbors.clear();
+        graph.entry_point = None;
+        Ok(())
+    }
+
+    fn filter(
+        &self,
+        filter: &mut dyn FnMut(Embedding<S>) -> bool,
+    ) -> Result<Candidates, VectorDbError> {
+        Ok(self.graph.read().unwrap().embeddings.filter(filter))
+    }
+
+    fn search(
+        &self,
+        query: VectorStoreQuery<'_, S>,
+    ) -> Result<Vec<VectorDBSearchResult<P>>, VectorDbError> {
+        let graph = self.graph.read().unwrap();
+        let vector = graph.embeddings.to_vectors([query.embedding])?.remove(0);
+        let candidates = graph.embeddings.candidates(&query);
+        let vector_results = graph.search(&vector, query.results, candidates.as_ref());
+
+        graph
+            .embeddings
+            .results(vector_results, &query, candidates.as_ref())
+    }
+}
+
+/// An embedding id and its distance from a query. Neighbors are ordered by distance, then id.
+#[derive(Debug, Clone, Copy)]
+struct Neighbor {
+    distance: f32,
+    id: u32,
+}
+
+impl PartialEq for Neighbor {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Neighbor {}
+
+impl PartialOrd for Neighbor {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Neighbor {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then(self.id.cmp(&other.id))
+    }
+}
+
+struct HnswGraph {
+    embeddings: MemoryEmbeddings,
+    /// The neighbors of each embedding on each layer it is in. An embedding in a layer is also in every layer below it.
+    neighbors: HashMap<u32, Vec<Vec<u32>>>,
+    /// The embedding with the most layers, where every search starts.
+    entry_point: Option<u32>,
+    connections: usize,
+    ef_construction: usize,
+    ef_search: usize,
+    rng: StdRng,
+}
+
+impl HnswGraph {
+    fn new() -> Self {
+        Self {
+            embeddings: MemoryEmbeddings::default(),
+            neighbors: HashMap::new(),
+            entry_point: None,
+            connections: 16,
+            ef_construction: 100,
+            ef_search: 64,
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    fn distance(&self, vector: &[f32], id: u32) -> f32 {
+        distance(vector, &self.embeddings.vectors[&id])
+    }
+
+    /// The maximum number of neighbors of each embedding on a layer. The bottom layer has every embedding, so it allows twice as many.
+    fn max_neighbors(&self, layer: usize) -> usize {
+        if layer == 0 {
+            self.connections * 2
+        } else {
+            self.connections
+        }
+    }
+
+    fn top_layer(&self, id: u32) -> usize {
+        self.neighbors[&id].len() - 1
+    }
+
+    /// Choose the top layer for a new embedding. Each layer has about `1 / connections` as many embeddings as the layer below it.
+    fn random_layer(&mut self) -> usize {
+        const MAX_LAYER: usize = 16;
+
+        let scale = 1. / (self.connections as f64).ln();
+        let uniform = 1. - self.rng.gen::<f64>();
+        ((-uniform.ln() * scale) as usize).min(MAX_LAYER)
+    }
+
+    /// Find the `ef` closest embeddings to the vector on a layer by walking the graph from the entry points. Returns the neighbors closest first.
+    fn search_layer(
+        &self,
+        vector: &[f32],
+        entry_points: &[u32],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<Neighbor> {
+        let mut visited: HashSet<u32> = entry_points.iter().copied().collect();
+        // The closest candidate that hasn't been expanded is on top
+        let mut candidates = BinaryHeap::new();
+        // The farthest neighbor that has been found is on top
+        let mut found = BinaryHeap::new();
+        for &id in entry_points {
+            let neighbor = Neighbor {
+                distance: self.distance(vector, id),
+                id,
+            };
+            candidates.push(Reverse(neighbor));
+            found.push(neighbor);
+        }
+        while found.len() > ef {
+            found.pop();
+        }
+
+        while let Some(Reverse(closest)) = candidates.pop() {
+            let farthest = found.peek().copied();
+            if found.len() >= ef && farthest.is_some_and(|farthest| closest > farthest) {
+                break;
+            }
+            for &id in &self.neighbors[&closest.id][layer] {
+                if !visited.insert(id) {
+                    continue;
+                }
+                let neighbor = Neighbor {
+                    distance: self.distance(vector, id),
+                    id,
+                };
+                let farthest = found.peek().copied();
+                if found.len() < ef || farthest.is_some_and(|farthest| neighbor < farthest) {
+                    candidates.push(Reverse(neighbor));
+                    found.push(neighbor);
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+
+        found.into_sorted_vec()
+    }
+
+    /// Walk down from the entry point to the closest embedding to the vector on a layer.
+    fn descend(&self, vector: &[f32], entry_point: u32, layer: usize) -> u32 {
+        let mut closest = entry_point;
+        for current in (layer + 1..=self.top_layer(entry_point)).rev() {
+            closest = self.search_layer(vector, &[closest], 1, current)[0].id;
+        }
+        closest
+    }
+
+    /// Add a vector with an optional payload and link it into the graph.
+    fn add(&mut self, vector: Vec<f32>, payload: Option<serde_json::Value>) -> EmbeddingId {
+        let id = self.embeddings.add(vector, payload);
+        self.insert(id.0);
+        id
+    }
+
+    /// Link an embedding that was just added to [`Self::embeddings`] into the graph.
+    fn insert(&mut self, id: u32) {
+        let vector = self.embeddings.vectors[&id].clone();
+        let layer = self.random_layer();
+        self.neighbors.insert(id, vec![Vec::new(); layer + 1]);
+        let Some(entry_point) = self.entry_point else {
+            self.entry_point = Some(id);
+            return;
+        };
+
+        let top_layer = self.top_layer(entry_point);
+        let mut entry_points = vec![self.descend(&vector, entry_point, layer)];
+        for current in (0..=layer.min(top_layer)).rev() {
+            let found = self.search_layer(&vector, &entry_points, self.ef_construction, current);
+            let neighbors = found
+                .iter()
+                .take(self.connections)
+                .map(|neighbor| neighbor.id)
+                .collect::<Vec<_>>();
+            for &neighbor in &neighbors {
+                self.neighbors.get_mut(&neighbor).unwrap()[current].push(id);
+                self.prune(neighbor, current);
+            }
+            self.neighbors.get_mut(&id).unwrap()[current] = neighbors;
+            entry_points = found.into_iter().map(|neighbor| neighbor.id).collect();
+        }
+
+        if layer > top_layer {
+            self.entry_point = Some(id);
+        }
+    }
+
+    /// Only keep the closest neighbors of an embedding on a layer if it has too many.
+    fn prune(&mut self, id: u32, layer: usize) {
+        let max_neighbors = self.max_neighbors(layer);
+        let links = &self.neighbors[&id][layer];
+        if links.len() <= max_neighbors {
+            return;
+        }
+        let vector = &self.embeddings.vectors[&id];
+        let mut scored = links
+            .iter()
+            .map(|&neighbor| (neighbor, self.distance(vector, neighbor)))
+            .collect::<Vec<_>>();
+        sort_by_distance(&mut scored);
+        let kept = scored
+            .into_iter()
+            .take(max_neighbors)
+            .map(|(neighbor, _)| neighbor)
+            .collect();
+        self.neighbors.get_mut(&id).unwrap()[layer] = kept;
+    }
+
+    /// Remove an embedding from the graph. Returns false if the embedding doesn't exist.
+    fn remove(&mut self, id: u32) -> bool {
+        let Some(removed) = self.neighbors.remove(&id) else {
+            return false;
+        };
+        self.embeddings.remove(EmbeddingId(id));
+
+        // Links are not always two way, so every embedding that links to the removed embedding needs to be found
+        let linked = self
+            .neighbors
+            .iter()
+            .filter(|(_, layers)| layers.iter().any(|links| links.contains(&id)))
+            .map(|(other, _)| *other)
+            .collect::<Vec<_>>();
+        for other in linked {
+            let layers = self.neighbors[&other].len();
+            for (layer, removed_links) in removed.iter().enumerate().take(layers) {
+                let links = &mut self.neighbors.get_mut(&other).unwrap()[layer];
+                let Some(position) = links.iter().position(|&link| link == id) else {
+                    continue;
+                };
+                links.swap_remove(position);
+                // Reconnect the graph through the neighbors of the removed embedding
+                for &candidate in removed_links {
+                    if candidate != other && !links.contains(&candidate) {
+                        links.push(candidate);
+                    }
+                }
+                self.prune(other, layer);
+            }
+        }
+
+        if self.entry_point == Some(id) {
+            self.entry_point = self
+                .neighbors
+                .iter()
+                .max_by_key(|(other, layers)| (layers.len(), Reverse(**other)))
+                .map(|(other, _)| *other);
+        }
+
+        true
+    }
+
+    /// Find the closest embeddings to the vector that pass the filter. Returns the ids and distances, closest first.
+    fn search(
+        &self,
+        vector: &[f32],
+        results: usize,
+        filter: Option<&Candidates>,
+    ) -> Vec<(u32, f32)> {
+        let Some(entry_point) = self.entry_point else {
+            return Vec::new();
+        };
+
+        let mut ef = self.ef_search.max(results);
+        if let Some(filter) = filter {
+            // Consider more candidates when most embeddings don't pass the filter
+            let total = self.neighbors.len() as u64;
+            let ratio = total.div_ceil(filter.len().max(1)).max(1);
+            ef = ef.saturating_mul(ratio as usize);
+            // If the search would consider every embedding that passes the filter anyway, checking each of them is faster and exact
+            if ef as u64 >= filter.len() {
+                let mut exact = filter
+                    .iter()
+                    .filter_map(|id| {
+                        Some((id, distance(vector, self.embeddings.vectors.get(&id)?)))
+                    })
+                    .collect::<Vec<_>>();
+                sort_by_distance(&mut exact);
+                exact.truncate(results);
+                return exact;
+            }
+        }
+
+        let closest = self.descend(vector, entry_point, 0);
+        self.search_layer(vector, &[closest], ef, 0)
+            .into_iter()
+            .filter(|neighbor| match filter {
+                Some(filter) => filter.contains(neighbor.id),
+                None => true,
+            })
+            .take(results)
+            .map(|neighbor| (neighbor.id, neighbor.distance))
+            .collect()
+    }
+}
@@ -0,0 +1,267 @@
+//! The embeddings and full text index shared by the in memory vector stores.
+
+use std::collections::{BTreeMap, HashMap};
+
+use kalosm_language_model::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use super::{
+    best_scores, query_terms, score_postings, text_terms, Candidates, EmbeddingId,
+    VectorDBSearchResult, VectorDbError, VectorStoreQuery,
+};
+
+/// The distance between two vectors. This is one minus the dot product, which is the cosine distance for normalized vectors.
+pub(crate) fn distance(first: &[f32], second: &[f32]) -> f32 {
+    1. - first
+        .iter()
+        .zip(second)
+        .map(|(first, second)| first * second)
+        .sum::<f32>()
+}
+
+/// Sort (id, distance) pairs by distance, closest first. Ties are sorted by id so results don't depend on the order of a hash map.
+pub(crate) fn sort_by_distance(results: &mut [(u32, f32)]) {
+    results.sort_by(|(first_id, first), (second_id, second)| {
+        first.total_cmp(second).then(first_id.cmp(second_id))
+    });
+}
+
+/// A set of embeddings in memory with a full text index and payloads.
+#[derive(Default)]
+pub(crate) struct MemoryEmbeddings {
+    pub(crate) vectors: HashMap<u32, Vec<f32>>,
+    /// The JSON form of the payload of each embedding.
+    payloads: HashMap<u32, Value>,
+    dim: Option<usize>,
+    max: u32,
+    free: Vec<u32>,
+    text: MemoryTextIndex,
+}
+
+impl MemoryEmbeddings {
+    /// Convert embeddings to vectors and check that they have the same number of dimensions as the other embeddings.
+    pub(crate) fn to_vectors<'a, S: VectorSpace>(
+        &self,
+        embeddings: impl IntoIterator<Item = &'a Embedding<S>>,
+    ) -> Result<Vec<Vec<f32>>, VectorDbError> {
+        let mut dim = self.dim;
+        embeddings
+            .into_iter()
+            .map(|embedding| {
+                let vector: Vec<f32> = embedding.vector().to_vec1()?;
+                let expected = *dim.get_or_insert(vector.len());
+                if expected != vector.len() {
+                    return Err(VectorDbError::DimensionMismatch {
+                        expected,
+                        found: vector.len(),
+                    });
+                }
+                Ok(vector)
+            })
+            .collect()
+    }
+
+    /// Add a vector with an optional payload and return its id.
+    pub(crate) fn add(&mut self, vector: Vec<f32>, payload: Option<Value>) -> EmbeddingId {
+        self.dim = Some(vector.len());
+        let id = self.free.pop().unwrap_or_else(|| {
+            self.max += 1;
+            self.max - 1
+        });
+        self.vectors.insert(id, vector);
+        if let Some(payload) = payload {
+            self.payloads.insert(id, payload);
+        }
+        EmbeddingId(id)
+    }
+
+    /// Convert payloads to JSON so they can be filtered.
+    pub(crate) fn to_payloads<'a, P: Serialize + 'a>(
+        payloads: impl IntoIterator<Item = &'a P>,
+    ) -> Result<Vec<Value>, VectorDbError> {
+        Ok(payloads
+            .into_iter()
+            .map(serde_json::to_value)
+            .collect::<Result<_, _>>()?)
+    }
+
+    /// Remove a vector, its text and its payload. Returns the vector if it existed.
+    pub(crate) fn remove(&mut self, embedding_id: EmbeddingId) -> Option<Vec<f32>> {
+        let vector = self.vectors.remove(&embedding_id.0)?;
+        self.text.remove_text(embedding_id.0);
+        self.payloads.remove(&embedding_id.0);
+        self.free.push(embedding_id.0);
+        Some(vector)
+    }
+
+    pub(crate) fn get<S: VectorSpace>(
+        &self,
+        embedding_id: EmbeddingId,
+    ) -> Result<Embedding<S>, VectorDbError> {
+        self.vectors
+            .get(&embedding_id.0)
+            .map(|vector| Embedding::from(vector.iter().copied()))
+            .ok_or(VectorDbError::EmbeddingNotFound(embedding_id))
+    }
+
+    pub(crate) fn get_payload<P: DeserializeOwned>(
+        &self,
+        embedding_id: EmbeddingId,
+    ) -> Result<Option<P>, VectorDbError> {
+        self.payloads
+            .get(&embedding_id.0)
+            .map(|payload| serde_json::from_value(payload.clone()))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    pub(crate) fn set_payload(
+        &mut self,
+        embedding_id: EmbeddingId,
+        payload: Value,
+    ) -> Result<(), VectorDbError> {
+        if !self.vectors.contains_key(&embedding_id.0) {
+            return Err(VectorDbError::EmbeddingNotFound(embedding_id));
+        }
+        self.payloads.insert(embedding_id.0, payload);
+        Ok(())
+    }
+
+    pub(crate) fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub(crate) fn filter<S: VectorSpace>(
+        &self,
+        filter: &mut dyn FnMut(Embedding<S>) -> bool,
+    ) -> Candidates {
+        let mut candidates = Candidates::new();
+        for (id, vector) in &self.vectors {
+            if filter(Embedding::from(vector.iter().copied())) {
+                candidates.insert(*id);
+            }
+        }
+        candidates
+    }
+
+    pub(crate) fn add_texts(&mut self, texts: Vec<(EmbeddingId, String)>) {
+        for (embedding_id, text) in texts {
+            self.text.add_text(embedding_id.0, &text);
+        }
+    }
+
+    /// Find the embeddings that may be returned for the query. Returns `None` if any embedding may be returned.
+    ///
+    /// Payloads in memory are checked one at a time instead of with the payload index of [`super::VectorDB`].
+    pub(crate) fn candidates<S: VectorSpace>(
+        &self,
+        query: &VectorStoreQuery<'_, S>,
+    ) -> Option<Candidates> {
+        let Some(payload_filter) = &query.payload_filter else {
+            return query.filter.clone();
+        };
+        let mut candidates = self
+            .payloads
+            .iter()
+            .filter(|(_, payload)| payload_filter.matches(payload))
+            .map(|(id, _)| *id)
+            .collect::<Candidates>();
+        if let Some(filter) = &query.filter {
+            candidates &= filter;
+        }
+        Some(candidates)
+    }
+
+    /// Turn the results of the vector search (ids and distances, closest first) into the results of the query. Hybrid searches also search the full text index for the candidates.
+    pub(crate) fn results<S: VectorSpace, P: DeserializeOwned>(
+        &self,
+        vector_results: Vec<(u32, f32)>,
+        query: &VectorStoreQuery<'_, S>,
+        candidates: Option<&Candidates>,
+    ) -> Result<Vec<VectorDBSearchResult<P>>, VectorDbError> {
+        let mut results = match &query.text_query {
+            Some(text_query) => {
+                let text_results = self.text.search(text_query, query.results, candidates);
+                let mut fused = query.fusion.fuse(&vector_results, &text_results);
+                fused.truncate(query.results);
+                fused
+            }
+            None => vector_results
+                .into_iter()
+                .map(|(id, distance)| VectorDBSearchResult {
+                    distance,
+                    value: EmbeddingId(id),
+                    relevance: None,
+                    payload: None,
+                })
+                .collect::<Vec<_>>(),
+        };
+
+        for result in &mut results {
+            result.payload = self.get_payload(result.value)?;
+        }
+
+        Ok(results)
+    }
+}
+
+/// A BM25 full text index in memory. This is the in memory version of the full text index in [`super::VectorDB`].
+#[derive(Default)]
+struct MemoryTextIndex {
+    /// The (term count, text length) of each text that contains a term, keyed by the term and the embedding id.
+    postings: BTreeMap<(String, u32), (u32, u32)>,
+    /// The terms in the text of each embedding.
+    terms: HashMap<u32, Vec<String>>,
+    total_length: u32,
+}
+
+impl MemoryTextIndex {
+    fn add_text(&mut self, id: u32, text: &str) {
+        self.remove_text(id);
+
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        for term in text_terms(text) {
+            *counts.entry(term).or_default() += 1;
+        }
+        let length = counts.values().sum();
+        for (term, count) in &counts {
+            self.postings.insert((term.clone(), id), (*count, length));
+        }
+        self.terms.insert(id, counts.into_keys().collect());
+        self.total_length += length;
+    }
+
+    fn remove_text(&mut self, id: u32) {
+        let Some(terms) = self.terms.remove(&id) else {
+            return;
+        };
+        let mut length = 0;
+        for term in terms {
+            if let Some((_, text_length)) = self.postings.remove(&(term, id)) {
+                length = text_length;
+            }
+        }
+        self.total_length = self.total_length.saturating_sub(length);
+    }
+
+    fn search(&self, query: &str, results: usize, filter: Option<&Candidates>) -> Vec<(u32, f32)> {
+        let documents = self.terms.len() as u32;
+        if documents == 0 {
+            return Vec::new();
+        }
+
+        let mut scores = HashMap::new();
+        for term in query_terms(query) {
+            let postings = self
+                .postings
+                .range((term.clone(), 0)..=(term, u32::MAX))
+                .map(|((_, id), (count, length))| (*id, *count, *length))
+                .collect::<Vec<_>>();
+            score_postings(&mut scores, &postings, documents, self.total_length, filter);
+        }
+
+        best_scores(scores, results)
+    }
+}
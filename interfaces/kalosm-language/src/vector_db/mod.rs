@@ -0,0 +1,1343 @@
+//! A vector database that can be used to store embeddings and search for similar embeddings.
+
+use arroy::distances::DotProduct;
+use heed::{byteorder::BigEndian, types::*, RoTxn, RwTxn};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::atomic::AtomicUsize;
+
+use arroy::{Database as ArroyDatabase, Reader, Writer};
+use candle_core::Tensor;
+use heed::types::SerdeJson;
+use heed::{Database, EnvOpenOptions};
+use kalosm_language_model::*;
+use kalosm_llama::accelerated_device_if_available;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::search::{Reranker, RerankerExt, RERANK_CANDIDATES_PER_RESULT};
+
+mod brute_force;
+pub use brute_force::*;
+mod hnsw;
+pub use hnsw::*;
+mod memory;
+mod payload;
+pub use payload::*;
+mod store;
+pub use store::*;
+
+/// A set of candidates for a vector search.
+pub type Candidates = roaring::RoaringBitmap;
+
+/// An error that can occur when adding or searching for an embedding to the vector database.
+#[derive(Debug, thiserror::Error)]
+pub enum VectorDbError {
+    /// An error from the arroy crate.
+    #[error("Arroy error: {0}")]
+    Arroy(#[from] arroy::Error),
+    /// An error from the Candle crate.
+    #[error("Candle error: {0}")]
+    Candle(#[from] candle_core::Error),
+    /// An error from querying an embedding id that does not exist.
+    #[error("Embedding {0:?} not found")]
+    EmbeddingNotFound(EmbeddingId),
+    /// An error from adding or searching for an embedding with a different number of dimensions than the other embeddings.
+    #[error("Expected an embedding with {expected} dimensions, but found {found} dimensions")]
+    DimensionMismatch {
+        /// The number of dimensions of the other embeddings.
+        expected: usize,
+        /// The number of dimensions of the embedding.
+        found: usize,
+    },
+    /// An error from converting a payload to or from JSON.
+    #[error("Payload error: {0}")]
+    Payload(#[from] serde_json::Error),
+}
+
+impl From<heed::Error> for VectorDbError {
+    fn from(value: heed::Error) -> Self {
+        Self::Arroy(value.into())
+    }
+}
+
+/// A vector database that can be used to store embeddings and search for similar embeddings.
+///
+/// It uses an in memory database with fast lookups for nearest neighbors and points within a certain distance.
+///
+/// You can also add the text of each embedding to a full text index with [`VectorDB::add_texts`]. Searches with [`VectorDBSearchBuilder::with_text_query`] combine the vector search with a [BM25](https://en.wikipedia.org/wiki/Okapi_BM25) keyword search, which finds exact matches like product codes, error messages and names that semantic search misses.
+///
+/// Each embedding can store a payload of type `P` in the same database. Create a database with payloads with [`VectorDB::new_with_payloads`], add embeddings with [`VectorDB::add_embeddings_with_payloads`] and filter searches by the fields of the payload with [`VectorDBSearchBuilder::with_payload_filter`].
+///
+/// # Example
+///
+/// ```rust, no_run
+/// # use kalosm_language::prelude::*;
+/// # use kalosm_language_model::*;
+/// # use rbert::*;
+/// # use std::collections::HashMap;
+/// # #[tokio::main]
+/// # async fn main() {
+/// // Create a good default Bert model for search
+/// let bert = Bert::new_for_search().await.unwrap();
+/// let sentences = [
+///     "Kalosm can be used to build local AI applications",
+///     "With private LLMs data never leaves your computer",
+///     "The quick brown fox jumps over the lazy dog",
+/// ];
+/// // Embed sentences into the vector space
+/// let embeddings = bert.embed_batch(sentences).await.unwrap();
+/// println!("embeddings {:?}", embeddings);
+///
+/// // Create a vector database from the embeddings along with a map between the embedding ids and the sentences
+/// let db = VectorDB::new().unwrap();
+/// let embeddings = db.add_embeddings(embeddings).unwrap();
+/// let embedding_id_to_sentence: HashMap<EmbeddingId, &str> =
+///     HashMap::from_iter(embeddings.into_iter().zip(sentences));
+///
+/// // Find the closest sentence to "What is Kalosm?"
+/// let query = "What is Kalosm?";
+/// // Embed the query into the vector space. We use `embed_query` instead of `embed` because some models embed queries differently than normal text.
+/// let embedding = bert.embed_query(query).await.unwrap();
+/// let closest = db.search(&embedding).run().unwrap();
+/// if let [closest] = closest.as_slice() {
+///     let distance = closest.distance;
+///     let text = embedding_id_to_sentence.get(&closest.value).unwrap();
+///     println!("distance: {distance}");
+///     println!("closest:  {text}");
+/// }
+/// # }
+/// ```
+#[doc(alias = "VectorDatabase")]
+#[doc(alias = "Vector Database")]
+pub struct VectorDB<S = UnknownVectorSpace, P = ()> {
+    database: ArroyDatabase<DotProduct>,
+    metadata: Database<Str, SerdeJson<Vec<u32>>>,
+    /// The (term count, text length) of each text that contains a term, keyed by the term and the embedding id.
+    text_postings: Database<Bytes, SerdeJson<(u32, u32)>>,
+    /// The terms in the text of each embedding.
+    text_terms: Database<U32<BigEndian>, SerdeJson<Vec<String>>>,
+    /// The payload of each embedding.
+    payloads: Database<U32<BigEndian>, SerdeJson<P>>,
+    /// The embeddings with each value of each payload field.
+    payload_index: PayloadIndex,
+    env: heed::Env,
+    dim: AtomicUsize,
+    _phantom: std::marker::PhantomData<S>,
+}
+
+impl<S: VectorSpace + Sync, P: 'static> Default for VectorDB<S, P> {
+    fn default() -> Self {
+        Self::new_with_payloads().unwrap()
+    }
+}
+
+impl<S: VectorSpace + Sync> VectorDB<S> {
+    /// Create a new temporary vector database.
+    #[tracing::instrument]
+    pub fn new() -> heed::Result<Self> {
+        Self::new_with_payloads()
+    }
+
+    /// Create a new vector database at the given path.
+    pub fn new_at(path: impl AsRef<std::path::Path>) -> heed::Result<Self> {
+        Self::new_with_payloads_at(path)
+    }
+}
+
+impl<S: VectorSpace + Sync, P: 'static> VectorDB<S, P> {
+    fn set_dim(&self, dim: usize) {
+        if dim == 0 {
+            panic!("Dimension cannot be 0");
+        }
+        self.dim.store(dim, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    fn get_dim(&self) -> Result<usize, arroy::Error> {
+        let mut dims = self.dim.load(std::sync::atomic::Ordering::Relaxed);
+        if dims == 0 {
+            let rtxn = self.env.read_txn()?;
+            let reader = Reader::<DotProduct>::open(&rtxn, 0, self.database)?;
+            dims = reader.dimensions();
+            self.set_dim(dims);
+        }
+        Ok(dims)
+    }
+
+    /// Create a new temporary vector database that stores a payload of type `P` with each embedding.
+    pub fn new_with_payloads() -> heed::Result<Self> {
+        let dir = tempfile::tempdir()?;
+
+        Self::new_with_payloads_at(dir.path())
+    }
+
+    /// Create a new vector database at the given path that stores a payload of type `P` with each embedding.
+    pub fn new_with_payloads_at(path: impl AsRef<std::path::Path>) -> heed::Result<Self> {
+        const TWENTY_HUNDRED_MIB: usize = 2 * 1024 * 1024 * 1024;
+
+        std::fs::create_dir_all(&path)?;
+
+        let env = unsafe {
+            EnvOpenOptions::new()
+                .map_size(TWENTY_HUNDRED_MIB)
+                .max_dbs(4)
+                .open(path)
+        }?;
+
+        let mut wtxn = env.write_txn()?;
+        let db: ArroyDatabase<DotProduct> = env.create_database(&mut wtxn, None)?;
+        let metadata: Database<Str, SerdeJson<Vec<u32>>> = env.create_database(&mut wtxn, None)?;
+        let text_postings = env.create_database(&mut wtxn, Some("text-postings"))?;
+        let text_terms = env.create_database(&mut wtxn, Some("text-terms"))?;
+        let payloads = env.create_database(&mut wtxn, Some("payloads"))?;
+        let payload_index = PayloadIndex::create(&env, &mut wtxn)?;
+        wtxn.commit()?;
+
+        Ok(Self {
+            database: db,
+            metadata,
+            text_postings,
+            text_terms,
+            payloads,
+            payload_index,
+            env,
+            dim: AtomicUsize::new(0),
+            _phantom: std::marker::PhantomData,
+        })
+    }
+
+    fn take_id(&self, wtxn: &mut RwTxn) -> Result<EmbeddingId, heed::Error> {
+        if let Some(mut free) = self.metadata.get(wtxn, "free")? {
+            if let Some(id) = free.pop() {
+                self.metadata.put(wtxn, "free", &free)?;
+                return Ok(EmbeddingId(id));
+            }
+        }
+        match self.metadata.get(wtxn, "max")? {
+            Some(max) => {
+                let id = max[0];
+                self.metadata.put(wtxn, "max", &vec![id + 1])?;
+                Ok(EmbeddingId(id))
+            }
+            None => {
+                self.metadata.put(wtxn, "max", &vec![1])?;
+                Ok(EmbeddingId(0))
+            }
+        }
+    }
+
+    fn recycle_id(&self, id: EmbeddingId, wtxn: &mut RwTxn) -> Result<(), heed::Error> {
+        let mut free = self.metadata.get(wtxn, "free")?.unwrap_or_default();
+        free.push(id.0);
+        self.metadata.put(wtxn, "free", &free)?;
+
+        Ok(())
+    }
+
+    /// Get the underlying database.
+    pub fn raw(&self) -> (&ArroyDatabase<DotProduct>, &heed::Env) {
+        (&self.database, &self.env)
+    }
+
+    /// Clear the vector database.
+    pub async fn clear(&self) -> Result<(), arroy::Error> {
+        self.clear_blocking()
+    }
+
+    fn clear_blocking(&self) -> Result<(), arroy::Error> {
+        let mut wtxn = self.env.write_txn()?;
+        let dims = self.get_dim()?;
+        let writer = Writer::<DotProduct>::new(self.database, 0, dims);
+        writer.clear(&mut wtxn)?;
+
+        // Reset the ids
+        self.metadata.put(&mut wtxn, "max", &vec![0])?;
+        self.metadata.put(&mut wtxn, "free", &vec![])?;
+
+        // Reset the full text index
+        self.text_postings.clear(&mut wtxn)?;
+        self.text_terms.clear(&mut wtxn)?;
+        self.metadata.delete(&mut wtxn, "text_stats")?;
+
+        self.payloads.clear(&mut wtxn)?;
+        self.payload_index.clear(&mut wtxn)?;
+        wtxn.commit()?;
+
+        Ok(())
+    }
+
+    /// Rebuild the database.
+    pub fn rebuild(
+        &self,
+        writer: &mut Writer<DotProduct>,
+        wtxn: &mut RwTxn,
+    ) -> Result<(), arroy::Error> {
+        let mut rng = StdRng::from_entropy();
+        writer.builder(&mut rng).build(wtxn)?;
+
+        Ok(())
+    }
+
+    /// Remove an embedding from the vector database.
+    pub fn remove_embedding(&self, embedding_id: EmbeddingId) -> Result<(), arroy::Error> {
+        let dims = self.get_dim()?;
+
+        let mut wtxn = self.env.write_txn()?;
+
+        let mut writer = Writer::<DotProduct>::new(self.database, 0, dims);
+
+        writer.del_item(&mut wtxn, embedding_id.0)?;
+        self.remove_text(&mut wtxn, embedding_id)?;
+        self.remove_payload(&mut wtxn, embedding_id)?;
+        self.recycle_id(embedding_id, &mut wtxn)?;
+
+        self.rebuild(&mut writer, &mut wtxn)?;
+
+        wtxn.commit()?;
+
+        Ok(())
+    }
+
+    /// Add a new embedding to the vector database.
+    ///
+    /// Note: Adding embeddings in a batch with [`VectorDB::add_embeddings`] will be faster.
+    pub fn add_embedding(&self, embedding: Embedding<S>) -> Result<EmbeddingId, VectorDbError> {
+        let embedding = embedding.vector().to_vec1()?;
+
+        self.set_dim(embedding.len());
+
+        let mut wtxn = self.env.write_txn()?;
+
+        let mut writer = Writer::<DotProduct>::new(self.database, 0, embedding.len());
+
+        let id = self.take_id(&mut wtxn)?;
+
+        writer.add_item(&mut wtxn, id.0, &embedding)?;
+
+        self.rebuild(&mut writer, &mut wtxn)?;
+
+        wtxn.commit()?;
+
+        Ok(id)
+    }
+
+    /// Add a new batch of embeddings to the vector database.
+    pub fn add_embeddings(
+        &self,
+        embedding: impl IntoIterator<Item = Embedding<S>>,
+    ) -> Result<Vec<EmbeddingId>, VectorDbError> {
+        self.add_embeddings_with(embedding, |_, _| Ok(()))
+    }
+
+    /// Add a batch of embeddings and call `on_add` with the id of each embedding in the same transaction.
+    fn add_embeddings_with(
+        &self,
+        embedding: impl IntoIterator<Item = Embedding<S>>,
+        mut on_add: impl FnMut(&mut RwTxn, EmbeddingId) -> Result<(), heed::Error>,
+    ) -> Result<Vec<EmbeddingId>, VectorDbError> {
+        let mut embeddings = embedding.into_iter().map(|e| e.vector().to_vec1());
+        let first_embedding = match embeddings.next() {
+            Some(e) => e?,
+            None => return Ok(Vec::new()),
+        };
+        self.set_dim(first_embedding.len());
+
+        let mut wtxn = self.env.write_txn()?;
+        let mut writer = Writer::<DotProduct>::new(self.database, 0, first_embedding.len());
+
+        let mut ids: Vec<_> = Vec::with_capacity(embeddings.size_hint().0 + 1);
+
+        {
+            let first_id = self.take_id(&mut wtxn)?;
+            writer.add_item(&mut wtxn, first_id.0, &first_embedding)?;
+            on_add(&mut wtxn, first_id)?;
+            ids.push(first_id);
+        }
+
+        for embedding in embeddings {
+            let id = self.take_id(&mut wtxn)?;
+            writer.add_item(&mut wtxn, id.0, &embedding?)?;
+            on_add(&mut wtxn, id)?;
+            ids.push(id);
+        }
+
+        self.rebuild(&mut writer, &mut wtxn)?;
+
+        wtxn.commit()?;
+
+        Ok(ids)
+    }
+
+    /// Get the embedding for an embedding id.
+    pub fn get_embedding(&self, embedding_id: EmbeddingId) -> Result<Embedding<S>, VectorDbError> {
+        let rtxn = self.env.read_txn()?;
+        let reader = Reader::<DotProduct>::open(&rtxn, 0, self.database)?;
+
+        let embedding = reader
+            .item_vector(&rtxn, embedding_id.0)?
+            .ok_or_else(|| VectorDbError::EmbeddingNotFound(embedding_id))?;
+
+        let shape = (embedding.len(),);
+        Ok(Embedding::new(Tensor::from_vec(
+            embedding,
+            shape,
+            &accelerated_device_if_available()?,
+        )?))
+    }
+
+    /// Add the text of an embedding to the full text index. If the embedding already has text in the index, the text is replaced.
+    ///
+    /// Note: Adding text in a batch with [`VectorDB::add_texts`] will be faster.
+    pub fn add_text(&self, embedding_id: EmbeddingId, text: &str) -> Result<(), VectorDbError> {
+        self.add_texts([(embedding_id, text)])
+    }
+
+    /// Add the text of a batch of embeddings to the full text index. If an embedding already has text in the index, the text is replaced.
+    pub fn add_texts<T: AsRef<str>>(
+        &self,
+        texts: impl IntoIterator<Item = (EmbeddingId, T)>,
+    ) -> Result<(), VectorDbError> {
+        let mut wtxn = self.env.write_txn()?;
+        // Only the last text for each embedding is kept
+        let texts: HashMap<EmbeddingId, T> = texts.into_iter().collect();
+        for id in texts.keys() {
+            self.remove_text(&mut wtxn, *id)?;
+        }
+
+        let (mut documents, mut total_length) = self.text_stats(&wtxn)?;
+        for (id, text) in texts {
+            let mut counts: HashMap<String, u32> = HashMap::new();
+            for term in text_terms(text.as_ref()) {
+                *counts.entry(term).or_default() += 1;
+            }
+            let length = counts.values().sum();
+            let terms = counts.keys().cloned().collect::<Vec<_>>();
+            self.text_terms.put(&mut wtxn, &id.0, &terms)?;
+            for (term, count) in counts {
+                self.text_postings
+                    .put(&mut wtxn, &posting_key(&term, id.0), &(count, length))?;
+            }
+            documents += 1;
+            total_length += length;
+        }
+        self.metadata
+            .put(&mut wtxn, "text_stats", &vec![documents, total_length])?;
+        wtxn.commit()?;
+
+        Ok(())
+    }
+
+    /// Remove the text of an embedding from the full text index.
+    fn remove_text(&self, wtxn: &mut RwTxn, embedding_id: EmbeddingId) -> Result<(), heed::Error> {
+        let Some(terms) = self.text_terms.get(wtxn, &embedding_id.0)? else {
+            return Ok(());
+        };
+        let mut length = 0;
+        for term in terms {
+            let key = posting_key(&term, embedding_id.0);
+            if let Some((_, text_length)) = self.text_postings.get(wtxn, &key)? {
+                length = text_length;
+            }
+            self.text_postings.delete(wtxn, &key)?;
+        }
+        self.text_terms.delete(wtxn, &embedding_id.0)?;
+
+        let (documents, total_length) = self.text_stats(wtxn)?;
+        self.metadata.put(
+            wtxn,
+            "text_stats",
+            &vec![
+                documents.saturating_sub(1),
+                total_length.saturating_sub(length),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Get the number of texts in the full text index and their total length in terms.
+    fn text_stats(&self, rtxn: &RoTxn) -> Result<(u32, u32), heed::Error> {
+        Ok(match self.metadata.get(rtxn, "text_stats")?.as_deref() {
+            Some(&[documents, total_length]) => (documents, total_length),
+            _ => (0, 0),
+        })
+    }
+
+    /// Find the texts that best match the query with BM25. Returns the embedding ids and scores of the best matches, best first.
+    fn search_text(
+        &self,
+        rtxn: &RoTxn,
+        query: &str,
+        results: usize,
+        filter: Option<&Candidates>,
+    ) -> Result<Vec<(u32, f32)>, heed::Error> {
+        let (documents, total_length) = self.text_stats(rtxn)?;
+        if documents == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut scores: HashMap<u32, f32> = HashMap::new();
+        for term in query_terms(query) {
+            let postings = self
+                .text_postings
+                .prefix_iter(rtxn, &posting_prefix(&term))?
+                .map(|item| {
+                    let (key, (count, length)) = item?;
+                    Ok((posting_id(key), count, length))
+                })
+                .collect::<Result<Vec<_>, heed::Error>>()?;
+            score_postings(&mut scores, &postings, documents, total_length, filter);
+        }
+
+        Ok(best_scores(scores, results))
+    }
+
+    /// Remove the payload of an embedding and its fields from the payload index.
+    fn remove_payload(&self, wtxn: &mut RwTxn, embedding_id: EmbeddingId) -> heed::Result<()> {
+        // The index only stores the fields of the payload, so it doesn't need to know the payload type
+        let payloads = self
+            .payloads
+            .remap_data_type::<SerdeJson<serde_json::Value>>();
+        if let Some(payload) = payloads.get(wtxn, &embedding_id.0)? {
+            self.payload_index.remove(wtxn, embedding_id.0, &payload)?;
+            payloads.delete(wtxn, &embedding_id.0)?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the closest N embeddings to the given embedding.
+    pub fn search<'a>(&'a self, embedding: &'a Embedding<S>) -> VectorDBSearchBuilder<'a, S, P> {
+        VectorDBSearchBuilder {
+            db: self,
+            embedding,
+            results: None,
+            candidates: None,
+            filter: None,
+            payload_filter: None,
+            text_query: None,
+            fusion: HybridFusion::default(),
+        }
+    }
+}
+
+impl<S: VectorSpace + Sync, P: Serialize + DeserializeOwned + 'static> VectorDB<S, P> {
+    /// Add a new embedding with a payload to the vector database.
+    ///
+    /// Note: Adding embeddings in a batch with [`VectorDB::add_embeddings_with_payloads`] will be faster.
+    pub fn add_embedding_with_payload(
+        &self,
+        embedding: Embedding<S>,
+        payload: P,
+    ) -> Result<EmbeddingId, VectorDbError> {
+        let ids = self.add_embeddings_with_payloads([(embedding, payload)])?;
+        Ok(ids[0])
+    }
+
+    /// Add a new batch of embeddings with payloads to the vector database.
+    pub fn add_embeddings_with_payloads(
+        &self,
+        embeddings: impl IntoIterator<Item = (Embedding<S>, P)>,
+    ) -> Result<Vec<EmbeddingId>, VectorDbError> {
+        let (embeddings, payloads): (Vec<_>, Vec<_>) = embeddings.into_iter().unzip();
+        let mut payloads = payloads.into_iter();
+        self.add_embeddings_with(embeddings, |wtxn, id| {
+            let payload = payloads.next().expect("every embedding has a payload");
+            self.put_payload(wtxn, id, &payload)
+        })
+    }
+
+    /// Get the payload of an embedding. Returns `None` if the embedding doesn't have a payload.
+    pub fn get_payload(&self, embedding_id: EmbeddingId) -> Result<Option<P>, VectorDbError> {
+        let rtxn = self.env.read_txn()?;
+        Ok(self.payloads.get(&rtxn, &embedding_id.0)?)
+    }
+
+    /// Set the payload of an embedding. If the embedding already has a payload, the payload is replaced.
+    pub fn set_payload(&self, embedding_id: EmbeddingId, payload: &P) -> Result<(), VectorDbError> {
+        let mut wtxn = self.env.write_txn()?;
+        self.remove_payload(&mut wtxn, embedding_id)?;
+        self.put_payload(&mut wtxn, embedding_id, payload)?;
+        wtxn.commit()?;
+
+        Ok(())
+    }
+
+    /// Store the payload of an embedding and add its fields to the payload index.
+    fn put_payload(
+        &self,
+        wtxn: &mut RwTxn,
+        embedding_id: EmbeddingId,
+        payload: &P,
+    ) -> heed::Result<()> {
+        let fields =
+            serde_json::to_value(payload).map_err(|err| heed::Error::Encoding(Box::new(err)))?;
+        self.payloads.put(wtxn, &embedding_id.0, payload)?;
+        self.payload_index.insert(wtxn, embedding_id.0, &fields)
+    }
+}
+
+/// Split text into lowercase terms for the full text index.
+fn text_terms(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|char: char| !char.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(|term| term.to_lowercase())
+}
+
+/// The start of the keys of every posting of a term. Terms only contain alphanumeric characters, so the zero byte ends the term and the postings of a term never share a prefix with the postings of a longer term.
+fn posting_prefix(term: &str) -> Vec<u8> {
+    let mut key = term.as_bytes().to_vec();
+    key.push(0);
+    key
+}
+
+/// The key of the posting of a term in the text of an embedding.
+fn posting_key(term: &str, id: u32) -> Vec<u8> {
+    let mut key = posting_prefix(term);
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+/// Read the embedding id from the end of the key of a posting.
+fn posting_id(key: &[u8]) -> u32 {
+    let (_, id) = key.split_at(key.len() - 4);
+    u32::from_be_bytes(id.try_into().unwrap())
+}
+
+/// Get the unique terms in a full text query.
+fn query_terms(query: &str) -> Vec<String> {
+    let mut terms = text_terms(query).collect::<Vec<_>>();
+    terms.sort_unstable();
+    terms.dedup();
+    terms
+}
+
+/// Add the BM25 score of one query term to the score of each text that contains it. The postings are the (embedding id, term count, text length) of every text that contains the term.
+fn score_postings(
+    scores: &mut HashMap<u32, f32>,
+    postings: &[(u32, u32, u32)],
+    documents: u32,
+    total_length: u32,
+    filter: Option<&Candidates>,
+) {
+    const K1: f32 = 1.2;
+    const B: f32 = 0.75;
+
+    let documents = documents as f32;
+    let average_length = (total_length as f32 / documents).max(1.);
+    // Terms that appear in fewer texts are worth more
+    let frequency = postings.len() as f32;
+    let idf = (1. + (documents - frequency + 0.5) / (frequency + 0.5)).ln();
+    for &(id, count, length) in postings {
+        if filter.is_some_and(|filter| !filter.contains(id)) {
+            continue;
+        }
+        let count = count as f32;
+        let length_norm = 1. - B + B * length as f32 / average_length;
+        *scores.entry(id).or_default() += idf * count * (K1 + 1.) / (count + K1 * length_norm);
+    }
+}
+
+/// Get the best scores, best first.
+fn best_scores(scores: HashMap<u32, f32>, results: usize) -> Vec<(u32, f32)> {
+    let mut scores = scores.into_iter().collect::<Vec<_>>();
+    scores.sort_by(|(_, first), (_, second)| second.total_cmp(first));
+    scores.truncate(results);
+    scores
+}
+
+/// How a hybrid search combines the results of the vector search and the full text search.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HybridFusion {
+    /// Reciprocal rank fusion. A result scores `1 / (k + rank)` for its rank in each search. This only depends on the order of the results, so it works well without tuning.
+    ReciprocalRank {
+        /// Higher values give results further down each list more weight. Defaults to 60.
+        k: f32,
+    },
+    /// A weighted sum of the scores from each search. The scores are scaled between 0 and 1 before they are combined.
+    Weighted {
+        /// The weight of the text search score between 0 and 1. The vector search score has a weight of `1 - text_weight`.
+        text_weight: f32,
+    },
+}
+
+impl Default for HybridFusion {
+    fn default() -> Self {
+        Self::ReciprocalRank { k: 60. }
+    }
+}
+
+impl HybridFusion {
+    /// Combine the results of the vector search (ids and distances, closest first) and the text search (ids and scores, best first).
+    fn fuse<P>(
+        &self,
+        vector_results: &[(u32, f32)],
+        text_results: &[(u32, f32)],
+    ) -> Vec<VectorDBSearchResult<P>> {
+        let mut fused: HashMap<u32, (Option<f32>, f32)> = HashMap::new();
+        match *self {
+            Self::ReciprocalRank { k } => {
+                for (rank, (id, distance)) in vector_results.iter().enumerate() {
+                    let entry = fused.entry(*id).or_default();
+                    entry.0 = Some(*distance);
+                    entry.1 += 1. / (k + rank as f32 + 1.);
+                }
+                for (rank, (id, _)) in text_results.iter().enumerate() {
+                    fused.entry(*id).or_default().1 += 1. / (k + rank as f32 + 1.);
+                }
+            }
+            Self::Weighted { text_weight } => {
+                let (min, max) = vector_results
+                    .iter()
+                    .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), (_, d)| {
+                        (min.min(*d), max.max(*d))
+                    });
+                for (id, distance) in vector_results {
+                    // The closest result scores 1 and the farthest scores 0
+                    let score = if max > min {
+                        (max - distance) / (max - min)
+                    } else {
+                        1.
+                    };
+                    let entry = fused.entry(*id).or_default();
+                    entry.0 = Some(*distance);
+                    entry.1 += (1. - text_weight) * score;
+                }
+                let max_score = text_results.first().map_or(1., |(_, score)| *score);
+                for (id, score) in text_results {
+                    fused.entry(*id).or_default().1 += text_weight * score / max_score;
+                }
+            }
+        }
+
+        let mut results = fused
+            .into_iter()
+            .map(|(id, (distance, score))| VectorDBSearchResult {
+                distance: distance.unwrap_or(f32::INFINITY),
+                value: EmbeddingId(id),
+                relevance: Some(score),
+                payload: None,
+            })
+            .collect::<Vec<_>>();
+        results.sort_by(|first, second| {
+            second
+                .relevance
+                .unwrap_or_default()
+                .total_cmp(&first.relevance.unwrap_or_default())
+        });
+        results
+    }
+}
+
+/// A trait for anything that can be used to filter the results of a vector search.
+///
+/// Unlike in 0.3, [`IntoVectorDbSearchFilter::into_vector_db_search_filter`] is generic over the payload type of the database. Implementations written for 0.3 take a `&VectorDB<S>` and need to add the `P` parameter.
+pub trait IntoVectorDbSearchFilter<S, M> {
+    /// Convert the filter into a set of candidates.
+    fn into_vector_db_search_filter<P>(self, db: &VectorDB<S, P>) -> Candidates;
+}
+
+impl<S: VectorSpace> IntoVectorDbSearchFilter<S, ()> for Candidates {
+    fn into_vector_db_search_filter<P>(self, _: &VectorDB<S, P>) -> Candidates {
+        self
+    }
+}
+
+/// A marker type that allows kalosm to specialize the [`IntoVectorDbSearchFilter`] trait for iterators.
+pub struct IteratorMarker;
+
+impl<S, I> IntoVectorDbSearchFilter<S, IteratorMarker> for I
+where
+    S: VectorSpace,
+    I: IntoIterator<Item = EmbeddingId>,
+{
+    fn into_vector_db_search_filter<P>(self, _: &VectorDB<S, P>) -> Candidates {
+        let mut candidates = Candidates::new();
+        for id in self {
+            candidates.insert(id.0);
+        }
+        candidates
+    }
+}
+
+/// A marker type that allows kalosm to specialize the [`IntoVectorDbSearchFilter`] trait for closures.
+pub struct ClosureMarker;
+
+impl<S, I> IntoVectorDbSearchFilter<S, ClosureMarker> for I
+where
+    S: VectorSpace,
+    I: FnMut(Embedding<S>) -> bool,
+{
+    fn into_vector_db_search_filter<P>(mut self, db: &VectorDB<S, P>) -> Candidates {
+        let mut candidates = Candidates::new();
+        let rtxn = match db.env.read_txn() {
+            Ok(rtxn) => rtxn,
+            Err(err) => {
+                tracing::error!("Error opening read transaction: {:?}", err);
+                return candidates;
+            }
+        };
+        let reader = match Reader::<DotProduct>::open(&rtxn, 0, db.database) {
+            Ok(reader) => reader,
+            Err(err) => {
+                tracing::error!("Error opening reader: {:?}", err);
+                return candidates;
+            }
+        };
+        for (key, tensor) in reader.iter(&rtxn).ok().into_iter().flatten().flatten() {
+            let embedding = Embedding::from(tensor);
+            if self(embedding) {
+                candidates.insert(key);
+            }
+        }
+        candidates
+    }
+}
+
+/// A builder for searching for embeddings in a vector database.
+pub struct VectorDBSearchBuilder<'a, S: VectorSpace, P = ()> {
+    db: &'a VectorDB<S, P>,
+    embedding: &'a Embedding<S>,
+    results: Option<usize>,
+    candidates: Option<usize>,
+    filter: Option<Candidates>,
+    payload_filter: Option<PayloadFilter>,
+    text_query: Option<String>,
+    fusion: HybridFusion,
+}
+
+impl<'a, S: VectorSpace + Sync, P: DeserializeOwned + 'static> VectorDBSearchBuilder<'a, S, P> {
+    /// Set the number of results to return. Defaults to 10.
+    pub fn with_results(mut self, results: usize) -> Self {
+        self.results = Some(results);
+        self
+    }
+
+    /// Set the number of candidates to fetch before reranking with [`Self::run_with_reranker`]. Defaults to 4 times the number of results.
+    pub fn with_candidates(mut self, candidates: usize) -> Self {
+        self.candidates = Some(candidates);
+        self
+    }
+
+    /// Turn the search into a hybrid search that also searches the [full text index](VectorDB::add_texts) for the query. The results of both searches are combined with the [`HybridFusion`] from [`Self::with_fusion`].
+    ///
+    /// Embeddings that were only found by the text search have an infinite [`VectorDBSearchResult::distance`]. The results are sorted by [`VectorDBSearchResult::relevance`].
+    pub fn with_text_query(mut self, query: impl ToString) -> Self {
+        self.text_query = Some(query.to_string());
+        self
+    }
+
+    /// Set how a hybrid search combines the vector and text results. Defaults to [`HybridFusion::ReciprocalRank`] with `k = 60`.
+    pub fn with_fusion(mut self, fusion: HybridFusion) -> Self {
+        self.fusion = fusion;
+        self
+    }
+
+    /// Set a filter to apply to the results. Only vectors that pass the filter will be returned.
+    pub fn with_filter<Marker>(
+        mut self,
+        filter: impl IntoVectorDbSearchFilter<S, Marker> + Send + Sync + 'static,
+    ) -> Self {
+        self.filter = Some(filter.into_vector_db_search_filter(self.db));
+        self
+    }
+
+    /// Only return embeddings with a payload that matches the filter. Embeddings without a payload never match. This can be combined with [`Self::with_filter`] to only return embeddings that pass both filters.
+    pub fn with_payload_filter(mut self, filter: PayloadFilter) -> Self {
+        self.payload_filter = Some(filter);
+        self
+    }
+
+    /// Run the search and return the results.
+    pub fn run(self) -> Result<Vec<VectorDBSearchResult<P>>, VectorDbError> {
+        let rtxn = self.db.env.read_txn()?;
+        let reader = Reader::<DotProduct>::open(&rtxn, 0, self.db.database)?;
+
+        let filter = match &self.payload_filter {
+            Some(payload_filter) => {
+                let mut candidates = self.db.payload_index.candidates(&rtxn, payload_filter)?;
+                if let Some(filter) = &self.filter {
+                    candidates &= filter;
+                }
+                Some(candidates)
+            }
+            None => self.filter,
+        };
+
+        let vector = self.embedding.vector().to_vec1()?;
+        let results = self.results.unwrap_or(10);
+        let mut query = reader.nns(results);
+        if let Some(filter) = filter.as_ref() {
+            query.candidates(filter);
+        }
+        let arroy_results = query.by_vector(&rtxn, &vector)?;
+
+        let mut search_results = match &self.text_query {
+            Some(text_query) => {
+                let text_results =
+                    self.db
+                        .search_text(&rtxn, text_query, results, filter.as_ref())?;
+                let mut fused = self.fusion.fuse(&arroy_results, &text_results);
+                fused.truncate(results);
+                fused
+            }
+            None => arroy_results
+                .into_iter()
+                .map(|(id, distance)| {
+                    let value = EmbeddingId(id);
+                    VectorDBSearchResult {
+                        distance,
+                        value,
+                        relevance: None,
+                        payload: None,
+                    }
+                })
+                .collect::<Vec<_>>(),
+        };
+
+        for result in &mut search_results {
+            result.payload = self.db.payloads.get(&rtxn, &result.value.0)?;
+        }
+
+        Ok(search_results)
+    }
+
+    /// Run the search and sort the results with a [`Reranker`]. The search fetches extra candidates, scores each candidate against the query with the reranker and returns the most relevant results with [`VectorDBSearchResult::relevance`] set.
+    ///
+    /// The vector database only stores embeddings, so `text` needs to look up the text of each embedding.
+    pub async fn run_with_reranker(
+        self,
+        reranker: &(impl Reranker + ?Sized),
+        query: impl ToString,
+        text: impl Fn(EmbeddingId) -> String,
+    ) -> anyhow::Result<Vec<VectorDBSearchResult<P>>>
+    where
+        P: Send,
+    {
+        let results = self.results.unwrap_or(10);
+        let candidates = self
+            .candidates
+            .unwrap_or(results * RERANK_CANDIDATES_PER_RESULT)
+            .max(results);
+        let candidates = self.with_results(candidates).run()?;
+        let ranked = reranker
+            .rerank(query, candidates, |result| text(result.value))
+            .await?;
+
+        Ok(ranked
+            .into_iter()
+            .take(results)
+            .map(|(relevance, mut result)| {
+                result.relevance = Some(relevance);
+                result
+            })
+            .collect())
+    }
+}
+
+/// A resulting point from a search.
+///
+/// Unlike in 0.3, results also have a [`relevance`](Self::relevance) and a [`payload`](Self::payload), so code that builds or destructures a result without `..` breaks. Results are created by searches, and the struct is `#[non_exhaustive]` so more fields can be added without breaking code that reads them.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct VectorDBSearchResult<P = ()> {
+    /// The distance from the searched point.
+    pub distance: f32,
+    /// The value of the point.
+    pub value: EmbeddingId,
+    /// The relevance score if the results were reranked or came from a hybrid search. Higher scores are more relevant.
+    pub relevance: Option<f32>,
+    /// The payload of the point if it has one.
+    pub payload: Option<P>,
+}
+
+/// A unique identifier for an embedding. If you delete an embedding, the id will be recycled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct EmbeddingId(pub u32);
+
+#[tokio::test]
+async fn test_vector_db_add_embedding() {
+    let db: VectorDB = VectorDB::new().unwrap();
+    let id = db.add_embedding(Embedding::from([1.0, 2.0, 3.0])).unwrap();
+    assert_eq!(db.get_embedding(id).unwrap().to_vec(), vec![1.0, 2.0, 3.0]);
+    db.remove_embedding(id).unwrap();
+    assert!(db.get_embedding(id).is_err());
+}
+
+#[tokio::test]
+async fn test_vector_db_get_closest() {
+    let db: VectorDB = VectorDB::new().unwrap();
+    let first_vector = Embedding::from([1.0, 2.0, 3.0]);
+    let second_embedding = Embedding::from([-1.0, 2.0, 3.0]);
+    let id1 = db.add_embedding(first_vector.clone()).unwrap();
+    let id2 = db.add_embedding(second_embedding.clone()).unwrap();
+    assert_eq!(
+        db.search(&first_vector)
+            .with_results(1)
+            .run()
+            .unwrap()
+            .iter()
+            .map(|r| r.value)
+            .collect::<Vec<_>>(),
+        vec![id1]
+    );
+    assert_eq!(
+        db.search(&second_embedding)
+            .with_results(1)
+            .run()
+            .unwrap()
+            .iter()
+            .map(|r| r.value)
+            .collect::<Vec<_>>(),
+        vec![id2]
+    );
+    let third_embedding = Embedding::from([1.0, 0.0, 0.0]);
+    assert_eq!(
+        db.search(&third_embedding)
+            .with_results(1)
+            .run()
+            .unwrap()
+            .iter()
+            .map(|r| r.value)
+            .collect::<Vec<_>>(),
+        vec![id1]
+    );
+    assert_eq!(
+        db.search(&third_embedding)
+            .with_filter(|vector: Embedding<UnknownVectorSpace>| vector.to_vec()[0] < 0.0)
+            .run()
+            .unwrap()
+            .iter()
+            .map(|r| r.value)
+            .collect::<Vec<_>>(),
+        vec![id2]
+    );
+}
+
+#[tokio::test]
+async fn test_vector_db_rerank() {
+    use std::{future::Future, pin::Pin};
+
+    // Prefers longer documents
+    struct LengthReranker;
+
+    impl Reranker for LengthReranker {
+        fn score(
+            &self,
+            _: String,
+            documents: Vec<String>,
+        ) -> Pin<Box<dyn Future<Output = anyhow::Result<Vec<f32>>> + Send + '_>> {
+            Box::pin(async move { Ok(documents.iter().map(|d| d.len() as f32).collect()) })
+        }
+    }
+
+    let db: VectorDB = VectorDB::new().unwrap();
+    let texts = ["a", "abc", "ab", "abcd"];
+    let ids = db
+        .add_embeddings([
+            Embedding::from([1.0, 0.0, 0.0]),
+            Embedding::from([0.9, 0.1, 0.0]),
+            Embedding::from([0.8, 0.2, 0.0]),
+            Embedding::from([0.0, 0.0, 1.0]),
+        ])
+        .unwrap();
+    let text = |id: EmbeddingId| texts[ids.iter().position(|i| *i == id).unwrap()].to_string();
+
+    let results = db
+        .search(&Embedding::from([1.0, 0.0, 0.0]))
+        .with_results(2)
+        .with_candidates(3)
+        .run_with_reranker(&LengthReranker, "query", text)
+        .await
+        .unwrap();
+    // The farthest embedding is not a candidate
+    assert_eq!(
+        results
+            .iter()
+            .map(|r| (r.value, r.relevance))
+            .collect::<Vec<_>>(),
+        vec![(ids[1], Some(3.0)), (ids[2], Some(2.0))]
+    );
+}
+
+#[tokio::test]
+async fn test_vector_db_hybrid_search() {
+    let db: VectorDB = VectorDB::new().unwrap();
+    let ids = db
+        .add_embeddings([
+            Embedding::from([1.0, 0.0, 0.0]),
+            Embedding::from([0.9, 0.1, 0.0]),
+            Embedding::from([0.0, 0.0, 1.0]),
+        ])
+        .unwrap();
+    db.add_texts([
+        (ids[0], "The printer is out of paper"),
+        (ids[1], "The printer is jammed"),
+        (ids[2], "Error E-4012 means the printer is jammed"),
+    ])
+    .unwrap();
+    let query = Embedding::from([1.0, 0.0, 0.0]);
+
+    // The vector search alone misses the error code
+    let results = db.search(&query).with_results(2).run().unwrap();
+    assert!(!results.iter().any(|r| r.value == ids[2]));
+    let results = db
+        .search(&query)
+        .with_results(2)
+        .with_text_query("What does E-4012 mean?")
+        .with_fusion(HybridFusion::Weighted { text_weight: 0.7 })
+        .run()
+        .unwrap();
+    assert_eq!(
+        results.iter().map(|r| r.value).collect::<Vec<_>>(),
+        vec![ids[2], ids[0]]
+    );
+    assert_eq!(results[0].distance, f32::INFINITY);
+
+    // Results found by both searches rank first
+    let results = db
+        .search(&query)
+        .with_text_query("jammed printer")
+        .run()
+        .unwrap();
+    assert_eq!(results[0].value, ids[1]);
+
+    // Removed embeddings are removed from the text index
+    db.remove_embedding(ids[2]).unwrap();
+    let results = db.search(&query).with_text_query("E-4012").run().unwrap();
+    assert!(!results.iter().any(|r| r.value == ids[2]));
+}
+
+#[tokio::test]
+async fn test_vector_db_payloads() {
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Post {
+        source: String,
+        year: u32,
+        tags: Vec<String>,
+    }
+
+    let post = |source: &str, year, tags: &[&str]| Post {
+        source: source.to_string(),
+        year,
+        tags: tags.iter().map(|tag| tag.to_string()).collect(),
+    };
+    let db: VectorDB<UnknownVectorSpace, Post> = VectorDB::new_with_payloads().unwrap();
+    let ids = db
+        .add_embeddings_with_payloads([
+            (
+                Embedding::from([1.0, 0.0, 0.0]),
+                post("blog", 2021, &["rust"]),
+            ),
+            (
+                Embedding::from([0.9, 0.1, 0.0]),
+                post("github", 2024, &["rust", "llm"]),
+            ),
+            (
+                Embedding::from([0.0, 0.0, 1.0]),
+                post("blog", 2024, &["llm"]),
+            ),
+        ])
+        .unwrap();
+    let no_payload = db.add_embedding(Embedding::from([1.0, 0.0, 0.0])).unwrap();
+    assert_eq!(
+        db.get_payload(ids[0]).unwrap(),
+        Some(post("blog", 2021, &["rust"]))
+    );
+    assert_eq!(db.get_payload(no_payload).unwrap(), None);
+
+    let query = Embedding::from([1.0, 0.0, 0.0]);
+    let search = |filter: PayloadFilter| {
+        db.search(&query)
+            .with_payload_filter(filter)
+            .run()
+            .unwrap()
+            .into_iter()
+            .map(|r| r.value)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        search(PayloadFilter::equals("source", "blog")),
+        vec![ids[0], ids[2]]
+    );
+    assert_eq!(
+        search(PayloadFilter::range("year", 2022..)),
+        vec![ids[1], ids[2]]
+    );
+    assert_eq!(
+        search(PayloadFilter::contains("tags", "rust")),
+        vec![ids[0], ids[1]]
+    );
+    // Embeddings without a payload never match
+    assert_eq!(
+        search(!PayloadFilter::equals("source", "blog")),
+        vec![ids[1]]
+    );
+
+    // Payload filters combine with other filters
+    let results = db
+        .search(&query)
+        .with_filter([ids[0], ids[2]])
+        .with_payload_filter(PayloadFilter::contains("tags", "llm"))
+        .run()
+        .unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].value, ids[2]);
+    assert_eq!(results[0].payload, Some(post("blog", 2024, &["llm"])));
+
+    // Removing an embedding removes the payload
+    db.remove_embedding(ids[1]).unwrap();
+    assert_eq!(db.get_payload(ids[1]).unwrap(), None);
+    assert_eq!(search(PayloadFilter::equals("year", 2024)), vec![ids[2]]);
+
+    // Replacing a payload replaces the fields that are filtered
+    db.set_payload(ids[2], &post("github", 2025, &["llm"]))
+        .unwrap();
+    assert_eq!(
+        search(PayloadFilter::equals("source", "blog")),
+        vec![ids[0]]
+    );
+    assert_eq!(
+        search(PayloadFilter::range("year", 2025..=2025)),
+        vec![ids[2]]
+    );
+}
+
+#[tokio::test]
+async fn test_vector_stores() {
+    use serde_json::{json, Value};
+
+    fn test_store(store: impl VectorStore<UnknownVectorSpace, Value>) {
+        let ids = store
+            .add_embeddings(vec![
+                Embedding::from([1.0, 0.0, 0.0]),
+                Embedding::from([0.9, 0.1, 0.0]),
+                Embedding::from([0.0, 0.0, 1.0]),
+            ])
+            .unwrap();
+        store
+            .add_texts(vec![(
+                ids[2],
+                "Error E-4012 means the printer is jammed".into(),
+            )])
+            .unwrap();
+        let query = Embedding::from([1.0, 0.0, 0.0]);
+        let search = |query: VectorStoreQuery<UnknownVectorSpace>| {
+            store
+                .search(query)
+                .unwrap()
+                .iter()
+                .map(|r| r.value)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            search(VectorStoreQuery::new(&query).with_results(2)),
+            vec![ids[0], ids[1]]
+        );
+        let filter = store
+            .filter(&mut |vector| vector.to_vec()[1] > 0.0)
+            .unwrap();
+        assert_eq!(
+            search(VectorStoreQuery::new(&query).with_filter(filter)),
+            vec![ids[1]]
+        );
+        assert_eq!(
+            search(
+                VectorStoreQuery::new(&query)
+                    .with_results(1)
+                    .with_text_query("E-4012")
+                    .with_fusion(HybridFusion::Weighted { text_weight: 0.7 })
+            ),
+            vec![ids[2]]
+        );
+        store.set_payload(ids[1], &json!({ "year": 2024 })).unwrap();
+        assert_eq!(
+            store.get_payload(ids[1]).unwrap(),
+            Some(json!({ "year": 2024 }))
+        );
+        assert_eq!(store.get_payload(ids[0]).unwrap(), None);
+        let results = store
+            .search(
+                VectorStoreQuery::new(&query)
+                    .with_payload_filter(PayloadFilter::equals("year", 2024))
+                    .with_text_query("E-4012"),
+            )
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].value, ids[1]);
+        assert_eq!(results[0].payload, Some(json!({ "year": 2024 })));
+
+        store.remove_embedding(ids[0]).unwrap();
+        assert!(store.get_embedding(ids[0]).is_err());
+        assert_eq!(
+            store.get_embedding(ids[1]).unwrap().to_vec(),
+            vec![0.9, 0.1, 0.0]
+        );
+        assert_eq!(
+            search(VectorStoreQuery::new(&query).with_results(1)),
+            vec![ids[1]]
+        );
+
+        store.clear().unwrap();
+        let ids = store
+            .add_embeddings_with_payloads(vec![
+                (Embedding::from([0.1, 0.9, 0.0]), json!({ "year": 2021 })),
+                (Embedding::from([0.0, 1.0, 0.0]), json!({ "year": 2024 })),
+            ])
+            .unwrap();
+        assert_eq!(search(VectorStoreQuery::new(&query)), ids);
+        assert_eq!(
+            search(
+                VectorStoreQuery::new(&query)
+                    .with_payload_filter(PayloadFilter::range("year", 2022..))
+            ),
+            vec![ids[1]]
+        );
+    }
+
+    test_store(VectorDB::new_with_payloads().unwrap());
+    test_store(BruteForceVectorStore::new());
+    test_store(HnswVectorStore::new());
+}
+
+#[test]
+fn test_hnsw_matches_brute_force() {
+    use rand::Rng;
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut random_embedding = || {
+        let vector = (0..16).map(|_| rng.gen::<f32>() - 0.5).collect::<Vec<_>>();
+        let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+        Embedding::<UnknownVectorSpace>::from(vector.into_iter().map(|x| x / norm))
+    };
+    let embeddings = (0..1000).map(|_| random_embedding()).collect::<Vec<_>>();
+    let exact: BruteForceVectorStore = BruteForceVectorStore::new();
+    // The layers of the graph are random, so a fixed seed keeps the recall the same every run
+    let hnsw: HnswVectorStore = HnswVectorStore::new().with_seed(0);
+    exact.add_embeddings(embeddings.clone()).unwrap();
+    hnsw.add_embeddings(embeddings).unwrap();
+    for id in (0..1000).step_by(5) {
+        exact.remove_embedding(EmbeddingId(id)).unwrap();
+        hnsw.remove_embedding(EmbeddingId(id)).unwrap();
+    }
+
+    let mut found = 0;
+    for _ in 0..20 {
+        let query = random_embedding();
+        let expected = exact
+            .search(VectorStoreQuery::new(&query).with_results(10))
+            .unwrap();
+        let results = hnsw
+            .search(VectorStoreQuery::new(&query).with_results(10))
+            .unwrap();
+        found += results
+            .iter()
+            .filter(|result| expected.iter().any(|e| e.value == result.value))
+            .count();
+    }
+    // The search is approximate, but should find almost every neighbor
+    assert!(found >= 190, "found {found} of 200 neighbors");
+}
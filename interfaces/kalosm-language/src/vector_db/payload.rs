@@ -0,0 +1,460 @@
+use std::cmp::Ordering;
+use std::ops::{Bound, Not, RangeBounds};
+
+use heed::types::{Bytes, Unit};
+use heed::{Database, RoTxn, RwTxn};
+use serde_json::Value;
+
+use super::Candidates;
+
+/// A filter over the fields of the payloads in a [`VectorDB`](super::VectorDB). Searches with [`VectorDBSearchBuilder::with_payload_filter`](super::VectorDBSearchBuilder::with_payload_filter) only return embeddings with a payload that matches the filter.
+///
+/// Fields are read from the JSON form of the payload. Nested fields are separated with a `.` like `"source.url"`. Numbers are compared as numbers and strings are compared in alphabetical order, so RFC 3339 timestamps can be compared as strings.
+///
+/// The vector database keeps an index of every field in each payload, so filters only read the index entries of the fields they check instead of every payload.
+///
+/// ```rust
+/// use kalosm_language::prelude::*;
+///
+/// // Posts about rust or llms from 2023 onwards that are not from reddit
+/// let filter = PayloadFilter::range("year", 2023..)
+///     .and(PayloadFilter::contains("tags", "rust").or(PayloadFilter::contains("tags", "llm")))
+///     .and(!PayloadFilter::equals("source.site", "reddit"));
+/// # let _ = filter;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum PayloadFilter {
+    /// The field is equal to the value.
+    Equals {
+        /// The path of the field.
+        field: String,
+        /// The value the field must be equal to.
+        value: Value,
+    },
+    /// The field is within the range.
+    Range {
+        /// The path of the field.
+        field: String,
+        /// The start of the range.
+        start: Bound<Value>,
+        /// The end of the range.
+        end: Bound<Value>,
+    },
+    /// The field is a list that contains the value.
+    Contains {
+        /// The path of the field.
+        field: String,
+        /// The value the list must contain.
+        value: Value,
+    },
+    /// Every filter matches.
+    And(Vec<PayloadFilter>),
+    /// At least one filter matches.
+    Or(Vec<PayloadFilter>),
+    /// The filter doesn't match.
+    Not(Box<PayloadFilter>),
+}
+
+impl PayloadFilter {
+    /// Create a filter for payloads where the field is equal to the value.
+    pub fn equals(field: impl ToString, value: impl Into<Value>) -> Self {
+        Self::Equals {
+            field: field.to_string(),
+            value: value.into(),
+        }
+    }
+
+    /// Create a filter for payloads where the field is within the range.
+    pub fn range<T: Into<Value> + Clone>(field: impl ToString, range: impl RangeBounds<T>) -> Self {
+        Self::Range {
+            field: field.to_string(),
+            start: range.start_bound().cloned().map(Into::into),
+            end: range.end_bound().cloned().map(Into::into),
+        }
+    }
+
+    /// Create a filter for payloads where the field is a list that contains the value. This is useful for filtering by tags.
+    pub fn contains(field: impl ToString, value: impl Into<Value>) -> Self {
+        Self::Contains {
+            field: field.to_string(),
+            value: value.into(),
+        }
+    }
+
+    /// Combine this filter with another filter that must also match.
+    pub fn and(self, other: Self) -> Self {
+        match self {
+            Self::And(mut filters) => {
+                filters.push(other);
+                Self::And(filters)
+            }
+            filter => Self::And(vec![filter, other]),
+        }
+    }
+
+    /// Combine this filter with another filter where either filter may match.
+    pub fn or(self, other: Self) -> Self {
+        match self {
+            Self::Or(mut filters) => {
+                filters.push(other);
+                Self::Or(filters)
+            }
+            filter => Self::Or(vec![filter, other]),
+        }
+    }
+
+    /// Check if a payload matches the filter.
+    pub fn matches(&self, payload: &Value) -> bool {
+        match self {
+            Self::Equals { field, value } => {
+                get_field(payload, field).is_some_and(|field| equal(field, value))
+            }
+            Self::Range { field, start, end } => {
+                let Some(field) = get_field(payload, field) else {
+                    return false;
+                };
+                let after_start = match start {
+                    Bound::Included(start) => compare(field, start).is_some_and(Ordering::is_ge),
+                    Bound::Excluded(start) => compare(field, start).is_some_and(Ordering::is_gt),
+                    Bound::Unbounded => true,
+                };
+                let before_end = match end {
+                    Bound::Included(end) => compare(field, end).is_some_and(Ordering::is_le),
+                    Bound::Excluded(end) => compare(field, end).is_some_and(Ordering::is_lt),
+                    Bound::Unbounded => true,
+                };
+                after_start && before_end
+            }
+            Self::Contains { field, value } => matches!(
+                get_field(payload, field),
+                Some(Value::Array(items)) if items.iter().any(|item| equal(item, value))
+            ),
+            Self::And(filters) => filters.iter().all(|filter| filter.matches(payload)),
+            Self::Or(filters) => filters.iter().any(|filter| filter.matches(payload)),
+            Self::Not(filter) => !filter.matches(payload),
+        }
+    }
+}
+
+impl Not for PayloadFilter {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        match self {
+            Self::Not(filter) => *filter,
+            filter => Self::Not(Box::new(filter)),
+        }
+    }
+}
+
+/// Get a field from a value with a path like `source.url`.
+fn get_field<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(value, |value, segment| value.as_object()?.get(segment))
+}
+
+/// Check if two values are equal. 1 and 1.0 are equal even though they are stored differently.
+fn equal(first: &Value, second: &Value) -> bool {
+    first == second || compare(first, second) == Some(Ordering::Equal)
+}
+
+/// Compare two numbers or two strings.
+fn compare(first: &Value, second: &Value) -> Option<Ordering> {
+    match (first, second) {
+        (Value::Number(first), Value::Number(second)) => {
+            first.as_f64()?.partial_cmp(&second.as_f64()?)
+        }
+        (Value::String(first), Value::String(second)) => Some(first.cmp(second)),
+        _ => None,
+    }
+}
+
+/// An index of the fields in each payload. Each field and each item of a list field has a key that starts with the path of the field and ends with the embedding id, so a filter only reads the keys of the values it matches.
+///
+/// Numbers and strings are encoded so the keys sort in the same order as the values, which lets range filters read a single range of keys.
+#[derive(Clone, Copy)]
+pub(crate) struct PayloadIndex {
+    database: Database<Bytes, Unit>,
+}
+
+/// The key of every embedding with a payload. Every encoded path ends with `[0, 1]`, so no path starts with this prefix.
+const PAYLOAD_KEY: &[u8] = &[0, 0];
+/// The kind of a key for the value of a field.
+const FIELD_KEY: u8 = b'f';
+/// The kind of a key for an item of a list field.
+const ITEM_KEY: u8 = b'i';
+const NUMBER_TAG: u8 = 1;
+const STRING_TAG: u8 = 2;
+const JSON_TAG: u8 = 3;
+
+impl PayloadIndex {
+    /// Create or open the index in the environment.
+    pub(crate) fn create(env: &heed::Env, wtxn: &mut RwTxn) -> heed::Result<Self> {
+        Ok(Self {
+            database: env.create_database(wtxn, Some("payload-index"))?,
+        })
+    }
+
+    /// Add the fields of a payload to the index.
+    pub(crate) fn insert(&self, wtxn: &mut RwTxn, id: u32, payload: &Value) -> heed::Result<()> {
+        for key in index_keys(payload, id) {
+            self.database.put(wtxn, &key, &())?;
+        }
+        Ok(())
+    }
+
+    /// Remove the fields of a payload from the index.
+    pub(crate) fn remove(&self, wtxn: &mut RwTxn, id: u32, payload: &Value) -> heed::Result<()> {
+        for key in index_keys(payload, id) {
+            self.database.delete(wtxn, &key)?;
+        }
+        Ok(())
+    }
+
+    /// Remove every payload from the index.
+    pub(crate) fn clear(&self, wtxn: &mut RwTxn) -> heed::Result<()> {
+        self.database.clear(wtxn)
+    }
+
+    /// Find the embeddings with a payload that matches the filter.
+    pub(crate) fn candidates(
+        &self,
+        rtxn: &RoTxn,
+        filter: &PayloadFilter,
+    ) -> heed::Result<Candidates> {
+        match filter {
+            PayloadFilter::Equals { field, value } => {
+                let Some(value) = encode_value(value) else {
+                    return Ok(Candidates::new());
+                };
+                self.prefix(rtxn, &[field_prefix(field, FIELD_KEY), value].concat())
+            }
+            PayloadFilter::Contains { field, value } => {
+                let Some(value) = encode_value(value) else {
+                    return Ok(Candidates::new());
+                };
+                self.prefix(rtxn, &[field_prefix(field, ITEM_KEY), value].concat())
+            }
+            PayloadFilter::Range { field, start, end } => self.range(rtxn, field, start, end),
+            PayloadFilter::And(filters) => {
+                let mut candidates = self.prefix(rtxn, PAYLOAD_KEY)?;
+                for filter in filters {
+                    if candidates.is_empty() {
+                        break;
+                    }
+                    candidates &= self.candidates(rtxn, filter)?;
+                }
+                Ok(candidates)
+            }
+            PayloadFilter::Or(filters) => {
+                let mut candidates = Candidates::new();
+                for filter in filters {
+                    candidates |= self.candidates(rtxn, filter)?;
+                }
+                Ok(candidates)
+            }
+            PayloadFilter::Not(filter) => {
+                Ok(self.prefix(rtxn, PAYLOAD_KEY)? - self.candidates(rtxn, filter)?)
+            }
+        }
+    }
+
+    /// Find the embeddings with a key that starts with the prefix.
+    fn prefix(&self, rtxn: &RoTxn, prefix: &[u8]) -> heed::Result<Candidates> {
+        let mut candidates = Candidates::new();
+        for item in self.database.prefix_iter(rtxn, prefix)? {
+            let (key, ()) = item?;
+            candidates.insert(key_id(key));
+        }
+        Ok(candidates)
+    }
+
+    /// Find the embeddings with a field within the range.
+    fn range(
+        &self,
+        rtxn: &RoTxn,
+        field: &str,
+        start: &Bound<Value>,
+        end: &Bound<Value>,
+    ) -> heed::Result<Candidates> {
+        let prefix = field_prefix(field, FIELD_KEY);
+        // The range must be between two numbers or two strings
+        let tag = match (bound_value(start), bound_value(end)) {
+            (None, None) => return self.prefix(rtxn, &prefix),
+            (Some(value), None) | (None, Some(value)) => value_tag(value),
+            (Some(start), Some(end)) => match (value_tag(start), value_tag(end)) {
+                (Some(start), Some(end)) if start == end => Some(start),
+                _ => None,
+            },
+        };
+        let Some(tag) = tag else {
+            return Ok(Candidates::new());
+        };
+        // Every encoded value starts with its tag
+        let encode = |value: &Value| [prefix.clone(), encode_value(value).unwrap()].concat();
+        // Keys end with the id after the value, so a key with the largest id comes after every other key for the value
+        let with_last_id =
+            |value: &Value| [encode(value), u32::MAX.to_be_bytes().to_vec()].concat();
+        let lower = match start {
+            Bound::Included(start) => Bound::Included(encode(start)),
+            Bound::Excluded(start) => Bound::Excluded(with_last_id(start)),
+            Bound::Unbounded => Bound::Included([prefix.clone(), vec![tag]].concat()),
+        };
+        let upper = match end {
+            Bound::Included(end) => Bound::Included(with_last_id(end)),
+            Bound::Excluded(end) => Bound::Excluded(encode(end)),
+            Bound::Unbounded => Bound::Excluded([prefix.clone(), vec![tag + 1]].concat()),
+        };
+        let mut candidates = Candidates::new();
+        let range = (
+            lower.as_ref().map(Vec::as_slice),
+            upper.as_ref().map(Vec::as_slice),
+        );
+        for item in self.database.range(rtxn, &range)? {
+            let (key, ()) = item?;
+            candidates.insert(key_id(key));
+        }
+        Ok(candidates)
+    }
+}
+
+fn bound_value(bound: &Bound<Value>) -> Option<&Value> {
+    match bound {
+        Bound::Included(value) | Bound::Excluded(value) => Some(value),
+        Bound::Unbounded => None,
+    }
+}
+
+/// The tag of a value that can be compared in a range.
+fn value_tag(value: &Value) -> Option<u8> {
+    match value {
+        Value::Number(_) => Some(NUMBER_TAG),
+        Value::String(_) => Some(STRING_TAG),
+        _ => None,
+    }
+}
+
+/// Get the keys of every field and list item in a payload.
+fn index_keys(payload: &Value, id: u32) -> Vec<Vec<u8>> {
+    let mut keys = vec![PAYLOAD_KEY.to_vec()];
+    for (name, value) in object_fields(payload) {
+        field_keys(name, value, &mut keys);
+    }
+    for key in &mut keys {
+        key.extend_from_slice(&id.to_be_bytes());
+    }
+    keys
+}
+
+fn field_keys(path: &str, value: &Value, keys: &mut Vec<Vec<u8>>) {
+    if let Some(encoded) = encode_value(value) {
+        keys.push([field_prefix(path, FIELD_KEY), encoded].concat());
+    }
+    match value {
+        Value::Object(_) => {
+            for (name, value) in object_fields(value) {
+                field_keys(&format!("{path}.{name}"), value, keys);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                if let Some(encoded) = encode_value(item) {
+                    keys.push([field_prefix(path, ITEM_KEY), encoded].concat());
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Get the fields of an object that a filter can read. Filters split paths at each `.`, so a field with a `.` in its name can't be filtered.
+fn object_fields(value: &Value) -> impl Iterator<Item = (&String, &Value)> {
+    value
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(name, _)| !name.contains('.'))
+}
+
+/// The start of the keys of a field or the items in a list field.
+fn field_prefix(path: &str, kind: u8) -> Vec<u8> {
+    let mut prefix = escape(path.as_bytes());
+    prefix.push(kind);
+    prefix
+}
+
+/// Encode a value so numbers and strings sort in the same order as their values. Every encoded value is followed by the end of the value, so no encoded value starts with another encoded value.
+fn encode_value(value: &Value) -> Option<Vec<u8>> {
+    Some(match value {
+        Value::Number(number) => {
+            // -0.0 is equal to 0.0
+            let number = number.as_f64()? + 0.0;
+            let bits = number.to_bits();
+            // Flip the sign bit of positive numbers and every bit of negative numbers so the bits sort like the numbers
+            let bits = if number.is_sign_negative() {
+                !bits
+            } else {
+                bits | (1 << 63)
+            };
+            [&[NUMBER_TAG][..], &bits.to_be_bytes()].concat()
+        }
+        Value::String(string) => [vec![STRING_TAG], escape(string.as_bytes())].concat(),
+        // serde_json sorts the fields of objects, so equal values have the same JSON
+        value => [vec![JSON_TAG], escape(&serde_json::to_vec(value).ok()?)].concat(),
+    })
+}
+
+/// Escape the zero bytes in text and end it with `[0, 1]`. The escaped text sorts in the same order as the text.
+fn escape(bytes: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(bytes.len() + 2);
+    for &byte in bytes {
+        escaped.push(byte);
+        if byte == 0 {
+            escaped.push(u8::MAX);
+        }
+    }
+    escaped.extend_from_slice(&[0, 1]);
+    escaped
+}
+
+/// Read the embedding id from the end of a key.
+fn key_id(key: &[u8]) -> u32 {
+    let (_, id) = key.split_at(key.len() - 4);
+    u32::from_be_bytes(id.try_into().unwrap())
+}
+
+#[test]
+fn payload_filter_matches() {
+    use serde_json::json;
+
+    let payload = json!({
+        "year": 2024,
+        "published": "2024-03-01T00:00:00Z",
+        "tags": ["rust", "llm"],
+        "source": { "site": "github", "stars": 12.5 }
+    });
+
+    assert!(PayloadFilter::equals("year", 2024).matches(&payload));
+    assert!(PayloadFilter::equals("year", 2024.0).matches(&payload));
+    assert!(PayloadFilter::equals("source.site", "github").matches(&payload));
+    assert!(!PayloadFilter::equals("source.site", "reddit").matches(&payload));
+    assert!(!PayloadFilter::equals("missing.field", 1).matches(&payload));
+
+    assert!(PayloadFilter::range("year", 2023..).matches(&payload));
+    assert!(PayloadFilter::range("year", ..=2024).matches(&payload));
+    assert!(!PayloadFilter::range("year", ..2024).matches(&payload));
+    assert!(PayloadFilter::range("source.stars", 10.0..20.0).matches(&payload));
+    assert!(PayloadFilter::range("published", "2024-01-01".to_string()..).matches(&payload));
+    // Numbers and strings can't be compared
+    assert!(!PayloadFilter::range("year", "2000".to_string()..).matches(&payload));
+
+    assert!(PayloadFilter::contains("tags", "rust").matches(&payload));
+    assert!(!PayloadFilter::contains("tags", "python").matches(&payload));
+    assert!(!PayloadFilter::contains("year", 2024).matches(&payload));
+
+    let filter = PayloadFilter::range("year", 2023..)
+        .and(PayloadFilter::contains("tags", "python").or(PayloadFilter::contains("tags", "llm")))
+        .and(!PayloadFilter::equals("source.site", "reddit"));
+    assert!(filter.matches(&payload));
+    assert!(!(!filter).matches(&payload));
+}
@@ -0,0 +1,194 @@
+use kalosm_language_model::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::{
+    Candidates, ClosureMarker, EmbeddingId, HybridFusion, IntoVectorDbSearchFilter, PayloadFilter,
+    VectorDB, VectorDBSearchResult, VectorDbError,
+};
+
+/// A store of embeddings that can search for the closest embeddings to a query.
+///
+/// Kalosm includes three vector stores:
+/// - [`VectorDB`]: An approximate index backed by [arroy](https://github.com/meilisearch/arroy) that is stored on disk. This is the best choice for large or persistent collections.
+/// - [`HnswVectorStore`](super::HnswVectorStore): An approximate [HNSW](https://en.wikipedia.org/wiki/Hierarchical_navigable_small_world) index in memory.
+/// - [`BruteForceVectorStore`](super::BruteForceVectorStore): An exact search in memory that compares the query to every embedding. This is the best choice for tests and small collections.
+///
+/// Every store also has a full text index for [hybrid search](VectorStoreQuery::with_text_query), and can store a payload of type `P` with each embedding to [filter searches](VectorStoreQuery::with_payload_filter) by.
+pub trait VectorStore<S: VectorSpace, P = ()>: Send + Sync {
+    /// Add a batch of embeddings to the store. Returns the ids of the new embeddings in the same order as the embeddings.
+    fn add_embeddings(
+        &self,
+        embeddings: Vec<Embedding<S>>,
+    ) -> Result<Vec<EmbeddingId>, VectorDbError>;
+
+    /// Add a batch of embeddings with payloads to the store. Returns the ids of the new embeddings in the same order as the embeddings.
+    fn add_embeddings_with_payloads(
+        &self,
+        embeddings: Vec<(Embedding<S>, P)>,
+    ) -> Result<Vec<EmbeddingId>, VectorDbError>;
+
+    /// Get the payload of an embedding. Returns `None` if the embedding doesn't have a payload.
+    fn get_payload(&self, embedding_id: EmbeddingId) -> Result<Option<P>, VectorDbError>;
+
+    /// Set the payload of an embedding. If the embedding already has a payload, the payload is replaced.
+    fn set_payload(&self, embedding_id: EmbeddingId, payload: &P) -> Result<(), VectorDbError>;
+
+    /// Add the text of a batch of embeddings to the full text index. If an embedding already has text in the index, the text is replaced.
+    fn add_texts(&self, texts: Vec<(EmbeddingId, String)>) -> Result<(), VectorDbError>;
+
+    /// Remove an embedding, its text and its payload from the store. The id may be reused for a new embedding.
+    fn remove_embedding(&self, embedding_id: EmbeddingId) -> Result<(), VectorDbError>;
+
+    /// Get the embedding for an embedding id.
+    fn get_embedding(&self, embedding_id: EmbeddingId) -> Result<Embedding<S>, VectorDbError>;
+
+    /// Remove every embedding from the store.
+    fn clear(&self) -> Result<(), VectorDbError>;
+
+    /// Find the embeddings that pass the filter. The result can be used as the filter of a [`VectorStoreQuery`].
+    fn filter(
+        &self,
+        filter: &mut dyn FnMut(Embedding<S>) -> bool,
+    ) -> Result<Candidates, VectorDbError>;
+
+    /// Find the closest embeddings to the query, closest first. Hybrid searches are sorted by [`VectorDBSearchResult::relevance`] instead.
+    fn search(
+        &self,
+        query: VectorStoreQuery<'_, S>,
+    ) -> Result<Vec<VectorDBSearchResult<P>>, VectorDbError>;
+}
+
+/// A search in a [`VectorStore`].
+pub struct VectorStoreQuery<'a, S: VectorSpace> {
+    /// The embedding to search for.
+    pub embedding: &'a Embedding<S>,
+    /// The number of results to return.
+    pub results: usize,
+    /// The embeddings that may be returned. If this is `None`, any embedding may be returned.
+    pub filter: Option<Candidates>,
+    /// The filter the payload of each result must match. If this is `None`, embeddings with and without payloads may be returned.
+    pub payload_filter: Option<PayloadFilter>,
+    /// The query for the full text index if this is a hybrid search.
+    pub text_query: Option<String>,
+    /// How a hybrid search combines the vector and text results.
+    pub fusion: HybridFusion,
+}
+
+impl<'a, S: VectorSpace> VectorStoreQuery<'a, S> {
+    /// Create a new query for the 10 closest embeddings.
+    pub fn new(embedding: &'a Embedding<S>) -> Self {
+        Self {
+            embedding,
+            results: 10,
+            filter: None,
+            payload_filter: None,
+            text_query: None,
+            fusion: HybridFusion::default(),
+        }
+    }
+
+    /// Set the number of results to return. Defaults to 10.
+    pub fn with_results(mut self, results: usize) -> Self {
+        self.results = results;
+        self
+    }
+
+    /// Only return embeddings in the set of candidates.
+    pub fn with_filter(mut self, filter: Candidates) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Only return embeddings with a payload that matches the filter. Embeddings without a payload never match. This can be combined with [`Self::with_filter`] to only return embeddings that pass both filters.
+    pub fn with_payload_filter(mut self, filter: PayloadFilter) -> Self {
+        self.payload_filter = Some(filter);
+        self
+    }
+
+    /// Turn the search into a hybrid search that also searches the full text index for the query. See [`VectorDBSearchBuilder::with_text_query`](super::VectorDBSearchBuilder::with_text_query) for more details.
+    pub fn with_text_query(mut self, query: impl ToString) -> Self {
+        self.text_query = Some(query.to_string());
+        self
+    }
+
+    /// Set how a hybrid search combines the vector and text results. Defaults to [`HybridFusion::ReciprocalRank`] with `k = 60`.
+    pub fn with_fusion(mut self, fusion: HybridFusion) -> Self {
+        self.fusion = fusion;
+        self
+    }
+}
+
+impl<S, P> VectorStore<S, P> for VectorDB<S, P>
+where
+    S: VectorSpace,
+    P: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    fn add_embeddings(
+        &self,
+        embeddings: Vec<Embedding<S>>,
+    ) -> Result<Vec<EmbeddingId>, VectorDbError> {
+        VectorDB::add_embeddings(self, embeddings)
+    }
+
+    fn add_embeddings_with_payloads(
+        &self,
+        embeddings: Vec<(Embedding<S>, P)>,
+    ) -> Result<Vec<EmbeddingId>, VectorDbError> {
+        VectorDB::add_embeddings_with_payloads(self, embeddings)
+    }
+
+    fn get_payload(&self, embedding_id: EmbeddingId) -> Result<Option<P>, VectorDbError> {
+        VectorDB::get_payload(self, embedding_id)
+    }
+
+    fn set_payload(&self, embedding_id: EmbeddingId, payload: &P) -> Result<(), VectorDbError> {
+        VectorDB::set_payload(self, embedding_id, payload)
+    }
+
+    fn add_texts(&self, texts: Vec<(EmbeddingId, String)>) -> Result<(), VectorDbError> {
+        VectorDB::add_texts(self, texts)
+    }
+
+    fn remove_embedding(&self, embedding_id: EmbeddingId) -> Result<(), VectorDbError> {
+        Ok(VectorDB::remove_embedding(self, embedding_id)?)
+    }
+
+    fn get_embedding(&self, embedding_id: EmbeddingId) -> Result<Embedding<S>, VectorDbError> {
+        VectorDB::get_embedding(self, embedding_id)
+    }
+
+    fn clear(&self) -> Result<(), VectorDbError> {
+        Ok(self.clear_blocking()?)
+    }
+
+    fn filter(
+        &self,
+        filter: &mut dyn FnMut(Embedding<S>) -> bool,
+    ) -> Result<Candidates, VectorDbError> {
+        Ok(
+            IntoVectorDbSearchFilter::<S, ClosureMarker>::into_vector_db_search_filter(
+                filter, self,
+            ),
+        )
+    }
+
+    fn search(
+        &self,
+        query: VectorStoreQuery<'_, S>,
+    ) -> Result<Vec<VectorDBSearchResult<P>>, VectorDbError> {
+        let mut search = VectorDB::search(self, query.embedding)
+            .with_results(query.results)
+            .with_fusion(query.fusion);
+        if let Some(filter) = query.filter {
+            search = search.with_filter(filter);
+        }
+        if let Some(payload_filter) = query.payload_filter {
+            search = search.with_payload_filter(payload_filter);
+        }
+        if let Some(text_query) = query.text_query {
+            search = search.with_text_query(text_query);
+        }
+        search.run()
+    }
+}
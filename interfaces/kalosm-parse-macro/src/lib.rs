@@ -9,7 +9,7 @@ use syn::meta::ParseNestedMeta;
 use syn::parse::{Parse, ParseStream};
 use syn::spanned::Spanned;
 use syn::{ext::IdentExt, parse_macro_input, DeriveInput, Field, Ident, LitStr};
-use syn::{DataEnum, Fields, FieldsNamed, LitInt, Path, TypePath, Variant};
+use syn::{DataEnum, Fields, LitInt, Path, TypePath, Variant};
 
 /// Derive a default JSON parser for a unit value, struct or enum.
 ///
@@ -63,6 +63,24 @@ use syn::{DataEnum, Fields, FieldsNamed, LitInt, Path, TypePath, Variant};
 /// assert_eq!(action, Action::Search { query: "my query".to_string() });
 /// ```
 ///
+/// Tuple structs are parsed as arrays and newtypes are parsed as the value they wrap. Generic types can be parsed if their generic types implement `Parse`:
+/// ```rust
+/// # use kalosm::language::*;
+/// #[derive(Parse, Schema, Debug, Clone, PartialEq)]
+/// struct Point(i32, i32);
+///
+/// #[derive(Parse, Schema, Debug, Clone, PartialEq)]
+/// struct Labeled<T> {
+///     label: String,
+///     value: T,
+/// }
+///
+/// let parser = Labeled::<Point>::new_parser();
+/// let state = parser.create_parser_state();
+/// let labeled = parser.parse(&state, b"{ \"label\": \"origin\", \"value\": [0, 0] } ").unwrap().unwrap_finished();
+/// assert_eq!(labeled.value, Point(0, 0));
+/// ```
+///
 /// ## Attributes
 ///
 /// The `#[parse]` attribute modifies the default behavior of the parser. It can be used in the following forms:
@@ -122,40 +140,101 @@ use syn::{DataEnum, Fields, FieldsNamed, LitInt, Path, TypePath, Variant};
 ///     Quit,
 /// }
 /// ```
+///
+/// - `#[parse(flatten)]` parses the fields of a struct with named fields as if they were fields of the parent struct
+///
+/// ```rust
+/// # use kalosm::language::*;
+/// #[derive(Parse, Schema, Clone)]
+/// struct Name {
+///     first: String,
+///     last: String,
+/// }
+///
+/// #[derive(Parse, Schema, Clone)]
+/// struct Person {
+///     // Parsed as { "first": "John", "last": "Doe", "age": 30 }
+///     #[parse(flatten)]
+///     name: Name,
+///     age: u32,
+/// }
+/// ```
+///
+/// - `#[parse(skip)]` leaves the field out of the parser and schema. The field is set to its `Default` value
+///
+/// ```rust
+/// # use kalosm::language::*;
+/// #[derive(Parse, Schema, Clone)]
+/// struct Person {
+///     name: String,
+///     #[parse(skip)]
+///     friends: Vec<String>,
+/// }
+/// ```
+///
+/// - `#[parse(format = "yaml")]` writes a struct with named fields in YAML, TOML (`"toml"`) or XML (`"xml"`) instead of JSON (`"json"`). The schema is displayed in the same format. Fields are written with the syntax of the format, see `ObjectFormat` for the details:
+///   - YAML writes a line for each field. Nested structs are indented under their field and lists and maps are written in the flow style: `tags: ["good", "loyal"]`
+///   - TOML writes a line for each field. Nested structs and maps are inline tables: `owner = { name = "Al" }`. Options are always `Some` because TOML can't write a missing value
+///   - XML writes an element named after the struct with an element named after each field. Text is written without quotes, lists are written as one element for each item and options are always `Some`
+///
+///   Fields with a custom parser (`with`) or options like `len` are written the same way they are in JSON. Tuples and enums with data are also written as JSON
+///
+/// ```rust
+/// # use kalosm::language::*;
+/// #[derive(Parse, Schema, Debug, Clone, PartialEq)]
+/// #[parse(format = "yaml")]
+/// struct Person {
+///     name: String,
+///     address: Address,
+/// }
+///
+/// #[derive(Parse, Schema, Debug, Clone, PartialEq)]
+/// struct Address {
+///     city: String,
+/// }
+///
+/// let parser = Person::new_parser();
+/// let state = parser.create_parser_state();
+/// let person = parser.parse(&state, b"name: \"John\"\naddress:\n  city: \"Oslo\"\n").unwrap().unwrap_finished();
+/// assert_eq!(person, Person { name: "John".to_string(), address: Address { city: "Oslo".to_string() } });
+///
+/// #[derive(Parse, Schema, Debug, Clone, PartialEq)]
+/// #[parse(format = "xml")]
+/// struct Pet {
+///     name: String,
+///     tags: Vec<String>,
+/// }
+///
+/// let parser = Pet::new_parser();
+/// let state = parser.create_parser_state();
+/// let pet = parser.parse(&state, b"<Pet>\n<name>Rex</name>\n<tags>good</tags><tags>loyal</tags>\n</Pet>").unwrap().unwrap_finished();
+/// assert_eq!(pet, Pet { name: "Rex".to_string(), tags: vec!["good".to_string(), "loyal".to_string()] });
+/// ```
+///
+/// Doc comments on structs, enum variants and fields are added to the schema as descriptions.
 #[proc_macro_derive(Parse, attributes(parse))]
 pub fn derive_parse(input: TokenStream) -> TokenStream {
     // Parse the input tokens into a syntax tree
     let input = parse_macro_input!(input as DeriveInput);
 
     match input.data {
-        syn::Data::Struct(data) => match data.fields {
-            syn::Fields::Named(fields) => {
-                let ty = input.ident;
-                if fields.named.is_empty() {
-                    return TokenStream::from(impl_unit_parser(
-                        &input.attrs,
-                        &ty,
-                        quote! { Self {} },
-                    ));
-                }
-                let struct_parser = match StructParser::new(input.attrs, fields, ty) {
+        syn::Data::Struct(data) => {
+            let ty = input.ident;
+            if data.fields.is_empty() {
+                let construct = match data.fields {
+                    syn::Fields::Unit => quote! { Self },
+                    _ => quote! { Self {} },
+                };
+                return TokenStream::from(impl_unit_parser(&input.attrs, &ty, construct));
+            }
+            let struct_parser =
+                match StructParser::new(input.attrs, data.fields, ty, input.generics) {
                     Ok(parser) => parser,
                     Err(err) => return err.to_compile_error().into(),
                 };
 
-                TokenStream::from(struct_parser.parser())
-            }
-            syn::Fields::Unit => {
-                let ty = input.ident;
-                TokenStream::from(impl_unit_parser(&input.attrs, &ty, quote! { Self }))
-            }
-            _ => syn::Error::new(
-                input.ident.span(),
-                "Only structs with named fields are supported",
-            )
-            .to_compile_error()
-            .into(),
-        },
+            TokenStream::from(struct_parser.parser())
+        }
         syn::Data::Enum(data) => {
             let ty = input.ident;
             if data.variants.is_empty() {
@@ -170,7 +249,7 @@ pub fn derive_parse(input: TokenStream) -> TokenStream {
                 .any(|variant| !matches!(&variant.fields, syn::Fields::Unit));
 
             if has_fields {
-                match EnumParser::new(input.attrs, data, ty)
+                match EnumParser::new(input.attrs, data, ty, input.generics)
                     .and_then(|parser| parser.quote_parser())
                 {
                     Ok(parser) => parser,
@@ -181,12 +260,9 @@ pub fn derive_parse(input: TokenStream) -> TokenStream {
             }
             .into()
         }
-        _ => syn::Error::new(
-            input.ident.span(),
-            "Only structs and unit value enums are supported",
-        )
-        .to_compile_error()
-        .into(),
+        _ => syn::Error::new(input.ident.span(), "Only structs and enums are supported")
+            .to_compile_error()
+            .into(),
     }
 }
 
@@ -196,30 +272,19 @@ pub fn derive_schema(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
 
     match input.data {
-        syn::Data::Struct(data) => match data.fields {
-            syn::Fields::Named(fields) => {
-                let ty = input.ident;
-                if fields.named.is_empty() {
-                    return TokenStream::from(unit_schema(&input.attrs, &ty));
-                }
-                let struct_parser = match StructParser::new(input.attrs, fields, ty) {
+        syn::Data::Struct(data) => {
+            let ty = input.ident;
+            if data.fields.is_empty() {
+                return TokenStream::from(unit_schema(&input.attrs, &ty));
+            }
+            let struct_parser =
+                match StructParser::new(input.attrs, data.fields, ty, input.generics) {
                     Ok(parser) => parser,
                     Err(err) => return err.to_compile_error().into(),
                 };
 
-                TokenStream::from(struct_parser.quote_schema())
-            }
-            syn::Fields::Unit => {
-                let ty = input.ident;
-                TokenStream::from(unit_schema(&input.attrs, &ty))
-            }
-            _ => syn::Error::new(
-                input.ident.span(),
-                "Only structs with named fields are supported",
-            )
-            .to_compile_error()
-            .into(),
-        },
+            TokenStream::from(struct_parser.quote_schema())
+        }
         syn::Data::Enum(data) => {
             let ty = input.ident;
             if data.variants.is_empty() {
@@ -234,7 +299,7 @@ pub fn derive_schema(input: TokenStream) -> TokenStream {
                 .any(|variant| !matches!(&variant.fields, syn::Fields::Unit));
 
             if has_fields {
-                match EnumParser::new(input.attrs, data, ty)
+                match EnumParser::new(input.attrs, data, ty, input.generics)
                     .and_then(|parser| parser.quote_schema())
                 {
                     Ok(parser) => parser,
@@ -245,137 +310,301 @@ pub fn derive_schema(input: TokenStream) -> TokenStream {
             }
             .into()
         }
-        _ => syn::Error::new(
-            input.ident.span(),
-            "Only structs and unit value enums are supported",
-        )
-        .to_compile_error()
-        .into(),
+        _ => syn::Error::new(input.ident.span(), "Only structs and enums are supported")
+            .to_compile_error()
+            .into(),
+    }
+}
+
+// Add a bound to every type parameter so the derived impl only applies when the generic types implement the trait
+fn bounded_generics(generics: &syn::Generics, bound: TokenStream2) -> syn::Generics {
+    let mut generics = generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(syn::parse_quote!(#bound));
     }
+    generics
 }
 
 struct StructParser {
     attributes: Vec<syn::Attribute>,
     ty: Ident,
+    generics: syn::Generics,
     name: String,
+    format: Format,
     fields: FieldsParser,
 }
 
 impl StructParser {
-    fn new(attributes: Vec<syn::Attribute>, fields: FieldsNamed, ty: Ident) -> syn::Result<Self> {
-        let named = fields.named.into_iter().collect::<Vec<_>>();
-
+    fn new(
+        attributes: Vec<syn::Attribute>,
+        fields: Fields,
+        ty: Ident,
+        generics: syn::Generics,
+    ) -> syn::Result<Self> {
         let mut name = ty.unraw().to_string();
+        let mut format = Format::Json;
         for attr in &attributes {
             if attr.path().is_ident("parse") {
                 attr.parse_nested_meta(|meta| {
                     if let Some(value) = parse_rename_attribute(&meta)? {
                         name = value.value();
+                    } else if meta.path.is_ident("format") {
+                        if !matches!(fields, Fields::Named(_)) {
+                            return Err(meta.error(
+                                "The format can only be set for structs with named fields",
+                            ));
+                        }
+                        let value = meta
+                            .value()
+                            .and_then(|value| value.parse::<syn::LitStr>())?;
+                        format = Format::new(&value)?;
                     } else {
-                        return Err(meta.error("expected `rename`"));
+                        return Err(meta.error("expected `rename` or `format`"));
                     }
                     Ok(())
                 })?;
             }
         }
 
+        let fields = FieldsParser::new(&fields, ty.span())?;
+
         Ok(Self {
             attributes,
             name,
+            format,
+            fields,
             ty,
-            fields: FieldsParser::new(&named)?,
+            generics,
         })
     }
 
     fn parser(&self) -> TokenStream2 {
-        let field_names = self
-            .fields
-            .fields
-            .iter()
-            .map(|f| f.field.ident.as_ref().unwrap());
-        let construct = quote! {
-            Self {
-                #(
-                    #field_names
-                ),*
-            }
+        let ty = &self.ty;
+        // Parsers in other formats are boxed, which needs the generic types to be 'static
+        let bound = match self.format {
+            Format::Json => quote! { kalosm_sample::Parse },
+            _ => quote! { kalosm_sample::Parse + 'static },
         };
+        let generics = bounded_generics(&self.generics, bound);
+        let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+        if !self.fields.named {
+            let parser = match self.fields.parser(quote! { Self }) {
+                Ok(parser) => parser,
+                Err(err) => return err.to_compile_error(),
+            };
+            let parsers_in_format = self.fields.newtype_parsers_in_format(quote! { Self });
+            return quote! {
+                impl #impl_generics kalosm_sample::Parse for #ty #ty_generics #where_clause {
+                    fn new_parser() -> impl kalosm_sample::SendCreateParserState<Output = Self> {
+                        #parser
+                    }
+
+                    #parsers_in_format
+                }
+            };
+        }
 
-        let parser = match self.fields.parser(construct) {
+        let properties_parser = match self.fields.properties_parser(quote! { Self }) {
             Ok(parser) => parser,
             Err(err) => return err.to_compile_error(),
         };
+        let properties_parser_in_format = self.fields.properties_parser_in_format(quote! { Self });
 
-        let ty = &self.ty;
+        let json_parser = object_parser(quote! {
+            <Self as kalosm_sample::ParseProperties>::new_properties_parser()
+        });
+        let parser = match self.format {
+            Format::Json => json_parser.clone(),
+            format => {
+                let format = format.quote();
+                quote! {
+                    <Self as kalosm_sample::Parse>::new_parser_in_format(#format, 0)
+                }
+            }
+        };
+        let name = &self.name;
 
         quote! {
-            impl kalosm_sample::Parse for #ty {
+            impl #impl_generics kalosm_sample::Parse for #ty #ty_generics #where_clause {
                 fn new_parser() -> impl kalosm_sample::SendCreateParserState<Output = Self> {
                     #parser
                 }
+
+                fn new_parser_in_format(format: kalosm_sample::ObjectFormat, depth: usize) -> kalosm_sample::ArcParser<Self>
+                where
+                    Self: 'static,
+                {
+                    match format {
+                        kalosm_sample::ObjectFormat::Json => kalosm_sample::ParserExt::boxed(#json_parser),
+                        format => {
+                            let (start, end) = format.object_delimiters(#name, depth);
+                            kalosm_sample::ParserExt::boxed(kalosm_sample::ParserExt::then_literal(
+                                kalosm_sample::ParserExt::ignore_output_then(
+                                    kalosm_sample::LiteralParser::new(start),
+                                    <Self as kalosm_sample::ParseProperties>::new_properties_parser_in_format(format, depth)
+                                ),
+                                end
+                            ))
+                        }
+                    }
+                }
+            }
+
+            impl #impl_generics kalosm_sample::ParseProperties for #ty #ty_generics #where_clause {
+                fn new_properties_parser() -> impl kalosm_sample::SendCreateParserState<Output = Self> {
+                    #properties_parser
+                }
+
+                fn new_properties_parser_in_format(format: kalosm_sample::ObjectFormat, depth: usize) -> kalosm_sample::ArcParser<Self>
+                where
+                    Self: 'static,
+                {
+                    match format {
+                        kalosm_sample::ObjectFormat::Json => kalosm_sample::ParserExt::boxed(
+                            <Self as kalosm_sample::ParseProperties>::new_properties_parser()
+                        ),
+                        format => #properties_parser_in_format,
+                    }
+                }
             }
         }
     }
 
     fn quote_schema(&self) -> proc_macro2::TokenStream {
-        let title = &self.name;
         let ty = &self.ty;
+        let generics = bounded_generics(&self.generics, quote! { kalosm_sample::Schema });
+        let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+        if !self.fields.named {
+            let schema = self.fields.quote_schema();
+            return quote! {
+                impl #impl_generics kalosm_sample::Schema for #ty #ty_generics #where_clause {
+                    fn schema() -> kalosm_sample::SchemaType {
+                        #schema
+                    }
+                }
+            };
+        }
+
+        let title = &self.name;
         let description = doc_comment(&self.attributes);
         let description = description.map(|description| quote! { .with_description(#description) });
-        let schema = self.fields.quote_schema();
+        let properties = self.fields.quote_properties();
+
+        if self.format != Format::Json {
+            let format = self.format.quote();
+            return quote! {
+                impl #impl_generics kalosm_sample::Schema for #ty #ty_generics #where_clause {
+                    fn schema() -> kalosm_sample::SchemaType {
+                        kalosm_sample::SchemaType::Object(
+                            kalosm_sample::JsonObjectSchema::new(#properties)
+                                .with_title(#title)
+                                #description
+                                .with_format(#format)
+                        )
+                    }
+                }
+            };
+        }
 
         quote! {
-            impl kalosm_sample::Schema for #ty {
+            impl #impl_generics kalosm_sample::Schema for #ty #ty_generics #where_clause {
                 fn schema() -> kalosm_sample::SchemaType {
                     kalosm_sample::SchemaType::Object(
-                        #schema
+                        kalosm_sample::JsonObjectSchema::new(
+                            <Self as kalosm_sample::SchemaProperties>::properties()
+                        )
                         .with_title(#title)
                         #description
                     )
                 }
             }
-        }
-    }
-}
 
-fn quote_fields(fields: Fields) -> TokenStream2 {
-    match fields {
-        Fields::Named(fields) => {
-            let field_names = fields.named.iter().map(|f| f.ident.as_ref().unwrap());
-            quote! {
-                {
-                    #(
-                        #field_names
-                    ),*
+            impl #impl_generics kalosm_sample::SchemaProperties for #ty #ty_generics #where_clause {
+                fn properties() -> ::std::vec::Vec<kalosm_sample::JsonPropertySchema> {
+                    #properties
                 }
             }
         }
-        Fields::Unnamed(fields) => {
-            let field_names = (0..fields.unnamed.len()).map(|i| format_ident!("data{}", i));
-            quote! {
-                (
-                    #(
-                        #field_names
-                    ),*
-                )
-            }
+    }
+}
+
+// Wrap a parser for the properties of a JSON object in the braces of the object
+fn object_parser(properties: TokenStream2) -> TokenStream2 {
+    quote! {
+        kalosm_sample::ParserExt::then_literal(
+            kalosm_sample::ParserExt::ignore_output_then(
+                kalosm_sample::LiteralParser::from("{ "),
+                #properties
+            ),
+            " }"
+        )
+    }
+}
+
+// The syntax of a struct. The syntax of each format is chosen at runtime by kalosm_sample::ObjectFormat
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Json,
+    Yaml,
+    Toml,
+    Xml,
+}
+
+impl Format {
+    fn new(value: &LitStr) -> syn::Result<Self> {
+        match value.value().to_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "yaml" => Ok(Self::Yaml),
+            "toml" => Ok(Self::Toml),
+            "xml" => Ok(Self::Xml),
+            _ => Err(syn::Error::new(
+                value.span(),
+                "expected one of \"json\", \"yaml\", \"toml\" or \"xml\"",
+            )),
         }
-        Fields::Unit => {
-            quote! {}
+    }
+
+    fn quote(self) -> TokenStream2 {
+        match self {
+            Self::Json => quote! { kalosm_sample::ObjectFormat::Json },
+            Self::Yaml => quote! { kalosm_sample::ObjectFormat::Yaml },
+            Self::Toml => quote! { kalosm_sample::ObjectFormat::Toml },
+            Self::Xml => quote! { kalosm_sample::ObjectFormat::Xml },
         }
     }
 }
 
 fn impl_unit_parser(attrs: &[syn::Attribute], ty: &Ident, construct: TokenStream2) -> TokenStream2 {
     let unit_parser = unit_parser(attrs, ty);
+    let name = match unit_parse_literal_name(attrs, ty) {
+        Ok(name) => name,
+        Err(err) => return err.to_compile_error(),
+    };
     quote! {
         impl kalosm_sample::Parse for #ty {
             fn new_parser() -> impl kalosm_sample::SendCreateParserState<Output = Self> {
-                kalosm_sample::ParserExt::map_output(
+                kalosm_sample::ParserExt::map_output_with_value(
                     #unit_parser,
-                    |_| #construct
+                    |_| #construct,
+                    |_, _| Some(kalosm_sample::serde_json::Value::String(#name.to_string()))
                 )
             }
+
+            fn new_parser_in_format(format: kalosm_sample::ObjectFormat, _: usize) -> kalosm_sample::ArcParser<Self> {
+                match format {
+                    // XML text is written without quotes
+                    kalosm_sample::ObjectFormat::Xml => kalosm_sample::ParserExt::boxed(
+                        kalosm_sample::ParserExt::map_output_with_value(
+                            kalosm_sample::LiteralParser::new(#name),
+                            |_| #construct,
+                            |_, _| Some(kalosm_sample::serde_json::Value::String(#name.to_string()))
+                        )
+                    ),
+                    format => format.scalar_parser(<Self as kalosm_sample::Parse>::new_parser()),
+                }
+            }
         }
     }
 }
@@ -437,13 +666,19 @@ fn unit_parser(attrs: &[syn::Attribute], ty: &Ident) -> TokenStream2 {
 
 struct EnumParser {
     ty: Ident,
+    generics: syn::Generics,
     tag: String,
     data: String,
     variants: Vec<EnumVariant>,
 }
 
 impl EnumParser {
-    fn new(attrs: Vec<syn::Attribute>, data: DataEnum, ty: Ident) -> syn::Result<Self> {
+    fn new(
+        attrs: Vec<syn::Attribute>,
+        data: DataEnum,
+        ty: Ident,
+        generics: syn::Generics,
+    ) -> syn::Result<Self> {
         // Look for the tag and content attributes within the #[parse] attribute
         let mut tag = "type".to_string();
         let mut content = "data".to_string();
@@ -477,6 +712,7 @@ impl EnumParser {
 
         Ok(EnumParser {
             ty,
+            generics,
             tag,
             data: content,
             variants,
@@ -490,7 +726,7 @@ impl EnumParser {
         let mut parser = None;
 
         for variant in &self.variants {
-            let parse_variant = variant.quote_parser(content)?;
+            let parse_variant = variant.quote_parser(tag, content)?;
             match &mut parser {
                 Some(current) => {
                     *current = quote! {
@@ -507,9 +743,11 @@ impl EnumParser {
         }
 
         let struct_start = format!("{{ \"{tag}\": \"");
+        let generics = bounded_generics(&self.generics, quote! { kalosm_sample::Parse });
+        let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
 
         Ok(quote! {
-            impl kalosm_sample::Parse for #ty {
+            impl #impl_generics kalosm_sample::Parse for #ty #ty_generics #where_clause {
                 fn new_parser() -> impl kalosm_sample::SendCreateParserState<Output = Self> {
                     kalosm_sample::ParserExt::then_literal(
                         kalosm_sample::ParserExt::ignore_output_then(
@@ -531,17 +769,13 @@ impl EnumParser {
         let variants: Vec<_> = self
             .variants
             .iter()
-            .map(|variant| {
-                let variant_name = &variant.name;
-                let variant_parser = variant.quote_schema(tag, content, variant_name)?;
-                Ok(quote! {
-                    #variant_parser
-                })
-            })
-            .collect::<syn::Result<_>>()?;
+            .map(|variant| variant.quote_schema(tag, content))
+            .collect();
+        let generics = bounded_generics(&self.generics, quote! { kalosm_sample::Schema });
+        let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
 
         Ok(quote! {
-            impl kalosm_sample::Schema for #ty {
+            impl #impl_generics kalosm_sample::Schema for #ty #ty_generics #where_clause {
                 fn schema() -> kalosm_sample::SchemaType {
                     kalosm_sample::SchemaType::OneOf(
                         kalosm_sample::OneOfSchema::new([
@@ -557,7 +791,7 @@ impl EnumParser {
 struct EnumVariant {
     variant: Variant,
     name: String,
-    ty: EnumVariantType,
+    fields: Option<FieldsParser>,
 }
 
 impl EnumVariant {
@@ -578,152 +812,90 @@ impl EnumVariant {
             }
         }
 
-        let parse_variant = match &variant.fields {
-            syn::Fields::Named(fields) => {
-                EnumVariantType::Struct(StructEnumVariantParser::new(fields)?)
-            }
-            syn::Fields::Unnamed(fields) => {
-                let field_vec = fields.unnamed.iter().collect::<Vec<_>>();
-                let [inner] = *field_vec else {
-                    return Err(syn::Error::new(
-                        variant.ident.span(),
-                        "Unnamed enum variants with more or less than one field are not supported",
-                    ));
-                };
-
-                EnumVariantType::Tuple(TupleEnumVariantParser::new(inner))
-            }
-            // If this is a unit variant, we can just parse the type
-            syn::Fields::Unit => EnumVariantType::Unit(UnitEnumVariantParser::new()),
+        // Unit variants only have a tag. Variants with fields also have content
+        let fields = match &variant.fields {
+            syn::Fields::Unit => None,
+            fields => Some(FieldsParser::new(fields, variant_ident.span())?),
         };
 
         Ok(Self {
             variant: variant.clone(),
             name: variant_name,
-            ty: parse_variant,
+            fields,
         })
     }
 
-    fn construct_variant(&self) -> TokenStream2 {
-        let fields = quote_fields(self.variant.fields.clone());
+    fn quote_parser(&self, tag_name: &str, content_name: &str) -> syn::Result<TokenStream2> {
         let variant_ident = &self.variant.ident;
-        quote! {
-            Self::#variant_ident #fields
-        }
-    }
-
-    fn quote_parser(&self, content_name: &str) -> syn::Result<TokenStream2> {
-        let construct_variant = self.construct_variant();
-        match &self.ty {
-            EnumVariantType::Struct(parser) => {
-                parser.quote_parser(&self.name, content_name, construct_variant)
+        let construct_variant = quote! { Self::#variant_ident };
+        let variant_name = &self.name;
+        // The tag is part of the value of every variant
+        let tag_value = quote! {
+            kalosm_sample::serde_json::json!({ #tag_name: #variant_name })
+        };
+        match &self.fields {
+            Some(fields) => {
+                let parse_name_and_data = LitStr::new(
+                    &format!("{variant_name}\", \"{content_name}\": "),
+                    Span::call_site(),
+                );
+                let fields_parser = fields.parser(construct_variant.clone())?;
+                let pattern = match fields.named {
+                    true => quote! { #construct_variant { .. } },
+                    false => quote! { #construct_variant(..) },
+                };
+                Ok(quote! {
+                    kalosm_sample::ParserExt::map_output_with_value(
+                        kalosm_sample::ParserExt::then(
+                            kalosm_sample::ParserExt::map_output_with_value(
+                                kalosm_sample::LiteralParser::from(#parse_name_and_data),
+                                |_| (),
+                                |_, _| Some(#tag_value)
+                            ),
+                            kalosm_sample::ParserExt::into_property(#fields_parser, #content_name)
+                        ),
+                        |((), data)| data,
+                        |__parser, __output: &Self| match __output {
+                            #pattern => kalosm_sample::Parser::output_value(__parser, &((), __output.clone())),
+                            #[allow(unreachable_patterns)]
+                            _ => None,
+                        }
+                    )
+                })
             }
-            EnumVariantType::Tuple(parser) => {
-                parser.quote_parser(&self.name, content_name, construct_variant)
+            None => {
+                let lit_str_name = LitStr::new(&format!("{variant_name}\""), Span::call_site());
+                Ok(quote! {
+                    kalosm_sample::ParserExt::map_output_with_value(
+                        kalosm_sample::LiteralParser::from(#lit_str_name),
+                        |_| #construct_variant,
+                        |_, __output: &Self| match __output {
+                            #construct_variant => Some(#tag_value),
+                            #[allow(unreachable_patterns)]
+                            _ => None,
+                        }
+                    )
+                })
             }
-            EnumVariantType::Unit(parser) => parser.quote_parser(&self.name, construct_variant),
-        }
-    }
-
-    fn quote_schema(
-        &self,
-        tag: &str,
-        content: &str,
-        variant_name: &str,
-    ) -> syn::Result<proc_macro2::TokenStream> {
-        match &self.ty {
-            EnumVariantType::Struct(parser) => parser.quote_schema(tag, content, variant_name),
-            EnumVariantType::Tuple(parser) => parser.quote_schema(tag, content, variant_name),
-            EnumVariantType::Unit(parser) => parser.quote_schema(tag, variant_name),
         }
     }
-}
-
-enum EnumVariantType {
-    Unit(UnitEnumVariantParser),
-    Tuple(TupleEnumVariantParser),
-    Struct(StructEnumVariantParser),
-}
-
-struct UnitEnumVariantParser {}
-
-impl UnitEnumVariantParser {
-    fn new() -> Self {
-        Self {}
-    }
-
-    fn quote_parser(
-        &self,
-        variant_name: &str,
-        construct_variant: TokenStream2,
-    ) -> syn::Result<TokenStream2> {
-        let lit_str_name = LitStr::new(&format!("{variant_name}\""), Span::call_site());
-        Ok(quote! {
-            kalosm_sample::ParserExt::map_output(
-                kalosm_sample::LiteralParser::from(#lit_str_name),
-                |_| #construct_variant
-            )
-        })
-    }
-
-    fn quote_schema(&self, tag: &str, variant_name: &str) -> syn::Result<proc_macro2::TokenStream> {
-        Ok(quote! {
-            kalosm_sample::SchemaType::Object(
-                kalosm_sample::JsonObjectSchema::new([
-                    kalosm_sample::JsonPropertySchema::new(
-                        #tag,
-                        kalosm_sample::SchemaType::Const(
-                            kalosm_sample::ConstSchema::new(
-                                kalosm_sample::SchemaLiteral::String(#variant_name.to_string())
-                            )
-                        )
-                    )
-                    .with_required(true)
-                ])
-            )
-        })
-    }
-}
-
-struct StructEnumVariantParser {
-    fields: FieldsParser,
-}
-
-impl StructEnumVariantParser {
-    fn new(fields: &FieldsNamed) -> syn::Result<Self> {
-        let fields = fields.named.iter().cloned().collect::<Vec<_>>();
-        Ok(Self {
-            fields: FieldsParser::new(&fields)?,
-        })
-    }
 
-    fn quote_parser(
-        &self,
-        variant_name: &str,
-        content_name: &str,
-        construct_variant: TokenStream2,
-    ) -> syn::Result<TokenStream2> {
-        let parse_name_and_data = LitStr::new(
-            &format!("{variant_name}\", \"{content_name}\": "),
-            Span::call_site(),
-        );
-        let field_parser = self.fields.parser(construct_variant)?;
-        Ok(quote! {
-            kalosm_sample::ParserExt::ignore_output_then(
-                kalosm_sample::LiteralParser::from(#parse_name_and_data),
-                #field_parser
-            )
-        })
-    }
-
-    fn quote_schema(
-        &self,
-        tag: &str,
-        content: &str,
-        variant_name: &str,
-    ) -> syn::Result<proc_macro2::TokenStream> {
-        let variant_parser = self.fields.quote_schema();
-        Ok(quote! {
+    fn quote_schema(&self, tag: &str, content: &str) -> proc_macro2::TokenStream {
+        let variant_name = &self.name;
+        let description = doc_comment(&self.variant.attrs);
+        let description = description.map(|description| quote! { .with_description(#description) });
+        let content = self.fields.as_ref().map(|fields| {
+            let schema = fields.quote_schema();
+            quote! {
+                ,
+                kalosm_sample::JsonPropertySchema::new(
+                    #content,
+                    #schema
+                )
+                .with_required(true)
+            }
+        });
+        quote! {
             kalosm_sample::SchemaType::Object(
                 kalosm_sample::JsonObjectSchema::new([
                     kalosm_sample::JsonPropertySchema::new(
@@ -734,81 +906,13 @@ impl StructEnumVariantParser {
                             )
                         )
                     )
-                    .with_required(true),
-                    kalosm_sample::JsonPropertySchema::new(
-                        #content,
-                        kalosm_sample::SchemaType::Object(
-                            #variant_parser
-                        )
-                    )
                     .with_required(true)
+                    #content
                 ])
+                #description
             )
-        })
-    }
-}
-
-struct TupleEnumVariantParser {
-    field: Field,
-}
-
-impl TupleEnumVariantParser {
-    fn new(fields: &Field) -> Self {
-        Self {
-            field: fields.clone(),
         }
     }
-
-    fn quote_parser(
-        &self,
-        variant_name: &str,
-        content: &str,
-        construct_variant: TokenStream2,
-    ) -> syn::Result<TokenStream2> {
-        let parse_name_and_data = LitStr::new(
-            &format!("{variant_name}\", \"{content}\": "),
-            Span::call_site(),
-        );
-        let ty = &self.field.ty;
-        Ok(quote! {
-            kalosm_sample::ParserExt::map_output(
-                kalosm_sample::ParserExt::ignore_output_then(
-                    kalosm_sample::LiteralParser::from(#parse_name_and_data),
-                    <#ty as kalosm_sample::Parse>::new_parser()
-                ),
-                |data0| #construct_variant
-            )
-        })
-    }
-
-    fn quote_schema(
-        &self,
-        tag: &str,
-        content: &str,
-        variant_name: &str,
-    ) -> syn::Result<proc_macro2::TokenStream> {
-        let ty = &self.field.ty;
-        Ok(quote! {
-            kalosm_sample::SchemaType::Object(
-                kalosm_sample::JsonObjectSchema::new([
-                    kalosm_sample::JsonPropertySchema::new(
-                        #tag,
-                        kalosm_sample::SchemaType::Const(
-                            kalosm_sample::ConstSchema::new(
-                                kalosm_sample::SchemaLiteral::String(#variant_name.to_string())
-                            )
-                        )
-                    )
-                    .with_required(true),
-                    kalosm_sample::JsonPropertySchema::new(
-                        #content,
-                        <#ty as kalosm_sample::Schema>::schema()
-                    )
-                    .with_required(true)
-                ])
-            )
-        })
-    }
 }
 
 fn unit_enum_parser(attrs: Vec<syn::Attribute>, data: DataEnum, ty: Ident) -> TokenStream2 {
@@ -833,6 +937,9 @@ fn unit_enum_parser(attrs: Vec<syn::Attribute>, data: DataEnum, ty: Ident) -> To
     }
 
     let mut parse_construction_map = HashMap::new();
+    let mut variant_values = Vec::new();
+    let mut variant_names = Vec::new();
+    let mut variant_constructors = Vec::new();
     for variant in data.variants.iter() {
         let variant_name = &variant.ident;
         let fields = &variant.fields;
@@ -843,6 +950,15 @@ fn unit_enum_parser(attrs: Vec<syn::Attribute>, data: DataEnum, ty: Ident) -> To
             Ok(literal_string) => literal_string,
             Err(err) => return err.to_compile_error(),
         };
+        let name = match unit_parse_literal_name(&variant.attrs, variant_name) {
+            Ok(name) => name,
+            Err(err) => return err.to_compile_error(),
+        };
+        variant_values.push(quote! {
+            #construct_variant => #name,
+        });
+        variant_names.push(name);
+        variant_constructors.push(construct_variant.clone());
         parse_construction_map.insert(literal_string.as_bytes().to_vec(), construct_variant);
     }
 
@@ -985,9 +1101,43 @@ fn unit_enum_parser(attrs: Vec<syn::Attribute>, data: DataEnum, ty: Ident) -> To
                     }),
                 })
             }
+
+            fn output_value(&self, output: &Self::Output) -> Option<kalosm_sample::serde_json::Value> {
+                let name = match output {
+                    #(#variant_values)*
+                };
+                Some(kalosm_sample::serde_json::Value::String(name.to_string()))
+            }
         }
     };
 
+    // XML text is written without quotes
+    let parser_in_format = (!unquoted).then(|| {
+        let indices = 0..variant_constructors.len();
+        quote! {
+            fn new_parser_in_format(format: kalosm_sample::ObjectFormat, _: usize) -> kalosm_sample::ArcParser<Self> {
+                match format {
+                    kalosm_sample::ObjectFormat::Xml => kalosm_sample::ParserExt::boxed(
+                        kalosm_sample::ParserExt::map_output_with_value(
+                            kalosm_sample::OneOfStringsParser::new([#(#variant_names),*]),
+                            |index| match index {
+                                #(#indices => #variant_constructors,)*
+                                _ => unreachable!("The parser only outputs the index of a variant"),
+                            },
+                            |_, output: &Self| {
+                                let name = match output {
+                                    #(#variant_values)*
+                                };
+                                Some(kalosm_sample::serde_json::Value::String(name.to_string()))
+                            }
+                        )
+                    ),
+                    format => format.scalar_parser(<Self as kalosm_sample::Parse>::new_parser()),
+                }
+            }
+        }
+    });
+
     quote! {
         impl kalosm_sample::Parse for #ty {
             fn new_parser() -> impl kalosm_sample::SendCreateParserState<Output = Self> {
@@ -996,6 +1146,8 @@ fn unit_enum_parser(attrs: Vec<syn::Attribute>, data: DataEnum, ty: Ident) -> To
 
                 #parser
             }
+
+            #parser_in_format
         }
     }
 }
@@ -1039,9 +1191,9 @@ fn unit_enum_schema_type(variants: impl IntoIterator<Item = String>) -> proc_mac
     }
 }
 
-fn wrap_tuple(ident: &Ident, current: TokenStream2) -> TokenStream2 {
+fn wrap_tuple(next: TokenStream2, current: TokenStream2) -> TokenStream2 {
     quote! {
-        (#current, #ident)
+        (#current, #next)
     }
 }
 
@@ -1056,122 +1208,419 @@ fn parse_rename_attribute(meta: &ParseNestedMeta) -> syn::Result<Option<LitStr>>
 }
 
 struct FieldsParser {
+    // Named fields are parsed as an object. Unnamed fields are parsed as an array, or as the value of the field if there is only one
+    named: bool,
     fields: Vec<FieldParser>,
 }
 
 impl FieldsParser {
-    fn new(fields: &[Field]) -> syn::Result<Self> {
-        Ok(Self {
+    fn new(fields: &Fields, span: Span) -> syn::Result<Self> {
+        let fields = Self {
+            named: matches!(fields, Fields::Named(_)),
             fields: fields
                 .iter()
-                .map(FieldParser::new)
+                .enumerate()
+                .map(|(index, field)| FieldParser::new(field, index))
                 .collect::<syn::Result<_>>()?,
-        })
+        };
+        if fields.parsed_fields().next().is_none() {
+            return Err(syn::Error::new(
+                span,
+                "At least one field must be parsed. Every field is skipped",
+            ));
+        }
+        Ok(fields)
     }
 
-    fn parser(&self, construct: TokenStream2) -> syn::Result<TokenStream2> {
-        let mut parsers = Vec::new();
-        let idents: Vec<_> = self
-            .fields
-            .iter()
-            .map(|f| format_ident!("{}_parser", f.field.ident.as_ref().unwrap().unraw()))
-            .collect();
-        for (i, (field, parser_ident)) in self.fields.iter().zip(idents.iter()).enumerate() {
-            let mut literal_text = String::new();
-            if i == 0 {
-                literal_text.push_str("{ ");
-            } else {
-                literal_text.push_str(", ");
+    // The fields that are not skipped
+    fn parsed_fields(&self) -> impl Iterator<Item = &FieldParser> {
+        self.fields.iter().filter(|field| !field.skip)
+    }
+
+    fn construct(&self, path: TokenStream2) -> TokenStream2 {
+        let values = self.fields.iter().map(|field| {
+            let binding = &field.binding;
+            match (field.skip, self.named) {
+                (true, true) => quote! { #binding: ::std::default::Default::default() },
+                (true, false) => quote! { ::std::default::Default::default() },
+                (false, _) => quote! { #binding },
             }
-            let field_name = &field.name;
+        });
+        if self.named {
+            quote! { #path { #(#values),* } }
+        } else {
+            quote! { #path ( #(#values),* ) }
+        }
+    }
+
+    // A parser for the whole value of the fields
+    fn parser(&self, path: TokenStream2) -> syn::Result<TokenStream2> {
+        if self.named {
+            return Ok(object_parser(self.properties_parser(path)?));
+        }
+
+        let construct = self.construct(path.clone());
+        let output_value = self.output_value(path);
+        let fields: Vec<_> = self.parsed_fields().collect();
+        if let [field] = *fields {
+            let binding = &field.binding;
             let field_parser = &field.parser;
-            literal_text.push_str(&format!("\"{field_name}\": "));
-            let literal_text = LitStr::new(&literal_text, field.field.ident.span());
+            return Ok(quote! {
+                kalosm_sample::ParserExt::map_output_with_value(
+                    #field_parser,
+                    |#binding| #construct,
+                    #output_value
+                )
+            });
+        }
 
+        let mut parsers = Vec::new();
+        for (i, field) in fields.iter().enumerate() {
+            let separator = if i == 0 { "[" } else { ", " };
+            let field_parser = &field.parser;
             parsers.push(quote! {
-                let #parser_ident = kalosm_sample::ParserExt::ignore_output_then(
-                    kalosm_sample::LiteralParser::from(#literal_text),
-                    #field_parser
-                );
+                kalosm_sample::ParserExt::ignore_output_then(
+                    kalosm_sample::LiteralParser::from(#separator),
+                    kalosm_sample::ParserExt::into_item(#field_parser)
+                )
             });
         }
+        let join_parser = join_parsers(parsers);
+        let output_tuple = self.output_tuple();
 
-        let mut output_tuple = None;
-        for field in self.fields.iter() {
-            let name = field.field.ident.as_ref().unwrap();
-            match output_tuple {
-                Some(current) => {
-                    output_tuple = Some(wrap_tuple(name, current));
+        Ok(quote! {
+            kalosm_sample::ParserExt::map_output_with_value(
+                kalosm_sample::ParserExt::then_literal(
+                    #join_parser,
+                    "]"
+                ),
+                |#output_tuple| #construct,
+                #output_value
+            )
+        })
+    }
+
+    // A parser for named fields without the start and end of the JSON object
+    fn properties_parser(&self, path: TokenStream2) -> syn::Result<TokenStream2> {
+        let construct = self.construct(path.clone());
+        let output_value = self.output_value(path);
+        let mut parsers = Vec::new();
+        let mut idents = Vec::new();
+        for (i, field) in self.parsed_fields().enumerate() {
+            let parser_ident = format_ident!("{}_parser", field.binding.unraw());
+            let separator = if i == 0 { "" } else { ", " };
+            let properties_parser = if field.flatten {
+                let ty = &field.field.ty;
+                let parser = quote_spanned! {
+                    ty.span() =>
+                    <#ty as kalosm_sample::ParseProperties>::new_properties_parser()
+                };
+                if i == 0 {
+                    parser
+                } else {
+                    quote! {
+                        kalosm_sample::ParserExt::ignore_output_then(
+                            kalosm_sample::LiteralParser::from(#separator),
+                            #parser
+                        )
+                    }
                 }
-                None => {
-                    output_tuple = Some(name.to_token_stream());
+            } else {
+                let field_parser = &field.parser;
+                let name = &field.name;
+                let start = LitStr::new(&format!("{separator}\"{name}\": "), field.binding.span());
+                quote! {
+                    kalosm_sample::ParserExt::ignore_output_then(
+                        kalosm_sample::LiteralParser::from(#start),
+                        kalosm_sample::ParserExt::into_property(#field_parser, #name)
+                    )
                 }
-            }
+            };
+
+            parsers.push(quote! {
+                let #parser_ident = #properties_parser;
+            });
+            idents.push(parser_ident.to_token_stream());
         }
 
-        let mut join_parser: Option<TokenStream2> = None;
-        for ident in idents.iter() {
-            match &mut join_parser {
-                Some(current) => {
-                    *current = quote! {
-                        kalosm_sample::ParserExt::then(#current, #ident)
-                    };
+        let join_parser = join_parsers(idents);
+        let output_tuple = self.output_tuple();
+
+        Ok(quote! {
+            {
+                #(
+                    #parsers
+                )*
+
+                kalosm_sample::ParserExt::map_output_with_value(
+                    #join_parser,
+                    |#output_tuple| #construct,
+                    #output_value
+                )
+            }
+        })
+    }
+
+    // A parser for named fields in the `format` for an object nested `depth` levels deep without the start and end of the object
+    fn properties_parser_in_format(&self, path: TokenStream2) -> TokenStream2 {
+        let construct = self.construct(path.clone());
+        let output_value = self.output_value(path);
+        let mut parsers = Vec::new();
+        let mut idents = Vec::new();
+        for (i, field) in self.parsed_fields().enumerate() {
+            let parser_ident = format_ident!("{}_parser", field.binding.unraw());
+            let property_parser = if field.flatten {
+                let ty = &field.field.ty;
+                let ty = quote_spanned! {
+                    ty.span() =>
+                    <#ty as kalosm_sample::ParseProperties>
+                };
+                quote! {
+                    #ty::new_properties_parser_in_format(format, depth)
                 }
-                None => {
-                    join_parser = Some(ident.to_token_stream());
+            } else {
+                let name = &field.name;
+                field
+                    .parser
+                    .quote_property_parser_in_format(quote! { #name })
+            };
+            let property_parser = if i == 0 {
+                property_parser
+            } else {
+                quote! {
+                    kalosm_sample::ParserExt::ignore_output_then(
+                        kalosm_sample::LiteralParser::from(format.property_separator(depth)),
+                        #property_parser
+                    )
                 }
-            }
+            };
+
+            parsers.push(quote! {
+                let #parser_ident = #property_parser;
+            });
+            idents.push(parser_ident.to_token_stream());
         }
 
-        Ok(quote! {
+        let join_parser = join_parsers(idents);
+        let output_tuple = self.output_tuple();
+
+        quote! {
             {
                 #(
                     #parsers
                 )*
 
-                kalosm_sample::ParserExt::map_output(
-                    kalosm_sample::ParserExt::then_literal(
-                        #join_parser,
-                        r#" }"#
-                    ),
-                    |#output_tuple| #construct
-                )
+                kalosm_sample::ParserExt::boxed(kalosm_sample::ParserExt::map_output_with_value(
+                    #join_parser,
+                    |#output_tuple| #construct,
+                    #output_value
+                ))
+            }
+        }
+    }
+
+    // The parsers in other formats for a struct with one unnamed field. The field is written in the format. Structs with more fields are written as JSON arrays
+    fn newtype_parsers_in_format(&self, path: TokenStream2) -> Option<TokenStream2> {
+        let fields: Vec<_> = self.parsed_fields().collect();
+        let [field] = *fields else {
+            return None;
+        };
+        let binding = &field.binding;
+        let construct = self.construct(path.clone());
+        let output_value = self.output_value(path);
+        let parser = field.parser.quote_parser_in_format();
+        let property_parser = field
+            .parser
+            .quote_property_parser_in_format(quote! { name });
+        Some(quote! {
+            fn new_parser_in_format(format: kalosm_sample::ObjectFormat, depth: usize) -> kalosm_sample::ArcParser<Self>
+            where
+                Self: 'static,
+            {
+                kalosm_sample::ParserExt::boxed(kalosm_sample::ParserExt::map_output_with_value(
+                    #parser,
+                    |#binding| #construct,
+                    #output_value
+                ))
+            }
+
+            fn new_property_parser_in_format(
+                format: kalosm_sample::ObjectFormat,
+                name: &str,
+                depth: usize,
+            ) -> kalosm_sample::ArcParser<Self>
+            where
+                Self: 'static,
+            {
+                kalosm_sample::ParserExt::boxed(kalosm_sample::ParserExt::map_output_with_value(
+                    #property_parser,
+                    |#binding| #construct,
+                    #output_value
+                ))
             }
         })
     }
 
+    // The nested tuple of the outputs of the parsed fields joined with `then`
+    fn output_tuple(&self) -> Option<TokenStream2> {
+        self.output_tuple_with(|binding| binding.to_token_stream())
+    }
+
+    fn output_tuple_with(&self, output: impl Fn(&Ident) -> TokenStream2) -> Option<TokenStream2> {
+        let mut output_tuple = None;
+        for field in self.parsed_fields() {
+            let name = output(&field.binding);
+            match output_tuple {
+                Some(current) => {
+                    output_tuple = Some(wrap_tuple(name, current));
+                }
+                None => {
+                    output_tuple = Some(name);
+                }
+            }
+        }
+        output_tuple
+    }
+
+    // A pattern that binds the parsed fields of the value
+    fn pattern(&self, path: TokenStream2) -> TokenStream2 {
+        if self.named {
+            let bindings = self.parsed_fields().map(|field| &field.binding);
+            quote! { #path { #(#bindings,)* .. } }
+        } else {
+            let bindings = self.fields.iter().map(|field| match field.skip {
+                true => quote! { _ },
+                false => field.binding.to_token_stream(),
+            });
+            quote! { #path ( #(#bindings),* ) }
+        }
+    }
+
+    // A function that gets the value of an output from the parser of the fields by turning the output back into the nested tuple the parser outputs. Enum variants only know the value of their own variant
+    fn output_value(&self, path: TokenStream2) -> TokenStream2 {
+        let pattern = self.pattern(path);
+        let output_tuple = self.output_tuple_with(|binding| quote! { #binding.clone() });
+        quote! {
+            |__parser, __output: &Self| match __output {
+                #pattern => kalosm_sample::Parser::output_value(__parser, &#output_tuple),
+                #[allow(unreachable_patterns)]
+                _ => None,
+            }
+        }
+    }
+
+    // The schema of the whole value of the fields
     fn quote_schema(&self) -> proc_macro2::TokenStream {
-        let properties = self.fields.iter().map(|field| field.quote_schema());
+        if self.named {
+            let properties = self.quote_properties();
+            return quote! {
+                kalosm_sample::SchemaType::Object(
+                    kalosm_sample::JsonObjectSchema::new(#properties)
+                )
+            };
+        }
+
+        let schemas: Vec<_> = self
+            .parsed_fields()
+            .map(|field| field.parser.quote_schema())
+            .collect();
+        if let [schema] = &*schemas {
+            return schema.clone();
+        }
         quote! {
-            kalosm_sample::JsonObjectSchema::new(
-                vec![#(#properties),*]
+            kalosm_sample::SchemaType::Tuple(
+                kalosm_sample::TupleSchema::new([#(#schemas),*])
             )
         }
     }
+
+    // The schema of each named field
+    fn quote_properties(&self) -> proc_macro2::TokenStream {
+        let properties = self.parsed_fields().map(|field| {
+            if field.flatten {
+                let ty = &field.field.ty;
+                quote_spanned! {
+                    ty.span() =>
+                    properties.extend(<#ty as kalosm_sample::SchemaProperties>::properties());
+                }
+            } else {
+                let property = field.quote_schema();
+                quote! {
+                    properties.push(#property);
+                }
+            }
+        });
+        quote! {
+            {
+                let mut properties = ::std::vec::Vec::new();
+                #(#properties)*
+                properties
+            }
+        }
+    }
+}
+
+fn join_parsers(parsers: impl IntoIterator<Item = TokenStream2>) -> Option<TokenStream2> {
+    let mut join_parser: Option<TokenStream2> = None;
+    for parser in parsers {
+        match &mut join_parser {
+            Some(current) => {
+                *current = quote! {
+                    kalosm_sample::ParserExt::then(#current, #parser)
+                };
+            }
+            None => {
+                join_parser = Some(parser);
+            }
+        }
+    }
+    join_parser
 }
 
 struct FieldParser {
     field: Field,
+    // The name of the variable the field is bound to while it is constructed. Unnamed fields are bound to `data0`, `data1`, etc.
+    binding: Ident,
     parser: Parser,
     name: String,
+    flatten: bool,
+    skip: bool,
 }
 
 impl FieldParser {
-    fn new(field: &Field) -> syn::Result<Self> {
-        let mut field_name = field.ident.as_ref().unwrap().unraw().to_string();
+    fn new(field: &Field, index: usize) -> syn::Result<Self> {
+        let binding = field
+            .ident
+            .clone()
+            .unwrap_or_else(|| format_ident!("data{}", index));
+        let mut field_name = binding.unraw().to_string();
         let mut parser: Parser = syn::parse2(field.ty.to_token_stream())?;
+        let mut flatten = false;
+        let mut skip = false;
 
-        // Look for #[parse(rename = "name")] or #[parse(with = expr)] attributes
+        // Look for #[parse(rename = "name")], #[parse(flatten)], #[parse(skip)] or #[parse(with = expr)] attributes
         for attr in field.attrs.iter() {
             if attr.path().is_ident("parse") {
                 attr.parse_nested_meta(|meta| {
                     if let Some(value) = parse_rename_attribute(&meta)? {
+                        if field.ident.is_none() {
+                            return Err(meta.error("Unnamed fields can't be renamed"));
+                        }
                         field_name = value.value();
                         Ok(())
+                    } else if meta.path.is_ident("flatten") {
+                        if field.ident.is_none() {
+                            return Err(meta.error("Only named fields can be flattened"));
+                        }
+                        flatten = true;
+                        Ok(())
+                    } else if meta.path.is_ident("skip") {
+                        skip = true;
+                        Ok(())
                     } else {
                         let attribute_applied = parser.apply_attribute(&meta)?;
                         if !attribute_applied {
-                            let mut possible_attributes = vec!["rename"];
+                            let mut possible_attributes = vec!["rename", "flatten", "skip"];
                             possible_attributes.extend(parser.possible_attributes());
                             return Err(meta.error(expected_attributes_error(possible_attributes)));
                         }
@@ -1183,8 +1632,11 @@ impl FieldParser {
 
         Ok(Self {
             field: field.clone(),
+            binding,
             parser,
             name: field_name,
+            flatten,
+            skip,
         })
     }
 
@@ -1331,6 +1783,38 @@ impl Parser {
         }
     }
 
+    // The type that writes the value in each format. Values with a custom parser or options are written the same way they are in JSON
+    fn type_in_format(&self) -> Option<TokenStream2> {
+        if self.with.is_some() {
+            return None;
+        }
+        let ty = match &self.ty {
+            ParserType::Custom(ty) => ty.clone(),
+            ParserType::String(options) if options.is_default() => options.path.to_token_stream(),
+            _ => return None,
+        };
+        Some(quote_spanned! {
+            ty.span() =>
+            <#ty as kalosm_sample::Parse>
+        })
+    }
+
+    // A parser for the value nested `depth` levels deep in the `format`
+    fn quote_parser_in_format(&self) -> TokenStream2 {
+        match self.type_in_format() {
+            Some(ty) => quote! { #ty::new_parser_in_format(format, depth) },
+            None => quote! { format.scalar_parser(#self) },
+        }
+    }
+
+    // A parser for the property `name` of an object nested `depth` levels deep in the `format`
+    fn quote_property_parser_in_format(&self, name: TokenStream2) -> TokenStream2 {
+        match self.type_in_format() {
+            Some(ty) => quote! { #ty::new_property_parser_in_format(format, #name, depth) },
+            None => quote! { format.property_parser(#name, depth, format.scalar_parser(#self)) },
+        }
+    }
+
     fn possible_attributes(&self) -> Vec<&'static str> {
         let mut attributes = vec!["with", "schema"];
         match &self.ty {
@@ -1356,18 +1840,7 @@ impl Parser {
                     kalosm_sample::SchemaType::String(#schema)
                 }
             }
-            ParserType::Number(options) => {
-                let schema = options.quote_schema();
-                quote! {
-                    kalosm_sample::SchemaType::Number(#schema)
-                }
-            }
-            ParserType::Integer(options) => {
-                let schema = options.quote_schema();
-                quote! {
-                    kalosm_sample::SchemaType::Integer(#schema)
-                }
-            }
+            ParserType::Number(options) | ParserType::Integer(options) => options.quote_schema(),
             ParserType::Boolean(options) => {
                 let schema = options.quote_schema();
                 quote! {
@@ -1452,6 +1925,11 @@ impl Parse for StringParserOptions {
 impl StringParserOptions {
     const ATTRIBUTES: &'static [&'static str] = &["character_filter", "len", "pattern"];
 
+    // Strings without options are parsed by the `Parse` implementation of the type
+    fn is_default(&self) -> bool {
+        self.character_filter.is_none() && self.len.is_none() && self.pattern.is_none()
+    }
+
     fn apply_attribute(&mut self, input: &syn::meta::ParseNestedMeta) -> syn::Result<bool> {
         if input.path.is_ident("character_filter") {
             self.character_filter = Some(input.value()?.parse()?);
@@ -1749,21 +2227,36 @@ impl NumberParserOptions {
                         range.span() =>
                             .with_range({
                                 let range = #range;
-                                let start = range.start() as f64;
-                                let end = range.end() as f64;
+                                let start = *range.start() as f64;
+                                let end = *range.end() as f64;
                                 start..=end
                             })
                     }
                 });
                 quote_spanned! {
                     self.path.span() =>
-                    kalosm_sample::NumberSchema::new()
-                    #range
+                    kalosm_sample::SchemaType::Number(
+                        kalosm_sample::NumberSchema::new()
+                        #range
+                    )
                 }
             }
-            _ => quote_spanned! {
-                self.path.span() =>
-                kalosm_sample::IntegerSchema::new()
+            _ => match &self.range {
+                Some(range) => quote_spanned! {
+                    range.span() =>
+                    kalosm_sample::SchemaType::BoundedInteger(
+                        kalosm_sample::IntegerSchema::new().with_range({
+                            let range = #range;
+                            let start = *range.start() as i128;
+                            let end = *range.end() as i128;
+                            start..=end
+                        })
+                    )
+                },
+                None => quote_spanned! {
+                    self.path.span() =>
+                    kalosm_sample::SchemaType::Integer(kalosm_sample::IntegerSchema::new())
+                },
             },
         }
     }
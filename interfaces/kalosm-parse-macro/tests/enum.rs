@@ -220,3 +220,66 @@ fn unit_enum_parses() {
         assert_eq!(color, Color::Red);
     }
 }
+
+#[derive(Parse, Schema, Debug, Clone, PartialEq)]
+enum Shape {
+    /// A circle with a radius
+    Circle(u32),
+    Rectangle(u32, u32),
+}
+
+#[test]
+fn tuple_payload_enum_parses() {
+    use kalosm::language::{CreateParserState, Parser};
+
+    let parser = Shape::new_parser();
+    let state = parser.create_parser_state();
+    let shape = parser
+        .parse(&state, b"{ \"type\": \"Rectangle\", \"data\": [1, 2] }")
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(shape, Shape::Rectangle(1, 2));
+    let shape = parser
+        .parse(&state, b"{ \"type\": \"Circle\", \"data\": 3 }")
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(shape, Shape::Circle(3));
+}
+
+#[test]
+fn tuple_payload_enum_schema() {
+    let schema = Shape::schema();
+    let json = serde_json::from_str::<serde_json::Value>(&schema.to_string()).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "oneOf": [
+                {
+                    "description": "A circle with a radius",
+                    "type": "object",
+                    "properties": {
+                        "type": { "const": "Circle" },
+                        "data": { "type": "integer" }
+                    },
+                    "required": ["type", "data"],
+                    "additionalProperties": false
+                },
+                {
+                    "type": "object",
+                    "properties": {
+                        "type": { "const": "Rectangle" },
+                        "data": {
+                            "type": "array",
+                            "prefixItems": [{ "type": "integer" }, { "type": "integer" }],
+                            "minItems": 2,
+                            "maxItems": 2,
+                            "unevaluatedItems": false
+                        }
+                    },
+                    "required": ["type", "data"],
+                    "additionalProperties": false
+                }
+            ]
+        })
+    );
+}
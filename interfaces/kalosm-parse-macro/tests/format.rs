@@ -0,0 +1,198 @@
+use kalosm::language::*;
+use pretty_assertions::assert_eq;
+use std::collections::BTreeMap;
+
+/// Parse the whole text with the parser of the type.
+fn parse<T: Parse>(text: &str) -> T {
+    let parser = T::new_parser();
+    let state = parser.create_parser_state();
+    parser
+        .parse(&state, text.as_bytes())
+        .unwrap()
+        .unwrap_finished()
+}
+
+#[derive(Parse, serde::Deserialize, Clone, Debug, PartialEq)]
+struct Owner {
+    name: String,
+    verified: bool,
+}
+
+#[derive(Parse, serde::Deserialize, Clone, Debug, PartialEq)]
+enum Color {
+    Red,
+    Brown,
+}
+
+#[derive(Parse, serde::Deserialize, Clone, Debug, PartialEq)]
+struct Markings {
+    color: Color,
+    pattern: String,
+}
+
+#[derive(Parse, serde::Deserialize, Clone, Debug, PartialEq)]
+#[parse(format = "yaml")]
+struct YamlPet {
+    name: String,
+    #[parse(rename = "pet age")]
+    #[serde(rename = "pet age")]
+    age: u32,
+    tags: Vec<String>,
+    owner: Owner,
+    friends: Vec<Owner>,
+    nickname: Option<String>,
+    scores: BTreeMap<String, i32>,
+    #[parse(flatten)]
+    #[serde(flatten)]
+    markings: Markings,
+}
+
+#[test]
+fn yaml_round_trips() {
+    let text = r#"name: "Rex"
+"pet age": 3
+tags: ["good", "loyal"]
+owner:
+  name: "Al"
+  verified: true
+friends: [{ "name": "Bo", "verified": false }]
+nickname: null
+scores: { "fetch": 10 }
+color: "Brown"
+pattern: "spots"
+"#;
+    let pet: YamlPet = parse(text);
+    assert_eq!(pet, serde_yaml::from_str::<YamlPet>(text).unwrap());
+    assert_eq!(pet.owner.name, "Al");
+    assert_eq!(pet.nickname, None);
+    assert_eq!(pet.markings.color, Color::Brown);
+
+    let text = "name: \"Rex\"\n\"pet age\": 3\ntags: []\nowner:\n  name: \"Al\"\n  verified: false\nfriends: []\nnickname: \"Rexy\"\nscores: {}\ncolor: \"Red\"\npattern: \"none\"\n";
+    let pet: YamlPet = parse(text);
+    assert_eq!(pet, serde_yaml::from_str::<YamlPet>(text).unwrap());
+    assert_eq!(pet.nickname.as_deref(), Some("Rexy"));
+}
+
+#[derive(Parse, serde::Deserialize, Clone, Debug, PartialEq)]
+#[parse(format = "toml")]
+struct TomlPet {
+    name: String,
+    #[parse(rename = "pet age")]
+    #[serde(rename = "pet age")]
+    age: u32,
+    tags: Vec<String>,
+    owner: Owner,
+    friends: Vec<Owner>,
+    nickname: Option<String>,
+    scores: BTreeMap<String, i32>,
+    #[parse(flatten)]
+    #[serde(flatten)]
+    markings: Markings,
+}
+
+#[test]
+fn toml_round_trips() {
+    let text = r#"name = "Rex"
+"pet age" = 3
+tags = ["good", "loyal"]
+owner = { name = "Al", verified = true }
+friends = [{ name = "Bo", verified = false }]
+nickname = "Rexy"
+scores = { "fetch" = 10 }
+color = "Brown"
+pattern = "spots"
+"#;
+    let pet: TomlPet = parse(text);
+    assert_eq!(pet, toml::from_str::<TomlPet>(text).unwrap());
+    assert_eq!(pet.friends[0].name, "Bo");
+    assert_eq!(pet.scores["fetch"], 10);
+    // TOML can't write a missing value
+    assert!(parse_fails::<TomlPet>(&text.replace("\"Rexy\"", "null")));
+}
+
+#[derive(Parse, serde::Deserialize, Clone, Debug, PartialEq)]
+#[parse(format = "xml", rename = "pet")]
+struct XmlPet {
+    name: String,
+    age: u32,
+    tags: Vec<String>,
+    owner: Owner,
+    friends: Vec<Owner>,
+    nickname: Option<String>,
+    color: Color,
+}
+
+#[test]
+fn xml_round_trips() {
+    let text = "<pet>
+<name>Rex &amp; Co</name>
+<age>3</age>
+<tags>good</tags><tags>loyal</tags>
+<owner>
+<name>Al</name>
+<verified>true</verified>
+</owner>
+<friends>
+<name>Bo</name>
+<verified>false</verified>
+</friends>
+<nickname>Rexy</nickname>
+<color>Brown</color>
+</pet>";
+    // Entities are not supported
+    assert!(parse_fails::<XmlPet>(text));
+
+    let text = text.replace(" &amp; Co", "");
+    let pet: XmlPet = parse(&text);
+    assert_eq!(pet, quick_xml::de::from_str::<XmlPet>(&text).unwrap());
+    assert_eq!(pet.tags, ["good", "loyal"]);
+    assert_eq!(pet.color, Color::Brown);
+    // XML can't write an empty list
+    assert!(parse_fails::<XmlPet>(
+        &text.replace("<tags>good</tags><tags>loyal</tags>\n", "")
+    ));
+}
+
+#[derive(Parse, Clone, Debug, PartialEq)]
+struct Origin {
+    city: String,
+    country: String,
+}
+
+#[derive(Parse, Clone, Debug, PartialEq)]
+#[parse(format = "xml", rename = "pet")]
+struct XmlPetWithOrigin {
+    name: String,
+    #[parse(flatten)]
+    origin: Origin,
+}
+
+/// quick-xml can't read flattened fields, so the text is read into a struct that lists the fields of both structs.
+#[derive(serde::Deserialize, Debug, PartialEq)]
+struct XmlPetWithOriginFields {
+    name: String,
+    city: String,
+    country: String,
+}
+
+#[test]
+fn xml_flattened_fields_round_trip() {
+    let text = "<pet>\n<name>Rex</name>\n<city>Oslo</city>\n<country>Norway</country>\n</pet>";
+    let pet: XmlPetWithOrigin = parse(text);
+    let fields: XmlPetWithOriginFields = quick_xml::de::from_str(text).unwrap();
+    assert_eq!(
+        fields,
+        XmlPetWithOriginFields {
+            name: pet.name,
+            city: pet.origin.city,
+            country: pet.origin.country,
+        }
+    );
+}
+
+/// Check that the parser rejects the text.
+fn parse_fails<T: Parse>(text: &str) -> bool {
+    let parser = T::new_parser();
+    let state = parser.create_parser_state();
+    parser.parse(&state, text.as_bytes()).is_err()
+}
@@ -0,0 +1,147 @@
+use kalosm::language::*;
+use pretty_assertions::assert_eq;
+use serde_json::{json, Value};
+
+/// Parse the text one byte at a time. Returns every value that is different from the value before it, ending with the value of the output.
+fn parsed_values<T: Parse>(text: &str) -> Vec<Value> {
+    let parser = T::new_parser();
+    let mut state = parser.create_parser_state();
+    let mut values: Vec<Value> = Vec::new();
+    for byte in text.bytes() {
+        let (value, finished) = match parser.parse(&state, &[byte]).unwrap() {
+            ParseStatus::Incomplete { new_state, .. } => {
+                let value = parser.partial_value(&new_state);
+                state = new_state;
+                (value, false)
+            }
+            ParseStatus::Finished { result, .. } => (parser.output_value(&result), true),
+        };
+        if let Some(value) = value {
+            if values.last() != Some(&value) {
+                values.push(value);
+            }
+        }
+        if finished {
+            return values;
+        }
+    }
+    panic!("The parser did not finish");
+}
+
+#[derive(Parse, Clone, Debug, PartialEq)]
+struct Pet {
+    name: String,
+    age: u32,
+}
+
+#[derive(Parse, Clone, Debug, PartialEq)]
+enum Color {
+    Red,
+    Blue,
+}
+
+#[derive(Parse, Clone, Debug, PartialEq)]
+struct Person {
+    #[parse(rename = "full name")]
+    name: String,
+    pets: Vec<Pet>,
+    color: Color,
+    nickname: Option<String>,
+    #[parse(skip)]
+    id: u64,
+}
+
+#[test]
+fn partial_values_of_nested_structs() {
+    let values = parsed_values::<Person>(
+        r#"{ "full name": "Al", "pets": [{ "name": "Rex", "age": 3 }], "color": "Blue", "nickname": null } "#,
+    );
+    assert_eq!(values[0], json!({ "full name": "" }));
+    assert_eq!(values[2], json!({ "full name": "Al" }));
+    assert!(values.contains(&json!({ "full name": "Al", "pets": [] })));
+    assert!(values.contains(&json!({ "full name": "Al", "pets": [{ "name": "Re" }] })));
+    assert!(values.contains(&json!({ "full name": "Al", "pets": [{ "name": "Rex", "age": 3 }] })));
+    assert_eq!(
+        values.last().unwrap(),
+        &json!({
+            "full name": "Al",
+            "pets": [{ "name": "Rex", "age": 3 }],
+            "color": "Blue",
+            "nickname": null
+        })
+    );
+}
+
+#[derive(Parse, Clone, Debug, PartialEq)]
+#[parse(format = "yaml")]
+struct YamlPet {
+    name: String,
+    tags: Vec<String>,
+    age: u32,
+    friend: Pet,
+}
+
+#[derive(Parse, Clone, Debug, PartialEq)]
+#[parse(format = "xml")]
+struct XmlPet {
+    name: String,
+    tags: Vec<String>,
+}
+
+#[test]
+fn partial_values_of_other_formats() {
+    let values = parsed_values::<YamlPet>(
+        "name: \"Rex\"\ntags: [\"good\"]\nage: 12\nfriend:\n  name: \"Bo\"\n  age: 3\n",
+    );
+    assert!(values.contains(&json!({ "name": "Re" })));
+    assert!(values.contains(&json!({ "name": "Rex", "tags": ["go"] })));
+    assert!(values.contains(&json!({ "name": "Rex", "tags": ["good"], "age": 1 })));
+    assert!(values.contains(
+        &json!({ "name": "Rex", "tags": ["good"], "age": 12, "friend": { "name": "B" } })
+    ));
+    assert_eq!(
+        values.last().unwrap(),
+        &json!({ "name": "Rex", "tags": ["good"], "age": 12, "friend": { "name": "Bo", "age": 3 } })
+    );
+
+    let values = parsed_values::<XmlPet>(
+        "<XmlPet>\n<name>Rex</name>\n<tags>good</tags><tags>loyal</tags>\n</XmlPet>",
+    );
+    assert!(values.contains(&json!({ "name": "Re" })));
+    assert!(values.contains(&json!({ "name": "Rex", "tags": ["good", "lo"] })));
+    assert_eq!(
+        values.last().unwrap(),
+        &json!({ "name": "Rex", "tags": ["good", "loyal"] })
+    );
+}
+
+#[derive(Parse, Clone, Debug, PartialEq)]
+struct Point(i32, i32);
+
+#[derive(Parse, Clone, Debug, PartialEq)]
+enum Action {
+    Search { query: String },
+    Move(Point),
+    Quit,
+}
+
+#[test]
+fn partial_values_of_enums_and_tuples() {
+    let values = parsed_values::<Action>(r#"{ "type": "Search", "data": { "query": "cats" } } "#);
+    assert_eq!(values[0], json!({ "type": "Search" }));
+    assert!(values.contains(&json!({ "type": "Search", "data": { "query": "ca" } })));
+    assert_eq!(
+        values.last().unwrap(),
+        &json!({ "type": "Search", "data": { "query": "cats" } })
+    );
+
+    let values = parsed_values::<Action>(r#"{ "type": "Move", "data": [1, -2] } "#);
+    assert!(values.contains(&json!({ "type": "Move", "data": [1] })));
+    assert_eq!(
+        values.last().unwrap(),
+        &json!({ "type": "Move", "data": [1, -2] })
+    );
+
+    let values = parsed_values::<Action>(r#"{ "type": "Quit" } "#);
+    assert_eq!(values, [json!({ "type": "Quit" })]);
+}
@@ -138,3 +138,210 @@ async fn with_struct() {
     assert!(output.contains("\"name\":"));
     assert!(output.contains("\"field name\":"));
 }
+
+#[derive(Parse, Schema, Clone, PartialEq, Debug)]
+struct Name {
+    first: String,
+    last: String,
+}
+
+/// A person
+#[derive(Parse, Schema, Clone, PartialEq, Debug)]
+struct FlattenedStruct<T> {
+    #[parse(flatten)]
+    name: Name,
+    /// Extra data about the person
+    extra: T,
+    #[parse(skip)]
+    friends: Vec<String>,
+}
+
+#[test]
+fn flattened_struct_parses() {
+    use kalosm::language::{CreateParserState, Parser};
+
+    let parser = FlattenedStruct::<u32>::new_parser();
+    let state = parser.create_parser_state();
+    let person = parser
+        .parse(
+            &state,
+            b"{ \"first\": \"John\", \"last\": \"Doe\", \"extra\": 30 } ",
+        )
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(
+        person,
+        FlattenedStruct {
+            name: Name {
+                first: "John".to_string(),
+                last: "Doe".to_string(),
+            },
+            extra: 30,
+            friends: Vec::new(),
+        }
+    );
+}
+
+#[test]
+fn flattened_struct_schema() {
+    let schema = FlattenedStruct::<u32>::schema();
+    let json = serde_json::from_str::<serde_json::Value>(&schema.to_string()).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "title": "FlattenedStruct",
+            "description": "A person",
+            "type": "object",
+            "properties": {
+                "first": { "type": "string" },
+                "last": { "type": "string" },
+                "extra": {
+                    "description": "Extra data about the person",
+                    "type": "integer"
+                }
+            },
+            "required": ["first", "last", "extra"],
+            "additionalProperties": false
+        })
+    );
+}
+
+#[derive(Parse, Schema, Clone, PartialEq, Debug)]
+struct Meters(#[parse(range = 0..=100)] u32);
+
+#[derive(Parse, Schema, Clone, PartialEq, Debug)]
+struct Point(i32, i32);
+
+#[test]
+fn tuple_structs_parse() {
+    use kalosm::language::{CreateParserState, ParseStatus, Parser};
+
+    let parser = Meters::new_parser();
+    let state = parser.create_parser_state();
+    let meters = parser.parse(&state, b"10 ").unwrap().unwrap_finished();
+    assert_eq!(meters, Meters(10));
+    // The range of the field stops the number before it gets too large
+    let ParseStatus::Finished { result, remaining } = parser.parse(&state, b"101 ").unwrap() else {
+        panic!("The parser should finish before the number is out of range");
+    };
+    assert_eq!(result, Meters(10));
+    assert_eq!(remaining, b"1 ");
+
+    let parser = Point::new_parser();
+    let state = parser.create_parser_state();
+    let point = parser.parse(&state, b"[1, -2]").unwrap().unwrap_finished();
+    assert_eq!(point, Point(1, -2));
+}
+
+#[test]
+fn tuple_struct_schema() {
+    let schema = Point::schema();
+    let json = serde_json::from_str::<serde_json::Value>(&schema.to_string()).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "type": "array",
+            "prefixItems": [{ "type": "integer" }, { "type": "integer" }],
+            "minItems": 2,
+            "maxItems": 2,
+            "unevaluatedItems": false
+        })
+    );
+
+    let schema = Meters::schema();
+    let json = serde_json::from_str::<serde_json::Value>(&schema.to_string()).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "type": "integer",
+            "minimum": 0,
+            "maximum": 100
+        })
+    );
+}
+
+/// A person
+#[derive(Parse, Schema, Clone, PartialEq, Debug)]
+#[parse(format = "yaml")]
+struct YamlPerson {
+    /// The name of the person
+    name: String,
+    age: u32,
+}
+
+#[derive(Parse, Schema, Clone, PartialEq, Debug)]
+#[parse(format = "toml")]
+struct TomlPerson {
+    #[parse(rename = "full name")]
+    name: String,
+    age: u32,
+}
+
+#[derive(Parse, Schema, Clone, PartialEq, Debug)]
+#[parse(format = "xml", rename = "person")]
+struct XmlPerson {
+    name: String,
+    age: u32,
+}
+
+#[test]
+fn formatted_structs_parse() {
+    use kalosm::language::{CreateParserState, Parser};
+
+    let parser = YamlPerson::new_parser();
+    let state = parser.create_parser_state();
+    let person = parser
+        .parse(&state, b"name: \"John\"\nage: 30\n")
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(
+        person,
+        YamlPerson {
+            name: "John".to_string(),
+            age: 30
+        }
+    );
+
+    let parser = TomlPerson::new_parser();
+    let state = parser.create_parser_state();
+    let person = parser
+        .parse(&state, b"\"full name\" = \"John\"\nage = 30\n")
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(
+        person,
+        TomlPerson {
+            name: "John".to_string(),
+            age: 30
+        }
+    );
+
+    let parser = XmlPerson::new_parser();
+    let state = parser.create_parser_state();
+    let person = parser
+        .parse(
+            &state,
+            b"<person>\n<name>John</name>\n<age>30</age>\n</person>",
+        )
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(
+        person,
+        XmlPerson {
+            name: "John".to_string(),
+            age: 30
+        }
+    );
+}
+
+#[test]
+fn formatted_struct_schemas() {
+    assert_eq!(
+        YamlPerson::schema().to_string(),
+        "# YamlPerson\n# A person\n# The name of the person\nname: {\n\t\"type\": \"string\"\n}\nage: { \"type\": \"integer\" }"
+    );
+    assert_eq!(
+        XmlPerson::schema().to_string(),
+        "<person>\n\t<name>{\n\t\t\"type\": \"string\"\n\t}</name>\n\t<age>{ \"type\": \"integer\" }</age>\n</person>"
+    );
+}
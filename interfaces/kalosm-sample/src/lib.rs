@@ -7,6 +7,7 @@
 
 #[doc(hidden)]
 pub use anyhow;
+pub use serde_json;
 
 mod structured_parser;
 pub use structured_parser::*;
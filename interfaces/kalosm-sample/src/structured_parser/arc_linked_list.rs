@@ -28,6 +28,12 @@ impl<T> ArcLinkedList<T> {
         self.len
     }
 
+    /// Iterate over the items in the list, starting with the last item that was pushed.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &T> {
+        std::iter::successors(self.tail.as_ref(), |node| node.prev.as_deref())
+            .map(|node| &*node.value)
+    }
+
     pub(crate) fn vec(&self) -> Vec<T>
     where
         T: Clone,
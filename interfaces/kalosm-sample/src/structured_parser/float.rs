@@ -1,7 +1,7 @@
 use crate::{CreateParserState, ParseStatus, Parser};
 use std::ops::RangeInclusive;
 
-#[derive(Debug, PartialEq, Eq, Default, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Default, Copy, Clone)]
 enum FloatParserProgress {
     #[default]
     Initial,
@@ -326,6 +326,25 @@ impl Parser for FloatParser {
             required_next: Default::default(),
         })
     }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        Some(crate::hash_state_key((
+            state.state,
+            state.value.to_bits(),
+            state.positive,
+        )))
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        if !state.state.is_after_digit() {
+            return None;
+        }
+        self.output_value(&(state.value * if state.positive { 1.0 } else { -1.0 }))
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        serde_json::Number::from_f64(*output).map(serde_json::Value::Number)
+    }
 }
 
 #[test]
@@ -0,0 +1,244 @@
+use std::ops::RangeInclusive;
+
+use crate::{
+    ArcParser, CreateParserState, LiteralParser, ObjectFormat, ParseStatus, Parser, ParserExt,
+    SendCreateParserState,
+};
+
+impl ObjectFormat {
+    /// The text before a value that is written on the same line as its property, like the space after `name:` in YAML.
+    pub fn scalar_prefix(self) -> &'static str {
+        match self {
+            ObjectFormat::Yaml => " ",
+            _ => "",
+        }
+    }
+
+    /// The text before and after the properties of an object nested `depth` levels deep. An object at depth zero is the whole document and XML documents are wrapped in an element with the name of the object.
+    pub fn object_delimiters(self, name: &str, depth: usize) -> (String, String) {
+        match (self, depth) {
+            (ObjectFormat::Json, _) | (ObjectFormat::Toml, 1..) => {
+                ("{ ".to_string(), " }".to_string())
+            }
+            (ObjectFormat::Yaml | ObjectFormat::Toml, 0) => (String::new(), "\n".to_string()),
+            (ObjectFormat::Yaml, _) => ("\n".to_string(), String::new()),
+            (ObjectFormat::Xml, 0) => (format!("<{name}>\n"), format!("\n</{name}>")),
+            (ObjectFormat::Xml, _) => ("\n".to_string(), "\n".to_string()),
+        }
+    }
+
+    /// The text between the properties of an object nested `depth` levels deep.
+    pub fn property_separator(self, depth: usize) -> &'static str {
+        match (self, depth) {
+            (ObjectFormat::Json, _) | (ObjectFormat::Toml, 1..) => ", ",
+            _ => "\n",
+        }
+    }
+
+    /// The text before and after the value of the property `name` of an object nested `depth` levels deep.
+    pub fn property_delimiters(self, name: &str, depth: usize) -> (String, String) {
+        match self {
+            ObjectFormat::Json => (format!("{}: ", quote(name)), String::new()),
+            ObjectFormat::Yaml => {
+                let indentation = "  ".repeat(depth);
+                (format!("{indentation}{}:", yaml_key(name)), String::new())
+            }
+            ObjectFormat::Toml => (format!("{} = ", toml_key(name)), String::new()),
+            ObjectFormat::Xml => (format!("<{name}>"), format!("</{name}>")),
+        }
+    }
+
+    /// Create a parser for a value that is written the same way in every format, like a number. The value is parsed after the [`ObjectFormat::scalar_prefix`].
+    pub fn scalar_parser<P>(self, parser: P) -> ArcParser<P::Output>
+    where
+        P: SendCreateParserState + 'static,
+        P::Output: Clone + 'static,
+        P::PartialState: 'static,
+    {
+        LiteralParser::new(self.scalar_prefix())
+            .ignore_output_then(parser)
+            .boxed()
+    }
+
+    /// Create a parser for the property `name` of an object nested `depth` levels deep with the value of the parser.
+    pub fn property_parser<P>(self, name: &str, depth: usize, value: P) -> ArcParser<P::Output>
+    where
+        P: SendCreateParserState + 'static,
+        P::Output: Clone + 'static,
+        P::PartialState: 'static,
+    {
+        let (start, end) = self.property_delimiters(name, depth);
+        LiteralParser::new(start)
+            .ignore_output_then(value.into_property(name))
+            .then_literal(end)
+            .boxed()
+    }
+}
+
+fn quote(name: &str) -> String {
+    serde_json::Value::String(name.to_string()).to_string()
+}
+
+fn is_bare_key(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+// YAML reads some plain keys as booleans, null or numbers
+fn yaml_key(name: &str) -> String {
+    let plain = is_bare_key(name)
+        && name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && !matches!(
+            name.to_ascii_lowercase().as_str(),
+            "true" | "false" | "null" | "yes" | "no" | "on" | "off" | "y" | "n"
+        );
+    if plain {
+        name.to_string()
+    } else {
+        quote(name)
+    }
+}
+
+fn toml_key(name: &str) -> String {
+    if is_bare_key(name) {
+        name.to_string()
+    } else {
+        quote(name)
+    }
+}
+
+/// A parser for the text of an XML element. The text ends before the `<` of the closing tag, which is left for the next parser.
+///
+/// Entities are not supported, so the text can't contain `&` or `<`. XML readers trim the whitespace around the text, so the text can't start or end with whitespace either.
+#[derive(Debug, Clone)]
+pub(crate) struct XmlTextParser {
+    len_range: RangeInclusive<usize>,
+}
+
+impl XmlTextParser {
+    /// Create a parser for text with a length in characters in the range.
+    pub(crate) fn new(len_range: RangeInclusive<usize>) -> Self {
+        Self { len_range }
+    }
+}
+
+/// The state of an [`XmlTextParser`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct XmlTextParserState {
+    text: Vec<u8>,
+    chars: usize,
+}
+
+impl CreateParserState for XmlTextParser {
+    fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+        XmlTextParserState::default()
+    }
+}
+
+impl Parser for XmlTextParser {
+    type Output = String;
+    type PartialState = XmlTextParserState;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> crate::ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+        let mut state = state.clone();
+        for (index, &byte) in input.iter().enumerate() {
+            match byte {
+                b'<' => {
+                    if !self.len_range.contains(&state.chars) {
+                        crate::bail!("The text is not the right length");
+                    }
+                    if state.text.last().is_some_and(u8::is_ascii_whitespace) {
+                        crate::bail!("The text can't end with whitespace");
+                    }
+                    let Ok(result) = String::from_utf8(state.text) else {
+                        crate::bail!("The text is not valid UTF-8");
+                    };
+                    return Ok(ParseStatus::Finished {
+                        result,
+                        remaining: &input[index..],
+                    });
+                }
+                b'&' => crate::bail!("The text can't contain entities"),
+                _ if state.text.is_empty() && byte.is_ascii_whitespace() => {
+                    crate::bail!("The text can't start with whitespace")
+                }
+                _ => {
+                    // Continuation bytes are part of the character before them
+                    if byte & 0xC0 != 0x80 {
+                        if state.chars == *self.len_range.end() {
+                            crate::bail!("The text is too long");
+                        }
+                        state.chars += 1;
+                    }
+                    state.text.push(byte);
+                }
+            }
+        }
+        Ok(ParseStatus::Incomplete {
+            new_state: state,
+            required_next: Default::default(),
+        })
+    }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        Some(crate::hash_state_key((
+            state.text.is_empty(),
+            state.text.last().is_some_and(u8::is_ascii_whitespace),
+            crate::count_state_key(state.chars, &self.len_range),
+        )))
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        Some(serde_json::Value::String(
+            String::from_utf8_lossy(&state.text).to_string(),
+        ))
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        Some(serde_json::Value::String(output.clone()))
+    }
+}
+
+#[test]
+fn test_xml_text() {
+    let parser = XmlTextParser::new(1..=3);
+    let state = parser.create_parser_state();
+    assert_eq!(
+        parser.parse(&state, "añb</".as_bytes()).unwrap(),
+        ParseStatus::Finished {
+            result: "añb".to_string(),
+            remaining: b"</"
+        }
+    );
+    assert!(parser.parse(&state, b"</").is_err());
+    assert!(parser.parse(&state, b"abcd").is_err());
+    assert!(parser.parse(&state, b" a").is_err());
+    assert!(parser.parse(&state, b"a <").is_err());
+    assert!(parser.parse(&state, b"a&").is_err());
+}
+
+#[test]
+fn test_property_delimiters() {
+    assert_eq!(
+        ObjectFormat::Yaml.property_delimiters("full name", 1),
+        ("  \"full name\":".to_string(), String::new())
+    );
+    assert_eq!(
+        ObjectFormat::Yaml.property_delimiters("null", 0),
+        ("\"null\":".to_string(), String::new())
+    );
+    assert_eq!(
+        ObjectFormat::Toml.property_delimiters("first-name", 0),
+        ("first-name = ".to_string(), String::new())
+    );
+    assert_eq!(
+        ObjectFormat::Xml.property_delimiters("name", 2),
+        ("<name>".to_string(), "</name>".to_string())
+    );
+}
@@ -0,0 +1,875 @@
+use std::{collections::HashMap, fmt::Display};
+
+use crate::{CreateParserState, ParseStatus, Parser};
+
+/// An error in a grammar passed to [`GrammarParser::new`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrammarError {
+    message: String,
+    line: usize,
+    column: usize,
+}
+
+impl GrammarError {
+    /// Get the message that describes the error.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Get the line of the grammar the error is on. Lines start at 1.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Get the column of the grammar the error is on. Columns start at 1.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+}
+
+impl Display for GrammarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}",
+            self.message, self.line, self.column
+        )
+    }
+}
+
+impl std::error::Error for GrammarError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Element {
+    /// A character in (or not in) any of the inclusive ranges.
+    Chars {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    /// A reference to another rule.
+    Rule(usize),
+}
+
+impl Element {
+    fn literal(char: char) -> Self {
+        Self::Chars {
+            ranges: vec![(char, char)],
+            negated: false,
+        }
+    }
+
+    fn matches(&self, char: char) -> bool {
+        match self {
+            Self::Chars { ranges, negated } => {
+                ranges
+                    .iter()
+                    .any(|(start, end)| (*start..=*end).contains(&char))
+                    != *negated
+            }
+            Self::Rule(_) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    name: String,
+    alternatives: Vec<Vec<Element>>,
+}
+
+/// A position in one alternative of a rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Position {
+    rule: u32,
+    alternative: u32,
+    element: u32,
+}
+
+/// The positions the parser is at, from the outermost rule to the innermost rule.
+type Stack = Vec<Position>;
+
+/// A parser for a grammar written in [GBNF](https://github.com/ggerganov/llama.cpp/blob/master/grammars/README.md), the EBNF dialect llama.cpp uses. The grammar is compiled at runtime, so it can be loaded from a file or written by someone else.
+///
+/// The grammar must define a `root` rule. Rules can reference each other recursively, but a rule can't reference itself before matching at least one character (left recursion).
+///
+/// Supported syntax:
+/// - `name ::= ...` defines a rule
+/// - `"text"` matches a literal string
+/// - `[a-z0-9_]` matches a character in a class and `[^"\\]` matches a character not in a class
+/// - `.` matches any character
+/// - `( ... )` groups elements and `|` separates alternatives
+/// - `*`, `+`, `?`, `{m}`, `{m,}` and `{m,n}` repeat the previous element
+/// - `#` starts a comment that runs to the end of the line
+///
+/// The parser outputs the text it matched. It keeps going as long as the grammar allows more text and finishes once the grammar is complete and the next character doesn't fit.
+///
+/// ```rust
+/// use kalosm_sample::*;
+///
+/// let grammar = r#"
+///     root ::= "[" (item ("," item)*)? "]"
+///     item ::= [a-z]+ | root
+/// "#;
+/// let parser = GrammarParser::new(grammar).unwrap();
+/// let state = parser.create_parser_state();
+/// let result = parser.parse(&state, b"[a,[bc,d]] and more").unwrap();
+/// assert_eq!(
+///     result,
+///     ParseStatus::Finished {
+///         result: "[a,[bc,d]]".to_string(),
+///         remaining: b" and more",
+///     }
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct GrammarParser {
+    rules: Vec<Rule>,
+    root: usize,
+}
+
+impl GrammarParser {
+    /// Compile a grammar in the GBNF format.
+    pub fn new(grammar: &str) -> Result<Self, GrammarError> {
+        Compiler::new(grammar).compile()
+    }
+
+    fn element(&self, position: Position) -> Option<&Element> {
+        self.rules[position.rule as usize].alternatives[position.alternative as usize]
+            .get(position.element as usize)
+    }
+
+    /// Move into rules until every stack either is empty (the grammar is complete) or points at a character.
+    fn expand(&self, mut stack: Stack, out: &mut Vec<Stack>) {
+        while let Some(&top) = stack.last() {
+            match self.element(top) {
+                // The alternative is finished, continue with the rule that referenced it
+                None => {
+                    stack.pop();
+                }
+                Some(Element::Chars { .. }) => break,
+                Some(&Element::Rule(rule)) => {
+                    stack.last_mut().unwrap().element += 1;
+                    // Drop finished positions so right recursion doesn't grow the stack
+                    while stack
+                        .last()
+                        .is_some_and(|position| self.element(*position).is_none())
+                    {
+                        stack.pop();
+                    }
+                    for alternative in 0..self.rules[rule].alternatives.len() {
+                        let mut stack = stack.clone();
+                        stack.push(Position {
+                            rule: rule as u32,
+                            alternative: alternative as u32,
+                            element: 0,
+                        });
+                        self.expand(stack, out);
+                    }
+                    return;
+                }
+            }
+        }
+        if !out.contains(&stack) {
+            out.push(stack);
+        }
+    }
+
+    /// Get the stacks after the character is matched.
+    fn advance(&self, stacks: &[Stack], char: char) -> Vec<Stack> {
+        let mut next = Vec::new();
+        for stack in stacks {
+            let Some(&top) = stack.last() else {
+                continue;
+            };
+            if self
+                .element(top)
+                .is_some_and(|element| element.matches(char))
+            {
+                let mut stack = stack.clone();
+                stack.last_mut().unwrap().element += 1;
+                self.expand(stack, &mut next);
+            }
+        }
+        next
+    }
+
+    /// Find the text that must come next because the grammar only allows one character at a time.
+    fn required_next(&self, stacks: &[Stack]) -> String {
+        const MAX_REQUIRED_NEXT: usize = 64;
+        let mut required_next = String::new();
+        let mut stacks = stacks.to_vec();
+        while required_next.len() < MAX_REQUIRED_NEXT {
+            let [stack] = stacks.as_slice() else {
+                break;
+            };
+            let Some(&top) = stack.last() else {
+                break;
+            };
+            let Some(Element::Chars {
+                ranges,
+                negated: false,
+            }) = self.element(top)
+            else {
+                break;
+            };
+            let &[(start, end)] = ranges.as_slice() else {
+                break;
+            };
+            if start != end {
+                break;
+            }
+            required_next.push(start);
+            stacks = self.advance(&stacks, start);
+        }
+        required_next
+    }
+}
+
+/// The state of a [`GrammarParser`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrammarParserState {
+    stacks: Vec<Stack>,
+    text: String,
+    /// The bytes of a character that was split between inputs.
+    partial_char: Vec<u8>,
+}
+
+impl CreateParserState for GrammarParser {
+    fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+        let mut stacks = Vec::new();
+        for alternative in 0..self.rules[self.root].alternatives.len() {
+            let start = Position {
+                rule: self.root as u32,
+                alternative: alternative as u32,
+                element: 0,
+            };
+            self.expand(vec![start], &mut stacks);
+        }
+        GrammarParserState {
+            stacks,
+            text: String::new(),
+            partial_char: Vec::new(),
+        }
+    }
+}
+
+impl Parser for GrammarParser {
+    type Output = String;
+    type PartialState = GrammarParserState;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> crate::ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+        let mut state = state.clone();
+        for (index, &byte) in input.iter().enumerate() {
+            state.partial_char.push(byte);
+            let char = match std::str::from_utf8(&state.partial_char) {
+                Ok(text) => text.chars().next().unwrap(),
+                // Wait for the rest of the character
+                Err(err) if err.error_len().is_none() => continue,
+                Err(_) => crate::bail!("Invalid UTF-8 in the input"),
+            };
+            let next = self.advance(&state.stacks, char);
+            if next.is_empty() {
+                // The grammar may already be complete before this character
+                if state.stacks.iter().any(Vec::is_empty) {
+                    let char_start = (index + 1).saturating_sub(state.partial_char.len());
+                    return Ok(ParseStatus::Finished {
+                        result: state.text,
+                        remaining: &input[char_start..],
+                    });
+                }
+                crate::bail!("Unexpected character {:?} after {:?}", char, state.text);
+            }
+            state.text.push(char);
+            state.partial_char.clear();
+            state.stacks = next;
+
+            // Finish early if nothing else can follow
+            if state.stacks.iter().all(Vec::is_empty) {
+                return Ok(ParseStatus::Finished {
+                    result: state.text,
+                    remaining: &input[index + 1..],
+                });
+            }
+        }
+
+        let required_next = if state.partial_char.is_empty() {
+            self.required_next(&state.stacks)
+        } else {
+            String::new()
+        };
+        Ok(ParseStatus::Incomplete {
+            new_state: state,
+            required_next: required_next.into(),
+        })
+    }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        use std::hash::{Hash, Hasher};
+        // The stacks decide what text is accepted next unless we are in the middle of a character
+        if !state.partial_char.is_empty() {
+            return None;
+        }
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        state.stacks.hash(&mut hasher);
+        Some(hasher.finish())
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        Some(serde_json::Value::String(state.text.clone()))
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        Some(serde_json::Value::String(output.clone()))
+    }
+}
+
+/// Compiles the text of a grammar into rules. Groups and repetitions become generated rules.
+struct Compiler {
+    chars: Vec<char>,
+    position: usize,
+    rules: Vec<Rule>,
+    rule_ids: HashMap<String, usize>,
+    /// Where each rule was defined, or `None` if it is only referenced so far.
+    definitions: Vec<Option<usize>>,
+    /// Where each rule was first referenced.
+    references: Vec<usize>,
+}
+
+impl Compiler {
+    fn new(grammar: &str) -> Self {
+        Self {
+            chars: grammar.chars().collect(),
+            position: 0,
+            rules: Vec::new(),
+            rule_ids: HashMap::new(),
+            definitions: Vec::new(),
+            references: Vec::new(),
+        }
+    }
+
+    fn compile(mut self) -> Result<GrammarParser, GrammarError> {
+        loop {
+            self.skip_space();
+            if self.position >= self.chars.len() {
+                break;
+            }
+            let start = self.position;
+            let name = self.parse_name()?;
+            self.skip_space();
+            if !self.eat_str("::=") {
+                return Err(self.error(format!("Expected `::=` after the rule name `{name}`")));
+            }
+            let id = self.rule_id(&name, start);
+            if self.definitions[id].is_some() {
+                return Err(
+                    self.error_at(start, format!("Rule `{name}` is defined more than once"))
+                );
+            }
+            self.definitions[id] = Some(start);
+            let alternatives = self.parse_alternatives(&name, false)?;
+            self.rules[id].alternatives = alternatives;
+        }
+
+        if let Some(id) = self.definitions.iter().position(Option::is_none) {
+            return Err(self.error_at(
+                self.references[id],
+                format!("Rule `{}` is used but never defined", self.rules[id].name),
+            ));
+        }
+        let Some(&root) = self.rule_ids.get("root") else {
+            return Err(self.error_at(0, "The grammar must define a `root` rule".to_string()));
+        };
+        self.check_left_recursion()?;
+
+        Ok(GrammarParser {
+            rules: self.rules,
+            root,
+        })
+    }
+
+    fn error(&self, message: String) -> GrammarError {
+        self.error_at(self.position, message)
+    }
+
+    fn error_at(&self, position: usize, message: String) -> GrammarError {
+        let before = &self.chars[..position.min(self.chars.len())];
+        let line = before.iter().filter(|char| **char == '\n').count() + 1;
+        let column = before
+            .iter()
+            .rev()
+            .take_while(|char| **char != '\n')
+            .count()
+            + 1;
+        GrammarError {
+            message,
+            line,
+            column,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn eat(&mut self, char: char) -> bool {
+        let matches = self.peek() == Some(char);
+        if matches {
+            self.position += 1;
+        }
+        matches
+    }
+
+    fn eat_str(&mut self, text: &str) -> bool {
+        let matches = text
+            .chars()
+            .enumerate()
+            .all(|(i, char)| self.chars.get(self.position + i) == Some(&char));
+        if matches {
+            self.position += text.chars().count();
+        }
+        matches
+    }
+
+    fn next_char(&mut self, context: &str) -> Result<char, GrammarError> {
+        let char = self
+            .peek()
+            .ok_or_else(|| self.error(format!("Unexpected end of the grammar in {context}")))?;
+        self.position += 1;
+        Ok(char)
+    }
+
+    fn skip_space(&mut self) {
+        while let Some(char) = self.peek() {
+            if char == '#' {
+                while self.peek().is_some_and(|char| char != '\n') {
+                    self.position += 1;
+                }
+            } else if char.is_whitespace() {
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn is_name_char(char: char) -> bool {
+        char.is_ascii_alphanumeric() || char == '-' || char == '_'
+    }
+
+    fn parse_name(&mut self) -> Result<String, GrammarError> {
+        let start = self.position;
+        while self.peek().is_some_and(Self::is_name_char) {
+            self.position += 1;
+        }
+        if start == self.position {
+            return Err(match self.peek() {
+                Some(char) => self.error(format!("Expected a rule name, found `{char}`")),
+                None => self.error("Expected a rule name".to_string()),
+            });
+        }
+        Ok(self.chars[start..self.position].iter().collect())
+    }
+
+    /// Check if the next text starts a new rule definition.
+    fn at_rule_definition(&mut self) -> bool {
+        let start = self.position;
+        let is_definition = self.parse_name().is_ok() && {
+            self.skip_space();
+            self.eat_str("::=")
+        };
+        self.position = start;
+        is_definition
+    }
+
+    fn rule_id(&mut self, name: &str, position: usize) -> usize {
+        if let Some(&id) = self.rule_ids.get(name) {
+            return id;
+        }
+        let id = self.rules.len();
+        self.rules.push(Rule {
+            name: name.to_string(),
+            alternatives: Vec::new(),
+        });
+        self.definitions.push(None);
+        self.references.push(position);
+        self.rule_ids.insert(name.to_string(), id);
+        id
+    }
+
+    /// Create a rule for a group or repetition inside the rule `parent`.
+    fn generated_rule(&mut self, parent: &str, alternatives: Vec<Vec<Element>>) -> usize {
+        let id = self.rules.len();
+        self.rules.push(Rule {
+            name: format!("{parent}-{id}"),
+            alternatives,
+        });
+        self.definitions.push(Some(self.position));
+        self.references.push(self.position);
+        id
+    }
+
+    fn parse_alternatives(
+        &mut self,
+        rule: &str,
+        in_group: bool,
+    ) -> Result<Vec<Vec<Element>>, GrammarError> {
+        let mut alternatives = vec![self.parse_sequence(rule, in_group)?];
+        while self.eat('|') {
+            alternatives.push(self.parse_sequence(rule, in_group)?);
+        }
+        Ok(alternatives)
+    }
+
+    fn parse_sequence(&mut self, rule: &str, in_group: bool) -> Result<Vec<Element>, GrammarError> {
+        let mut sequence = Vec::new();
+        loop {
+            self.skip_space();
+            match self.peek() {
+                None | Some('|') => break,
+                Some(')') if in_group => break,
+                Some(')') => return Err(self.error("Unmatched `)`".to_string())),
+                // A new rule starts on the next line
+                _ if !in_group && self.at_rule_definition() => break,
+                _ => {}
+            }
+            let atom = self.parse_atom(rule)?;
+            let repeated = self.parse_repetition(rule, atom)?;
+            sequence.extend(repeated);
+        }
+        Ok(sequence)
+    }
+
+    fn parse_atom(&mut self, rule: &str) -> Result<Vec<Element>, GrammarError> {
+        let start = self.position;
+        match self.next_char("a rule")? {
+            '"' => {
+                let mut elements = Vec::new();
+                loop {
+                    match self.next_char("a string")? {
+                        '"' => break,
+                        '\\' => elements.push(Element::literal(self.parse_escape()?)),
+                        char => elements.push(Element::literal(char)),
+                    }
+                }
+                Ok(elements)
+            }
+            '[' => {
+                let negated = self.eat('^');
+                let mut ranges = Vec::new();
+                loop {
+                    let start = match self.next_char("a character class")? {
+                        ']' => break,
+                        '\\' => self.parse_escape()?,
+                        char => char,
+                    };
+                    let end = if self.peek() == Some('-')
+                        && self.chars.get(self.position + 1) != Some(&']')
+                    {
+                        self.position += 1;
+                        match self.next_char("a character class")? {
+                            '\\' => self.parse_escape()?,
+                            char => char,
+                        }
+                    } else {
+                        start
+                    };
+                    if end < start {
+                        return Err(self.error(format!(
+                            "The character range `{start}-{end}` is out of order"
+                        )));
+                    }
+                    ranges.push((start, end));
+                }
+                Ok(vec![Element::Chars { ranges, negated }])
+            }
+            '.' => Ok(vec![Element::Chars {
+                ranges: Vec::new(),
+                negated: true,
+            }]),
+            '(' => {
+                let mut alternatives = self.parse_alternatives(rule, true)?;
+                if !self.eat(')') {
+                    return Err(self.error_at(start, "Unclosed `(`".to_string()));
+                }
+                if alternatives.len() == 1 {
+                    Ok(alternatives.remove(0))
+                } else {
+                    Ok(vec![Element::Rule(self.generated_rule(rule, alternatives))])
+                }
+            }
+            char if Self::is_name_char(char) => {
+                self.position = start;
+                let name = self.parse_name()?;
+                Ok(vec![Element::Rule(self.rule_id(&name, start))])
+            }
+            char => Err(self.error_at(start, format!("Unexpected character `{char}`"))),
+        }
+    }
+
+    fn parse_escape(&mut self) -> Result<char, GrammarError> {
+        let start = self.position - 1;
+        let hex_digits = match self.next_char("an escape sequence")? {
+            'n' => return Ok('\n'),
+            'r' => return Ok('\r'),
+            't' => return Ok('\t'),
+            'x' => 2,
+            'u' => 4,
+            'U' => 8,
+            char @ ('\\' | '"' | '[' | ']' | '-' | '^' | '.') => return Ok(char),
+            char => return Err(self.error_at(start, format!("Unknown escape sequence `\\{char}`"))),
+        };
+        let mut value = 0;
+        for _ in 0..hex_digits {
+            let digit = self
+                .next_char("an escape sequence")?
+                .to_digit(16)
+                .ok_or_else(|| self.error_at(start, "Invalid hex escape sequence".to_string()))?;
+            value = value * 16 + digit;
+        }
+        char::from_u32(value)
+            .ok_or_else(|| self.error_at(start, format!("`{value:#x}` is not a valid character")))
+    }
+
+    fn parse_repetition(
+        &mut self,
+        rule: &str,
+        atom: Vec<Element>,
+    ) -> Result<Vec<Element>, GrammarError> {
+        let start = self.position;
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                self.position += 1;
+                let min = self.parse_count()?;
+                let max = if self.eat(',') {
+                    self.skip_space();
+                    if self.peek() == Some('}') {
+                        None
+                    } else {
+                        Some(self.parse_count()?)
+                    }
+                } else {
+                    Some(min)
+                };
+                self.skip_space();
+                if self.peek() != Some('}') {
+                    return Err(self.error_at(start, "Unclosed `{`".to_string()));
+                }
+                if max.is_some_and(|max| max < min) {
+                    return Err(self.error_at(
+                        start,
+                        "The maximum repetition count is smaller than the minimum".to_string(),
+                    ));
+                }
+                (min, max)
+            }
+            _ => return Ok(atom),
+        };
+        self.position += 1;
+
+        // Repeat a single element so every copy can share it
+        let element = match <[Element; 1]>::try_from(atom) {
+            Ok([element]) => element,
+            Err(atom) => Element::Rule(self.generated_rule(rule, vec![atom])),
+        };
+        let mut repeated = vec![element.clone(); min];
+        match max {
+            // element* becomes `repeat ::= element repeat | ""`
+            None => {
+                let id = self.generated_rule(rule, Vec::new());
+                self.rules[id].alternatives = vec![vec![element, Element::Rule(id)], Vec::new()];
+                repeated.push(Element::Rule(id));
+            }
+            // Each optional copy becomes `optional ::= element next_optional | ""`
+            Some(max) => {
+                let mut optional = None;
+                for _ in min..max {
+                    let mut alternative = vec![element.clone()];
+                    alternative.extend(optional.map(Element::Rule));
+                    optional = Some(self.generated_rule(rule, vec![alternative, Vec::new()]));
+                }
+                repeated.extend(optional.map(Element::Rule));
+            }
+        }
+        Ok(repeated)
+    }
+
+    fn parse_count(&mut self) -> Result<usize, GrammarError> {
+        self.skip_space();
+        let start = self.position;
+        while self.peek().is_some_and(|char| char.is_ascii_digit()) {
+            self.position += 1;
+        }
+        let digits: String = self.chars[start..self.position].iter().collect();
+        let count = digits
+            .parse()
+            .map_err(|_| self.error_at(start, "Expected a repetition count".to_string()))?;
+        self.skip_space();
+        Ok(count)
+    }
+
+    /// A rule that can reach itself without matching a character would make the parser loop forever.
+    fn check_left_recursion(&self) -> Result<(), GrammarError> {
+        let mut nullable = vec![false; self.rules.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (id, rule) in self.rules.iter().enumerate() {
+                if !nullable[id]
+                    && rule.alternatives.iter().any(|alternative| {
+                        alternative.iter().all(|element| match element {
+                            Element::Rule(rule) => nullable[*rule],
+                            Element::Chars { .. } => false,
+                        })
+                    })
+                {
+                    nullable[id] = true;
+                    changed = true;
+                }
+            }
+        }
+
+        // The rules each rule can start with
+        let first_rules: Vec<Vec<usize>> = self
+            .rules
+            .iter()
+            .map(|rule| {
+                let mut first = Vec::new();
+                for alternative in &rule.alternatives {
+                    for element in alternative {
+                        match element {
+                            Element::Rule(rule) => {
+                                first.push(*rule);
+                                if !nullable[*rule] {
+                                    break;
+                                }
+                            }
+                            Element::Chars { .. } => break,
+                        }
+                    }
+                }
+                first
+            })
+            .collect();
+
+        // Depth first search for a cycle: 0 is unvisited, 1 is in progress, 2 is done
+        fn visit(rule: usize, first_rules: &[Vec<usize>], marks: &mut [u8]) -> Option<usize> {
+            match marks[rule] {
+                1 => return Some(rule),
+                2 => return None,
+                _ => {}
+            }
+            marks[rule] = 1;
+            for &next in &first_rules[rule] {
+                if let Some(cycle) = visit(next, first_rules, marks) {
+                    return Some(cycle);
+                }
+            }
+            marks[rule] = 2;
+            None
+        }
+        let mut marks = vec![0; self.rules.len()];
+        for rule in 0..self.rules.len() {
+            if let Some(cycle) = visit(rule, &first_rules, &mut marks) {
+                return Err(self.error_at(
+                    self.definitions[cycle].unwrap_or_default(),
+                    format!(
+                        "Rule `{}` can reach itself without matching any text. Left recursion is not supported",
+                        self.rules[cycle].name
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn parse_recursive_grammar() {
+    let grammar = r#"
+        # A JSON like list of numbers
+        root   ::= list
+        list   ::= "[" ws (value ("," ws value)*)? "]"
+        value  ::= number | list
+        number ::= "-"? [0-9]{1,3}
+        ws     ::= [ \t\n]*
+    "#;
+    let parser = GrammarParser::new(grammar).unwrap();
+    let state = parser.create_parser_state();
+
+    let result = parser.parse(&state, b"[1, [-20,300], []]").unwrap();
+    assert_eq!(result.unwrap_finished(), "[1, [-20,300], []]");
+
+    assert!(parser.parse(&state, b"[1234]").is_err());
+    assert!(parser.parse(&state, b"[1,]").is_err());
+
+    let (state, required_next) = parser.parse(&state, b"[1").unwrap().unwrap_incomplete();
+    assert!(required_next.is_empty());
+    let result = parser.parse(&state, b"]").unwrap();
+    assert_eq!(result.unwrap_finished(), "[1]");
+}
+
+#[test]
+fn grammar_finishes_when_the_next_character_does_not_fit() {
+    let parser = GrammarParser::new(r#"root ::= "name: " [a-zA-Z]+"#).unwrap();
+    let state = parser.create_parser_state();
+
+    let (state, required_next) = parser.parse(&state, b"").unwrap().unwrap_incomplete();
+    assert_eq!(required_next, "name: ");
+
+    let (state, _) = parser
+        .parse(&state, b"name: Al")
+        .unwrap()
+        .unwrap_incomplete();
+    let result = parser.parse(&state, b"ice\n").unwrap();
+    assert_eq!(
+        result,
+        ParseStatus::Finished {
+            result: "name: Alice".to_string(),
+            remaining: b"\n",
+        }
+    );
+}
+
+#[test]
+fn grammar_handles_unicode() {
+    let parser = GrammarParser::new(r#"root ::= [^"]* "é""#).unwrap();
+    let state = parser.create_parser_state();
+    let text = "caf\u{e9}".as_bytes();
+    // Split the input in the middle of the last character
+    let (state, _) = parser
+        .parse(&state, &text[..text.len() - 1])
+        .unwrap()
+        .unwrap_incomplete();
+    let result = parser.parse(&state, &text[text.len() - 1..]).unwrap();
+    assert!(matches!(result, ParseStatus::Incomplete { .. }));
+}
+
+#[test]
+fn bad_grammars_have_clear_errors() {
+    let error = GrammarParser::new("root ::= item").unwrap_err();
+    assert_eq!(error.message(), "Rule `item` is used but never defined");
+    assert_eq!((error.line(), error.column()), (1, 10));
+
+    let error = GrammarParser::new("item ::= \"a\"").unwrap_err();
+    assert_eq!(error.message(), "The grammar must define a `root` rule");
+
+    let error = GrammarParser::new("root ::= \"a\"\nroot ::= \"b\"").unwrap_err();
+    assert_eq!(error.message(), "Rule `root` is defined more than once");
+    assert_eq!(error.line(), 2);
+
+    let error = GrammarParser::new("root ::= root \"a\" | \"b\"").unwrap_err();
+    assert!(error.message().contains("Left recursion"));
+
+    let error = GrammarParser::new("root ::= \"unterminated").unwrap_err();
+    assert_eq!(error.message(), "Unexpected end of the grammar in a string");
+
+    let error = GrammarParser::new("root ::= (\"a\" | \"b\"").unwrap_err();
+    assert_eq!(error.message(), "Unclosed `(`");
+}
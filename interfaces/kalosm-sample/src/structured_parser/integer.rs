@@ -52,64 +52,39 @@ impl IntegerParser {
 
     fn could_number_become_valid(&self, value: i128) -> bool {
         if self.is_number_valid(value) {
-            true
-        } else {
-            let start_value = *self.range.start();
-            let end_value = *self.range.end();
-            let positive = value >= 0;
-            // Check if adding a digit would make the number invalid
-            if positive {
-                if value * 10 > end_value {
-                    return false;
-                }
-            } else if value * 10 < start_value {
+            return true;
+        }
+        // A leading zero can't be followed by more digits
+        if value == 0 {
+            return false;
+        }
+        // After k more digits, the number is in [value * 10^k, value * 10^k + 10^k - 1] for positive numbers or the mirrored range for negative numbers
+        let magnitude = value.abs();
+        let mut scale: i128 = 10;
+        while let Some(low) = magnitude.checked_mul(scale) {
+            let high = low + scale - 1;
+            let (low, high) = if value > 0 {
+                (low, high)
+            } else {
+                (-high, -low)
+            };
+            // Adding more digits only moves the number further from the range
+            if (value > 0 && low > *self.range.end()) || (value < 0 && high < *self.range.start()) {
                 return false;
             }
-
-            // Check if the digits are within the range so far
-            let digits = value.abs().checked_ilog10().map(|x| x + 1).unwrap_or(1);
-            let start_digits = start_value
-                .abs()
-                .checked_ilog10()
-                .map(|x| x + 1)
-                .unwrap_or(1);
-            let end_digits = end_value.abs().checked_ilog10().map(|x| x + 1).unwrap_or(1);
-            let mut check_end = true;
-            let mut check_start = true;
-            for digit in 1..(digits + 1) {
-                let selected_digit = value / (10_i128.pow(digits - digit)) % 10;
-                let selected_start_digit = start_value / (10_i128.pow(start_digits - digit)) % 10;
-                let selected_end_digit = end_value / (10_i128.pow(end_digits - digit)) % 10;
-
-                if check_start {
-                    match selected_digit.cmp(&selected_start_digit) {
-                        std::cmp::Ordering::Greater => {
-                            check_start = false;
-                        }
-                        std::cmp::Ordering::Less => {
-                            return false;
-                        }
-                        std::cmp::Ordering::Equal => {}
-                    }
-                }
-                if check_end {
-                    match selected_digit.cmp(&selected_end_digit) {
-                        std::cmp::Ordering::Greater => {
-                            return false;
-                        }
-                        std::cmp::Ordering::Less => {
-                            check_end = false;
-                        }
-                        std::cmp::Ordering::Equal => {}
-                    }
-                }
+            if low <= *self.range.end() && high >= *self.range.start() {
+                return true;
+            }
+            match scale.checked_mul(10) {
+                Some(next) => scale = next,
+                None => return false,
             }
-            true
         }
+        false
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Default)]
 enum IntegerParserProgress {
     #[default]
     Initial,
@@ -194,12 +169,19 @@ impl Parser for IntegerParser {
                 }
             };
 
-            state = IntegerParserProgress::AfterDigit;
-            match value.checked_mul(10) {
-                Some(v) => value = v + u64::from(digit),
+            let next_value = value
+                .checked_mul(10)
+                .map(|value| value + u64::from(digit))
+                .filter(|next_value| {
+                    let signed_value = *next_value as i128 * if positive { 1 } else { -1 };
+                    self.could_number_become_valid(signed_value)
+                });
+            match next_value {
+                Some(next_value) => value = next_value,
+                // If the digit would take the number out of the range, the number ends before the digit
                 None => {
                     let signed_value = value as i128 * if positive { 1 } else { -1 };
-                    if self.is_number_valid(signed_value) {
+                    if state.is_after_digit() && self.is_number_valid(signed_value) {
                         return Ok(ParseStatus::Finished {
                             result: signed_value,
                             remaining: &input[index..],
@@ -208,17 +190,11 @@ impl Parser for IntegerParser {
                     bail!(OutOfRangeError)
                 }
             }
+            state = IntegerParserProgress::AfterDigit;
 
             let signed_value = value as i128 * if positive { 1 } else { -1 };
 
             if self.should_stop(signed_value) {
-                return Ok(ParseStatus::Finished {
-                    result: signed_value,
-                    remaining: &input[index + 1..],
-                });
-            }
-
-            if !self.could_number_become_valid(signed_value) {
                 if self.is_number_valid(signed_value) {
                     return Ok(ParseStatus::Finished {
                         result: signed_value,
@@ -238,6 +214,35 @@ impl Parser for IntegerParser {
             required_next: Default::default(),
         })
     }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        // The range is checked against the digits so far, so the whole state matters
+        Some(crate::hash_state_key((
+            state.state,
+            state.value,
+            state.positive,
+        )))
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        if !state.state.is_after_digit() {
+            return None;
+        }
+        let value = state.value as i128;
+        integer_value(if state.positive { value } else { -value })
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        integer_value(*output)
+    }
+}
+
+/// Convert an integer to a JSON number if it fits in one.
+fn integer_value(value: i128) -> Option<serde_json::Value> {
+    match i64::try_from(value) {
+        Ok(value) => Some(value.into()),
+        Err(_) => u64::try_from(value).ok().map(Into::into),
+    }
 }
 
 #[test]
@@ -0,0 +1,735 @@
+use anyhow::{anyhow, bail};
+use serde_json::{Map, Value};
+
+use regex_syntax::hir::{Class, ClassBytes, ClassBytesRange, ClassUnicode, ClassUnicodeRange};
+use regex_syntax::hir::{Hir, HirKind, Look};
+
+use crate::{
+    AnyOfSchema, ArcParser, ArraySchema, BooleanSchema, ConstSchema, EnumSchema, FloatParser,
+    IntegerParser, IntegerSchema, JsonObjectSchema, JsonPropertySchema, LiteralParser,
+    NumberSchema, ParserExt, RegexParser, SchemaLiteral, SchemaType, SeparatedParser, StringParser,
+    StringSchema, TupleSchema,
+};
+
+/// The keywords [`SchemaType::from_json_schema`] turns into a parser.
+const SUPPORTED_KEYWORDS: &[&str] = &[
+    "type",
+    "enum",
+    "const",
+    "anyOf",
+    "properties",
+    "additionalProperties",
+    "required",
+    "items",
+    "prefixItems",
+    "minItems",
+    "maxItems",
+    "uniqueItems",
+    "minLength",
+    "maxLength",
+    "pattern",
+    "format",
+    "minimum",
+    "maximum",
+];
+
+/// Keywords that only describe the schema and don't change which values match it.
+const ANNOTATION_KEYWORDS: &[&str] = &[
+    "title",
+    "description",
+    "default",
+    "examples",
+    "deprecated",
+    "readOnly",
+    "writeOnly",
+    "$schema",
+    "$id",
+    "$comment",
+    "$defs",
+    "definitions",
+];
+
+impl SchemaType {
+    /// Read a schema from a JSON schema.
+    ///
+    /// This supports the subset of JSON schema that can be turned into a parser: `type`, `enum`, `const`, `anyOf`, `properties`, `additionalProperties`, `required`, `items`, `prefixItems`, `minItems`, `maxItems`, `uniqueItems`, `minLength`, `maxLength`, `pattern`, `format`, `minimum` and `maximum`. Schemas that use any other keyword that limits which values match (like `oneOf`, `exclusiveMinimum` or `multipleOf`) return an error instead of ignoring it.
+    pub fn from_json_schema(schema: &Value) -> anyhow::Result<Self> {
+        let schema = match schema {
+            Value::Object(schema) => schema,
+            // `true` accepts anything, but we can't build a parser for any JSON value
+            _ => bail!("Expected the JSON schema to be an object, found {schema}"),
+        };
+        if schema.contains_key("$ref") {
+            bail!("References in JSON schemas are not supported");
+        }
+        if let Some(keyword) = schema.keys().find(|keyword| {
+            !SUPPORTED_KEYWORDS.contains(&keyword.as_str())
+                && !ANNOTATION_KEYWORDS.contains(&keyword.as_str())
+        }) {
+            bail!("The JSON schema keyword `{keyword}` is not supported");
+        }
+
+        if let Some(value) = schema.get("const") {
+            return Ok(SchemaType::Const(ConstSchema::new(schema_literal(value)?)));
+        }
+        if let Some(variants) = schema.get("enum") {
+            let variants = variants
+                .as_array()
+                .ok_or_else(|| anyhow!("Expected `enum` to be an array"))?
+                .iter()
+                .map(schema_literal)
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            return Ok(SchemaType::Enum(EnumSchema::new(variants)));
+        }
+        if let Some(variants) = schema.get("anyOf") {
+            return Ok(SchemaType::AnyOf(AnyOfSchema::new(schema_list(
+                variants, "anyOf",
+            )?)));
+        }
+
+        match schema.get("type") {
+            Some(Value::String(ty)) => schema_for_type(ty, schema),
+            Some(Value::Array(types)) => Ok(SchemaType::AnyOf(AnyOfSchema::new(
+                types
+                    .iter()
+                    .map(|ty| {
+                        let ty = ty
+                            .as_str()
+                            .ok_or_else(|| anyhow!("Expected `type` to be a string, found {ty}"))?;
+                        schema_for_type(ty, schema)
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?,
+            ))),
+            Some(ty) => bail!("Expected `type` to be a string, found {ty}"),
+            // Schemas with properties are objects even if the type is missing
+            None if schema.contains_key("properties") => schema_for_type("object", schema),
+            None => bail!("JSON schemas without a `type` are not supported"),
+        }
+    }
+
+    /// Create a parser that only accepts JSON that matches the schema. The parser outputs the JSON value it parsed.
+    ///
+    /// The JSON uses the same format as the parsers created by `#[derive(Parse)]`: `{ "key": value, "other": value }` and `[value, value]`. Properties are parsed in the order they appear in the schema and properties that are not required may be left out.
+    ///
+    /// ```rust
+    /// use kalosm_sample::*;
+    /// use serde_json::json;
+    ///
+    /// let schema = SchemaType::from_json_schema(&json!({
+    ///     "type": "object",
+    ///     "properties": {
+    ///         "name": { "type": "string" },
+    ///         "age": { "type": "integer", "minimum": 0 }
+    ///     },
+    ///     "required": ["name"]
+    /// }))
+    /// .unwrap();
+    /// let parser = schema.to_parser().unwrap();
+    /// let state = parser.create_parser_state();
+    /// let result = parser.parse(&state, br#"{ "name": "Alice" }"#).unwrap();
+    /// assert_eq!(result.unwrap_finished(), json!({ "name": "Alice" }));
+    /// ```
+    pub fn to_parser(&self) -> anyhow::Result<ArcParser<Value>> {
+        match self {
+            SchemaType::String(schema) => string_parser(schema),
+            SchemaType::Number(schema) => {
+                let range = schema.range.clone().unwrap_or(f64::MIN..=f64::MAX);
+                Ok(FloatParser::new(range)
+                    .map_output(|number| {
+                        serde_json::Number::from_f64(number).map_or(Value::Null, Value::Number)
+                    })
+                    .boxed())
+            }
+            SchemaType::Integer(_) => Ok(IntegerParser::new(i64::MIN as i128..=i64::MAX as i128)
+                .map_output(integer_value)
+                .boxed()),
+            SchemaType::BoundedInteger(schema) => Ok(IntegerParser::new(schema.range.clone())
+                .map_output(integer_value)
+                .boxed()),
+            SchemaType::Boolean(_) => choice([
+                literal_parser(Value::Bool(true)),
+                literal_parser(Value::Bool(false)),
+            ]),
+            SchemaType::Array(schema) => {
+                let length = schema.length.clone().unwrap_or(0..=usize::MAX);
+                Ok(LiteralParser::new("[")
+                    .ignore_output_then(SeparatedParser::new(
+                        schema.items.to_parser()?,
+                        LiteralParser::new(", "),
+                        length,
+                    ))
+                    .then_literal("]")
+                    .map_output(Value::Array)
+                    .boxed())
+            }
+            SchemaType::Tuple(schema) => tuple_parser(schema),
+            SchemaType::Object(schema) => object_parser(schema),
+            SchemaType::Enum(schema) => choice(
+                schema
+                    .variants
+                    .iter()
+                    .map(|variant| literal_parser(literal_value(variant))),
+            ),
+            SchemaType::AnyOf(schema) => choice(schema.any_of.iter().map(SchemaType::to_parser)),
+            SchemaType::OneOf(schema) => choice(schema.one_of.iter().map(SchemaType::to_parser)),
+            SchemaType::Const(schema) => literal_parser(literal_value(&schema.value)),
+            SchemaType::IfThen(_) => bail!("Conditional schemas can't be turned into a parser"),
+            SchemaType::Null => literal_parser(Value::Null),
+        }
+    }
+}
+
+fn schema_for_type(ty: &str, schema: &Map<String, Value>) -> anyhow::Result<SchemaType> {
+    match ty {
+        "null" => Ok(SchemaType::Null),
+        "boolean" => Ok(SchemaType::Boolean(BooleanSchema)),
+        "string" => {
+            let length = length_range(schema, "minLength", "maxLength")?;
+            let mut string = StringSchema::new().with_length(length.clone());
+            if let Some(pattern) = schema.get("pattern") {
+                let pattern = pattern
+                    .as_str()
+                    .ok_or_else(|| anyhow!("Expected `pattern` to be a string"))?;
+                // The parser for a pattern can't count characters
+                if length.is_some() {
+                    bail!("`minLength` and `maxLength` can't be combined with `pattern`");
+                }
+                string = string.with_pattern(pattern);
+            }
+            if let Some(format) = schema.get("format").and_then(Value::as_str) {
+                string = string.with_format(format);
+            }
+            Ok(SchemaType::String(string))
+        }
+        "number" => {
+            let number = |keyword: &str| {
+                schema
+                    .get(keyword)
+                    .map(|value| {
+                        value
+                            .as_f64()
+                            .ok_or_else(|| anyhow!("Expected `{keyword}` to be a number"))
+                    })
+                    .transpose()
+            };
+            let min = number("minimum")?;
+            let max = number("maximum")?;
+            let range = (min.is_some() || max.is_some())
+                .then(|| min.unwrap_or(f64::MIN)..=max.unwrap_or(f64::MAX));
+            Ok(SchemaType::Number(NumberSchema::new().with_range(range)))
+        }
+        "integer" => {
+            let integer = |keyword: &str| {
+                schema
+                    .get(keyword)
+                    .map(|value| {
+                        value
+                            .as_i64()
+                            .map(i128::from)
+                            .or_else(|| value.as_u64().map(i128::from))
+                            .ok_or_else(|| {
+                                anyhow!("Expected `{keyword}` of an integer schema to be an integer, found {value}")
+                            })
+                    })
+                    .transpose()
+            };
+            let min = integer("minimum")?;
+            let max = integer("maximum")?;
+            if min.is_none() && max.is_none() {
+                return Ok(SchemaType::Integer(IntegerSchema::new()));
+            }
+            Ok(SchemaType::BoundedInteger(IntegerSchema::new().with_range(
+                min.unwrap_or(i64::MIN as i128)..=max.unwrap_or(i64::MAX as i128),
+            )))
+        }
+        "array" => {
+            if let Some(items) = schema.get("prefixItems") {
+                return Ok(SchemaType::Tuple(TupleSchema::new(schema_list(
+                    items,
+                    "prefixItems",
+                )?)));
+            }
+            let items = match schema.get("items") {
+                Some(items) => SchemaType::from_json_schema(items)?,
+                None => bail!("Arrays without `items` are not supported"),
+            };
+            let unique_items = schema
+                .get("uniqueItems")
+                .and_then(Value::as_bool)
+                .unwrap_or_default();
+            Ok(SchemaType::Array(
+                ArraySchema::new(items)
+                    .with_length(length_range(schema, "minItems", "maxItems")?)
+                    .with_unique_items(unique_items),
+            ))
+        }
+        "object" => {
+            let required = match schema.get("required") {
+                Some(Value::Array(required)) => required.iter().filter_map(Value::as_str).collect(),
+                Some(required) => bail!("Expected `required` to be an array, found {required}"),
+                None => Vec::new(),
+            };
+            let properties = match schema.get("properties") {
+                Some(Value::Object(properties)) => properties
+                    .iter()
+                    .map(|(name, property)| {
+                        Ok(
+                            JsonPropertySchema::new(name, SchemaType::from_json_schema(property)?)
+                                .with_required(required.contains(&name.as_str())),
+                        )
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?,
+                Some(properties) => {
+                    bail!("Expected `properties` to be an object, found {properties}")
+                }
+                None => Vec::new(),
+            };
+            let mut object = JsonObjectSchema::new(properties);
+            if let Some(title) = schema.get("title").and_then(Value::as_str) {
+                object = object.with_title(title);
+            }
+            // `additionalProperties` may also be a boolean. Only schemas describe which values a parser should generate
+            if let Some(additional @ Value::Object(_)) = schema.get("additionalProperties") {
+                object =
+                    object.with_additional_properties(SchemaType::from_json_schema(additional)?);
+            }
+            Ok(SchemaType::Object(object))
+        }
+        _ => bail!("Unsupported JSON schema type: {ty}"),
+    }
+}
+
+fn schema_list(schemas: &Value, keyword: &str) -> anyhow::Result<Vec<SchemaType>> {
+    schemas
+        .as_array()
+        .ok_or_else(|| anyhow!("Expected `{keyword}` to be an array"))?
+        .iter()
+        .map(SchemaType::from_json_schema)
+        .collect()
+}
+
+fn length_range(
+    schema: &Map<String, Value>,
+    min_keyword: &str,
+    max_keyword: &str,
+) -> anyhow::Result<Option<std::ops::RangeInclusive<usize>>> {
+    let min = usize_keyword(schema, min_keyword)?;
+    let max = usize_keyword(schema, max_keyword)?;
+    Ok((min.is_some() || max.is_some()).then(|| min.unwrap_or(0)..=max.unwrap_or(usize::MAX)))
+}
+
+fn usize_keyword(schema: &Map<String, Value>, keyword: &str) -> anyhow::Result<Option<usize>> {
+    schema
+        .get(keyword)
+        .map(|value| {
+            value
+                .as_u64()
+                .map(|value| value as usize)
+                .ok_or_else(|| anyhow!("Expected `{keyword}` to be a positive integer"))
+        })
+        .transpose()
+}
+
+fn schema_literal(value: &Value) -> anyhow::Result<SchemaLiteral> {
+    match value {
+        Value::String(string) => Ok(SchemaLiteral::String(string.clone())),
+        Value::Number(number) => Ok(SchemaLiteral::Number(
+            number
+                .as_f64()
+                .ok_or_else(|| anyhow!("Unsupported number {number}"))?,
+        )),
+        Value::Bool(boolean) => Ok(SchemaLiteral::Boolean(*boolean)),
+        Value::Null => Ok(SchemaLiteral::Null),
+        _ => bail!("Only strings, numbers, booleans and null are supported in `const` and `enum`, found {value}"),
+    }
+}
+
+fn literal_value(literal: &SchemaLiteral) -> Value {
+    match literal {
+        SchemaLiteral::String(string) => Value::String(string.clone()),
+        // Whole numbers are written without a decimal point
+        SchemaLiteral::Number(number)
+            if number.fract() == 0.0 && number.abs() < (1u64 << 53) as f64 =>
+        {
+            Value::from(*number as i64)
+        }
+        SchemaLiteral::Number(number) => {
+            serde_json::Number::from_f64(*number).map_or(Value::Null, Value::Number)
+        }
+        SchemaLiteral::Boolean(boolean) => Value::Bool(*boolean),
+        SchemaLiteral::Null => Value::Null,
+    }
+}
+
+fn integer_value(integer: i128) -> Value {
+    i64::try_from(integer)
+        .map(Value::from)
+        .or_else(|_| u64::try_from(integer).map(Value::from))
+        .unwrap_or_else(|_| Value::from(integer as f64))
+}
+
+fn string_parser(schema: &StringSchema) -> anyhow::Result<ArcParser<Value>> {
+    if let Some(pattern) = &schema.pattern {
+        let json_pattern = json_string_pattern(pattern)?;
+        let parser = RegexParser::new(&format!("\"(?:{json_pattern})\""))
+            .map_err(|err| anyhow!("Invalid pattern {pattern}: {err}"))?;
+        return Ok(parser
+            .map_output(|text| Value::String(text[1..text.len() - 1].to_string()))
+            .boxed());
+    }
+    let length = schema.length.clone().unwrap_or(0..=usize::MAX);
+    Ok(StringParser::new(length).map_output(Value::String).boxed())
+}
+
+/// Rewrite a pattern so it only matches text that can be written inside a JSON string without escapes. Characters that would need to be escaped are removed from every character class, and patterns that require one of those characters are rejected.
+fn json_string_pattern(pattern: &str) -> anyhow::Result<String> {
+    let hir = regex_syntax::Parser::new()
+        .parse(pattern)
+        .map_err(|err| anyhow!("Invalid pattern {pattern}: {err}"))?;
+    Ok(without_escaped_characters(hir, pattern)?.to_string())
+}
+
+fn without_escaped_characters(hir: Hir, pattern: &str) -> anyhow::Result<Hir> {
+    let escaped = |byte: u8| byte == b'"' || byte == b'\\' || byte < 0x20;
+    Ok(match hir.into_kind() {
+        HirKind::Literal(literal) => {
+            if literal.0.iter().any(|byte| escaped(*byte)) {
+                bail!("The pattern {pattern} matches characters that must be escaped in JSON strings, which is not supported");
+            }
+            Hir::literal(literal.0)
+        }
+        HirKind::Class(Class::Unicode(mut class)) => {
+            class.difference(&ClassUnicode::new([
+                ClassUnicodeRange::new('\0', '\x1f'),
+                ClassUnicodeRange::new('"', '"'),
+                ClassUnicodeRange::new('\\', '\\'),
+            ]));
+            Hir::class(Class::Unicode(class))
+        }
+        HirKind::Class(Class::Bytes(mut class)) => {
+            class.difference(&ClassBytes::new([
+                ClassBytesRange::new(0, 0x1f),
+                ClassBytesRange::new(b'"', b'"'),
+                ClassBytesRange::new(b'\\', b'\\'),
+            ]));
+            Hir::class(Class::Bytes(class))
+        }
+        // The pattern is matched against the whole string, so anchors don't change anything
+        HirKind::Look(
+            Look::Start | Look::End | Look::StartLF | Look::EndLF | Look::StartCRLF | Look::EndCRLF,
+        ) => Hir::empty(),
+        HirKind::Repetition(mut repetition) => {
+            repetition.sub = Box::new(without_escaped_characters(*repetition.sub, pattern)?);
+            Hir::repetition(repetition)
+        }
+        HirKind::Capture(mut capture) => {
+            capture.sub = Box::new(without_escaped_characters(*capture.sub, pattern)?);
+            Hir::capture(capture)
+        }
+        HirKind::Concat(items) => Hir::concat(
+            items
+                .into_iter()
+                .map(|item| without_escaped_characters(item, pattern))
+                .collect::<anyhow::Result<_>>()?,
+        ),
+        HirKind::Alternation(items) => Hir::alternation(
+            items
+                .into_iter()
+                .map(|item| without_escaped_characters(item, pattern))
+                .collect::<anyhow::Result<_>>()?,
+        ),
+        HirKind::Look(look) => Hir::look(look),
+        HirKind::Empty => Hir::empty(),
+    })
+}
+
+fn tuple_parser(schema: &TupleSchema) -> anyhow::Result<ArcParser<Value>> {
+    let mut items = schema.items.iter();
+    let Some(first) = items.next() else {
+        return literal_parser(Value::Array(Vec::new()));
+    };
+    let mut parser = LiteralParser::new("[")
+        .ignore_output_then(first.to_parser()?)
+        .map_output(|item| vec![item])
+        .boxed();
+    for item in items {
+        parser = parser
+            .then_literal(", ")
+            .then(item.to_parser()?)
+            .map_output(|(mut items, item)| {
+                items.push(item);
+                items
+            })
+            .boxed();
+    }
+    Ok(parser.then_literal("]").map_output(Value::Array).boxed())
+}
+
+fn object_parser(schema: &JsonObjectSchema) -> anyhow::Result<ArcParser<Value>> {
+    type Properties = ArcParser<Vec<(String, Value)>>;
+
+    // Objects with only additional properties are maps with any keys
+    if let (true, Some(additional_properties)) =
+        (schema.properties.is_empty(), &schema.additional_properties)
+    {
+        return Ok(crate::map_parser(additional_properties.to_parser()?, ": ")
+            .map_output(|entries| Value::Object(entries.into_iter().collect()))
+            .boxed());
+    }
+
+    // Build the parser from the last property to the first. Each property has two parsers: one for after another property was written, which starts with a comma, and one for before any property was written
+    let mut after_first: Properties = LiteralParser::new(" }").map_output(|_| Vec::new()).boxed();
+    let mut before_first: Properties = LiteralParser::new("}").map_output(|_| Vec::new()).boxed();
+    for property in schema.properties.iter().rev() {
+        let value = property.ty.to_parser()?;
+        let key = serde_json::to_string(&property.name)?;
+        let with_property = |separator: &str| -> Properties {
+            let name = property.name.clone();
+            LiteralParser::new(format!("{separator}{key}: "))
+                .ignore_output_then(value.clone())
+                .then(after_first.clone())
+                .map_output(move |(value, mut rest)| {
+                    rest.insert(0, (name.clone(), value));
+                    rest
+                })
+                .boxed()
+        };
+        let next_after_first = with_property(", ");
+        let next_before_first = with_property(" ");
+        if property.required {
+            after_first = next_after_first;
+            before_first = next_before_first;
+        } else {
+            after_first = next_after_first.or(after_first).boxed();
+            before_first = next_before_first.or(before_first).boxed();
+        }
+    }
+
+    Ok(LiteralParser::new("{")
+        .ignore_output_then(before_first)
+        .map_output(|properties| Value::Object(properties.into_iter().collect()))
+        .boxed())
+}
+
+fn literal_parser(value: Value) -> anyhow::Result<ArcParser<Value>> {
+    let text = serde_json::to_string(&value)?;
+    Ok(LiteralParser::new(text)
+        .map_output(move |_| value.clone())
+        .boxed())
+}
+
+fn choice(
+    parsers: impl IntoIterator<Item = anyhow::Result<ArcParser<Value>>>,
+) -> anyhow::Result<ArcParser<Value>> {
+    let mut parsers = parsers.into_iter();
+    let mut parser = parsers
+        .next()
+        .ok_or_else(|| anyhow!("Expected at least one option in the schema"))??;
+    for next in parsers {
+        parser = parser.or(next?).boxed();
+    }
+    Ok(parser)
+}
+
+#[cfg(test)]
+fn parse_json(schema: Value, input: &str) -> Option<Value> {
+    use crate::{CreateParserState, ParseStatus, Parser};
+
+    let parser = SchemaType::from_json_schema(&schema)
+        .unwrap()
+        .to_parser()
+        .unwrap();
+    let state = parser.create_parser_state();
+    match parser.parse(&state, input.as_bytes()) {
+        Ok(ParseStatus::Finished {
+            result,
+            remaining: [],
+        }) => Some(result),
+        _ => None,
+    }
+}
+
+#[test]
+fn json_object_schema() {
+    use serde_json::json;
+
+    let schema = json!({
+        "type": "object",
+        "properties": {
+            "name": { "type": "string" },
+            "age": { "type": "integer", "minimum": 0, "maximum": 150 },
+            "tags": { "type": "array", "items": { "type": "string" }, "maxItems": 2 }
+        },
+        "required": ["name", "age", "tags"]
+    });
+    assert_eq!(
+        parse_json(
+            schema.clone(),
+            r#"{ "name": "John", "age": 30, "tags": ["a", "b"] }"#
+        ),
+        Some(json!({ "name": "John", "age": 30, "tags": ["a", "b"] }))
+    );
+    assert_eq!(parse_json(schema.clone(), r#"{ "age": 30 }"#), None);
+    assert_eq!(
+        parse_json(
+            schema,
+            r#"{ "name": "John", "age": 30, "tags": ["a", "b", "c"] }"#
+        ),
+        None
+    );
+}
+
+#[test]
+fn json_optional_properties() {
+    use serde_json::json;
+
+    let schema = json!({
+        "type": "object",
+        "properties": {
+            "first": { "type": "boolean" },
+            "second": { "type": "null" },
+            "third": { "type": "number", "minimum": 0 }
+        },
+        "required": ["second"]
+    });
+    assert_eq!(
+        parse_json(schema.clone(), r#"{ "second": null }"#),
+        Some(json!({ "second": null }))
+    );
+    assert_eq!(
+        parse_json(
+            schema.clone(),
+            r#"{ "first": true, "second": null, "third": 1.5 }"#
+        ),
+        Some(json!({ "first": true, "second": null, "third": 1.5 }))
+    );
+    assert_eq!(parse_json(schema, r#"{ "first": true }"#), None);
+
+    let schema = json!({ "type": "object", "properties": { "a": { "type": "boolean" } } });
+    assert_eq!(parse_json(schema, "{}"), Some(json!({})));
+}
+
+#[test]
+fn json_enum_schema() {
+    use serde_json::json;
+
+    let schema = json!({ "enum": ["small", "large", 1] });
+    assert_eq!(
+        parse_json(schema.clone(), r#""small""#),
+        Some(json!("small"))
+    );
+    assert_eq!(parse_json(schema.clone(), "1"), Some(json!(1)));
+    assert_eq!(parse_json(schema, r#""medium""#), None);
+}
+
+#[test]
+fn json_any_of_schema() {
+    use serde_json::json;
+
+    let schema = json!({ "type": ["boolean", "null"] });
+    assert_eq!(parse_json(schema.clone(), "true"), Some(json!(true)));
+    assert_eq!(parse_json(schema, "null"), Some(json!(null)));
+
+    let schema = json!({
+        "anyOf": [
+            { "type": "string", "pattern": "^[0-9]{3}$" },
+            { "type": "integer" }
+        ]
+    });
+    assert_eq!(parse_json(schema.clone(), r#""123""#), Some(json!("123")));
+    assert_eq!(parse_json(schema, r#""12a""#), None);
+}
+
+#[test]
+fn json_tuple_and_map_schemas() {
+    use serde_json::json;
+
+    let schema = json!({
+        "type": "array",
+        "prefixItems": [{ "type": "string" }, { "type": "boolean" }]
+    });
+    assert_eq!(
+        parse_json(schema.clone(), r#"["a", true]"#),
+        Some(json!(["a", true]))
+    );
+    assert_eq!(parse_json(schema, r#"["a"]"#), None);
+
+    let schema = json!({
+        "type": "object",
+        "additionalProperties": { "type": "integer" }
+    });
+    assert_eq!(
+        parse_json(schema.clone(), r#"{ "a": 1, "b": 2 }"#),
+        Some(json!({ "a": 1, "b": 2 }))
+    );
+    assert_eq!(parse_json(schema, "{}"), Some(json!({})));
+}
+
+#[test]
+fn unsupported_json_schemas() {
+    use serde_json::json;
+
+    assert!(SchemaType::from_json_schema(&json!({ "$ref": "#/definitions/a" })).is_err());
+    assert!(SchemaType::from_json_schema(&json!({ "type": "array" })).is_err());
+    assert!(SchemaType::from_json_schema(&json!(true)).is_err());
+    // Keywords that limit the values can't be ignored
+    for schema in [
+        json!({ "oneOf": [{ "type": "integer" }, { "type": "number" }] }),
+        json!({ "type": "integer", "exclusiveMinimum": 0 }),
+        json!({ "type": "number", "exclusiveMaximum": 1 }),
+        json!({ "type": "number", "multipleOf": 2 }),
+        json!({ "type": "integer", "minimum": 0.5 }),
+        json!({ "type": "integer", "maximum": "10" }),
+        json!({ "type": "string", "pattern": "[a-z]+", "maxLength": 4 }),
+        json!({ "type": "string", "pattern": "a\"b" }),
+        json!({ "type": "string", "pattern": "a\nb" }),
+    ] {
+        assert!(
+            SchemaType::from_json_schema(&schema)
+                .and_then(|schema| schema.to_parser())
+                .is_err(),
+            "{schema} should not be supported"
+        );
+    }
+    // Annotations are fine
+    assert!(SchemaType::from_json_schema(
+        &json!({ "type": "string", "title": "Name", "description": "A name", "examples": ["Ada"] })
+    )
+    .is_ok());
+}
+
+#[test]
+fn json_integer_ranges() {
+    use serde_json::json;
+
+    let schema = json!({ "type": "integer", "minimum": 1, "maximum": 10 });
+    assert_eq!(parse_json(schema.clone(), "10"), Some(json!(10)));
+    assert_eq!(parse_json(schema.clone(), "11"), None);
+    assert_eq!(parse_json(schema, "0"), None);
+
+    let schema = json!({ "type": "array", "items": { "type": "integer", "minimum": 5 } });
+    assert_eq!(
+        parse_json(schema.clone(), "[123456, 5]"),
+        Some(json!([123456, 5]))
+    );
+    assert_eq!(parse_json(schema, "[4]"), None);
+}
+
+#[test]
+fn json_patterns_stay_valid_json() {
+    use serde_json::json;
+
+    let schema = json!({ "type": "string", "pattern": ".*" });
+    assert_eq!(
+        parse_json(schema.clone(), r#""hello world""#),
+        Some(json!("hello world"))
+    );
+    // The pattern can't match a quote or escape that would end or break the string
+    assert_eq!(parse_json(schema.clone(), r#""a"b""#), None);
+    assert_eq!(parse_json(schema.clone(), r#""a\b""#), None);
+    assert_eq!(parse_json(schema, "\"a\tb\""), None);
+
+    let schema = json!({ "type": "string", "pattern": "^[^a]+$" });
+    assert_eq!(parse_json(schema.clone(), r#""xyz""#), Some(json!("xyz")));
+    assert_eq!(parse_json(schema, "\"x\ny\""), None);
+}
@@ -102,6 +102,10 @@ impl Parser for LiteralParser {
             })
         }
     }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        Some(state.offset as u64)
+    }
 }
 
 #[test]
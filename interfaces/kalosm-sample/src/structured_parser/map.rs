@@ -6,6 +6,7 @@ use crate::{CreateParserState, ParseStatus, Parser};
 pub struct MapOutputParser<P: Parser, O, F = fn(<P as Parser>::Output) -> O> {
     pub(crate) parser: P,
     pub(crate) map: F,
+    pub(crate) output_value: Option<fn(&P, &O) -> Option<serde_json::Value>>,
     pub(crate) _output: std::marker::PhantomData<O>,
 }
 
@@ -26,6 +27,7 @@ impl<P: Parser + Clone, O, F: Clone> Clone for MapOutputParser<P, O, F> {
         Self {
             parser: self.parser.clone(),
             map: self.map.clone(),
+            output_value: self.output_value,
             _output: PhantomData,
         }
     }
@@ -63,4 +65,17 @@ impl<P: Parser, O: Clone, F: Fn(P::Output) -> O> Parser for MapOutputParser<P, O
             }),
         }
     }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        self.parser.state_key(state)
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        self.parser.partial_value(state)
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        self.output_value
+            .and_then(|output_value| output_value(&self.parser, output))
+    }
 }
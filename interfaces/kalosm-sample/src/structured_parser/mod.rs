@@ -38,10 +38,23 @@ mod map;
 pub use map::*;
 mod regex;
 pub use regex::*;
+mod grammar;
+pub use grammar::*;
+mod one_of;
+pub use one_of::*;
+mod sql;
+pub use sql::*;
+mod validate;
+pub use validate::*;
 mod arc_linked_list;
 pub(crate) use arc_linked_list::*;
 mod schema;
 pub use schema::*;
+mod format;
+pub(crate) use format::*;
+mod json_schema;
+mod partial;
+pub use partial::*;
 
 /// An error that occurred while parsing.
 #[derive(Debug, Clone)]
@@ -165,6 +178,47 @@ pub trait Parser {
         state: &Self::PartialState,
         input: &'a [u8],
     ) -> ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>>;
+
+    /// Get a key that identifies which text the parser can accept next from the state. Two states of the same parser with the same key must accept exactly the same text.
+    ///
+    /// Constrained generation uses the key to reuse the set of valid tokens for states it has already seen. Parsers that can't summarize their state cheaply return `None`, which is the default.
+    fn state_key(&self, _state: &Self::PartialState) -> Option<u64> {
+        None
+    }
+
+    /// Get the value the parser has read so far from the state. Strings and numbers that are still being parsed contain the text so far, and objects and arrays contain the fields and items that have started.
+    ///
+    /// Structured generation uses the value to stream the output while it is being generated. Parsers that don't know what value their text represents return `None`, which is the default.
+    fn partial_value(&self, _state: &Self::PartialState) -> Option<serde_json::Value> {
+        None
+    }
+
+    /// Get the value of a finished output of the parser. This is the value [`Parser::partial_value`] ends with once the parser finishes.
+    fn output_value(&self, _output: &Self::Output) -> Option<serde_json::Value> {
+        None
+    }
+}
+
+/// Combine the state key of a part of a parser with a tag that identifies the part.
+pub(crate) fn combine_state_key(tag: u64, key: u64) -> u64 {
+    hash_state_key((tag, key))
+}
+
+/// Hash the parts of a state that decide which text the parser accepts next into a state key.
+pub(crate) fn hash_state_key(parts: impl std::hash::Hash) -> u64 {
+    use std::hash::Hasher;
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    parts.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The part of a state key for a count that must stay in a range. Counts above the start of the range only differ in how much room is left before the end, and a range that ends at `usize::MAX` never runs out of room.
+pub(crate) fn count_state_key(
+    count: usize,
+    range: &std::ops::RangeInclusive<usize>,
+) -> (usize, Option<usize>) {
+    let room = (*range.end() != usize::MAX).then(|| range.end().saturating_sub(count));
+    (count.min(*range.start()), room)
 }
 
 impl Parser for () {
@@ -194,6 +248,18 @@ impl<P: ?Sized + Parser> Parser for &P {
     ) -> ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
         (*self).parse(state, input)
     }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        (*self).state_key(state)
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        (*self).partial_value(state)
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        (*self).output_value(output)
+    }
 }
 
 impl<P: ?Sized + Parser> Parser for Box<P> {
@@ -208,6 +274,21 @@ impl<P: ?Sized + Parser> Parser for Box<P> {
         let _self: &P = self;
         _self.parse(state, input)
     }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        let _self: &P = self;
+        _self.state_key(state)
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        let _self: &P = self;
+        _self.partial_value(state)
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        let _self: &P = self;
+        _self.output_value(output)
+    }
 }
 
 impl<P: ?Sized + Parser> Parser for Arc<P> {
@@ -222,6 +303,21 @@ impl<P: ?Sized + Parser> Parser for Arc<P> {
         let _self: &P = self;
         _self.parse(state, input)
     }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        let _self: &P = self;
+        _self.state_key(state)
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        let _self: &P = self;
+        _self.partial_value(state)
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        let _self: &P = self;
+        _self.output_value(output)
+    }
 }
 
 trait AnyCreateParserState:
@@ -268,6 +364,18 @@ impl<O: Clone> Parser for ArcParser<O> {
         let _self: &dyn Parser<Output = O, PartialState = Arc<dyn Any + Send + Sync>> = &self.0;
         _self.parse(state, input)
     }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        self.0.state_key(state)
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        self.0.partial_value(state)
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        self.0.output_value(output)
+    }
 }
 
 /// A wrapper for a parser that implements an easily boxable version of Parser.
@@ -305,6 +413,19 @@ where
             .parse(state, input)
             .map(|result| result.map_state(|state| Arc::new(state) as Arc<dyn Any + Sync + Send>))
     }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        self.0.state_key(state.downcast_ref::<P::PartialState>()?)
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        self.0
+            .partial_value(state.downcast_ref::<P::PartialState>()?)
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        self.0.output_value(output)
+    }
 }
 
 impl<P: CreateParserState> CreateParserState for AnyParser<P>
@@ -339,10 +460,19 @@ pub trait ParserExt: Parser {
     where
         Self: Sized,
     {
-        self.otherwise(other).map_output(|either| match either {
-            Either::Left(left) => left,
-            Either::Right(right) => right,
-        })
+        self.otherwise(other).map_output_with_value(
+            |either| match either {
+                Either::Left(left) => left,
+                Either::Right(right) => right,
+            },
+            // Parsers like the variants of an enum only know the value of some outputs
+            |parser, output| {
+                parser
+                    .parser1
+                    .output_value(output)
+                    .or_else(|| parser.parser2.output_value(output))
+            },
+        )
     }
 
     /// Parse this parser, then the other parser.
@@ -371,7 +501,10 @@ pub trait ParserExt: Parser {
     where
         Self: Sized,
     {
-        SequenceParser::new(self, other).map_output(|(_, second)| second)
+        SequenceParser::new(self, other).map_output_with_value(
+            |(_, second)| second,
+            |parser, output| parser.parser2.output_value(output),
+        )
     }
 
     /// Parse this parser, then the other parser while ignoring the output of the other parser.
@@ -382,7 +515,10 @@ pub trait ParserExt: Parser {
     where
         Self: Sized,
     {
-        SequenceParser::new(self, other).map_output(|(first, _)| first)
+        SequenceParser::new(self, other).map_output_with_value(
+            |(first, _)| first,
+            |parser, output| parser.parser1.output_value(output),
+        )
     }
 
     /// Parse this parser, then a literal. This is equivalent to `.then_ignore_output(LiteralParser::new(literal))`.
@@ -413,10 +549,87 @@ pub trait ParserExt: Parser {
         MapOutputParser {
             parser: self,
             map: f,
+            output_value: None,
             _output: std::marker::PhantomData,
         }
     }
 
+    /// Map the output of this parser and get the [`Parser::output_value`] of a mapped output from this parser. Parsers created with [`ParserExt::map_output`] don't know the value of their output because the map can't be undone.
+    ///
+    /// ```rust
+    /// use kalosm_sample::*;
+    ///
+    /// let parser = U32Parser::new().map_output_with_value(
+    ///     |number| number.to_string(),
+    ///     |parser, text| parser.output_value(&text.parse().ok()?),
+    /// );
+    /// let state = parser.create_parser_state();
+    /// let text = parser.parse(&state, b"12 ").unwrap().unwrap_finished();
+    /// assert_eq!(parser.output_value(&text), Some(serde_json::json!(12)));
+    /// ```
+    fn map_output_with_value<F, O>(
+        self,
+        f: F,
+        output_value: fn(&Self, &O) -> Option<serde_json::Value>,
+    ) -> MapOutputParser<Self, O, F>
+    where
+        Self: Sized,
+        F: Fn(Self::Output) -> O,
+    {
+        MapOutputParser {
+            parser: self,
+            map: f,
+            output_value: Some(output_value),
+            _output: std::marker::PhantomData,
+        }
+    }
+
+    /// Read the value of this parser as the property of a JSON object: `{ "name": value }`. Objects of properties that are parsed in a sequence are merged into one object.
+    fn into_property(self, name: impl Into<String>) -> PropertyParser<Self>
+    where
+        Self: Sized,
+    {
+        PropertyParser::new(self, name)
+    }
+
+    /// Read the value of this parser as an item of a JSON array: `[value]`. Arrays of items that are parsed in a sequence are merged into one array.
+    fn into_item(self) -> ItemParser<Self>
+    where
+        Self: Sized,
+    {
+        ItemParser::new(self)
+    }
+
+    /// Reject the output of this parser if the validation function returns an error. This can enforce constraints that a grammar can't express, like a date that must be after another date or an ID that must exist in a database.
+    ///
+    /// The output is validated as soon as this parser finishes, so during constrained generation any token that would finish with an invalid output is rejected and the next best token is sampled instead.
+    ///
+    /// ```rust
+    /// use kalosm_sample::*;
+    ///
+    /// let known_ids = [1, 2, 3];
+    /// let parser = U32Parser::new().validate(move |id| {
+    ///     if known_ids.contains(id) {
+    ///         Ok(())
+    ///     } else {
+    ///         Err(ParserError::msg(format!("Unknown ID {id}")))
+    ///     }
+    /// });
+    /// let state = parser.create_parser_state();
+    /// assert!(parser.parse(&state, b"2 ").is_ok());
+    /// assert!(parser.parse(&state, b"4 ").is_err());
+    /// ```
+    fn validate<F>(self, validate: F) -> ValidateParser<Self, F>
+    where
+        Self: Sized,
+        F: Fn(&Self::Output) -> Result<(), ParserError>,
+    {
+        ValidateParser {
+            parser: self,
+            validate,
+        }
+    }
+
     /// Get a boxed version of this parser.
     fn boxed(self) -> ArcParser<Self::Output>
     where
@@ -474,6 +687,18 @@ impl<P: Parser, F: Fn() -> P::PartialState + Clone> Parser for WithInitialState<
     ) -> ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
         self.parser.parse(state, input)
     }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        self.parser.state_key(state)
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        self.parser.partial_value(state)
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        self.parser.output_value(output)
+    }
 }
 
 /// A parser that is lazily initialized.
@@ -520,6 +745,18 @@ impl<P: Parser, F: FnOnce() -> P> Parser for LazyParser<P, F> {
     ) -> ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
         self.get_parser().parse(state, input)
     }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        self.get_parser().state_key(state)
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        self.get_parser().partial_value(state)
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        self.get_parser().output_value(output)
+    }
 }
 
 /// A parser for a choice between two parsers.
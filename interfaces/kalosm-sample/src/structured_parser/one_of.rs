@@ -0,0 +1,275 @@
+use std::{borrow::Cow, sync::Arc};
+
+use crate::{CreateParserState, ParseStatus, Parser};
+
+/// A parser for one of a list of strings. The output is the index of the string that was parsed.
+///
+/// The options are stored in a prefix tree, so building and parsing stays fast even with tens of thousands of options. Each state is a position in the tree, which lets [`Parser::state_key`] share token masks between states during constrained generation.
+///
+/// If one option is a prefix of another option, the longer option is parsed if the text continues it.
+///
+/// ```rust
+/// use kalosm_sample::*;
+///
+/// let parser = OneOfStringsParser::new(["apple", "apricot", "banana"]);
+/// let state = parser.create_parser_state();
+/// let result = parser.parse(&state, b"apricot").unwrap();
+/// assert_eq!(result.unwrap_finished(), 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct OneOfStringsParser {
+    nodes: Arc<[OneOfStringsNode]>,
+}
+
+#[derive(Debug)]
+struct OneOfStringsNode {
+    /// The bytes on the edge from the parent node to this node.
+    text: Box<[u8]>,
+    /// The children of this node with the first byte of their text, sorted by that byte.
+    children: Vec<(u8, u32)>,
+    /// The index of the option that ends at this node.
+    option: Option<usize>,
+}
+
+impl OneOfStringsParser {
+    /// Create a new parser for one of the options. If an option is repeated, the index of the first copy is parsed.
+    pub fn new<S: AsRef<str>>(options: impl IntoIterator<Item = S>) -> Self {
+        let options: Vec<S> = options.into_iter().collect();
+        let mut entries: Vec<(&[u8], usize)> = options
+            .iter()
+            .enumerate()
+            .map(|(index, option)| (option.as_ref().as_bytes(), index))
+            .collect();
+        entries.sort_unstable();
+        entries.dedup_by_key(|(option, _)| *option);
+
+        let mut nodes = Vec::new();
+        Self::build(&mut nodes, Box::default(), &entries, 0);
+        Self {
+            nodes: nodes.into(),
+        }
+    }
+
+    /// Add a node for the entries that all share the first `depth` bytes. Returns the index of the node.
+    fn build(
+        nodes: &mut Vec<OneOfStringsNode>,
+        text: Box<[u8]>,
+        entries: &[(&[u8], usize)],
+        depth: usize,
+    ) -> u32 {
+        let index = nodes.len();
+        // The entries are sorted, so an option that ends here is always first
+        let (option, mut rest) = match entries.first() {
+            Some((option, index)) if option.len() == depth => (Some(*index), &entries[1..]),
+            _ => (None, entries),
+        };
+        nodes.push(OneOfStringsNode {
+            text,
+            children: Vec::new(),
+            option,
+        });
+
+        // Group the rest of the entries by their next byte
+        while let Some((first, _)) = rest.first() {
+            let next_byte = first[depth];
+            let group_len = rest
+                .iter()
+                .take_while(|(option, _)| option[depth] == next_byte)
+                .count();
+            let (group, remaining) = rest.split_at(group_len);
+            rest = remaining;
+
+            // The prefix shared by the group is the prefix shared by the first and last entry
+            let last = group[group.len() - 1].0;
+            let shared = depth
+                + first[depth..]
+                    .iter()
+                    .zip(&last[depth..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+            let child = Self::build(nodes, first[depth..shared].into(), group, shared);
+            nodes[index].children.push((next_byte, child));
+        }
+
+        index as u32
+    }
+
+    fn child(&self, node: u32, byte: u8) -> Option<u32> {
+        let children = &self.nodes[node as usize].children;
+        children
+            .binary_search_by_key(&byte, |(byte, _)| *byte)
+            .ok()
+            .map(|index| children[index].1)
+    }
+}
+
+impl CreateParserState for OneOfStringsParser {
+    fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+        OneOfStringsParserState::default()
+    }
+}
+
+/// The state of a [`OneOfStringsParser`].
+#[derive(Default, Debug, PartialEq, Eq, Copy, Clone)]
+pub struct OneOfStringsParserState {
+    node: u32,
+    offset: u32,
+}
+
+/// The error type for a [`OneOfStringsParser`].
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct NoMatchingOptionError;
+
+impl std::fmt::Display for NoMatchingOptionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The text does not match any option")
+    }
+}
+
+impl std::error::Error for NoMatchingOptionError {}
+
+impl Parser for OneOfStringsParser {
+    type Output = usize;
+    type PartialState = OneOfStringsParserState;
+
+    fn parse<'a>(
+        &self,
+        state: &OneOfStringsParserState,
+        input: &'a [u8],
+    ) -> crate::ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+        let mut node = state.node;
+        let mut offset = state.offset as usize;
+        let mut input = input;
+
+        loop {
+            let current = &self.nodes[node as usize];
+            let remaining_text = &current.text[offset..];
+            let matched = input
+                .iter()
+                .zip(remaining_text)
+                .take_while(|(a, b)| a == b)
+                .count();
+            if matched < remaining_text.len().min(input.len()) {
+                crate::bail!(NoMatchingOptionError);
+            }
+            input = &input[matched..];
+            offset += matched;
+
+            if offset < current.text.len() {
+                // The rest of the text of this node is the only way to continue
+                let required_next = std::str::from_utf8(&current.text[offset..])
+                    .map(|text| Cow::Owned(text.to_string()))
+                    .unwrap_or_default();
+                return Ok(ParseStatus::Incomplete {
+                    new_state: OneOfStringsParserState {
+                        node,
+                        offset: offset as u32,
+                    },
+                    required_next,
+                });
+            }
+
+            match (current.option, input.first()) {
+                (Some(result), _) if current.children.is_empty() => {
+                    return Ok(ParseStatus::Finished {
+                        result,
+                        remaining: input,
+                    });
+                }
+                (_, None) => {
+                    return Ok(ParseStatus::Incomplete {
+                        new_state: OneOfStringsParserState {
+                            node,
+                            offset: offset as u32,
+                        },
+                        required_next: Cow::Borrowed(""),
+                    });
+                }
+                (option, Some(&byte)) => match (self.child(node, byte), option) {
+                    (Some(child), _) => {
+                        node = child;
+                        offset = 0;
+                    }
+                    (None, Some(result)) => {
+                        return Ok(ParseStatus::Finished {
+                            result,
+                            remaining: input,
+                        });
+                    }
+                    (None, None) => crate::bail!(NoMatchingOptionError),
+                },
+            }
+        }
+    }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        Some(((state.node as u64) << 32) | state.offset as u64)
+    }
+}
+
+#[test]
+fn one_of_strings_parser() {
+    let options = [
+        "apple", "app", "apricot", "banana", "band", "ébène", "ébauche",
+    ];
+    let parser = OneOfStringsParser::new(options);
+    let state = parser.create_parser_state();
+
+    for (index, option) in options.iter().enumerate() {
+        let text = format!("{option}\"");
+        assert_eq!(
+            parser.parse(&state, text.as_bytes()).unwrap(),
+            ParseStatus::Finished {
+                result: index,
+                remaining: b"\""
+            }
+        );
+        // Parsing one byte at a time should give the same result
+        let (start, end) = text.split_at(option.len() - 1);
+        let mut state = state;
+        for &byte in start.as_bytes() {
+            state = parser.parse(&state, &[byte]).unwrap().unwrap_incomplete().0;
+        }
+        assert_eq!(
+            parser.parse(&state, end.as_bytes()).unwrap(),
+            ParseStatus::Finished {
+                result: index,
+                remaining: b"\""
+            }
+        );
+    }
+
+    let (state, required_next) = parser.parse(&state, b"apr").unwrap().unwrap_incomplete();
+    assert_eq!(required_next, "icot");
+    assert_eq!(parser.parse(&state, b"icot").unwrap().unwrap_finished(), 2);
+    let state = parser.create_parser_state();
+    assert_eq!(
+        parser.parse(&state, b"apples").unwrap(),
+        ParseStatus::Finished {
+            result: 0,
+            remaining: b"s"
+        }
+    );
+    assert!(parser.parse(&state, b"ax").is_err());
+    assert!(parser.parse(&state, b"bank").is_err());
+}
+
+#[test]
+fn one_of_strings_parser_duplicates_and_state_keys() {
+    let parser = OneOfStringsParser::new(vec!["a".to_string(), "b".into(), "a".into()]);
+    let state = parser.create_parser_state();
+    assert_eq!(parser.parse(&state, b"a ").unwrap().unwrap_finished(), 0);
+
+    let parser = OneOfStringsParser::new(["hello", "help"]);
+    let state = parser.create_parser_state();
+    let first = parser.parse(&state, b"he").unwrap().unwrap_incomplete().0;
+    let second = parser
+        .parse(
+            &parser.parse(&state, b"h").unwrap().unwrap_incomplete().0,
+            b"e",
+        )
+        .unwrap()
+        .unwrap_incomplete()
+        .0;
+    assert_eq!(parser.state_key(&first), parser.state_key(&second));
+}
@@ -4,7 +4,7 @@ use std::{
     fmt::{Display, Formatter},
 };
 
-use crate::{CreateParserState, ParseResult, ParseStatus, Parser};
+use crate::{combine_state_key, CreateParserState, ParseResult, ParseStatus, Parser};
 
 /// State of a choice parser.
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -239,6 +239,39 @@ impl<P1: Parser, P2: Parser> Parser for ChoiceParser<P1, P2> {
             }
         }
     }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        // A parser that already failed doesn't accept anything
+        let key1 = match &state.state1 {
+            Ok(p1) => combine_state_key(1, self.parser1.state_key(p1)?),
+            Err(_) => 0,
+        };
+        let key2 = match &state.state2 {
+            Ok(p2) => combine_state_key(1, self.parser2.state_key(p2)?),
+            Err(_) => 0,
+        };
+        Some(combine_state_key(key1, key2))
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        // Use the first parser that is still running and knows its value
+        let value1 = state
+            .state1
+            .as_ref()
+            .ok()
+            .and_then(|p1| self.parser1.partial_value(p1));
+        value1.or_else(|| {
+            let p2 = state.state2.as_ref().ok()?;
+            self.parser2.partial_value(p2)
+        })
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        match output {
+            Either::Left(left) => self.parser1.output_value(left),
+            Either::Right(right) => self.parser2.output_value(right),
+        }
+    }
 }
 
 #[test]
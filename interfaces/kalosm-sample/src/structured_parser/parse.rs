@@ -1,12 +1,18 @@
-use crate::{CreateParserState, SendCreateParserState, SeparatedParser};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::Hash;
+
+use crate::{ArcParser, ObjectFormat, SeparatedParserState, XmlTextParser};
+use crate::{ChoiceParser, CreateParserState, Either, SendCreateParserState, SeparatedParser};
 use crate::{
-    IntegerParser, LiteralParser, ParseStatus, Parser, ParserExt, SequenceParser, StringParser,
+    FloatParser, IntegerParser, LiteralParser, ParseStatus, Parser, ParserExt, StringParser,
 };
 
 /// Data that can be parsed incrementally.
 ///
 /// You can derive this trait for unit values, unit enums or structs or implement it manually for custom types.
 ///
+/// Parse is implemented for strings, numbers, `bool`, `char`, `Option`, `Box`, `Vec`, arrays, tuples, `HashSet`, `BTreeSet` and maps with string keys (`HashMap<String, T>` and `BTreeMap<String, T>`). Dates from `chrono`, `url::Url` and `uuid::Uuid` are supported with the `chrono`, `url` and `uuid` features.
+///
 /// # Example
 /// ```rust
 /// use kalosm_sample::*;
@@ -52,11 +58,70 @@ use crate::{
 pub trait Parse: Clone + Send + Sync {
     /// Create a new parser that parses the current type and can be sent between threads.
     fn new_parser() -> impl SendCreateParserState<Output = Self>;
+
+    /// Create a parser for the current type written in a format as a value nested `depth` levels deep. Objects at the top level are at depth zero and their properties are at depth one.
+    ///
+    /// The default parser writes the value the same way it is written in JSON after the [`ObjectFormat::scalar_prefix`], which works for values like numbers and booleans that have the same syntax in every format.
+    fn new_parser_in_format(format: ObjectFormat, depth: usize) -> ArcParser<Self>
+    where
+        Self: 'static,
+    {
+        let _ = depth;
+        format.scalar_parser(Self::new_parser())
+    }
+
+    /// Create a parser for the property `name` of an object nested `depth` levels deep with the current type as the value. XML writes lists as one element for each item, so lists override this method.
+    fn new_property_parser_in_format(
+        format: ObjectFormat,
+        name: &str,
+        depth: usize,
+    ) -> ArcParser<Self>
+    where
+        Self: 'static,
+    {
+        format.property_parser(name, depth, Self::new_parser_in_format(format, depth + 1))
+    }
+}
+
+/// Data that is parsed as the properties of an object without the start and end of the object: `"name": value, "other": value` in JSON.
+///
+/// `#[derive(Parse)]` implements this trait for structs with named fields so they can be flattened into other structs with `#[parse(flatten)]`.
+pub trait ParseProperties: Parse {
+    /// Create a new parser that parses the properties of the current type.
+    fn new_properties_parser() -> impl SendCreateParserState<Output = Self>;
+
+    /// Create a new parser that parses the properties of the current type in a format for an object nested `depth` levels deep.
+    fn new_properties_parser_in_format(format: ObjectFormat, depth: usize) -> ArcParser<Self>
+    where
+        Self: 'static;
 }
 
 impl<T: Parse> Parse for Box<T> {
     fn new_parser() -> impl SendCreateParserState<Output = Self> {
-        T::new_parser().map_output(Box::new)
+        T::new_parser()
+            .map_output_with_value(Box::new, |parser, output| parser.output_value(output))
+    }
+
+    fn new_parser_in_format(format: ObjectFormat, depth: usize) -> ArcParser<Self>
+    where
+        Self: 'static,
+    {
+        T::new_parser_in_format(format, depth)
+            .map_output_with_value(Box::new, |parser, output| parser.output_value(output))
+            .boxed()
+    }
+
+    fn new_property_parser_in_format(
+        format: ObjectFormat,
+        name: &str,
+        depth: usize,
+    ) -> ArcParser<Self>
+    where
+        Self: 'static,
+    {
+        T::new_property_parser_in_format(format, name, depth)
+            .map_output_with_value(Box::new, |parser, output| parser.output_value(output))
+            .boxed()
     }
 }
 
@@ -112,6 +177,18 @@ macro_rules! int_parser {
                     .parse(state, input)
                     .map(|result| result.map(|output| output as $num))
             }
+
+            fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+                self.parser.state_key(state)
+            }
+
+            fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+                self.parser.partial_value(state)
+            }
+
+            fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+                self.parser.output_value(&(*output as i128))
+            }
         }
 
         impl Parse for $num {
@@ -152,47 +229,963 @@ int_parser!(I16Parser, i16, test_i16);
 int_parser!(I32Parser, i32, test_i32);
 int_parser!(I64Parser, i64, test_i64);
 
+impl Parse for f64 {
+    fn new_parser() -> impl SendCreateParserState<Output = Self> {
+        FloatParser::new(f64::MIN..=f64::MAX)
+    }
+}
+
+impl Parse for f32 {
+    fn new_parser() -> impl SendCreateParserState<Output = Self> {
+        FloatParser::new(f32::MIN as f64..=f32::MAX as f64).map_output_with_value(
+            |output| output as f32,
+            |parser, output| parser.output_value(&(*output as f64)),
+        )
+    }
+}
+
+/// A parser for `true` or `false`.
+#[derive(Clone, Debug)]
+pub struct BoolParser {
+    parser: ChoiceParser<LiteralParser, LiteralParser>,
+}
+
+impl BoolParser {
+    /// Create a new parser.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Default for BoolParser {
+    fn default() -> Self {
+        Self {
+            parser: ChoiceParser::new(LiteralParser::new("true"), LiteralParser::new("false")),
+        }
+    }
+}
+
+impl CreateParserState for BoolParser {
+    fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+        self.parser.create_parser_state()
+    }
+}
+
+impl Parser for BoolParser {
+    type Output = bool;
+    type PartialState = <ChoiceParser<LiteralParser, LiteralParser> as Parser>::PartialState;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> crate::ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+        self.parser
+            .parse(state, input)
+            .map(|result| result.map(|output| matches!(output, Either::Left(_))))
+    }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        self.parser.state_key(state)
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        Some(serde_json::Value::Bool(*output))
+    }
+}
+
+impl Parse for bool {
+    fn new_parser() -> impl SendCreateParserState<Output = Self> {
+        BoolParser::new()
+    }
+}
+
+#[test]
+fn test_bool() {
+    let parser = bool::new_parser();
+    let state = parser.create_parser_state();
+    assert!(parser.parse(&state, b"true").unwrap().unwrap_finished());
+    assert!(!parser.parse(&state, b"false").unwrap().unwrap_finished());
+    assert!(parser.parse(&state, b"trfalse").is_err());
+}
+
 impl Parse for String {
     fn new_parser() -> impl SendCreateParserState<Output = Self> {
         StringParser::new(0..=usize::MAX)
     }
+
+    fn new_parser_in_format(format: ObjectFormat, _: usize) -> ArcParser<Self> {
+        match format {
+            ObjectFormat::Xml => XmlTextParser::new(0..=usize::MAX).boxed(),
+            _ => format.scalar_parser(Self::new_parser()),
+        }
+    }
+}
+
+impl Parse for char {
+    fn new_parser() -> impl SendCreateParserState<Output = Self> {
+        char_parser(StringParser::new(1..=1))
+    }
+
+    fn new_parser_in_format(format: ObjectFormat, _: usize) -> ArcParser<Self> {
+        match format {
+            ObjectFormat::Xml => char_parser(XmlTextParser::new(1..=1)).boxed(),
+            _ => format.scalar_parser(Self::new_parser()),
+        }
+    }
+}
+
+/// Read the output of a parser for strings with one character as a `char`.
+fn char_parser<P: SendCreateParserState<Output = String>>(
+    parser: P,
+) -> impl SendCreateParserState<Output = char> {
+    parser.map_output_with_value(
+        |string| {
+            string
+                .chars()
+                .next()
+                .expect("The string parser only parses strings with one character")
+        },
+        |parser, output| parser.output_value(&output.to_string()),
+    )
+}
+
+#[test]
+fn test_char() {
+    let parser = char::new_parser();
+    let state = parser.create_parser_state();
+    assert_eq!(
+        parser.parse(&state, b"\"a\"").unwrap().unwrap_finished(),
+        'a'
+    );
+    assert_eq!(
+        parser.parse(&state, b"\"\\\"\"").unwrap().unwrap_finished(),
+        '"'
+    );
+    assert!(parser.parse(&state, b"\"ab\"").is_err());
 }
 
 impl<T: Parse + Clone + Send + Sync> Parse for std::vec::Vec<T> {
     fn new_parser() -> impl SendCreateParserState<Output = Self> {
-        SequenceParser::new(
-            LiteralParser::new("["),
-            SequenceParser::new(
-                SeparatedParser::new(T::new_parser(), LiteralParser::new(", "), 0..=usize::MAX),
-                LiteralParser::new("]"),
-            ),
-        )
-        .map_output(|((), (outputs, ()))| outputs)
+        LiteralParser::new("[")
+            .ignore_output_then(SeparatedParser::new(
+                T::new_parser(),
+                LiteralParser::new(", "),
+                0..=usize::MAX,
+            ))
+            .then_literal("]")
+    }
+
+    fn new_parser_in_format(format: ObjectFormat, depth: usize) -> ArcParser<Self>
+    where
+        Self: 'static,
+    {
+        list_parser_in_format(format, depth, 0..=usize::MAX, |items| items)
+    }
+
+    fn new_property_parser_in_format(
+        format: ObjectFormat,
+        name: &str,
+        depth: usize,
+    ) -> ArcParser<Self>
+    where
+        Self: 'static,
+    {
+        match format {
+            ObjectFormat::Xml => {
+                xml_list_property_parser(name, depth, 0..=usize::MAX, |items| items)
+            }
+            _ => format.property_parser(name, depth, Self::new_parser_in_format(format, depth + 1)),
+        }
     }
 }
 
+/// Create a parser for a list nested `depth` levels deep in a format: `[first, second]`. TOML lists hold TOML values. Other formats write the list as JSON, which YAML reads as a flow sequence. The parser for the items is created from a parser that separates the items.
+fn list_parser_in_format<T, P>(
+    format: ObjectFormat,
+    depth: usize,
+    len_range: std::ops::RangeInclusive<usize>,
+    items: impl FnOnce(SeparatedParser<ArcParser<T>, LiteralParser>) -> P,
+) -> ArcParser<Vec<T>>
+where
+    T: Parse + 'static,
+    P: SendCreateParserState<Output = Vec<T>> + 'static,
+    P::PartialState: 'static,
+{
+    let item_format = match format {
+        ObjectFormat::Toml => ObjectFormat::Toml,
+        _ => ObjectFormat::Json,
+    };
+    let item = T::new_parser_in_format(item_format, depth + 1);
+    let items = items(SeparatedParser::new(
+        item,
+        LiteralParser::new(", "),
+        len_range,
+    ));
+    format.scalar_parser(
+        LiteralParser::new("[")
+            .ignore_output_then(items)
+            .then_literal("]"),
+    )
+}
+
+/// Create a parser for a list property in XML with one element for each item: `<name>first</name><name>second</name>`. XML can't write an empty list, so the list has at least one item.
+fn xml_list_property_parser<T, P>(
+    name: &str,
+    depth: usize,
+    len_range: std::ops::RangeInclusive<usize>,
+    items: impl FnOnce(SeparatedParser<ArcParser<T>, LiteralParser>) -> P,
+) -> ArcParser<Vec<T>>
+where
+    T: Parse + 'static,
+    P: SendCreateParserState<Output = Vec<T>> + 'static,
+    P::PartialState: 'static,
+{
+    let (start, end) = ObjectFormat::Xml.property_delimiters(name, depth);
+    let item = LiteralParser::new(start)
+        .ignore_output_then(T::new_parser_in_format(ObjectFormat::Xml, depth + 1))
+        .then_literal(end)
+        .boxed();
+    // The items are written next to each other so the `<` of the next item can't be confused with the newline before the next property
+    let len_range = (*len_range.start()).max(1)..=*len_range.end();
+    items(SeparatedParser::new(
+        item,
+        LiteralParser::new(""),
+        len_range,
+    ))
+    .into_property(name)
+    .boxed()
+}
+
 impl<const N: usize, T: Parse + Clone + Send + Sync> Parse for [T; N] {
     fn new_parser() -> impl SendCreateParserState<Output = Self> {
-        SequenceParser::new(
-            LiteralParser::new("["),
-            SequenceParser::new(
-                SeparatedParser::new(T::new_parser(), LiteralParser::new(", "), N..=N),
-                LiteralParser::new("]"),
-            ),
+        array_parser(
+            LiteralParser::new("[")
+                .ignore_output_then(SeparatedParser::new(
+                    T::new_parser(),
+                    LiteralParser::new(", "),
+                    N..=N,
+                ))
+                .then_literal("]"),
         )
-        .map_output(|((), (outputs, ()))| {
+    }
+
+    fn new_parser_in_format(format: ObjectFormat, depth: usize) -> ArcParser<Self>
+    where
+        Self: 'static,
+    {
+        array_parser(list_parser_in_format(format, depth, N..=N, |items| items)).boxed()
+    }
+
+    fn new_property_parser_in_format(
+        format: ObjectFormat,
+        name: &str,
+        depth: usize,
+    ) -> ArcParser<Self>
+    where
+        Self: 'static,
+    {
+        match format {
+            ObjectFormat::Xml => {
+                array_parser(xml_list_property_parser(name, depth, N..=N, |items| items)).boxed()
+            }
+            _ => format.property_parser(name, depth, Self::new_parser_in_format(format, depth + 1)),
+        }
+    }
+}
+
+/// Turn the output of a parser for a list with `N` items into an array.
+fn array_parser<const N: usize, T: Clone + Send + Sync>(
+    parser: impl SendCreateParserState<Output = Vec<T>>,
+) -> impl SendCreateParserState<Output = [T; N]> {
+    parser.map_output_with_value(
+        |outputs| {
             outputs
                 .try_into()
                 .unwrap_or_else(|_| panic!("Array is not the correct size"))
-        })
-    }
+        },
+        |parser, output: &[T; N]| parser.output_value(&output.to_vec()),
+    )
 }
 
 impl<T: Parse> Parse for Option<T> {
     fn new_parser() -> impl SendCreateParserState<Output = Self> {
-        let parser = T::new_parser();
+        option_parser(T::new_parser(), "null")
+    }
+
+    /// TOML and XML can't write a missing value, so options are always written as `Some` in those formats.
+    fn new_parser_in_format(format: ObjectFormat, depth: usize) -> ArcParser<Self>
+    where
+        Self: 'static,
+    {
+        let parser = T::new_parser_in_format(format, depth);
+        match format {
+            ObjectFormat::Json => option_parser(parser, "null").boxed(),
+            ObjectFormat::Yaml => option_parser(parser, " null").boxed(),
+            ObjectFormat::Toml | ObjectFormat::Xml => some_parser(parser).boxed(),
+        }
+    }
+
+    fn new_property_parser_in_format(
+        format: ObjectFormat,
+        name: &str,
+        depth: usize,
+    ) -> ArcParser<Self>
+    where
+        Self: 'static,
+    {
+        match format {
+            ObjectFormat::Toml | ObjectFormat::Xml => {
+                some_parser(T::new_property_parser_in_format(format, name, depth)).boxed()
+            }
+            _ => format.property_parser(name, depth, Self::new_parser_in_format(format, depth + 1)),
+        }
+    }
+}
+
+/// Create a parser for a value or the text of a missing value.
+fn option_parser<P: SendCreateParserState>(
+    parser: P,
+    null: &'static str,
+) -> impl SendCreateParserState<Output = Option<P::Output>>
+where
+    P::Output: Clone,
+{
+    some_parser(parser).or(LiteralParser::new(null).map_output_with_value(
+        |_| None,
+        |_, output| output.is_none().then_some(serde_json::Value::Null),
+    ))
+}
+
+fn some_parser<P: SendCreateParserState>(
+    parser: P,
+) -> impl SendCreateParserState<Output = Option<P::Output>> {
+    parser.map_output_with_value(Some, |parser, output| {
+        output
+            .as_ref()
+            .and_then(|output| parser.output_value(output))
+    })
+}
+
+/// Create a parser for a JSON object with any keys: `{ "key": value, "other": value }` or `{}`. The key and value are separated by the key separator, which is `": "` in JSON. The parser outputs the entries in the order they were parsed.
+pub(crate) fn map_parser<P: SendCreateParserState>(
+    value_parser: P,
+    key_separator: &'static str,
+) -> impl SendCreateParserState<Output = Vec<(String, P::Output)>> {
+    let entry = StringParser::new(0..=usize::MAX)
+        .into_item()
+        .then_literal(key_separator)
+        .then(value_parser.into_item());
+    let parser = LiteralParser::new("{}")
+        .map_output(|_| Vec::new())
+        .or(LiteralParser::new("{ ")
+            .ignore_output_then(SeparatedParser::new(
+                entry,
+                LiteralParser::new(", "),
+                1..=usize::MAX,
+            ))
+            .then_literal(" }"));
+    EntriesParser { parser }
+}
+
+/// A parser for the entries of a map that reads the `[key, value]` values of the entries into an object.
+struct EntriesParser<P> {
+    parser: P,
+}
+
+impl<P: CreateParserState> CreateParserState for EntriesParser<P> {
+    fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+        self.parser.create_parser_state()
+    }
+}
+
+impl<P: Parser> Parser for EntriesParser<P> {
+    type Output = P::Output;
+    type PartialState = P::PartialState;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> crate::ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+        self.parser.parse(state, input)
+    }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        self.parser.state_key(state)
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        self.parser.partial_value(state).map(entries_object)
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        self.parser.output_value(output).map(entries_object)
+    }
+}
+
+/// Turn an array of `[key, value]` entries into an object. Entries that don't have a value yet are left out.
+fn entries_object(entries: serde_json::Value) -> serde_json::Value {
+    let serde_json::Value::Array(entries) = entries else {
+        return entries;
+    };
+    let entries = entries.into_iter().filter_map(|entry| match entry {
+        serde_json::Value::Array(entry) => match <[serde_json::Value; 2]>::try_from(entry) {
+            Ok([serde_json::Value::String(key), value]) => Some((key, value)),
+            _ => None,
+        },
+        _ => None,
+    });
+    serde_json::Value::Object(entries.collect())
+}
+
+/// Create a parser for a map nested `depth` levels deep in a format. TOML maps are inline tables that hold TOML values: `{ "key" = value }`. Other formats write the map as JSON, which YAML reads as a flow mapping.
+fn map_parser_in_format<T: Parse + 'static>(
+    format: ObjectFormat,
+    depth: usize,
+) -> ArcParser<Vec<(String, T)>> {
+    let (value_format, key_separator) = match format {
+        ObjectFormat::Toml => (ObjectFormat::Toml, " = "),
+        _ => (ObjectFormat::Json, ": "),
+    };
+    format.scalar_parser(map_parser(
+        T::new_parser_in_format(value_format, depth + 1),
+        key_separator,
+    ))
+}
+
+/// Collect the entries of a map parser into a map.
+fn collect_map<T, M>(
+    parser: impl SendCreateParserState<Output = Vec<(String, T)>>,
+) -> impl SendCreateParserState<Output = M>
+where
+    T: Clone + Send + Sync,
+    M: FromIterator<(String, T)> + Clone + Send + Sync,
+    for<'a> &'a M: IntoIterator<Item = (&'a String, &'a T)>,
+{
+    parser.map_output_with_value(
+        |entries| entries.into_iter().collect(),
+        |parser, map: &M| {
+            let entries = map
+                .into_iter()
+                .map(|(key, value)| (key.clone(), value.clone()));
+            parser.output_value(&entries.collect())
+        },
+    )
+}
+
+impl<T: Parse> Parse for HashMap<String, T> {
+    fn new_parser() -> impl SendCreateParserState<Output = Self> {
+        collect_map::<T, Self>(map_parser(T::new_parser(), ": "))
+    }
+
+    fn new_parser_in_format(format: ObjectFormat, depth: usize) -> ArcParser<Self>
+    where
+        Self: 'static,
+    {
+        collect_map::<T, Self>(map_parser_in_format(format, depth)).boxed()
+    }
+}
+
+impl<T: Parse> Parse for BTreeMap<String, T> {
+    fn new_parser() -> impl SendCreateParserState<Output = Self> {
+        collect_map::<T, Self>(map_parser(T::new_parser(), ": "))
+    }
+
+    fn new_parser_in_format(format: ObjectFormat, depth: usize) -> ArcParser<Self>
+    where
+        Self: 'static,
+    {
+        collect_map::<T, Self>(map_parser_in_format(format, depth)).boxed()
+    }
+}
+
+#[test]
+fn test_map() {
+    let parser = HashMap::<String, i32>::new_parser();
+    let state = parser.create_parser_state();
+    let map = parser
+        .parse(&state, br#"{ "a": 1, "b": 2 }"#)
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(
+        map,
+        HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)])
+    );
+
+    let parser = BTreeMap::<String, String>::new_parser();
+    let state = parser.create_parser_state();
+    assert!(parser
+        .parse(&state, b"{}")
+        .unwrap()
+        .unwrap_finished()
+        .is_empty());
+    assert!(parser.parse(&state, b"{ }").is_err());
+}
+
+/// Create a parser for a JSON array of items that are all different: `[first, second]`. The parser outputs the items in the order they were parsed.
+pub(crate) fn set_parser<P: SendCreateParserState>(
+    item_parser: P,
+) -> impl SendCreateParserState<Output = Vec<P::Output>>
+where
+    P::Output: PartialEq,
+{
+    LiteralParser::new("[")
+        .ignore_output_then(unique_items(SeparatedParser::new(
+            item_parser,
+            LiteralParser::new(", "),
+            0..=usize::MAX,
+        )))
+        .then_literal("]")
+}
+
+fn unique_items<P, S>(parser: SeparatedParser<P, S>) -> UniqueItemsParser<P, S> {
+    UniqueItemsParser { parser }
+}
+
+/// A parser for a list of items that rejects an item as soon as it is parsed if it is already in the list. Rejecting the item early keeps structured generation from writing a duplicate.
+struct UniqueItemsParser<P, S> {
+    parser: SeparatedParser<P, S>,
+}
+
+impl<P: CreateParserState, S: CreateParserState> CreateParserState for UniqueItemsParser<P, S>
+where
+    P::Output: PartialEq,
+{
+    fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+        self.parser.create_parser_state()
+    }
+}
+
+impl<P: CreateParserState, S: CreateParserState> Parser for UniqueItemsParser<P, S>
+where
+    P::Output: PartialEq,
+{
+    type Output = Vec<P::Output>;
+    type PartialState = SeparatedParserState<P, S>;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> crate::ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+        let result = self.parser.parse(state, input)?;
+        // Only the items that were finished in this chunk need to be checked
+        let known = state.outputs.len();
+        let duplicate = match &result {
+            ParseStatus::Incomplete { new_state, .. } => {
+                let items: Vec<_> = new_state.outputs.iter().collect();
+                (0..items.len() - known).any(|i| items[i + 1..].contains(&items[i]))
+            }
+            ParseStatus::Finished { result, .. } => {
+                (known..result.len()).any(|i| result[..i].contains(&result[i]))
+            }
+        };
+        if duplicate {
+            crate::bail!("The item is already in the set");
+        }
+        Ok(result)
+    }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        // The items that are already parsed change which items are valid next
+        if state.outputs.len() > 0 {
+            return None;
+        }
+        self.parser.state_key(state)
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        self.parser.partial_value(state)
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        self.parser.output_value(output)
+    }
+}
+
+/// Collect the items of a list parser into a set.
+fn collect_set<T, S>(
+    parser: impl SendCreateParserState<Output = Vec<T>>,
+) -> impl SendCreateParserState<Output = S>
+where
+    T: Clone + Send + Sync,
+    S: FromIterator<T> + Clone + Send + Sync,
+    for<'a> &'a S: IntoIterator<Item = &'a T>,
+{
+    parser.map_output_with_value(
+        |items| items.into_iter().collect(),
+        |parser, set: &S| parser.output_value(&set.into_iter().cloned().collect()),
+    )
+}
+
+impl<T: Parse + Eq + Hash> Parse for HashSet<T> {
+    fn new_parser() -> impl SendCreateParserState<Output = Self> {
+        collect_set::<T, Self>(set_parser(T::new_parser()))
+    }
+
+    fn new_parser_in_format(format: ObjectFormat, depth: usize) -> ArcParser<Self>
+    where
+        Self: 'static,
+    {
+        collect_set::<T, Self>(list_parser_in_format(
+            format,
+            depth,
+            0..=usize::MAX,
+            unique_items,
+        ))
+        .boxed()
+    }
+
+    fn new_property_parser_in_format(
+        format: ObjectFormat,
+        name: &str,
+        depth: usize,
+    ) -> ArcParser<Self>
+    where
+        Self: 'static,
+    {
+        match format {
+            ObjectFormat::Xml => collect_set::<T, Self>(xml_list_property_parser(
+                name,
+                depth,
+                0..=usize::MAX,
+                unique_items,
+            ))
+            .boxed(),
+            _ => format.property_parser(name, depth, Self::new_parser_in_format(format, depth + 1)),
+        }
+    }
+}
+
+impl<T: Parse + Ord> Parse for BTreeSet<T> {
+    fn new_parser() -> impl SendCreateParserState<Output = Self> {
+        collect_set::<T, Self>(set_parser(T::new_parser()))
+    }
+
+    fn new_parser_in_format(format: ObjectFormat, depth: usize) -> ArcParser<Self>
+    where
+        Self: 'static,
+    {
+        collect_set::<T, Self>(list_parser_in_format(
+            format,
+            depth,
+            0..=usize::MAX,
+            unique_items,
+        ))
+        .boxed()
+    }
+
+    fn new_property_parser_in_format(
+        format: ObjectFormat,
+        name: &str,
+        depth: usize,
+    ) -> ArcParser<Self>
+    where
+        Self: 'static,
+    {
+        match format {
+            ObjectFormat::Xml => collect_set::<T, Self>(xml_list_property_parser(
+                name,
+                depth,
+                0..=usize::MAX,
+                unique_items,
+            ))
+            .boxed(),
+            _ => format.property_parser(name, depth, Self::new_parser_in_format(format, depth + 1)),
+        }
+    }
+}
+
+#[test]
+fn test_set() {
+    let parser = HashSet::<i32>::new_parser();
+    let state = parser.create_parser_state();
+    let set = parser.parse(&state, b"[1, 2]").unwrap().unwrap_finished();
+    assert_eq!(set, HashSet::from([1, 2]));
+    // Duplicates are rejected as soon as the item is finished
+    assert!(parser.parse(&state, b"[1, 2, 1,").is_err());
+    assert!(parser.parse(&state, b"[1, 2, 1]").is_err());
+    assert!(parser.parse(&state, b"[1, 2, 12").is_ok());
+
+    let parser = BTreeSet::<String>::new_parser();
+    let state = parser.create_parser_state();
+    let state = parser
+        .parse(&state, br#"["a", "b"#)
+        .unwrap()
+        .unwrap_incomplete()
+        .0;
+    assert!(parser.parse(&state, br#"", "a""#).is_err());
+    assert_eq!(
         parser
-            .map_output(|output| Some(output))
-            .or(LiteralParser::new("null").map_output(|_| None))
+            .parse(&state, br#"", "c"]"#)
+            .unwrap()
+            .unwrap_finished(),
+        BTreeSet::from(["a".to_string(), "b".to_string(), "c".to_string()])
+    );
+}
+
+// Tuples are parsed as JSON arrays with one item for each element: `[first, second]`
+macro_rules! tuple_parser {
+    ($first:ident $(, $rest:ident)*; $nested:tt => $flat:tt) => {
+        impl<$first: Parse, $($rest: Parse),*> Parse for ($first, $($rest,)*) {
+            fn new_parser() -> impl SendCreateParserState<Output = Self> {
+                LiteralParser::new("[")
+                    .ignore_output_then(<$first as Parse>::new_parser().into_item())
+                    $(.then_literal(", ").then(<$rest as Parse>::new_parser().into_item()))*
+                    .then_literal("]")
+                    .map_output_with_value(
+                        |$nested| $flat,
+                        |parser, output| {
+                            let $flat = output.clone();
+                            parser.output_value(&$nested)
+                        },
+                    )
+            }
+        }
+    };
+}
+
+tuple_parser!(A; a => (a,));
+tuple_parser!(A, B; (a, b) => (a, b));
+tuple_parser!(A, B, C; ((a, b), c) => (a, b, c));
+tuple_parser!(A, B, C, D; (((a, b), c), d) => (a, b, c, d));
+tuple_parser!(A, B, C, D, E; ((((a, b), c), d), e) => (a, b, c, d, e));
+tuple_parser!(A, B, C, D, E, F; (((((a, b), c), d), e), f) => (a, b, c, d, e, f));
+
+#[test]
+fn test_tuple() {
+    let parser = <(String, i32, bool)>::new_parser();
+    let state = parser.create_parser_state();
+    assert_eq!(
+        parser
+            .parse(&state, br#"["a", 1, true]"#)
+            .unwrap()
+            .unwrap_finished(),
+        ("a".to_string(), 1, true)
+    );
+    assert!(parser.parse(&state, br#"["a", 1]"#).is_err());
+}
+
+/// The pattern of a hyphenated lowercase UUID.
+#[cfg(feature = "uuid")]
+pub(crate) const UUID_PATTERN: &str =
+    "[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}";
+
+/// The pattern of an http or https URL.
+#[cfg(feature = "url")]
+pub(crate) const URL_PATTERN: &str = "https?://[a-zA-Z0-9-]+([.][a-zA-Z0-9-]+)*(:[0-9]{1,4})?(/[a-zA-Z0-9._~%!$&'()*+,;=:@/-]*)?([?][a-zA-Z0-9._~%!$&'()*+,;=:@/?-]*)?";
+
+/// The pattern of a full date.
+#[cfg(feature = "chrono")]
+pub(crate) const DATE_PATTERN: &str = "[0-9]{4}-(0[1-9]|1[0-2])-(0[1-9]|[12][0-9]|3[01])";
+
+/// The pattern of an RFC 3339 date and time.
+#[cfg(feature = "chrono")]
+pub(crate) const DATE_TIME_PATTERN: &str = "[0-9]{4}-(0[1-9]|1[0-2])-(0[1-9]|[12][0-9]|3[01])T([01][0-9]|2[0-3]):[0-5][0-9]:[0-5][0-9]([.][0-9]{1,9})?(Z|[+-]([01][0-9]|2[0-3]):[0-5][0-9])";
+
+/// A parser for a JSON string that matches a pattern and is converted to the output with [`std::str::FromStr`].
+#[cfg(any(feature = "chrono", feature = "url", feature = "uuid"))]
+struct FromStrParser<T> {
+    parser: crate::RegexParser,
+    /// Write the output in the format of the pattern.
+    to_string: fn(&T) -> String,
+    /// Parse the text of an XML element that ends before the `<` of the closing tag instead of a quoted string.
+    xml_text: bool,
+}
+
+#[cfg(any(feature = "chrono", feature = "url", feature = "uuid"))]
+impl<T: std::str::FromStr + Clone + Send + Sync + 'static> FromStrParser<T> {
+    fn new(pattern: &str, to_string: fn(&T) -> String) -> Self {
+        Self {
+            parser: crate::RegexParser::new(&format!("\"(?:{pattern})\""))
+                .expect("The pattern is a valid regex"),
+            to_string,
+            xml_text: false,
+        }
+    }
+
+    /// Create a parser for the value in a format. XML writes the value without quotes.
+    fn in_format(pattern: &str, to_string: fn(&T) -> String, format: ObjectFormat) -> ArcParser<T> {
+        match format {
+            ObjectFormat::Xml => Self {
+                parser: crate::RegexParser::new(&format!("(?:{pattern})<"))
+                    .expect("The pattern is a valid regex"),
+                to_string,
+                xml_text: true,
+            }
+            .boxed(),
+            _ => format.scalar_parser(Self::new(pattern, to_string)),
+        }
+    }
+}
+
+#[cfg(any(feature = "chrono", feature = "url", feature = "uuid"))]
+impl<T: std::str::FromStr + Clone> CreateParserState for FromStrParser<T> {
+    fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+        self.parser.create_parser_state()
+    }
+}
+
+#[cfg(any(feature = "chrono", feature = "url", feature = "uuid"))]
+impl<T: std::str::FromStr + Clone> Parser for FromStrParser<T> {
+    type Output = T;
+    type PartialState = crate::RegexParserState;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> crate::ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+        match self.parser.parse(state, input)? {
+            ParseStatus::Incomplete {
+                new_state,
+                required_next,
+            } => Ok(ParseStatus::Incomplete {
+                new_state,
+                required_next,
+            }),
+            ParseStatus::Finished { result, remaining } => {
+                let (text, remaining) = if self.xml_text {
+                    // Leave the `<` of the closing tag for the next parser. The regex finishes on the `<`, so it is always in this input
+                    let text = &result[..result.len() - 1];
+                    (text, &input[input.len() - remaining.len() - 1..])
+                } else {
+                    // The pattern doesn't allow escapes, so the value is everything between the quotes
+                    (&result[1..result.len() - 1], remaining)
+                };
+                match text.parse() {
+                    Ok(result) => Ok(ParseStatus::Finished { result, remaining }),
+                    Err(_) => crate::bail!("{text} is not a valid value"),
+                }
+            }
+        }
+    }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        self.parser.state_key(state)
     }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        match self.parser.partial_value(state)? {
+            serde_json::Value::String(text) if self.xml_text => {
+                Some(serde_json::Value::String(text))
+            }
+            serde_json::Value::String(text) => {
+                let text = text.strip_prefix('"')?;
+                Some(serde_json::Value::String(text.to_string()))
+            }
+            value => Some(value),
+        }
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        Some(serde_json::Value::String((self.to_string)(output)))
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl Parse for uuid::Uuid {
+    fn new_parser() -> impl SendCreateParserState<Output = Self> {
+        FromStrParser::new(UUID_PATTERN, uuid::Uuid::to_string)
+    }
+
+    fn new_parser_in_format(format: ObjectFormat, _: usize) -> ArcParser<Self> {
+        FromStrParser::in_format(UUID_PATTERN, uuid::Uuid::to_string, format)
+    }
+}
+
+#[cfg(feature = "url")]
+impl Parse for url::Url {
+    fn new_parser() -> impl SendCreateParserState<Output = Self> {
+        FromStrParser::new(URL_PATTERN, url::Url::to_string)
+    }
+
+    fn new_parser_in_format(format: ObjectFormat, _: usize) -> ArcParser<Self> {
+        FromStrParser::in_format(URL_PATTERN, url::Url::to_string, format)
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl Parse for chrono::DateTime<chrono::Utc> {
+    fn new_parser() -> impl SendCreateParserState<Output = Self> {
+        FromStrParser::new(DATE_TIME_PATTERN, |date: &Self| date.to_rfc3339())
+    }
+
+    fn new_parser_in_format(format: ObjectFormat, _: usize) -> ArcParser<Self> {
+        FromStrParser::in_format(DATE_TIME_PATTERN, |date: &Self| date.to_rfc3339(), format)
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl Parse for chrono::DateTime<chrono::FixedOffset> {
+    fn new_parser() -> impl SendCreateParserState<Output = Self> {
+        FromStrParser::new(DATE_TIME_PATTERN, |date: &Self| date.to_rfc3339())
+    }
+
+    fn new_parser_in_format(format: ObjectFormat, _: usize) -> ArcParser<Self> {
+        FromStrParser::in_format(DATE_TIME_PATTERN, |date: &Self| date.to_rfc3339(), format)
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl Parse for chrono::NaiveDate {
+    fn new_parser() -> impl SendCreateParserState<Output = Self> {
+        FromStrParser::new(DATE_PATTERN, chrono::NaiveDate::to_string)
+    }
+
+    fn new_parser_in_format(format: ObjectFormat, _: usize) -> ArcParser<Self> {
+        FromStrParser::in_format(DATE_PATTERN, chrono::NaiveDate::to_string, format)
+    }
+}
+
+#[cfg(feature = "chrono")]
+#[test]
+fn test_date_time() {
+    let parser = chrono::DateTime::<chrono::Utc>::new_parser();
+    let state = parser.create_parser_state();
+    let date = parser
+        .parse(&state, br#""2024-02-29T12:30:00Z""#)
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(date.to_rfc3339(), "2024-02-29T12:30:00+00:00");
+    // The pattern allows the 30th of February, but it isn't a valid date
+    assert!(parser.parse(&state, br#""2023-02-30T12:30:00Z""#).is_err());
+}
+
+#[cfg(feature = "uuid")]
+#[test]
+fn test_uuid() {
+    let parser = uuid::Uuid::new_parser();
+    let state = parser.create_parser_state();
+    let uuid = parser
+        .parse(&state, br#""67e55044-10b1-426f-9247-bb680e5fe0c8""#)
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(uuid.to_string(), "67e55044-10b1-426f-9247-bb680e5fe0c8");
+
+    // XML text is not quoted and ends before the closing tag
+    let parser = uuid::Uuid::new_parser_in_format(ObjectFormat::Xml, 1);
+    let state = parser.create_parser_state();
+    let ParseStatus::Finished { result, remaining } = parser
+        .parse(&state, b"67e55044-10b1-426f-9247-bb680e5fe0c8</id>")
+        .unwrap()
+    else {
+        panic!("The parser did not finish");
+    };
+    assert_eq!(result, uuid);
+    assert_eq!(remaining, b"</id>");
+}
+
+#[cfg(feature = "url")]
+#[test]
+fn test_url() {
+    let parser = url::Url::new_parser();
+    let state = parser.create_parser_state();
+    let url = parser
+        .parse(&state, br#""https://floneum.com/kalosm?page=1""#)
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(url.host_str(), Some("floneum.com"));
+    assert!(parser.parse(&state, br#""floneum.com""#).is_err());
 }
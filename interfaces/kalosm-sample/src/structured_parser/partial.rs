@@ -0,0 +1,152 @@
+use serde_json::{Map, Value};
+
+use crate::{CreateParserState, ParseResult, ParseStatus, Parser};
+
+/// Merge the values of two parsers that run one after the other. Objects are merged into one object and arrays are joined. Otherwise the value of the second parser wins because most sequences are a value surrounded by text that has no value.
+pub(crate) fn merge_values(first: Option<Value>, second: Option<Value>) -> Option<Value> {
+    match (first, second) {
+        (Some(Value::Object(mut first)), Some(Value::Object(second))) => {
+            first.extend(second);
+            Some(Value::Object(first))
+        }
+        (Some(Value::Array(mut first)), Some(Value::Array(second))) => {
+            first.extend(second);
+            Some(Value::Array(first))
+        }
+        (first, None) => first,
+        (_, second) => second,
+    }
+}
+
+/// The value of an item in a list. Items keep their place in the list, even if the parser doesn't know their value.
+pub(crate) fn item_value<P: Parser>(parser: &P, output: &P::Output) -> Value {
+    parser.output_value(output).unwrap_or(Value::Null)
+}
+
+/// The values of the items in a list that are already parsed.
+pub(crate) fn items_value<P: Parser>(
+    parser: &P,
+    outputs: &crate::ArcLinkedList<P::Output>,
+) -> Vec<Value> {
+    outputs
+        .vec()
+        .iter()
+        .map(|output| item_value(parser, output))
+        .collect()
+}
+
+/// A parser that reads the value of another parser as the property of a JSON object. Created with [`crate::ParserExt::into_property`].
+///
+/// ```rust
+/// use kalosm_sample::*;
+/// use serde_json::json;
+///
+/// let parser = LiteralParser::new("name: ")
+///     .ignore_output_then(StringParser::new(0..=usize::MAX).into_property("name"))
+///     .then(LiteralParser::new(", age: ").ignore_output_then(U8Parser::new().into_property("age")));
+/// let state = parser.create_parser_state();
+/// let (state, _) = parser
+///     .parse(&state, br#"name: "Alice", age: 3"#)
+///     .unwrap()
+///     .unwrap_incomplete();
+/// assert_eq!(
+///     parser.partial_value(&state),
+///     Some(json!({ "name": "Alice", "age": 3 }))
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertyParser<P> {
+    parser: P,
+    name: String,
+}
+
+impl<P> PropertyParser<P> {
+    /// Create a new property parser.
+    pub fn new(parser: P, name: impl Into<String>) -> Self {
+        Self {
+            parser,
+            name: name.into(),
+        }
+    }
+
+    fn property(&self, value: Value) -> Value {
+        Value::Object(Map::from_iter([(self.name.clone(), value)]))
+    }
+}
+
+impl<P: CreateParserState> CreateParserState for PropertyParser<P> {
+    fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+        self.parser.create_parser_state()
+    }
+}
+
+impl<P: Parser> Parser for PropertyParser<P> {
+    type Output = P::Output;
+    type PartialState = P::PartialState;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+        self.parser.parse(state, input)
+    }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        self.parser.state_key(state)
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<Value> {
+        Some(self.property(self.parser.partial_value(state)?))
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<Value> {
+        // A finished property is always in the object, even if the parser doesn't know its value
+        let value = self.parser.output_value(output).unwrap_or(Value::Null);
+        Some(self.property(value))
+    }
+}
+
+/// A parser that reads the value of another parser as an item of a JSON array. Created with [`crate::ParserExt::into_item`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItemParser<P> {
+    parser: P,
+}
+
+impl<P> ItemParser<P> {
+    /// Create a new item parser.
+    pub fn new(parser: P) -> Self {
+        Self { parser }
+    }
+}
+
+impl<P: CreateParserState> CreateParserState for ItemParser<P> {
+    fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+        self.parser.create_parser_state()
+    }
+}
+
+impl<P: Parser> Parser for ItemParser<P> {
+    type Output = P::Output;
+    type PartialState = P::PartialState;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+        self.parser.parse(state, input)
+    }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        self.parser.state_key(state)
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<Value> {
+        Some(Value::Array(vec![self.parser.partial_value(state)?]))
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<Value> {
+        Some(Value::Array(vec![item_value(&self.parser, output)]))
+    }
+}
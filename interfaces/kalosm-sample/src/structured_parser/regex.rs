@@ -115,6 +115,20 @@ impl Parser for RegexParser {
             required_next: required_next.into(),
         })
     }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        // The text the regex accepts next only depends on the state of the DFA
+        Some(state.state.as_usize() as u64)
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        let text = String::from_utf8_lossy(&state.value);
+        Some(serde_json::Value::String(text.into_owned()))
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        Some(serde_json::Value::String(output.clone()))
+    }
 }
 
 /// The state of a regex parser.
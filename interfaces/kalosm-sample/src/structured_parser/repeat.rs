@@ -1,6 +1,6 @@
 use std::{borrow::Cow, sync::Arc};
 
-use crate::{CreateParserState, ParseStatus, Parser};
+use crate::{item_value, items_value, CreateParserState, ParseStatus, Parser};
 
 use super::ArcLinkedList;
 
@@ -184,6 +184,30 @@ where
             }
         }
     }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        // The items that were already parsed only matter through how many there are
+        Some(crate::hash_state_key((
+            self.parser.state_key(&state.last_state)?,
+            state.new_state_in_progress,
+            crate::count_state_key(state.outputs.len(), &self.length_range),
+        )))
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        let mut items = items_value(&self.parser, &state.outputs);
+        items.extend(self.parser.partial_value(&state.last_state));
+        Some(serde_json::Value::Array(items))
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        Some(serde_json::Value::Array(
+            output
+                .iter()
+                .map(|output| item_value(&self.parser, output))
+                .collect(),
+        ))
+    }
 }
 
 #[test]
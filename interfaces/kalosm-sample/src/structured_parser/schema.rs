@@ -71,10 +71,14 @@ pub enum SchemaType {
     Number(NumberSchema),
     /// An integer schema
     Integer(IntegerSchema),
+    /// An integer schema with a range
+    BoundedInteger(BoundedIntegerSchema),
     /// A boolean schema
     Boolean(BooleanSchema),
     /// An array schema
     Array(ArraySchema),
+    /// A tuple schema
+    Tuple(TupleSchema),
     /// An object schema
     Object(JsonObjectSchema),
     /// An enum schema
@@ -92,6 +96,14 @@ pub enum SchemaType {
 }
 
 impl SchemaType {
+    /// Get the syntax values that match the schema are written in. Only objects can be written in a format other than JSON
+    pub fn format(&self) -> ObjectFormat {
+        match self {
+            SchemaType::Object(schema) => schema.format(),
+            _ => ObjectFormat::Json,
+        }
+    }
+
     fn display_with_description(
         &self,
         f: &mut std::fmt::Formatter<'_>,
@@ -101,8 +113,10 @@ impl SchemaType {
             SchemaType::String(schema) => schema.display_with_description(f, description),
             SchemaType::Number(schema) => schema.display_with_description(f, description),
             SchemaType::Integer(schema) => schema.display_with_description(f, description),
+            SchemaType::BoundedInteger(schema) => schema.display_with_description(f, description),
             SchemaType::Boolean(schema) => schema.display_with_description(f, description),
             SchemaType::Array(schema) => schema.display_with_description(f, description),
+            SchemaType::Tuple(schema) => schema.display_with_description(f, description),
             SchemaType::Object(schema) => schema.display_with_description(f, description),
             SchemaType::Enum(schema) => schema.display_with_description(f, description),
             SchemaType::AnyOf(schema) => schema.display_with_description(f, description),
@@ -170,7 +184,7 @@ impl Display for IfThenSchema {
 /// A schema that matches any of the composite schemas
 #[derive(Debug, Clone)]
 pub struct AnyOfSchema {
-    any_of: Vec<SchemaType>,
+    pub(crate) any_of: Vec<SchemaType>,
 }
 
 impl AnyOfSchema {
@@ -220,7 +234,7 @@ impl Display for AnyOfSchema {
 /// A schema that matches one of the composite schemas
 #[derive(Debug, Clone)]
 pub struct OneOfSchema {
-    one_of: Vec<SchemaType>,
+    pub(crate) one_of: Vec<SchemaType>,
 }
 
 impl OneOfSchema {
@@ -270,7 +284,7 @@ impl Display for OneOfSchema {
 /// A schema for a constant
 #[derive(Debug, Clone)]
 pub struct ConstSchema {
-    value: SchemaLiteral,
+    pub(crate) value: SchemaLiteral,
 }
 
 impl ConstSchema {
@@ -314,7 +328,7 @@ fn test_const_schema() {
 /// A schema for an enum
 #[derive(Debug, Clone)]
 pub struct EnumSchema {
-    variants: Vec<SchemaLiteral>,
+    pub(crate) variants: Vec<SchemaLiteral>,
 }
 
 impl EnumSchema {
@@ -361,9 +375,11 @@ impl Display for EnumSchema {
 #[derive(Debug, Clone)]
 pub struct StringSchema {
     /// The length that is valid for the string
-    length: Option<std::ops::RangeInclusive<usize>>,
+    pub(crate) length: Option<std::ops::RangeInclusive<usize>>,
     /// The regex pattern that the string must match
-    pattern: Option<String>,
+    pub(crate) pattern: Option<String>,
+    /// The format of the string
+    pub(crate) format: Option<String>,
 }
 
 impl Schema for String {
@@ -372,6 +388,56 @@ impl Schema for String {
     }
 }
 
+impl Schema for char {
+    fn schema() -> SchemaType {
+        SchemaType::String(StringSchema::new().with_length(1..=1))
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl Schema for uuid::Uuid {
+    fn schema() -> SchemaType {
+        SchemaType::String(
+            StringSchema::new()
+                .with_pattern(crate::UUID_PATTERN)
+                .with_format("uuid"),
+        )
+    }
+}
+
+#[cfg(feature = "url")]
+impl Schema for url::Url {
+    fn schema() -> SchemaType {
+        SchemaType::String(
+            StringSchema::new()
+                .with_pattern(crate::URL_PATTERN)
+                .with_format("uri"),
+        )
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl<Tz: chrono::TimeZone> Schema for chrono::DateTime<Tz> {
+    fn schema() -> SchemaType {
+        SchemaType::String(
+            StringSchema::new()
+                .with_pattern(crate::DATE_TIME_PATTERN)
+                .with_format("date-time"),
+        )
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl Schema for chrono::NaiveDate {
+    fn schema() -> SchemaType {
+        SchemaType::String(
+            StringSchema::new()
+                .with_pattern(crate::DATE_PATTERN)
+                .with_format("date"),
+        )
+    }
+}
+
 impl Default for StringSchema {
     fn default() -> Self {
         Self::new()
@@ -384,6 +450,7 @@ impl StringSchema {
         Self {
             length: None,
             pattern: None,
+            format: None,
         }
     }
 
@@ -402,6 +469,12 @@ impl StringSchema {
         self
     }
 
+    /// Set the format of the string, like `date-time` or `uuid`
+    pub fn with_format(mut self, format: impl ToString) -> Self {
+        self.format = Some(format.to_string());
+        self
+    }
+
     fn display_with_description(
         &self,
         f: &mut std::fmt::Formatter<'_>,
@@ -425,6 +498,9 @@ impl StringSchema {
             if let Some(pattern) = &self.pattern {
                 writer.write_fmt(format_args!(",\n\"pattern\": \"{}\"", pattern))?;
             }
+            if let Some(format) = &self.format {
+                writer.write_fmt(format_args!(",\n\"format\": \"{}\"", format))?;
+            }
         }
         f.write_str("\n}")
     }
@@ -440,7 +516,7 @@ impl Display for StringSchema {
 #[derive(Debug, Clone)]
 pub struct NumberSchema {
     /// The range that the number must be in
-    range: Option<std::ops::RangeInclusive<f64>>,
+    pub(crate) range: Option<std::ops::RangeInclusive<f64>>,
 }
 
 macro_rules! impl_schema_for_number {
@@ -530,13 +606,18 @@ impl IntegerSchema {
     pub fn new() -> Self {
         Self
     }
+
+    /// Limit the integer to a range
+    pub fn with_range(self, range: std::ops::RangeInclusive<i128>) -> BoundedIntegerSchema {
+        BoundedIntegerSchema { range }
+    }
 }
 
 macro_rules! impl_schema_for_integer {
     ($ty:ty) => {
         impl Schema for $ty {
             fn schema() -> SchemaType {
-                SchemaType::Number(NumberSchema::new())
+                SchemaType::Integer(IntegerSchema::new())
             }
         }
     };
@@ -586,11 +667,65 @@ fn test_integer_schema() {
     assert_eq!(schema.to_string(), "{ \"type\": \"integer\" }");
 }
 
+/// A schema for an integer in a range. Created with [`IntegerSchema::with_range`]
+#[derive(Debug, Clone)]
+pub struct BoundedIntegerSchema {
+    /// The range that the integer must be in
+    pub(crate) range: std::ops::RangeInclusive<i128>,
+}
+
+impl BoundedIntegerSchema {
+    fn display_with_description(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        description: Option<&str>,
+    ) -> std::fmt::Result {
+        f.write_char('{')?;
+        {
+            let mut writer = IndentationWriter::new(1, f);
+            if let Some(description) = description {
+                write!(&mut writer, "\n\"description\": \"{description}\",")?;
+            }
+            writer.write_str("\n\"type\": \"integer\",")?;
+            writer.write_fmt(format_args!("\n\"minimum\": {},", self.range.start()))?;
+            writer.write_fmt(format_args!("\n\"maximum\": {}", self.range.end()))?;
+        }
+        f.write_str("\n}")
+    }
+}
+
+impl Display for BoundedIntegerSchema {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.display_with_description(f, None)
+    }
+}
+
+#[test]
+fn test_bounded_integer_schema() {
+    let schema = IntegerSchema::new().with_range(0..=10);
+
+    assert_eq!(
+        schema.to_string(),
+        "{\n\t\"type\": \"integer\",\n\t\"minimum\": 0,\n\t\"maximum\": 10\n}"
+    );
+}
+
 /// A schema for a boolean
 #[derive(Debug, Clone, Default)]
 pub struct BooleanSchema;
 
+impl Schema for bool {
+    fn schema() -> SchemaType {
+        SchemaType::Boolean(BooleanSchema::new())
+    }
+}
+
 impl BooleanSchema {
+    /// Create a new boolean schema
+    pub fn new() -> Self {
+        Self
+    }
+
     fn display_with_description(
         &self,
         f: &mut std::fmt::Formatter<'_>,
@@ -623,8 +758,9 @@ fn test_boolean_schema() {
 /// A schema for an array
 #[derive(Debug, Clone)]
 pub struct ArraySchema {
-    items: Box<SchemaType>,
-    length: Option<std::ops::RangeInclusive<usize>>,
+    pub(crate) items: Box<SchemaType>,
+    pub(crate) length: Option<std::ops::RangeInclusive<usize>>,
+    pub(crate) unique_items: bool,
 }
 
 impl<T: Schema> Schema for Vec<T> {
@@ -639,12 +775,25 @@ impl<const N: usize, T: Schema> Schema for [T; N] {
     }
 }
 
+impl<T: Schema> Schema for std::collections::HashSet<T> {
+    fn schema() -> SchemaType {
+        SchemaType::Array(ArraySchema::new(T::schema()).with_unique_items(true))
+    }
+}
+
+impl<T: Schema> Schema for std::collections::BTreeSet<T> {
+    fn schema() -> SchemaType {
+        SchemaType::Array(ArraySchema::new(T::schema()).with_unique_items(true))
+    }
+}
+
 impl ArraySchema {
     /// Create a new array schema
     pub fn new(items: SchemaType) -> Self {
         Self {
             items: Box::new(items),
             length: None,
+            unique_items: false,
         }
     }
 
@@ -657,6 +806,12 @@ impl ArraySchema {
         self
     }
 
+    /// Set whether every item in the array must be unique
+    pub fn with_unique_items(mut self, unique_items: bool) -> Self {
+        self.unique_items = unique_items;
+        self
+    }
+
     fn display_with_description(
         &self,
         f: &mut std::fmt::Formatter<'_>,
@@ -681,6 +836,9 @@ impl ArraySchema {
                     write!(&mut writer, "{}", length.end())?;
                 }
             }
+            if self.unique_items {
+                writer.write_str(",\n\"uniqueItems\": true")?;
+            }
             writer.write_str(",\n\"unevaluatedItems\": false")?;
         }
         f.write_str("\n}")
@@ -699,8 +857,10 @@ fn test_array_schema() {
         items: Box::new(SchemaType::String(StringSchema {
             length: Some(1..=10),
             pattern: None,
+            format: None,
         })),
         length: Some(0..=10),
+        unique_items: false,
     };
 
     assert_eq!(schema.to_string(), "{\n\t\"type\": \"array\",\n\t\"items\": {\n\t\t\"type\": \"string\",\n\t\t\"minLength\": 1,\n\t\t\"maxLength\": 10\n\t},\n\t\"maxItems\": 10,\n\t\"unevaluatedItems\": false\n}");
@@ -709,8 +869,10 @@ fn test_array_schema() {
         items: Box::new(SchemaType::String(StringSchema {
             length: None,
             pattern: None,
+            format: None,
         })),
         length: Some(1..=usize::MAX),
+        unique_items: false,
     };
 
     assert_eq!(schema.to_string(), "{\n\t\"type\": \"array\",\n\t\"items\": {\n\t\t\"type\": \"string\"\n\t},\n\t\"minItems\": 1,\n\t\"unevaluatedItems\": false\n}");
@@ -718,11 +880,145 @@ fn test_array_schema() {
         items: Box::new(SchemaType::String(StringSchema {
             length: None,
             pattern: None,
+            format: None,
         })),
         length: None,
+        unique_items: false,
     };
 
     assert_eq!(schema.to_string(), "{\n\t\"type\": \"array\",\n\t\"items\": {\n\t\t\"type\": \"string\"\n\t},\n\t\"unevaluatedItems\": false\n}");
+
+    let schema = ArraySchema {
+        items: Box::new(SchemaType::String(StringSchema {
+            length: None,
+            pattern: None,
+            format: None,
+        })),
+        length: Some(1..=usize::MAX),
+        unique_items: true,
+    };
+
+    assert_eq!(schema.to_string(), "{\n\t\"type\": \"array\",\n\t\"items\": {\n\t\t\"type\": \"string\"\n\t},\n\t\"minItems\": 1,\n\t\"uniqueItems\": true,\n\t\"unevaluatedItems\": false\n}");
+}
+
+/// A schema for an array with a fixed number of items that each have their own schema
+#[derive(Debug, Clone)]
+pub struct TupleSchema {
+    pub(crate) items: Vec<SchemaType>,
+}
+
+macro_rules! impl_schema_for_tuple {
+    ($($ty:ident),+) => {
+        impl<$($ty: Schema),+> Schema for ($($ty,)+) {
+            fn schema() -> SchemaType {
+                SchemaType::Tuple(TupleSchema::new([$($ty::schema()),+]))
+            }
+        }
+    };
+}
+
+impl_schema_for_tuple!(A);
+impl_schema_for_tuple!(A, B);
+impl_schema_for_tuple!(A, B, C);
+impl_schema_for_tuple!(A, B, C, D);
+impl_schema_for_tuple!(A, B, C, D, E);
+impl_schema_for_tuple!(A, B, C, D, E, F);
+
+impl TupleSchema {
+    /// Create a new tuple schema
+    pub fn new(items: impl IntoIterator<Item = SchemaType>) -> Self {
+        Self {
+            items: items.into_iter().collect(),
+        }
+    }
+
+    fn display_with_description(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        description: Option<&str>,
+    ) -> std::fmt::Result {
+        f.write_char('{')?;
+        {
+            let mut writer = IndentationWriter::new(1, f);
+            if let Some(description) = description {
+                write!(&mut writer, "\n\"description\": \"{description}\",")?;
+            }
+            writer.write_str("\n\"type\": \"array\"")?;
+            writer.write_str(",\n\"prefixItems\": [")?;
+            if !self.items.is_empty() {
+                writer.with_indent(|writer| {
+                    for (i, item) in self.items.iter().enumerate() {
+                        if i > 0 {
+                            writer.write_char(',')?;
+                        }
+                        write!(writer, "\n{}", item)?;
+                    }
+                    Ok(())
+                })?;
+                writer.write_str("\n")?;
+            }
+            writer.write_str("]")?;
+            write!(&mut writer, ",\n\"minItems\": {}", self.items.len())?;
+            write!(&mut writer, ",\n\"maxItems\": {}", self.items.len())?;
+            writer.write_str(",\n\"unevaluatedItems\": false")?;
+        }
+        f.write_str("\n}")
+    }
+}
+
+impl Display for TupleSchema {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.display_with_description(f, None)
+    }
+}
+
+#[test]
+fn test_tuple_schema() {
+    let schema = <(String, bool)>::schema();
+
+    assert_eq!(schema.to_string(), "{\n\t\"type\": \"array\",\n\t\"prefixItems\": [\n\t\t{\n\t\t\t\"type\": \"string\"\n\t\t},\n\t\t{ \"type\": \"boolean\" }\n\t],\n\t\"minItems\": 2,\n\t\"maxItems\": 2,\n\t\"unevaluatedItems\": false\n}");
+}
+
+/// The syntax an object is written in. Strings, lists and nested objects are written in the syntax of the format. Types the format can't write natively, like tuples and enums with data, are written as JSON
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ObjectFormat {
+    /// `{ "name": value, "other": value }`
+    #[default]
+    Json,
+    /// A `name: value` line for each property. Nested objects are indented under their property and lists and maps are written in the flow style: `[first, second]`
+    Yaml,
+    /// A `name = value` line for each property. Nested objects are written as inline tables: `{ name = value }`
+    Toml,
+    /// An element named after the title of the object with a `<name>value</name>` element for each property. Text is written without quotes and lists are written as one element for each item
+    Xml,
+}
+
+impl ObjectFormat {
+    fn write_comment(self, f: &mut dyn std::fmt::Write, comment: &str) -> std::fmt::Result {
+        match self {
+            ObjectFormat::Xml => write!(f, "<!-- {comment} -->"),
+            _ => {
+                for (i, line) in comment.lines().enumerate() {
+                    if i > 0 {
+                        f.write_char('\n')?;
+                    }
+                    write!(f, "# {line}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Display for ObjectFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObjectFormat::Json => f.write_str("JSON"),
+            ObjectFormat::Yaml => f.write_str("YAML"),
+            ObjectFormat::Toml => f.write_str("TOML"),
+            ObjectFormat::Xml => f.write_str("XML"),
+        }
+    }
 }
 
 /// A schema for an object
@@ -730,7 +1026,21 @@ fn test_array_schema() {
 pub struct JsonObjectSchema {
     title: Option<String>,
     description: Option<&'static str>,
-    properties: Vec<JsonPropertySchema>,
+    pub(crate) properties: Vec<JsonPropertySchema>,
+    pub(crate) additional_properties: Option<Box<SchemaType>>,
+    format: ObjectFormat,
+}
+
+impl<T: Schema> Schema for std::collections::HashMap<String, T> {
+    fn schema() -> SchemaType {
+        SchemaType::Object(JsonObjectSchema::new([]).with_additional_properties(T::schema()))
+    }
+}
+
+impl<T: Schema> Schema for std::collections::BTreeMap<String, T> {
+    fn schema() -> SchemaType {
+        SchemaType::Object(JsonObjectSchema::new([]).with_additional_properties(T::schema()))
+    }
 }
 
 impl JsonObjectSchema {
@@ -740,9 +1050,28 @@ impl JsonObjectSchema {
             title: None,
             description: None,
             properties: properties.into_iter().collect(),
+            additional_properties: None,
+            format: ObjectFormat::Json,
         }
     }
 
+    /// Set the syntax the object is written in. Objects are written as JSON by default
+    pub fn with_format(mut self, format: ObjectFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Get the syntax the object is written in
+    pub fn format(&self) -> ObjectFormat {
+        self.format
+    }
+
+    /// Allow properties other than the listed properties if they match the schema
+    pub fn with_additional_properties(mut self, schema: impl Into<Option<SchemaType>>) -> Self {
+        self.additional_properties = schema.into().map(Box::new);
+        self
+    }
+
     /// Set the title of the object
     pub fn with_title(mut self, title: impl ToString) -> Self {
         self.title = Some(title.to_string());
@@ -760,6 +1089,9 @@ impl JsonObjectSchema {
         f: &mut std::fmt::Formatter<'_>,
         description: Option<&str>,
     ) -> std::fmt::Result {
+        if self.format != ObjectFormat::Json {
+            return self.display_template(f, description);
+        }
         f.write_char('{')?;
         {
             let mut writer = IndentationWriter::new(1, f);
@@ -807,12 +1139,76 @@ impl JsonObjectSchema {
                 }
                 writer.write_str("]")?;
             }
-            writer.write_str(",\n\"additionalProperties\": false")?;
+            match &self.additional_properties {
+                Some(schema) => write!(writer, ",\n\"additionalProperties\": {}", schema)?,
+                None => writer.write_str(",\n\"additionalProperties\": false")?,
+            }
         }
         f.write_str("\n}")
     }
 }
 
+impl JsonObjectSchema {
+    // Objects in other formats are displayed as a template of the object with the JSON schema of each value in place of the value
+    fn display_template(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        description: Option<&str>,
+    ) -> std::fmt::Result {
+        let format = self.format;
+        let mut comments = Vec::new();
+        comments.extend(description);
+        if format != ObjectFormat::Xml {
+            comments.extend(self.title.as_deref());
+        }
+        comments.extend(self.description);
+        for comment in comments {
+            format.write_comment(f, comment)?;
+            f.write_char('\n')?;
+        }
+        let tag = self.title.as_deref().unwrap_or("object");
+        let mut writer = if format == ObjectFormat::Xml {
+            write!(f, "<{tag}>")?;
+            IndentationWriter::new(1, f)
+        } else {
+            IndentationWriter::new(0, f)
+        };
+        let mut first = format != ObjectFormat::Xml;
+        for property in &self.properties {
+            let mut comments = Vec::new();
+            comments.extend(property.description);
+            if !property.required {
+                comments.push("optional");
+            }
+            for comment in comments {
+                if !std::mem::take(&mut first) {
+                    writer.write_char('\n')?;
+                }
+                format.write_comment(&mut writer, comment)?;
+            }
+            if !std::mem::take(&mut first) {
+                writer.write_char('\n')?;
+            }
+            let name = &property.name;
+            match format {
+                ObjectFormat::Yaml => write!(writer, "{name}: {}", property.ty)?,
+                ObjectFormat::Toml => write!(writer, "{name} = {}", property.ty)?,
+                _ => write!(writer, "<{name}>{}</{name}>", property.ty)?,
+            }
+        }
+        if let Some(schema) = &self.additional_properties {
+            if !first {
+                writer.write_char('\n')?;
+            }
+            format.write_comment(&mut writer, &format!("any other properties: {schema}"))?;
+        }
+        if format == ObjectFormat::Xml {
+            write!(f, "\n</{tag}>")?;
+        }
+        Ok(())
+    }
+}
+
 impl Display for JsonObjectSchema {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.display_with_description(f, None)
@@ -832,6 +1228,7 @@ fn test_object_schema() {
                 ty: SchemaType::String(StringSchema {
                     length: Some(1..=10),
                     pattern: None,
+                    format: None,
                 }),
             },
             JsonPropertySchema {
@@ -851,18 +1248,41 @@ fn test_object_schema() {
                 }),
             },
         ],
+        additional_properties: None,
+        format: ObjectFormat::Json,
     };
 
     assert_eq!(schema.to_string(), "{\n\t\"title\": \"Person\",\n\t\"description\": \"A person\",\n\t\"type\": \"object\",\n\t\"properties\": {\n\t\t\"name\": {\n\t\t\t\"type\": \"string\",\n\t\t\t\"minLength\": 1,\n\t\t\t\"maxLength\": 10\n\t\t},\n\t\t\"age\": {\n\t\t\t\"type\": \"number\",\n\t\t\t\"minimum\": 0,\n\t\t\t\"maximum\": 100\n\t\t},\n\t\t\"height\": {\n\t\t\t\"type\": \"number\",\n\t\t\t\"minimum\": 0,\n\t\t\t\"maximum\": 500\n\t\t}\n\t},\n\t\"required\": [\"name\", \"age\"],\n\t\"additionalProperties\": false\n}");
 }
 
+#[test]
+fn test_object_schema_formats() {
+    let schema = JsonObjectSchema::new([
+        JsonPropertySchema::new("name", SchemaType::String(StringSchema::new()))
+            .with_description("The name of the person")
+            .with_required(true),
+        JsonPropertySchema::new("age", SchemaType::Integer(IntegerSchema::new())),
+    ])
+    .with_title("Person")
+    .with_description("A person");
+
+    let yaml = schema.clone().with_format(ObjectFormat::Yaml);
+    assert_eq!(yaml.to_string(), "# Person\n# A person\n# The name of the person\nname: {\n\t\"type\": \"string\"\n}\n# optional\nage: { \"type\": \"integer\" }");
+
+    let toml = schema.clone().with_format(ObjectFormat::Toml);
+    assert_eq!(toml.to_string(), "# Person\n# A person\n# The name of the person\nname = {\n\t\"type\": \"string\"\n}\n# optional\nage = { \"type\": \"integer\" }");
+
+    let xml = schema.with_format(ObjectFormat::Xml);
+    assert_eq!(xml.to_string(), "<!-- A person -->\n<Person>\n\t<!-- The name of the person -->\n\t<name>{\n\t\t\"type\": \"string\"\n\t}</name>\n\t<!-- optional -->\n\t<age>{ \"type\": \"integer\" }</age>\n</Person>");
+}
+
 /// A schema for a property of an object
 #[derive(Debug, Clone)]
 pub struct JsonPropertySchema {
-    name: String,
+    pub(crate) name: String,
     description: Option<&'static str>,
-    required: bool,
-    ty: SchemaType,
+    pub(crate) required: bool,
+    pub(crate) ty: SchemaType,
 }
 
 impl JsonPropertySchema {
@@ -902,6 +1322,14 @@ pub trait Schema {
     fn schema() -> SchemaType;
 }
 
+/// A description of the properties of a type that is an object
+///
+/// `#[derive(Schema)]` implements this trait for structs with named fields so they can be flattened into other structs with `#[parse(flatten)]`.
+pub trait SchemaProperties: Schema {
+    /// Get the schema for each property of the type
+    fn properties() -> Vec<JsonPropertySchema>;
+}
+
 impl<T: Schema> Schema for Option<T> {
     fn schema() -> SchemaType {
         SchemaType::OneOf(OneOfSchema::new([SchemaType::Null, T::schema()]))
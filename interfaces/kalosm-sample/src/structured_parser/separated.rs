@@ -1,6 +1,6 @@
 use std::{borrow::Cow, sync::Arc};
 
-use crate::{CreateParserState, ParseStatus, Parser};
+use crate::{item_value, items_value, CreateParserState, ParseStatus, Parser};
 
 use super::ArcLinkedList;
 
@@ -244,6 +244,40 @@ impl<P: CreateParserState, S: CreateParserState> Parser for SeparatedParser<P, S
             required_next: required_next.unwrap_or_default(),
         })
     }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        let last_state = match &state.last_state {
+            SeparatedItemState::Item(item) => {
+                crate::combine_state_key(0, self.parser.state_key(item)?)
+            }
+            SeparatedItemState::Separator(separator) => {
+                crate::combine_state_key(1, self.separator.state_key(separator)?)
+            }
+        };
+        // The items that were already parsed only matter through how many there are
+        Some(crate::hash_state_key((
+            last_state,
+            state.new_state_in_progress,
+            crate::count_state_key(state.outputs.len(), &self.length_range),
+        )))
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        let mut items = items_value(&self.parser, &state.outputs);
+        if let SeparatedItemState::Item(item) = &state.last_state {
+            items.extend(self.parser.partial_value(item));
+        }
+        Some(serde_json::Value::Array(items))
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        Some(serde_json::Value::Array(
+            output
+                .iter()
+                .map(|output| item_value(&self.parser, output))
+                .collect(),
+        ))
+    }
 }
 
 #[test]
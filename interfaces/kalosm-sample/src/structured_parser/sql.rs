@@ -0,0 +1,625 @@
+use std::{borrow::Cow, fmt::Display, fmt::Write};
+
+use crate::GrammarParser;
+
+/// The type of a column in a [`SqlTable`]. Literals compared to the column must have this type.
+///
+/// The types are the column affinities SQLite uses. [`SqlType::from_declared_type`] reads the affinity from the type a column was declared with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SqlType {
+    /// Whole numbers like `-12`
+    Integer,
+    /// Numbers like `1.5` or `2`
+    Real,
+    /// Numbers like `1.5` or `2`. SQLite uses this for `NUMERIC`, `DECIMAL`, `BOOLEAN` and `DATE` columns
+    Numeric,
+    /// Strings like `'it''s'`
+    Text,
+    /// Hex encoded bytes like `X'00ff'`
+    Blob,
+}
+
+impl SqlType {
+    /// Get the type of a column from the type in its declaration with the [rules SQLite uses](https://www.sqlite.org/datatype3.html#determination_of_column_affinity).
+    ///
+    /// ```rust
+    /// use kalosm_sample::*;
+    ///
+    /// assert_eq!(SqlType::from_declared_type("BIGINT"), SqlType::Integer);
+    /// assert_eq!(SqlType::from_declared_type("VARCHAR(255)"), SqlType::Text);
+    /// assert_eq!(SqlType::from_declared_type("DOUBLE"), SqlType::Real);
+    /// assert_eq!(SqlType::from_declared_type("BOOLEAN"), SqlType::Numeric);
+    /// assert_eq!(SqlType::from_declared_type(""), SqlType::Blob);
+    /// ```
+    pub fn from_declared_type(declared_type: &str) -> Self {
+        let declared_type = declared_type.to_ascii_uppercase();
+        let contains = |names: &[&str]| names.iter().any(|name| declared_type.contains(name));
+        if contains(&["INT"]) {
+            Self::Integer
+        } else if contains(&["CHAR", "CLOB", "TEXT"]) {
+            Self::Text
+        } else if declared_type.is_empty() || contains(&["BLOB"]) {
+            Self::Blob
+        } else if contains(&["REAL", "FLOA", "DOUB"]) {
+            Self::Real
+        } else {
+            Self::Numeric
+        }
+    }
+
+    /// The name of the rule for literals of this type in the grammar.
+    fn literal_rule(&self) -> &'static str {
+        match self {
+            Self::Integer => "integer",
+            Self::Real | Self::Numeric => "real",
+            Self::Text => "text",
+            Self::Blob => "blob",
+        }
+    }
+
+    fn is_number(&self) -> bool {
+        matches!(self, Self::Integer | Self::Real | Self::Numeric)
+    }
+}
+
+impl Display for SqlType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Integer => write!(f, "INTEGER"),
+            Self::Real => write!(f, "REAL"),
+            Self::Numeric => write!(f, "NUMERIC"),
+            Self::Text => write!(f, "TEXT"),
+            Self::Blob => write!(f, "BLOB"),
+        }
+    }
+}
+
+/// A table in a [`SqlSchema`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqlTable {
+    name: String,
+    columns: Vec<(String, SqlType)>,
+}
+
+impl SqlTable {
+    /// Create a new table with no columns.
+    pub fn new(name: impl ToString) -> Self {
+        Self {
+            name: name.to_string(),
+            columns: Vec::new(),
+        }
+    }
+
+    /// Add a column to the table.
+    pub fn with_column(mut self, name: impl ToString, ty: SqlType) -> Self {
+        self.columns.push((name.to_string(), ty));
+        self
+    }
+
+    /// Get the name of the table.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the names and types of the columns in the table.
+    pub fn columns(&self) -> &[(String, SqlType)] {
+        &self.columns
+    }
+}
+
+impl Display for SqlTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CREATE TABLE {} (", quote_identifier(&self.name))?;
+        for (i, (name, ty)) in self.columns.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} {ty}", quote_identifier(name))?;
+        }
+        write!(f, ");")
+    }
+}
+
+/// The tables in a database. The schema can build a parser for read only SQL queries against the database.
+///
+/// The queries the parser accepts are a safe subset of SQLite `SELECT` statements on one table. Queries can only use the tables and columns in the schema, and literals must match the type of the column they are compared to. Each query ends with a `;`:
+///
+/// ```sql
+/// SELECT [DISTINCT] (* | result, ...) FROM table
+///     [WHERE condition (AND | OR) ...]
+///     [GROUP BY column, ...]
+///     [ORDER BY column [ASC | DESC], ...]
+///     [LIMIT number];
+/// ```
+///
+/// A result is a column, `COUNT(*)`, `COUNT(column)`, `MIN(column)`, `MAX(column)`, `SUM(number_column)` or `AVG(number_column)`. A condition is `column IS [NOT] NULL`, `column (= | != | < | <= | > | >=) literal`, `column IN (literal, ...)` or `text_column LIKE 'pattern'`.
+///
+/// The [`Display`] implementation writes the schema as `CREATE TABLE` statements you can add to the prompt.
+///
+/// ```rust
+/// use kalosm_sample::*;
+///
+/// let schema = SqlSchema::new().with_table(
+///     SqlTable::new("users")
+///         .with_column("id", SqlType::Integer)
+///         .with_column("name", SqlType::Text),
+/// );
+/// assert_eq!(
+///     schema.to_string(),
+///     "CREATE TABLE users (id INTEGER, name TEXT);"
+/// );
+///
+/// let parser = schema.parser();
+/// let state = parser.create_parser_state();
+/// let query = "SELECT name FROM users WHERE id > 10 ORDER BY name LIMIT 5;";
+/// let result = parser.parse(&state, query.as_bytes()).unwrap();
+/// assert_eq!(result.unwrap_finished(), query);
+///
+/// // The table must exist
+/// assert!(parser.parse(&state, b"SELECT * FROM posts;").is_err());
+/// // Integer columns can't be compared to text
+/// assert!(parser
+///     .parse(&state, b"SELECT * FROM users WHERE id = 'one';")
+///     .is_err());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SqlSchema {
+    tables: Vec<SqlTable>,
+}
+
+impl SqlSchema {
+    /// Create a new schema with no tables.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a table to the schema.
+    pub fn with_table(mut self, table: SqlTable) -> Self {
+        self.tables.push(table);
+        self
+    }
+
+    /// Get the tables in the schema.
+    pub fn tables(&self) -> &[SqlTable] {
+        &self.tables
+    }
+
+    /// Read the schema of every table in a SQLite database with `PRAGMA table_info`. This requires the `rusqlite` feature, which the `kalosm-language` and `kalosm` crates forward.
+    #[cfg(feature = "rusqlite")]
+    pub fn from_sqlite(connection: &rusqlite::Connection) -> rusqlite::Result<Self> {
+        let mut tables = connection.prepare(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name",
+        )?;
+        let table_names = tables
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut columns = connection.prepare("SELECT name, type FROM pragma_table_info(?1)")?;
+        let mut schema = Self::new();
+        for table_name in table_names {
+            let mut table = SqlTable::new(&table_name);
+            let rows = columns.query_map([&table_name], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?;
+            for row in rows {
+                let (name, declared_type) = row?;
+                table = table.with_column(name, SqlType::from_declared_type(&declared_type));
+            }
+            schema = schema.with_table(table);
+        }
+        Ok(schema)
+    }
+
+    /// Get the [GBNF](GrammarParser) grammar for queries against the schema. Tables without columns are left out.
+    pub fn grammar(&self) -> String {
+        let mut grammar = String::new();
+        let tables = self
+            .tables
+            .iter()
+            .enumerate()
+            .filter(|(_, table)| !table.columns.is_empty());
+        let queries: Vec<_> = tables.clone().map(|(i, _)| format!("t{i}")).collect();
+        _ = writeln!(grammar, r#"root ::= query ";""#);
+        _ = writeln!(grammar, "query ::= {}", queries.join(" | "));
+
+        for (i, table) in tables {
+            let t = format!("t{i}");
+            let columns_of = |filter: &dyn Fn(SqlType) -> bool| {
+                table
+                    .columns
+                    .iter()
+                    .filter(|(_, ty)| filter(*ty))
+                    .map(|(name, _)| gbnf_literal(&quote_identifier(name)))
+                    .collect::<Vec<_>>()
+            };
+
+            _ = writeln!(
+                grammar,
+                r#"{t} ::= "SELECT " "DISTINCT "? {t}-results " FROM " {} {t}-where? {t}-group? {t}-order? limit?"#,
+                gbnf_literal(&quote_identifier(&table.name))
+            );
+            _ = writeln!(
+                grammar,
+                r#"{t}-results ::= "*" | {t}-result (", " {t}-result)*"#
+            );
+            let mut results = vec![
+                format!("{t}-column"),
+                r#""COUNT(*)""#.to_string(),
+                format!(r#"("COUNT" | "MIN" | "MAX") "(" {t}-column ")""#),
+            ];
+            let numbers = columns_of(&|ty| ty.is_number());
+            if !numbers.is_empty() {
+                _ = writeln!(grammar, "{t}-number ::= {}", numbers.join(" | "));
+                results.push(format!(r#"("SUM" | "AVG") "(" {t}-number ")""#));
+            }
+            _ = writeln!(grammar, "{t}-result ::= {}", results.join(" | "));
+            _ = writeln!(
+                grammar,
+                "{t}-column ::= {}",
+                columns_of(&|_| true).join(" | ")
+            );
+
+            // Each type of column is compared to literals of the same type
+            let mut conditions = vec![format!(r#"{t}-column " IS " "NOT "? "NULL""#)];
+            for ty in [
+                SqlType::Integer,
+                SqlType::Real,
+                SqlType::Numeric,
+                SqlType::Text,
+                SqlType::Blob,
+            ] {
+                let columns = columns_of(&|other| other == ty);
+                if columns.is_empty() {
+                    continue;
+                }
+                let rule = format!("{t}-{}", ty.to_string().to_lowercase());
+                let literal = ty.literal_rule();
+                _ = writeln!(grammar, "{rule} ::= {}", columns.join(" | "));
+                conditions.push(format!(r#"{rule} " " comparison " " {literal}"#));
+                conditions.push(format!(r#"{rule} " IN (" {literal} (", " {literal})* ")""#));
+                if ty == SqlType::Text {
+                    conditions.push(format!(r#"{rule} " LIKE " text"#));
+                }
+            }
+            _ = writeln!(grammar, "{t}-condition ::= {}", conditions.join(" | "));
+            _ = writeln!(
+                grammar,
+                r#"{t}-where ::= " WHERE " {t}-condition ((" AND " | " OR ") {t}-condition)*"#
+            );
+            _ = writeln!(
+                grammar,
+                r#"{t}-group ::= " GROUP BY " {t}-column (", " {t}-column)*"#
+            );
+            _ = writeln!(
+                grammar,
+                r#"{t}-order ::= " ORDER BY " {t}-ordering (", " {t}-ordering)*"#
+            );
+            _ = writeln!(
+                grammar,
+                r#"{t}-ordering ::= {t}-column (" ASC" | " DESC")?"#
+            );
+        }
+
+        grammar.push_str(
+            r#"limit ::= " LIMIT " [0-9]+
+comparison ::= "=" | "!=" | "<" | "<=" | ">" | ">="
+integer ::= "-"? [0-9]+
+real ::= "-"? [0-9]+ ("." [0-9]+)?
+text ::= "'" ([^'] | "''")* "'"
+blob ::= "X'" ([0-9a-fA-F] [0-9a-fA-F])* "'"
+"#,
+        );
+        grammar
+    }
+
+    /// Create a parser for queries against the schema. The parser outputs the query it parsed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no table in the schema has any columns.
+    pub fn parser(&self) -> GrammarParser {
+        assert!(
+            self.tables.iter().any(|table| !table.columns.is_empty()),
+            "The schema must contain at least one table with columns"
+        );
+        GrammarParser::new(&self.grammar()).expect("The generated SQL grammar is valid")
+    }
+}
+
+impl Display for SqlSchema {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, table) in self.tables.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{table}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Quote an identifier if it isn't a plain name or is a SQLite keyword.
+fn quote_identifier(name: &str) -> Cow<'_, str> {
+    // Every keyword from https://www.sqlite.org/lang_keywords.html along with the COUNT function the queries use
+    const KEYWORDS: &[&str] = &[
+        "ABORT",
+        "ACTION",
+        "ADD",
+        "AFTER",
+        "ALL",
+        "ALTER",
+        "ALWAYS",
+        "ANALYZE",
+        "AND",
+        "AS",
+        "ASC",
+        "ATTACH",
+        "AUTOINCREMENT",
+        "BEFORE",
+        "BEGIN",
+        "BETWEEN",
+        "BY",
+        "CASCADE",
+        "CASE",
+        "CAST",
+        "CHECK",
+        "COLLATE",
+        "COLUMN",
+        "COMMIT",
+        "CONFLICT",
+        "CONSTRAINT",
+        "COUNT",
+        "CREATE",
+        "CROSS",
+        "CURRENT",
+        "CURRENT_DATE",
+        "CURRENT_TIME",
+        "CURRENT_TIMESTAMP",
+        "DATABASE",
+        "DEFAULT",
+        "DEFERRABLE",
+        "DEFERRED",
+        "DELETE",
+        "DESC",
+        "DETACH",
+        "DISTINCT",
+        "DO",
+        "DROP",
+        "EACH",
+        "ELSE",
+        "END",
+        "ESCAPE",
+        "EXCEPT",
+        "EXCLUDE",
+        "EXCLUSIVE",
+        "EXISTS",
+        "EXPLAIN",
+        "FAIL",
+        "FILTER",
+        "FIRST",
+        "FOLLOWING",
+        "FOR",
+        "FOREIGN",
+        "FROM",
+        "FULL",
+        "GENERATED",
+        "GLOB",
+        "GROUP",
+        "GROUPS",
+        "HAVING",
+        "IF",
+        "IGNORE",
+        "IMMEDIATE",
+        "IN",
+        "INDEX",
+        "INDEXED",
+        "INITIALLY",
+        "INNER",
+        "INSERT",
+        "INSTEAD",
+        "INTERSECT",
+        "INTO",
+        "IS",
+        "ISNULL",
+        "JOIN",
+        "KEY",
+        "LAST",
+        "LEFT",
+        "LIKE",
+        "LIMIT",
+        "MATCH",
+        "MATERIALIZED",
+        "NATURAL",
+        "NO",
+        "NOT",
+        "NOTHING",
+        "NOTNULL",
+        "NULL",
+        "NULLS",
+        "OF",
+        "OFFSET",
+        "ON",
+        "OR",
+        "ORDER",
+        "OTHERS",
+        "OUTER",
+        "OVER",
+        "PARTITION",
+        "PLAN",
+        "PRAGMA",
+        "PRECEDING",
+        "PRIMARY",
+        "QUERY",
+        "RAISE",
+        "RANGE",
+        "RECURSIVE",
+        "REFERENCES",
+        "REGEXP",
+        "REINDEX",
+        "RELEASE",
+        "RENAME",
+        "REPLACE",
+        "RESTRICT",
+        "RETURNING",
+        "RIGHT",
+        "ROLLBACK",
+        "ROW",
+        "ROWS",
+        "SAVEPOINT",
+        "SELECT",
+        "SET",
+        "TABLE",
+        "TEMP",
+        "TEMPORARY",
+        "THEN",
+        "TIES",
+        "TO",
+        "TRANSACTION",
+        "TRIGGER",
+        "UNBOUNDED",
+        "UNION",
+        "UNIQUE",
+        "UPDATE",
+        "USING",
+        "VACUUM",
+        "VALUES",
+        "VIEW",
+        "VIRTUAL",
+        "WHEN",
+        "WHERE",
+        "WINDOW",
+        "WITH",
+        "WITHOUT",
+    ];
+    let plain = name
+        .chars()
+        .next()
+        .is_some_and(|char| char.is_ascii_alphabetic() || char == '_')
+        && name
+            .chars()
+            .all(|char| char.is_ascii_alphanumeric() || char == '_');
+    if plain
+        && !KEYWORDS
+            .iter()
+            .any(|keyword| keyword.eq_ignore_ascii_case(name))
+    {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(format!("\"{}\"", name.replace('"', "\"\"")))
+    }
+}
+
+/// Write text as a GBNF string literal.
+fn gbnf_literal(text: &str) -> String {
+    let mut literal = String::from('"');
+    for char in text.chars() {
+        match char {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\t' => literal.push_str("\\t"),
+            char => literal.push(char),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+#[test]
+fn sql_schema_parser() {
+    use crate::{CreateParserState, Parser};
+
+    let schema = SqlSchema::new()
+        .with_table(
+            SqlTable::new("users")
+                .with_column("id", SqlType::Integer)
+                .with_column("name", SqlType::Text)
+                .with_column("order", SqlType::Real)
+                .with_column("avatar", SqlType::Blob),
+        )
+        .with_table(SqlTable::new("weird \"table\"").with_column("a\\b", SqlType::Numeric))
+        .with_table(
+            SqlTable::new("settings")
+                .with_column("key", SqlType::Text)
+                .with_column("default", SqlType::Integer)
+                .with_column("values", SqlType::Text),
+        )
+        .with_table(SqlTable::new("empty"));
+    assert_eq!(
+        schema.to_string(),
+        "CREATE TABLE users (id INTEGER, name TEXT, \"order\" REAL, avatar BLOB);\n\
+        CREATE TABLE \"weird \"\"table\"\"\" (\"a\\b\" NUMERIC);\n\
+        CREATE TABLE settings (\"key\" TEXT, \"default\" INTEGER, \"values\" TEXT);\n\
+        CREATE TABLE empty ();"
+    );
+
+    let parser = schema.parser();
+    let state = parser.create_parser_state();
+    for query in [
+        "SELECT * FROM users;",
+        "SELECT DISTINCT name, COUNT(*) FROM users GROUP BY name;",
+        "SELECT id, \"order\" FROM users WHERE name LIKE 'it''s%' AND id IN (1, -2) OR \"order\" >= 1.5 ORDER BY id DESC, name LIMIT 10;",
+        "SELECT AVG(\"order\"), MAX(name) FROM users WHERE avatar = X'00ff' OR name IS NOT NULL;",
+        "SELECT SUM(\"a\\b\") FROM \"weird \"\"table\"\"\" WHERE \"a\\b\" != 2;",
+        "SELECT \"key\", \"values\" FROM settings WHERE \"default\" > 1;",
+    ] {
+        let result = parser.parse(&state, query.as_bytes()).unwrap();
+        assert_eq!(result.unwrap_finished(), query);
+    }
+
+    for query in [
+        // Tables and columns must exist
+        "SELECT * FROM empty;",
+        "SELECT * FROM posts;",
+        "SELECT email FROM users;",
+        "SELECT \"a\\b\" FROM users;",
+        // Literals must match the column type
+        "SELECT * FROM users WHERE id = 1.5;",
+        "SELECT * FROM users WHERE name = 1;",
+        "SELECT SUM(name) FROM users;",
+        // Keywords used as names must be quoted
+        "SELECT order FROM users;",
+        "SELECT key FROM settings;",
+        "SELECT * FROM settings WHERE default = 1;",
+        // Only queries are allowed
+        "DELETE FROM users;",
+        "SELECT * FROM users; DROP TABLE users;",
+    ] {
+        let result = parser.parse(&state, query.as_bytes());
+        assert!(
+            !matches!(result, Ok(crate::ParseStatus::Finished { remaining, .. }) if remaining.is_empty()),
+            "{query}"
+        );
+    }
+}
+
+#[cfg(feature = "rusqlite")]
+#[test]
+fn sql_schema_from_sqlite() {
+    let connection = rusqlite::Connection::open_in_memory().unwrap();
+    connection
+        .execute_batch(
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name VARCHAR(64), score DOUBLE, data);
+            CREATE TABLE posts (id INT, body TEXT, published BOOLEAN);",
+        )
+        .unwrap();
+    let schema = SqlSchema::from_sqlite(&connection).unwrap();
+    assert_eq!(
+        schema,
+        SqlSchema::new()
+            .with_table(
+                SqlTable::new("posts")
+                    .with_column("id", SqlType::Integer)
+                    .with_column("body", SqlType::Text)
+                    .with_column("published", SqlType::Numeric)
+            )
+            .with_table(
+                SqlTable::new("users")
+                    .with_column("id", SqlType::Integer)
+                    .with_column("name", SqlType::Text)
+                    .with_column("score", SqlType::Real)
+                    .with_column("data", SqlType::Blob)
+            )
+    );
+}
@@ -127,6 +127,19 @@ impl<S: AsRef<str>, F: Fn(char) -> bool + 'static> Parser for StopOn<S, F> {
             required_next: "".into(),
         })
     }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        // The text before the stop literal doesn't change what comes next
+        Some(state.offset as u64)
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        Some(serde_json::Value::String(state.text.clone()))
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        Some(serde_json::Value::String(output.clone()))
+    }
 }
 
 #[test]
@@ -58,7 +58,7 @@ impl<F: Fn(char) -> bool + 'static> StringParser<F> {
     }
 }
 
-#[derive(Default, Debug, PartialEq, Eq, Clone)]
+#[derive(Default, Debug, PartialEq, Eq, Hash, Clone)]
 enum StringParserProgress {
     #[default]
     BeforeQuote,
@@ -164,6 +164,26 @@ impl<F: Fn(char) -> bool + 'static> Parser for StringParser<F> {
             required_next: "".into(),
         })
     }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        // The text of the string only matters through its length
+        Some(crate::hash_state_key((
+            &state.progress,
+            state.next_char_escaped,
+            crate::count_state_key(state.string.len(), &self.len_range),
+        )))
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        match state.progress {
+            StringParserProgress::BeforeQuote => None,
+            StringParserProgress::InString => Some(serde_json::Value::String(state.string.clone())),
+        }
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        Some(serde_json::Value::String(output.clone()))
+    }
 }
 
 #[test]
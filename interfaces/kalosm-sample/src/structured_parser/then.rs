@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use crate::{CreateParserState, ParseResult, ParseStatus, Parser};
+use crate::{combine_state_key, merge_values, CreateParserState, ParseResult, ParseStatus, Parser};
 
 /// State of a sequence parser.
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -33,8 +33,8 @@ impl<P1: CreateParserState, P2: CreateParserState> CreateParserState for Sequenc
 /// A parser for a sequence of two parsers.
 #[derive(Default, Debug, PartialEq, Eq, Copy, Clone)]
 pub struct SequenceParser<P1, P2> {
-    parser1: P1,
-    parser2: P2,
+    pub(crate) parser1: P1,
+    pub(crate) parser2: P2,
 }
 
 impl<P1, P2> SequenceParser<P1, P2> {
@@ -115,6 +115,31 @@ impl<P1: Parser, P2: CreateParserState> Parser for SequenceParser<P1, P2> {
             }
         }
     }
+
+    fn state_key(&self, state: &Self::PartialState) -> Option<u64> {
+        match state {
+            SequenceParserState::FirstParser(p1) => {
+                Some(combine_state_key(0, self.parser1.state_key(p1)?))
+            }
+            SequenceParserState::SecondParser(p2, _) => {
+                Some(combine_state_key(1, self.parser2.state_key(p2)?))
+            }
+        }
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        match state {
+            SequenceParserState::FirstParser(p1) => self.parser1.partial_value(p1),
+            SequenceParserState::SecondParser(p2, o1) => merge_values(
+                self.parser1.output_value(o1),
+                self.parser2.partial_value(p2),
+            ),
+        }
+    }
+
+    fn output_value(&self, (o1, o2): &Self::Output) -> Option<serde_json::Value> {
+        merge_values(self.parser1.output_value(o1), self.parser2.output_value(o2))
+    }
 }
 
 #[test]
@@ -314,4 +339,22 @@ impl<P1: Parser, P2: CreateParserState, F: Fn(&P1::Output) -> P2> Parser for The
             }
         }
     }
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        match state {
+            ThenLazyParserState::FirstParser(p1) => self.parser1.partial_value(p1),
+            ThenLazyParserState::SecondParser {
+                first_output,
+                second_parser,
+                second_state,
+            } => merge_values(
+                self.parser1.output_value(first_output),
+                second_parser.partial_value(second_state),
+            ),
+        }
+    }
+
+    fn output_value(&self, (o1, o2): &Self::Output) -> Option<serde_json::Value> {
+        let parser2 = (self.parser_fn)(o1);
+        merge_values(self.parser1.output_value(o1), parser2.output_value(o2))
+    }
 }
@@ -0,0 +1,99 @@
+use std::fmt::Debug;
+
+use crate::{CreateParserState, ParseStatus, Parser, ParserError};
+
+/// A parser that rejects the output of another parser if it doesn't pass a validation function. Created with [`crate::ParserExt::validate`].
+pub struct ValidateParser<P, F> {
+    pub(crate) parser: P,
+    pub(crate) validate: F,
+}
+
+impl<P: Debug, F> Debug for ValidateParser<P, F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ValidateParser")
+            .field("parser", &self.parser)
+            .finish()
+    }
+}
+
+impl<P: Clone, F: Clone> Clone for ValidateParser<P, F> {
+    fn clone(&self) -> Self {
+        Self {
+            parser: self.parser.clone(),
+            validate: self.validate.clone(),
+        }
+    }
+}
+
+impl<P, F> CreateParserState for ValidateParser<P, F>
+where
+    P: CreateParserState,
+    F: Fn(&P::Output) -> Result<(), ParserError>,
+{
+    fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+        self.parser.create_parser_state()
+    }
+}
+
+impl<P, F> Parser for ValidateParser<P, F>
+where
+    P: Parser,
+    F: Fn(&P::Output) -> Result<(), ParserError>,
+{
+    type Output = P::Output;
+    type PartialState = P::PartialState;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> crate::ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+        let result = self.parser.parse(state, input)?;
+        if let ParseStatus::Finished { result, .. } = &result {
+            (self.validate)(result)?;
+        }
+        Ok(result)
+    }
+
+    fn state_key(&self, _state: &Self::PartialState) -> Option<u64> {
+        // Whether a token passes validation depends on the whole output, which states with the same key of the inner parser can disagree on
+        None
+    }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        self.parser.partial_value(state)
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        self.parser.output_value(output)
+    }
+}
+
+#[test]
+fn validate_parser() {
+    use crate::{LiteralParser, ParserExt, U32Parser};
+
+    // The end of the range must be after the start
+    let parser = U32Parser::new()
+        .then_literal(",")
+        .then(U32Parser::new())
+        .validate(|(start, end)| {
+            if end > start {
+                Ok(())
+            } else {
+                Err(ParserError::msg("The end must be after the start"))
+            }
+        })
+        .then(LiteralParser::new("!"));
+    let state = parser.create_parser_state();
+
+    assert_eq!(
+        parser.parse(&state, b"1,12!").unwrap().unwrap_finished(),
+        ((1, 12), ())
+    );
+    // The output is only validated once it is finished
+    let state = parser.parse(&state, b"10,2").unwrap().unwrap_incomplete().0;
+    assert!(parser.parse(&state, b"!").is_err());
+    assert!(parser.parse(&state, b"0!").is_ok());
+    assert!(parser.state_key(&state).is_none());
+}
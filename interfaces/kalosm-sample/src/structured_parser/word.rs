@@ -92,6 +92,14 @@ impl<const MIN_LENGTH: usize, const MAX_LENGTH: usize> Parser
             .parse(state, input)
             .map(|result| result.map(Into::into))
     }
+
+    fn partial_value(&self, state: &Self::PartialState) -> Option<serde_json::Value> {
+        self.parser.partial_value(state)
+    }
+
+    fn output_value(&self, output: &Self::Output) -> Option<serde_json::Value> {
+        self.parser.output_value(&output.0)
+    }
 }
 
 impl<const MIN_LENGTH: usize, const MAX_LENGTH: usize> Parse for Word<MIN_LENGTH, MAX_LENGTH> {
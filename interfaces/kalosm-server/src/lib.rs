@@ -0,0 +1,666 @@
+//! # Kalosm Server
+//!
+//! An [OpenAI compatible](https://platform.openai.com/docs/api-reference) HTTP server for any Kalosm [`Model`] and [`Embedder`].
+//!
+//! The server exposes:
+//! - `POST /v1/completions` for text completion
+//! - `POST /v1/chat/completions` for chat models
+//! - `POST /v1/embeddings` for embedding models
+//! - `GET /v1/models` to list the models the server hosts
+//!
+//! Both completion endpoints support streaming with server sent events and `response_format: { "type": "json_schema" }` which constrains the model to JSON that matches the schema.
+//!
+//! ```rust, no_run
+//! use kalosm::language::*;
+//! use kalosm_server::ServerBuilder;
+//!
+//! #[tokio::main]
+//! async fn main() -> anyhow::Result<()> {
+//!     let llm = Llama::new_chat().await?;
+//!     let bert = Bert::new().await?;
+//!
+//!     ServerBuilder::new()
+//!         .with_model("llama", llm)
+//!         .with_embedder("bert", bert)
+//!         .serve("127.0.0.1:8080")
+//!         .await
+//! }
+//! ```
+
+#![warn(missing_docs)]
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post},
+    Json, Router,
+};
+use futures_util::{Stream, StreamExt};
+use kalosm_language_model::{
+    ChatHistoryItem, ChatMarkers, ChatTemplate, Embedder, GenerationControl, GenerationParameters,
+    Interrupt, MessageType, Model, ModelExt, StopReason, SyncModel, SyncModelExt,
+};
+use kalosm_sample::{ArcParser, CreateParserState, ParserExt, SchemaType};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+mod stop;
+pub mod types;
+
+use stop::StopSequences;
+use types::*;
+
+type TextStream = Pin<Box<dyn Stream<Item = String> + Send>>;
+
+/// A running generation along with the control the model reports why it stopped to.
+struct Generation {
+    stream: TextStream,
+    control: GenerationControl,
+}
+
+/// A model that has been type erased so the server can host models of different types.
+#[async_trait::async_trait]
+trait ServedModel: Send + Sync {
+    async fn stream_text(
+        &self,
+        prompt: &str,
+        parameters: GenerationParameters,
+    ) -> anyhow::Result<Generation>;
+
+    fn stream_structured_text(
+        &self,
+        prompt: &str,
+        parser: ArcParser,
+        parameters: GenerationParameters,
+    ) -> Generation;
+
+    fn chat_markers(&self) -> Option<ChatMarkers>;
+
+    fn chat_template(&self) -> Option<ChatTemplate>;
+}
+
+#[async_trait::async_trait]
+impl<M> ServedModel for M
+where
+    M: Model,
+    M::TextStream: From<tokio::sync::mpsc::UnboundedReceiver<String>>,
+{
+    async fn stream_text(
+        &self,
+        prompt: &str,
+        parameters: GenerationParameters,
+    ) -> anyhow::Result<Generation> {
+        let generation = ModelExt::stream_text(self, prompt)
+            .with_generation_parameters(parameters)
+            .with_interrupt(Interrupt::new())
+            .await?;
+        Ok(Generation {
+            control: generation.control().clone(),
+            stream: Box::pin(generation),
+        })
+    }
+
+    fn stream_structured_text(
+        &self,
+        prompt: &str,
+        parser: ArcParser,
+        parameters: GenerationParameters,
+    ) -> Generation {
+        let control = GenerationControl::default();
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let max_tokens = parameters.max_length() as usize;
+        let sampler = Arc::new(Mutex::new(parameters.seeded_sampler()));
+        let prompt = prompt.to_string();
+        let generation_control = control.clone();
+        let result = self.run_sync(move |llm: &mut M::SyncModel| {
+            Box::pin(async move {
+                let control = generation_control;
+                let tokenizer = llm.tokenizer();
+                let mut tokens = 0;
+                // The generated text is sent one token at a time, so we can stop once the text reaches the token limit
+                let on_token = |text: String| {
+                    tokens += tokenizer
+                        .encode(text.as_str(), false)
+                        .map_err(anyhow::Error::msg)?
+                        .len();
+                    if tokens > max_tokens {
+                        control.finish(StopReason::MaxLength);
+                        anyhow::bail!("The response is longer than {max_tokens} tokens");
+                    }
+                    sender.send(text)?;
+                    Ok(())
+                };
+                let state = parser.create_parser_state();
+                let result = llm.new_session().and_then(|mut session| {
+                    llm.generate_structured(
+                        &mut session,
+                        prompt,
+                        parser,
+                        state,
+                        sampler,
+                        on_token,
+                        Some(64),
+                    )
+                });
+                match result {
+                    Ok(_) => control.finish(StopReason::Finished),
+                    Err(err) => {
+                        tracing::error!("Error generating structured text: {err}");
+                        control.finish(StopReason::Error);
+                    }
+                }
+                // Only close the stream once the control knows why the generation stopped
+                drop(sender);
+            })
+        });
+        if let Err(err) = result {
+            tracing::error!("Error starting structured generation: {err}");
+            control.finish(StopReason::Error);
+        }
+        Generation {
+            stream: Box::pin(M::TextStream::from(receiver)),
+            control,
+        }
+    }
+
+    fn chat_markers(&self) -> Option<ChatMarkers> {
+        Model::chat_markers(self)
+    }
+
+    fn chat_template(&self) -> Option<ChatTemplate> {
+        Model::chat_template(self)
+    }
+}
+
+/// An embedder that has been type erased so the server can host embedders of different types.
+trait ServedEmbedder: Send + Sync {
+    fn embed(
+        &self,
+        inputs: Vec<String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<Vec<Vec<f32>>>> + Send + '_>>;
+}
+
+impl<E: Embedder> ServedEmbedder for E {
+    fn embed(
+        &self,
+        inputs: Vec<String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<Vec<Vec<f32>>>> + Send + '_>> {
+        Box::pin(async move {
+            let embeddings = self.embed_vec(inputs).await?;
+            Ok(embeddings
+                .iter()
+                .map(|embedding| embedding.to_vec())
+                .collect())
+        })
+    }
+}
+
+/// A builder for an OpenAI compatible server.
+#[derive(Default)]
+pub struct ServerBuilder {
+    models: HashMap<String, Arc<dyn ServedModel>>,
+    embedders: HashMap<String, Arc<dyn ServedEmbedder>>,
+}
+
+impl ServerBuilder {
+    /// Create a new server builder without any models.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Host a model for the completion endpoints. Requests that set `model` to `name` will be sent to this model.
+    ///
+    /// Chat completions are only supported for models with [`ChatMarkers`] and JSON schema constraints are only supported for local models.
+    pub fn with_model<M>(mut self, name: impl ToString, model: M) -> Self
+    where
+        M: Model,
+        M::TextStream: From<tokio::sync::mpsc::UnboundedReceiver<String>>,
+    {
+        self.models.insert(name.to_string(), Arc::new(model));
+        self
+    }
+
+    /// Host an embedder for the embedding endpoint. Requests that set `model` to `name` will be sent to this embedder.
+    pub fn with_embedder<E: Embedder>(mut self, name: impl ToString, embedder: E) -> Self {
+        self.embedders.insert(name.to_string(), Arc::new(embedder));
+        self
+    }
+
+    /// Build an [`axum::Router`] with the OpenAI compatible routes. The router can be merged into an existing axum app.
+    pub fn build(self) -> Router {
+        let state = Arc::new(ServerState {
+            models: self.models,
+            embedders: self.embedders,
+        });
+        Router::new()
+            .route("/v1/models", get(list_models))
+            .route("/v1/completions", post(completions))
+            .route("/v1/chat/completions", post(chat_completions))
+            .route("/v1/embeddings", post(embeddings))
+            .with_state(state)
+    }
+
+    /// Serve the OpenAI compatible routes at the given address.
+    pub async fn serve(self, address: impl tokio::net::ToSocketAddrs) -> anyhow::Result<()> {
+        let listener = tokio::net::TcpListener::bind(address).await?;
+        axum::serve(listener, self.build()).await?;
+        Ok(())
+    }
+}
+
+struct ServerState {
+    models: HashMap<String, Arc<dyn ServedModel>>,
+    embedders: HashMap<String, Arc<dyn ServedEmbedder>>,
+}
+
+impl ServerState {
+    fn model(&self, name: &str) -> Result<Arc<dyn ServedModel>, ApiError> {
+        self.models
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ApiError::model_not_found(name))
+    }
+
+    fn embedder(&self, name: &str) -> Result<Arc<dyn ServedEmbedder>, ApiError> {
+        self.embedders
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ApiError::model_not_found(name))
+    }
+}
+
+/// An error returned to the client in the OpenAI error format.
+#[derive(Debug)]
+struct ApiError {
+    status: StatusCode,
+    message: String,
+    code: Option<&'static str>,
+}
+
+impl ApiError {
+    fn bad_request(message: impl ToString) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            message: message.to_string(),
+            code: None,
+        }
+    }
+
+    fn model_not_found(name: &str) -> Self {
+        Self {
+            status: StatusCode::NOT_FOUND,
+            message: format!("The model `{name}` does not exist"),
+            code: Some("model_not_found"),
+        }
+    }
+
+    fn generation_failed() -> Self {
+        Self {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            message: "The model failed before the generation finished".to_string(),
+            code: None,
+        }
+    }
+
+    fn body(self) -> ErrorResponse {
+        let ty = if self.status.is_client_error() {
+            "invalid_request_error"
+        } else {
+            "server_error"
+        };
+        ErrorResponse {
+            error: ErrorBody {
+                message: self.message,
+                ty,
+                param: None,
+                code: self.code.map(str::to_string),
+            },
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        Self {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            message: err.to_string(),
+            code: None,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.body())).into_response()
+    }
+}
+
+async fn list_models(State(state): State<Arc<ServerState>>) -> impl IntoResponse {
+    let data: Vec<_> = state
+        .models
+        .keys()
+        .chain(state.embedders.keys())
+        .map(|name| {
+            serde_json::json!({
+                "id": name,
+                "object": "model",
+                "created": 0,
+                "owned_by": "kalosm",
+            })
+        })
+        .collect();
+    Json(serde_json::json!({ "object": "list", "data": data }))
+}
+
+async fn completions(
+    State(state): State<Arc<ServerState>>,
+    Json(request): Json<CompletionRequest>,
+) -> Result<Response, ApiError> {
+    let model = state.model(&request.model)?;
+    let mut prompts = request.prompt.into_vec();
+    if prompts.len() != 1 {
+        return Err(ApiError::bad_request("Exactly one prompt is supported"));
+    }
+    let prompt = prompts.remove(0);
+
+    let stop = request.stop.map(OneOrMany::into_vec).unwrap_or_default();
+    let parameters = generation_parameters(request.temperature, request.max_tokens)
+        .with_stop_on(stop.first().cloned());
+    let chunks = generate(&*model, &prompt, parameters, request.response_format, stop).await?;
+
+    let id = new_id("cmpl");
+    let created = now();
+    let model_name = request.model;
+    let completion = move |text: String, finish_reason: Option<FinishReason>| CompletionResponse {
+        id: id.clone(),
+        object: "text_completion",
+        created,
+        model: model_name.clone(),
+        choices: vec![CompletionChoice {
+            text,
+            index: 0,
+            logprobs: None,
+            finish_reason,
+        }],
+    };
+
+    if request.stream {
+        let events = chunks
+            .map(move |chunk| chunk.map(|(text, finish_reason)| completion(text, finish_reason)));
+        Ok(sse(events))
+    } else {
+        let (text, finish_reason) = collect(chunks).await?;
+        Ok(Json(completion(text, Some(finish_reason))).into_response())
+    }
+}
+
+async fn chat_completions(
+    State(state): State<Arc<ServerState>>,
+    Json(request): Json<ChatCompletionRequest>,
+) -> Result<Response, ApiError> {
+    let model = state.model(&request.model)?;
+    let (prompt, end_assistant_marker) = match (model.chat_template(), model.chat_markers()) {
+        (Some(template), _) => {
+            let history: Vec<_> = request.messages.iter().map(history_item).collect();
+            // Templates reject chats they can't format, like chats that don't alternate between the user and assistant
+            let prompt = template
+                .render(&history, true)
+                .map_err(ApiError::bad_request)?;
+            (prompt, template.end_assistant_marker()?)
+        }
+        (None, Some(markers)) => (
+            chat_prompt(&markers, &request.messages),
+            markers.end_assistant_marker.to_string(),
+        ),
+        (None, None) => {
+            return Err(ApiError::bad_request(format!(
+                "The model `{}` does not support chat completions",
+                request.model
+            )))
+        }
+    };
+
+    // The model stops at the end of the assistant's message. Any other stop sequences are handled by the server
+    let stop = request.stop.map(OneOrMany::into_vec).unwrap_or_default();
+    let parameters = generation_parameters(request.temperature, request.max_tokens)
+        .with_stop_on(end_assistant_marker);
+    let chunks = generate(&*model, &prompt, parameters, request.response_format, stop).await?;
+
+    let id = new_id("chatcmpl");
+    let created = now();
+    let model_name = request.model;
+
+    if request.stream {
+        let mut first = true;
+        let events = chunks.map(move |chunk| {
+            let (text, finish_reason) = chunk?;
+            let role = std::mem::take(&mut first).then_some(Role::Assistant);
+            Ok(ChatCompletionChunk {
+                id: id.clone(),
+                object: "chat.completion.chunk",
+                created,
+                model: model_name.clone(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 0,
+                    delta: ChatCompletionDelta {
+                        role,
+                        content: (!text.is_empty()).then_some(text),
+                    },
+                    logprobs: None,
+                    finish_reason,
+                }],
+            })
+        });
+        Ok(sse(events))
+    } else {
+        let (text, finish_reason) = collect(chunks).await?;
+        Ok(Json(ChatCompletionResponse {
+            id,
+            object: "chat.completion",
+            created,
+            model: model_name,
+            choices: vec![ChatCompletionChoice {
+                index: 0,
+                message: ChatMessage {
+                    role: Role::Assistant,
+                    content: text,
+                },
+                logprobs: None,
+                finish_reason: Some(finish_reason),
+            }],
+        })
+        .into_response())
+    }
+}
+
+async fn embeddings(
+    State(state): State<Arc<ServerState>>,
+    Json(request): Json<EmbeddingRequest>,
+) -> Result<Json<EmbeddingResponse>, ApiError> {
+    let embedder = state.embedder(&request.model)?;
+    let embeddings = embedder.embed(request.input.into_vec()).await?;
+    let data = embeddings
+        .into_iter()
+        .enumerate()
+        .map(|(index, embedding)| EmbeddingData {
+            object: "embedding",
+            embedding,
+            index: index as u32,
+        })
+        .collect();
+
+    Ok(Json(EmbeddingResponse {
+        object: "list",
+        data,
+        model: request.model,
+        usage: Usage::default(),
+    }))
+}
+
+fn generation_parameters(
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+) -> GenerationParameters {
+    let mut parameters = GenerationParameters::default();
+    if let Some(temperature) = temperature {
+        parameters = parameters.with_temperature(temperature);
+    }
+    if let Some(max_tokens) = max_tokens {
+        parameters = parameters.with_max_length(max_tokens);
+    }
+    parameters
+}
+
+fn history_item(message: &ChatMessage) -> ChatHistoryItem {
+    let ty = match message.role {
+        Role::System => MessageType::SystemPrompt,
+        Role::User => MessageType::UserMessage,
+        Role::Assistant => MessageType::ModelAnswer,
+    };
+    ChatHistoryItem::new(ty, message.content.clone())
+}
+
+/// Format the messages of a chat with the chat markers of a model that doesn't have a chat template. The prompt ends with the start of the assistant's response.
+fn chat_prompt(markers: &ChatMarkers, messages: &[ChatMessage]) -> String {
+    let mut prompt = String::new();
+    for message in messages {
+        let (start, end) = match message.role {
+            Role::System => (
+                markers.system_prompt_marker,
+                markers.end_system_prompt_marker,
+            ),
+            Role::User => (markers.user_marker, markers.end_user_marker),
+            Role::Assistant => (markers.assistant_marker, markers.end_assistant_marker),
+        };
+        prompt += start;
+        prompt += &message.content;
+        prompt += end;
+    }
+    prompt += markers.assistant_marker;
+    prompt
+}
+
+/// Start generating text and return a stream of new text along with the reason the generation finished in the last chunk. If the model fails, the last chunk is an error instead.
+async fn generate(
+    model: &dyn ServedModel,
+    prompt: &str,
+    parameters: GenerationParameters,
+    response_format: Option<ResponseFormat>,
+    stop: Vec<String>,
+) -> Result<impl Stream<Item = Result<(String, Option<FinishReason>), ApiError>> + Send, ApiError> {
+    let Generation { stream, control } = match response_format {
+        Some(ResponseFormat::JsonSchema { json_schema }) => {
+            let schema = json_schema.schema.ok_or_else(|| {
+                ApiError::bad_request(format!(
+                    "The JSON schema `{}` is missing a `schema`",
+                    json_schema.name
+                ))
+            })?;
+            let parser = SchemaType::from_json_schema(&schema)
+                .and_then(|schema| schema.to_parser())
+                .map_err(ApiError::bad_request)?
+                .map_output(|_| ())
+                .boxed();
+            model.stream_structured_text(prompt, parser, parameters)
+        }
+        Some(ResponseFormat::Text) | None => model.stream_text(prompt, parameters).await?,
+    };
+
+    let chunks = futures_util::stream::unfold(
+        Some((stream, StopSequences::new(stop), control)),
+        move |state| async move {
+            let (mut stream, mut stop, control) = state?;
+            match stream.next().await {
+                Some(text) => match stop.push(&text) {
+                    (text, true) => Some((Ok((text, Some(FinishReason::Stop))), None)),
+                    (text, false) => Some((Ok((text, None)), Some((stream, stop, control)))),
+                },
+                None => {
+                    // The model records why it stopped before the stream ends
+                    let finish_reason = match control.stop_reason() {
+                        Some(StopReason::MaxLength) => FinishReason::Length,
+                        Some(StopReason::Error) => {
+                            return Some((Err(ApiError::generation_failed()), None))
+                        }
+                        _ => FinishReason::Stop,
+                    };
+                    Some((Ok((stop.flush(), Some(finish_reason))), None))
+                }
+            }
+        },
+    );
+
+    Ok(chunks.filter(|chunk| {
+        std::future::ready(match chunk {
+            Ok((text, finish_reason)) => !text.is_empty() || finish_reason.is_some(),
+            Err(_) => true,
+        })
+    }))
+}
+
+/// Collect all of the chunks of a generation into a single string.
+async fn collect(
+    chunks: impl Stream<Item = Result<(String, Option<FinishReason>), ApiError>>,
+) -> Result<(String, FinishReason), ApiError> {
+    let mut chunks = std::pin::pin!(chunks);
+    let mut text = String::new();
+    let mut finish_reason = FinishReason::Stop;
+    while let Some(chunk) = chunks.next().await {
+        let (chunk, reason) = chunk?;
+        text += &chunk;
+        if let Some(reason) = reason {
+            finish_reason = reason;
+        }
+    }
+    Ok((text, finish_reason))
+}
+
+/// Stream the chunks as server sent events followed by the `[DONE]` message. If the generation fails, the stream ends with an error event instead.
+fn sse<T: Serialize>(chunks: impl Stream<Item = Result<T, ApiError>> + Send + 'static) -> Response {
+    let events = chunks
+        .map(Some)
+        .chain(futures_util::stream::once(async { None }))
+        .scan(false, |failed, chunk| {
+            let event = match chunk {
+                Some(Ok(chunk)) => Some(Event::default().json_data(chunk)),
+                Some(Err(err)) => {
+                    *failed = true;
+                    Some(Event::default().json_data(err.body()))
+                }
+                None => (!*failed).then(|| Ok(Event::default().data("[DONE]"))),
+            };
+            std::future::ready(event)
+        });
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+fn new_id(prefix: &str) -> String {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    format!(
+        "{prefix}-{}-{}",
+        now(),
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as u32)
+        .unwrap_or_default()
+}
@@ -0,0 +1,74 @@
+/// Holds back streamed text that could be the start of a stop sequence so the stop sequence is never sent.
+pub(crate) struct StopSequences {
+    stop: Vec<String>,
+    held: String,
+}
+
+impl StopSequences {
+    pub(crate) fn new(stop: Vec<String>) -> Self {
+        Self {
+            stop: stop.into_iter().filter(|stop| !stop.is_empty()).collect(),
+            held: String::new(),
+        }
+    }
+
+    /// Add new text and return the text that is safe to send. Returns true if a stop sequence was found.
+    pub(crate) fn push(&mut self, text: &str) -> (String, bool) {
+        self.held.push_str(text);
+
+        // Stop at the earliest stop sequence in the text
+        if let Some(index) = self
+            .stop
+            .iter()
+            .filter_map(|stop| self.held.find(stop.as_str()))
+            .min()
+        {
+            self.held.truncate(index);
+            return (std::mem::take(&mut self.held), true);
+        }
+
+        // Keep holding the end of the text if it could be the start of a stop sequence
+        let held_from = self
+            .held
+            .char_indices()
+            .map(|(i, _)| i)
+            .find(|&i| {
+                let end = &self.held[i..];
+                self.stop.iter().any(|stop| stop.starts_with(end))
+            })
+            .unwrap_or(self.held.len());
+        let held = self.held.split_off(held_from);
+        (std::mem::replace(&mut self.held, held), false)
+    }
+
+    /// Release any text that is still held.
+    pub(crate) fn flush(&mut self) -> String {
+        std::mem::take(&mut self.held)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_partial_stop_sequences() {
+        let mut stops = StopSequences::new(vec!["\n\n".to_string(), "END".to_string()]);
+        assert_eq!(stops.push("Hello E"), ("Hello ".to_string(), false));
+        assert_eq!(stops.push("N"), (String::new(), false));
+        assert_eq!(stops.push("D world"), (String::new(), true));
+    }
+
+    #[test]
+    fn releases_false_matches() {
+        let mut stops = StopSequences::new(vec!["END".to_string()]);
+        assert_eq!(stops.push("Hello E"), ("Hello ".to_string(), false));
+        assert_eq!(stops.push("ND"), (String::new(), true));
+
+        let mut stops = StopSequences::new(vec!["END".to_string()]);
+        assert_eq!(stops.push("E"), (String::new(), false));
+        assert_eq!(stops.push("x"), ("Ex".to_string(), false));
+        assert_eq!(stops.push("EN"), (String::new(), false));
+        assert_eq!(stops.flush(), "EN");
+    }
+}
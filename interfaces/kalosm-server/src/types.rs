@@ -0,0 +1,289 @@
+//! The request and response bodies of the OpenAI API that the server implements.
+
+use serde::{Deserialize, Serialize};
+
+/// A value that may be sent as a single item or as a list of items.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum OneOrMany<T> {
+    /// A single item.
+    One(T),
+    /// A list of items.
+    Many(Vec<T>),
+}
+
+impl<T> OneOrMany<T> {
+    /// Convert the value into a list of items.
+    pub fn into_vec(self) -> Vec<T> {
+        match self {
+            OneOrMany::One(item) => vec![item],
+            OneOrMany::Many(items) => items,
+        }
+    }
+}
+
+/// The format the response must follow.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    /// Any text.
+    Text,
+    /// JSON that matches the given schema.
+    JsonSchema {
+        /// The schema of the response.
+        json_schema: JsonSchemaFormat,
+    },
+}
+
+/// A named JSON schema the response must follow.
+#[derive(Debug, Clone, Deserialize)]
+pub struct JsonSchemaFormat {
+    /// The name of the schema.
+    pub name: String,
+    /// A description of the schema.
+    #[serde(default)]
+    pub description: Option<String>,
+    /// The JSON schema.
+    #[serde(default)]
+    pub schema: Option<serde_json::Value>,
+    /// If the response must follow the schema exactly. The server always follows the schema exactly.
+    #[serde(default)]
+    pub strict: Option<bool>,
+}
+
+/// A request to the `/v1/completions` endpoint.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CompletionRequest {
+    /// The name of the model to use.
+    pub model: String,
+    /// The prompt to complete.
+    pub prompt: OneOrMany<String>,
+    /// The maximum number of tokens to generate.
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    /// The temperature to sample with.
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    /// The sequence to stop generating at.
+    #[serde(default)]
+    pub stop: Option<OneOrMany<String>>,
+    /// If the response should be streamed as server sent events.
+    #[serde(default)]
+    pub stream: bool,
+    /// The format the response must follow.
+    #[serde(default)]
+    pub response_format: Option<ResponseFormat>,
+}
+
+/// The reason a choice stopped generating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    /// The model finished the response or hit a stop sequence.
+    Stop,
+    /// The response hit the maximum number of tokens.
+    Length,
+}
+
+/// A choice in a completion response.
+#[derive(Debug, Clone, Serialize)]
+pub struct CompletionChoice {
+    /// The generated text.
+    pub text: String,
+    /// The index of the choice.
+    pub index: u32,
+    /// The log probabilities of the tokens. The server does not report log probabilities.
+    pub logprobs: Option<()>,
+    /// The reason the choice stopped generating. This is `None` for every chunk but the last when streaming.
+    pub finish_reason: Option<FinishReason>,
+}
+
+/// A response from the `/v1/completions` endpoint or a chunk of a streamed response.
+#[derive(Debug, Clone, Serialize)]
+pub struct CompletionResponse {
+    /// The id of the completion.
+    pub id: String,
+    /// Always `text_completion`.
+    pub object: &'static str,
+    /// The unix timestamp the completion was created at.
+    pub created: u32,
+    /// The name of the model used.
+    pub model: String,
+    /// The generated choices.
+    pub choices: Vec<CompletionChoice>,
+}
+
+/// The role of the author of a chat message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// The system prompt.
+    System,
+    /// A message from the user.
+    User,
+    /// A message from the model.
+    Assistant,
+}
+
+/// A message in a chat.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    /// The author of the message.
+    pub role: Role,
+    /// The text of the message.
+    pub content: String,
+}
+
+/// A request to the `/v1/chat/completions` endpoint.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatCompletionRequest {
+    /// The name of the model to use.
+    pub model: String,
+    /// The messages in the chat so far.
+    pub messages: Vec<ChatMessage>,
+    /// The maximum number of tokens to generate.
+    #[serde(default, alias = "max_completion_tokens")]
+    pub max_tokens: Option<u32>,
+    /// The temperature to sample with.
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    /// The sequence to stop generating at.
+    #[serde(default)]
+    pub stop: Option<OneOrMany<String>>,
+    /// If the response should be streamed as server sent events.
+    #[serde(default)]
+    pub stream: bool,
+    /// The format the response must follow.
+    #[serde(default)]
+    pub response_format: Option<ResponseFormat>,
+}
+
+/// A choice in a chat completion response.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatCompletionChoice {
+    /// The index of the choice.
+    pub index: u32,
+    /// The generated message.
+    pub message: ChatMessage,
+    /// The log probabilities of the tokens. The server does not report log probabilities.
+    pub logprobs: Option<()>,
+    /// The reason the choice stopped generating.
+    pub finish_reason: Option<FinishReason>,
+}
+
+/// A response from the `/v1/chat/completions` endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatCompletionResponse {
+    /// The id of the completion.
+    pub id: String,
+    /// Always `chat.completion`.
+    pub object: &'static str,
+    /// The unix timestamp the completion was created at.
+    pub created: u32,
+    /// The name of the model used.
+    pub model: String,
+    /// The generated choices.
+    pub choices: Vec<ChatCompletionChoice>,
+}
+
+/// The new part of a message in a streamed chat completion.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ChatCompletionDelta {
+    /// The author of the message. This is only set in the first chunk.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+    /// The new text of the message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+}
+
+/// A choice in a streamed chat completion chunk.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatCompletionChunkChoice {
+    /// The index of the choice.
+    pub index: u32,
+    /// The new part of the message.
+    pub delta: ChatCompletionDelta,
+    /// The log probabilities of the tokens. The server does not report log probabilities.
+    pub logprobs: Option<()>,
+    /// The reason the choice stopped generating. This is `None` for every chunk but the last.
+    pub finish_reason: Option<FinishReason>,
+}
+
+/// A chunk of a streamed response from the `/v1/chat/completions` endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatCompletionChunk {
+    /// The id of the completion.
+    pub id: String,
+    /// Always `chat.completion.chunk`.
+    pub object: &'static str,
+    /// The unix timestamp the completion was created at.
+    pub created: u32,
+    /// The name of the model used.
+    pub model: String,
+    /// The new parts of the choices.
+    pub choices: Vec<ChatCompletionChunkChoice>,
+}
+
+/// A request to the `/v1/embeddings` endpoint.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmbeddingRequest {
+    /// The name of the embedding model to use.
+    pub model: String,
+    /// The text to embed.
+    pub input: OneOrMany<String>,
+}
+
+/// A single embedding in an embedding response.
+#[derive(Debug, Clone, Serialize)]
+pub struct EmbeddingData {
+    /// Always `embedding`.
+    pub object: &'static str,
+    /// The embedding vector.
+    pub embedding: Vec<f32>,
+    /// The index of the input the embedding is for.
+    pub index: u32,
+}
+
+/// The number of tokens used by a request.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Usage {
+    /// The number of tokens in the prompt.
+    pub prompt_tokens: u32,
+    /// The total number of tokens used.
+    pub total_tokens: u32,
+}
+
+/// A response from the `/v1/embeddings` endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct EmbeddingResponse {
+    /// Always `list`.
+    pub object: &'static str,
+    /// The embeddings in the same order as the inputs.
+    pub data: Vec<EmbeddingData>,
+    /// The name of the model used.
+    pub model: String,
+    /// The number of tokens used. Embedders do not report token counts, so this is always zero.
+    pub usage: Usage,
+}
+
+/// The body of an error response.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorResponse {
+    /// The error.
+    pub error: ErrorBody,
+}
+
+/// The details of an error.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorBody {
+    /// A message describing the error.
+    pub message: String,
+    /// The type of the error.
+    #[serde(rename = "type")]
+    pub ty: &'static str,
+    /// The parameter that caused the error.
+    pub param: Option<String>,
+    /// The error code.
+    pub code: Option<String>,
+}
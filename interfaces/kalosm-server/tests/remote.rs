@@ -0,0 +1,392 @@
+use async_openai::config::OpenAIConfig;
+use async_openai::types::{
+    ChatCompletionRequestUserMessageArgs, CreateChatCompletionRequest,
+    CreateChatCompletionRequestArgs, CreateChatCompletionResponse, FinishReason, ResponseFormat,
+    ResponseFormatJsonSchema,
+};
+use kalosm_language_model::*;
+use kalosm_server::ServerBuilder;
+use kalosm_streams::text_stream::ChannelTextStream;
+use std::{future::Future, pin::Pin, sync::Arc};
+use tokenizers::decoders::fuse::Fuse;
+use tokenizers::models::bpe::BPE;
+use tokenizers::Tokenizer;
+
+/// A tokenizer with one token for every printable ASCII character. The last token is the stop token.
+fn tokenizer() -> Arc<Tokenizer> {
+    let vocab = (b' '..=b'~')
+        .map(|byte| (byte as char).to_string())
+        .chain(["</s>".to_string()])
+        .enumerate()
+        .map(|(id, text)| (text, id as u32))
+        .collect();
+    let model = BPE::builder()
+        .vocab_and_merges(vocab, Vec::new())
+        .build()
+        .unwrap();
+    let mut tokenizer = Tokenizer::new(model);
+    tokenizer.with_decoder(Fuse::new());
+    Arc::new(tokenizer)
+}
+
+/// A model that always generates the same tokens, or fails after the first token if the prompt contains `Fail`. Structured generation runs on a [`ScriptedSyncModel`].
+struct ScriptedModel {
+    tokenizer: Arc<Tokenizer>,
+}
+
+const TOKENS: [&str; 4] = ["Hello", ",", " world", "!"];
+
+#[async_trait::async_trait]
+impl Model for ScriptedModel {
+    type TextStream = ChannelTextStream;
+    type SyncModel = ScriptedSyncModel;
+
+    fn tokenizer(&self) -> Arc<Tokenizer> {
+        self.tokenizer.clone()
+    }
+
+    fn run_sync_raw(
+        &self,
+        f: Box<
+            dyn for<'a> FnOnce(&'a mut Self::SyncModel) -> Pin<Box<dyn Future<Output = ()> + 'a>>
+                + Send,
+        >,
+    ) -> anyhow::Result<()> {
+        let mut model = ScriptedSyncModel {
+            tokenizer: self.tokenizer.clone(),
+        };
+        std::thread::spawn(move || {
+            tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap()
+                .block_on(f(&mut model))
+        });
+        Ok(())
+    }
+
+    async fn stream_text_inner(
+        &self,
+        prompt: &str,
+        parameters: GenerationParameters,
+    ) -> anyhow::Result<Self::TextStream> {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        for (index, token) in TOKENS.iter().enumerate() {
+            if index == 1 && prompt.contains("Fail") {
+                if let Some(control) = parameters.control() {
+                    control.finish(StopReason::Error);
+                }
+                break;
+            }
+            if parameters
+                .stop_on()
+                .is_some_and(|stop_on| token.contains(stop_on))
+            {
+                break;
+            }
+            if index == parameters.max_length() as usize {
+                if let Some(control) = parameters.control() {
+                    control.finish(StopReason::MaxLength);
+                }
+                break;
+            }
+            sender.send(token.to_string())?;
+        }
+        Ok(receiver.into())
+    }
+
+    fn chat_markers(&self) -> Option<ChatMarkers> {
+        Some(ChatMarkers {
+            system_prompt_marker: "<system>",
+            end_system_prompt_marker: "</system>",
+            user_marker: "<user>",
+            end_user_marker: "</user>",
+            assistant_marker: "<assistant>",
+            end_assistant_marker: "</assistant>",
+        })
+    }
+}
+
+/// A model that strongly prefers the letter `a`, but can generate any character.
+struct ScriptedSyncModel {
+    tokenizer: Arc<Tokenizer>,
+}
+
+#[derive(Clone, Default)]
+struct ScriptedSession {
+    tokens: Vec<u32>,
+}
+
+impl Session for ScriptedSession {
+    fn tokens(&self) -> &[u32] {
+        &self.tokens
+    }
+
+    fn try_clone(&self) -> anyhow::Result<Self> {
+        Ok(self.clone())
+    }
+}
+
+impl SyncModel for ScriptedSyncModel {
+    type Session = ScriptedSession;
+
+    fn new_session(&self) -> anyhow::Result<Self::Session> {
+        Ok(ScriptedSession::default())
+    }
+
+    fn feed_text(
+        &self,
+        session: &mut Self::Session,
+        prompt: &str,
+        into: &mut Vec<f32>,
+    ) -> anyhow::Result<()> {
+        let tokens = self
+            .tokenizer
+            .encode(prompt, false)
+            .map_err(anyhow::Error::msg)?;
+        self.feed_tokens(session, tokens.get_ids(), into)
+    }
+
+    fn feed_tokens(
+        &self,
+        session: &mut Self::Session,
+        tokens: &[u32],
+        into: &mut Vec<f32>,
+    ) -> anyhow::Result<()> {
+        session.tokens.extend_from_slice(tokens);
+        *into = vec![0.0; self.tokenizer.get_vocab_size(true)];
+        let a = self.tokenizer.token_to_id("a").unwrap();
+        into[a as usize] = 10.0;
+        Ok(())
+    }
+
+    fn stop_token(&self) -> anyhow::Result<u32> {
+        Ok(self.tokenizer.token_to_id("</s>").unwrap())
+    }
+
+    fn tokenizer(&self) -> Arc<Tokenizer> {
+        self.tokenizer.clone()
+    }
+}
+
+struct ScriptedEmbedding;
+
+impl VectorSpace for ScriptedEmbedding {}
+
+/// An embedder that embeds text into a vector of its length.
+struct ScriptedEmbedder;
+
+impl Embedder for ScriptedEmbedder {
+    type VectorSpace = ScriptedEmbedding;
+
+    fn embed_for(
+        &self,
+        input: EmbeddingInput,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<Embedding<ScriptedEmbedding>>> + Send + '_>>
+    {
+        Box::pin(async move { Ok(Embedding::from([input.text.len() as f32, 1.0])) })
+    }
+}
+
+async fn start_server() -> String {
+    let router = ServerBuilder::new()
+        .with_model(
+            "scripted",
+            ScriptedModel {
+                tokenizer: tokenizer(),
+            },
+        )
+        .with_embedder(AdaEmbedder::MODEL_ID, ScriptedEmbedder)
+        .build();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+    format!("http://{address}/v1")
+}
+
+fn chat_request(max_tokens: u32) -> CreateChatCompletionRequestArgs {
+    let mut builder = CreateChatCompletionRequestArgs::default();
+    builder
+        .model("scripted")
+        .messages([ChatCompletionRequestUserMessageArgs::default()
+            .content("Say hello")
+            .build()
+            .unwrap()
+            .into()])
+        .max_tokens(max_tokens);
+    builder
+}
+
+async fn chat(base_url: &str, request: CreateChatCompletionRequest) -> (String, FinishReason) {
+    let client = async_openai::Client::with_config(OpenAIConfig::new().with_api_base(base_url));
+    let response: CreateChatCompletionResponse = client.chat().create(request).await.unwrap();
+    let choice = response.choices.into_iter().next().unwrap();
+    (
+        choice.message.content.unwrap_or_default(),
+        choice.finish_reason.unwrap(),
+    )
+}
+
+#[tokio::test]
+async fn streams_completions_to_remote_model() {
+    let base_url = start_server().await;
+    let model = RemoteOpenAICompatibleModel::builder()
+        .with_model("scripted")
+        .with_base_url(&base_url)
+        .build();
+
+    let text = model.generate_text("Say hello").await.unwrap();
+    assert_eq!(text, "Hello, world!");
+
+    let text = model
+        .generate_text("Say hello")
+        .with_max_length(2)
+        .await
+        .unwrap();
+    assert_eq!(text, "Hello,");
+
+    let text = model
+        .generate_text("Say hello")
+        .with_stop_on(" world".to_string())
+        .await
+        .unwrap();
+    assert_eq!(text, "Hello,");
+
+    // The server reports why the generation stopped
+    let (text, stop_reason) = model
+        .generate_text("Say hello")
+        .with_max_length(2)
+        .text_with_stop_reason()
+        .await
+        .unwrap();
+    assert_eq!(text, "Hello,");
+    assert_eq!(stop_reason, StopReason::MaxLength);
+    let stream = model
+        .stream_text("Say hello")
+        .with_max_length(2)
+        .with_interrupt(Interrupt::new())
+        .await
+        .unwrap();
+    assert_eq!(stream.result().await, StopReason::MaxLength);
+    let stream = model
+        .stream_text("Say hello")
+        .with_interrupt(Interrupt::new())
+        .await
+        .unwrap();
+    assert_eq!(stream.result().await, StopReason::Finished);
+}
+
+#[tokio::test]
+async fn chat_completions() {
+    let base_url = start_server().await;
+
+    let request = chat_request(16).build().unwrap();
+    let (text, finish_reason) = chat(&base_url, request).await;
+    assert_eq!(text, "Hello, world!");
+    assert_eq!(finish_reason, FinishReason::Stop);
+
+    // Every token fits, so the response finished on its own even though it hit the limit exactly
+    let request = chat_request(4).build().unwrap();
+    let (text, finish_reason) = chat(&base_url, request).await;
+    assert_eq!(text, "Hello, world!");
+    assert_eq!(finish_reason, FinishReason::Stop);
+
+    let request = chat_request(2).build().unwrap();
+    let (text, finish_reason) = chat(&base_url, request).await;
+    assert_eq!(text, "Hello,");
+    assert_eq!(finish_reason, FinishReason::Length);
+}
+
+#[tokio::test]
+async fn chat_completions_with_json_schema() {
+    let base_url = start_server().await;
+    let model = RemoteOpenAICompatibleModel::builder()
+        .with_model("scripted")
+        .with_base_url(&base_url)
+        .build();
+
+    // The remote model requests a response with a JSON schema and parses it
+    model
+        .generate_with_schema::<bool>("Is the sky blue? ")
+        .result()
+        .await
+        .unwrap();
+
+    let json_schema = |schema: serde_json::Value| ResponseFormat::JsonSchema {
+        json_schema: ResponseFormatJsonSchema {
+            description: None,
+            name: "response".to_string(),
+            schema: Some(schema),
+            strict: Some(true),
+        },
+    };
+
+    let request = chat_request(64)
+        .response_format(json_schema(serde_json::json!({
+            "type": "object",
+            "properties": { "answer": { "type": "boolean" } },
+            "required": ["answer"],
+            "additionalProperties": false
+        })))
+        .build()
+        .unwrap();
+    let (text, finish_reason) = chat(&base_url, request).await;
+    let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+    assert!(value["answer"].is_boolean());
+    assert_eq!(finish_reason, FinishReason::Stop);
+
+    // The model keeps generating `a` inside the string until it hits the token limit
+    let request = chat_request(4)
+        .response_format(json_schema(serde_json::json!({ "type": "string" })))
+        .build()
+        .unwrap();
+    let (text, finish_reason) = chat(&base_url, request).await;
+    assert_eq!(text, "\"aaa");
+    assert_eq!(finish_reason, FinishReason::Length);
+}
+
+#[tokio::test]
+async fn reports_generation_errors() {
+    let base_url = start_server().await;
+
+    // The streamed response ends with an error event instead of a finish reason
+    let model = RemoteOpenAICompatibleModel::builder()
+        .with_model("scripted")
+        .with_base_url(&base_url)
+        .build();
+    let stream = model
+        .stream_text("Fail")
+        .with_interrupt(Interrupt::new())
+        .await
+        .unwrap();
+    assert_eq!(stream.result().await, StopReason::Error);
+
+    // The full response is an error instead of the partial text
+    let response = reqwest::Client::new()
+        .post(format!("{base_url}/completions"))
+        .json(&serde_json::json!({ "model": "scripted", "prompt": "Fail" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        reqwest::StatusCode::INTERNAL_SERVER_ERROR
+    );
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["type"], "server_error");
+}
+
+#[tokio::test]
+async fn embeds_with_remote_embedder() {
+    let base_url = start_server().await;
+    let embedder = AdaEmbedder::builder().with_base_url(&base_url).build();
+
+    let embeddings = embedder
+        .embed_vec(vec!["a".to_string(), "abc".to_string()])
+        .await
+        .unwrap();
+    let vectors: Vec<_> = embeddings
+        .iter()
+        .map(|embedding| embedding.to_vec())
+        .collect();
+    assert_eq!(vectors, vec![vec![1.0, 1.0], vec![3.0, 1.0]]);
+}
@@ -0,0 +1,17 @@
+//! Serve a local model with an OpenAI compatible API. Any OpenAI client can use the model by setting the base URL to `http://127.0.0.1:8080/v1`.
+
+use kalosm::language::*;
+use kalosm::server::ServerBuilder;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let llm = Llama::new_chat().await?;
+    let bert = Bert::new().await?;
+
+    println!("Serving at http://127.0.0.1:8080/v1");
+    ServerBuilder::new()
+        .with_model("llama", llm)
+        .with_embedder("bert", bert)
+        .serve("127.0.0.1:8080")
+        .await
+}
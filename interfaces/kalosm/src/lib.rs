@@ -28,6 +28,8 @@ pub mod language {
     #[cfg(feature = "surrealdb")]
     pub use crate::surrealdb_integration::document_table::*;
 }
+#[cfg(feature = "server")]
+pub use kalosm_server as server;
 #[cfg(feature = "sound")]
 pub mod sound {
     #![doc = include_str!("../docs/sound.md")]
@@ -69,22 +69,27 @@ use surrealdb::Surreal;
 ///     println!("{:?}", nearest_5);
 /// }
 /// ```
+///
+/// The embeddings are stored in a [`VectorDB`] by default. You can use any other [`VectorStore`] with [`DocumentTableBuilder::build_with_vector_store`].
 pub struct DocumentTable<
     C: Connection,
     R = Document,
     M: Embedder = Bert,
     K: Chunker = SemanticChunker,
+    V = VectorDB<<M as Embedder>::VectorSpace>,
 > {
     embedding_model: M,
     chunker: K,
-    table: EmbeddingIndexedTable<C, R, M::VectorSpace>,
+    table: EmbeddingIndexedTable<C, R, M::VectorSpace, V>,
 }
 
-impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
+impl<C: Connection, R, M: Embedder, K: Chunker, V: VectorStore<M::VectorSpace>>
+    DocumentTable<C, R, M, K, V>
+{
     /// Create a new document table.
     pub fn new(
         embedding_model: M,
-        table: EmbeddingIndexedTable<C, R, M::VectorSpace>,
+        table: EmbeddingIndexedTable<C, R, M::VectorSpace, V>,
         chunker: K,
     ) -> Self {
         Self {
@@ -95,7 +100,7 @@ impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
     }
 
     /// Get the raw table.
-    pub fn table(&self) -> &EmbeddingIndexedTable<C, R, M::VectorSpace> {
+    pub fn table(&self) -> &EmbeddingIndexedTable<C, R, M::VectorSpace, V> {
         &self.table
     }
 
@@ -114,16 +119,16 @@ impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
         self.table.delete_table().await
     }
 
-    /// Insert a new record into the table with pre-computed chunks.
+    /// Insert a new record into the table with pre-computed chunks. The text of each chunk is added to the full text index like [`Self::insert`].
     pub async fn insert_with_chunks(
         &self,
         value: R,
         chunks: impl IntoIterator<Item = Chunk<M::VectorSpace>>,
     ) -> Result<Id, EmbeddedIndexedTableError>
     where
-        R: Serialize + DeserializeOwned,
+        R: AsRef<Document> + Serialize + DeserializeOwned,
     {
-        self.table.insert(chunks, value).await
+        self.table.insert_with_text(chunks, value).await
     }
 
     /// Insert a new record into the table and return the id of the record.
@@ -135,7 +140,7 @@ impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
             .chunker
             .chunk(value.as_ref(), &self.embedding_model)
             .await?;
-        Ok(self.insert_with_chunks(value, chunks).await?)
+        Ok(self.table.insert_with_text(chunks, value).await?)
     }
 
     /// Extend the table with a iterator of new records.
@@ -152,7 +157,7 @@ impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
             .await?;
         let mut ids = Vec::new();
         for (value, embeddings) in entries.into_iter().zip(embeddings) {
-            let id = self.table.insert(embeddings, value).await?;
+            let id = self.table.insert_with_text(embeddings, value).await?;
             ids.push(id);
         }
         Ok(ids)
@@ -193,7 +198,10 @@ impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
     /// Select the top k records nearest records to the given item.
     ///
     /// NOTE: If your embedding model has a different query embedding and you pass in a raw embedding, that embedding will perform best if it was created with [`EmbedderExt::embed_query`].
-    pub fn search<E>(&self, embedding: E) -> DocumentTableSearchBuilder<C, R, M, K, E>
+    pub fn search<E>(
+        &self,
+        embedding: E,
+    ) -> DocumentTableSearchBuilder<C, R, M, K, E, Candidates, (), V>
     where
         E: IntoEmbedding<M::VectorSpace>,
         R: DeserializeOwned,
@@ -202,13 +210,19 @@ impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
             table: self,
             embedding,
             results: None,
+            candidates: None,
             filter: None,
+            reranker: None,
+            text_query: None,
+            fusion: HybridFusion::default(),
             phantom: std::marker::PhantomData,
         }
     }
 }
 
-impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
+impl<C: Connection, R, M: Embedder, K: Chunker, V: VectorStore<M::VectorSpace>>
+    DocumentTable<C, R, M, K, V>
+{
     /// Extend the table from [`IntoDocuments`]
     pub async fn add_context(&self, context: impl IntoDocuments) -> anyhow::Result<Vec<Id>>
     where
@@ -231,14 +245,26 @@ pub struct DocumentTableSearchBuilder<
     E = Embedding<<Model as Embedder>::VectorSpace>,
     F = Candidates,
     M = (),
+    Store = VectorDB<<Model as Embedder>::VectorSpace>,
 > {
-    table: &'a DocumentTable<Conn, Doc, Model, Chkr>,
+    table: &'a DocumentTable<Conn, Doc, Model, Chkr, Store>,
     embedding: E,
     results: Option<usize>,
+    candidates: Option<usize>,
     filter: Option<F>,
+    reranker: Option<SearchReranker<'a, Doc>>,
+    text_query: Option<String>,
+    fusion: HybridFusion,
     phantom: std::marker::PhantomData<M>,
 }
 
+/// A reranker with the query and a function to get the text of each result.
+struct SearchReranker<'a, Doc> {
+    reranker: &'a dyn Reranker,
+    query: String,
+    text: fn(&EmbeddingIndexedTableSearchResult<Doc>) -> String,
+}
+
 impl<
         'a,
         Conn: Connection,
@@ -247,8 +273,9 @@ impl<
         E: IntoEmbedding<Model::VectorSpace>,
         F: IntoEmbeddingIndexedTableSearchFilter<Conn, Doc, Model::VectorSpace, M>,
         Chkr: Chunker,
+        Store: VectorStore<Model::VectorSpace>,
         M,
-    > DocumentTableSearchBuilder<'a, Conn, Doc, Model, Chkr, E, F, M>
+    > DocumentTableSearchBuilder<'a, Conn, Doc, Model, Chkr, E, F, M, Store>
 {
     /// Set the number of results to return. Defaults to 10.
     pub fn with_results(mut self, results: usize) -> Self {
@@ -256,22 +283,72 @@ impl<
         self
     }
 
+    /// Set the number of candidates to fetch before reranking with [`Self::with_reranker`]. Defaults to 4 times the number of results.
+    pub fn with_candidates(mut self, candidates: usize) -> Self {
+        self.candidates = Some(candidates);
+        self
+    }
+
+    /// Turn the search into a hybrid search that also searches the text of each chunk for the query. See [`VectorDBSearchBuilder::with_text_query`] for more details.
+    pub fn with_text_query(mut self, query: impl ToString) -> Self {
+        self.text_query = Some(query.to_string());
+        self
+    }
+
+    /// Set how a hybrid search combines the vector and text results. Defaults to [`HybridFusion::ReciprocalRank`] with `k = 60`.
+    pub fn with_fusion(mut self, fusion: HybridFusion) -> Self {
+        self.fusion = fusion;
+        self
+    }
+
     /// Run the search and return the results.
     pub async fn run(self) -> anyhow::Result<Vec<EmbeddingIndexedTableSearchResult<Doc>>> {
+        let results = self.results.unwrap_or(10);
+        // Fetch extra candidates for the reranker to choose from
+        let candidates = match &self.reranker {
+            Some(_) => self
+                .candidates
+                .unwrap_or(results * RERANK_CANDIDATES_PER_RESULT)
+                .max(results),
+            None => results,
+        };
         let embedding = self
             .embedding
             .into_embedding(&self.table.embedding_model)
             .await?;
-        let mut query = self.table.table.search(&embedding);
-        if let Some(results) = self.results {
-            query = query.with_results(results);
+        let mut query = self
+            .table
+            .table
+            .search(&embedding)
+            .with_results(candidates)
+            .with_fusion(self.fusion);
+        if let Some(text_query) = self.text_query {
+            query = query.with_text_query(text_query);
         }
-        if let Some(filter) = self.filter {
+        let records = if let Some(filter) = self.filter {
             let query = query.with_filter(filter);
-            Ok(query.run().await?)
+            query.run().await?
         } else {
-            Ok(query.run().await?)
-        }
+            query.run().await?
+        };
+
+        let Some(SearchReranker {
+            reranker,
+            query,
+            text,
+        }) = self.reranker
+        else {
+            return Ok(records);
+        };
+        let ranked = reranker.rerank(query, records, text).await?;
+        Ok(ranked
+            .into_iter()
+            .take(results)
+            .map(|(relevance, mut record)| {
+                record.relevance = Some(relevance);
+                record
+            })
+            .collect())
     }
 }
 
@@ -283,8 +360,9 @@ impl<
         E: IntoEmbedding<Model::VectorSpace> + Send + 'a,
         F: IntoEmbeddingIndexedTableSearchFilter<Conn, Doc, Model::VectorSpace, M> + Send + Sync + 'a,
         Chkr: Chunker + Send + Sync + 'a,
+        Store: VectorStore<Model::VectorSpace> + 'a,
         M: Send + 'a,
-    > IntoFuture for DocumentTableSearchBuilder<'a, Conn, Doc, Model, Chkr, E, F, M>
+    > IntoFuture for DocumentTableSearchBuilder<'a, Conn, Doc, Model, Chkr, E, F, M, Store>
 {
     type IntoFuture = Pin<Box<dyn Future<Output = Self::Output> + Send + 'a>>;
     type Output = anyhow::Result<Vec<EmbeddingIndexedTableSearchResult<Doc>>>;
@@ -302,14 +380,15 @@ impl<
         E: IntoEmbedding<Model::VectorSpace>,
         F: IntoEmbeddingIndexedTableSearchFilter<Conn, Doc, Model::VectorSpace, M>,
         Chkr: Chunker,
+        Store: VectorStore<Model::VectorSpace>,
         M,
-    > DocumentTableSearchBuilder<'a, Conn, Doc, Model, Chkr, E, F, M>
+    > DocumentTableSearchBuilder<'a, Conn, Doc, Model, Chkr, E, F, M, Store>
 {
     /// Set a filter to apply to the results. Only vectors that pass the filter will be returned.
     pub fn with_filter<Marker, F2>(
         self,
         filter: F2,
-    ) -> DocumentTableSearchBuilder<'a, Conn, Doc, Model, Chkr, E, F2, Marker>
+    ) -> DocumentTableSearchBuilder<'a, Conn, Doc, Model, Chkr, E, F2, Marker, Store>
     where
         F2: IntoEmbeddingIndexedTableSearchFilter<Conn, Doc, Model::VectorSpace, Marker>
             + Send
@@ -320,10 +399,50 @@ impl<
             table: self.table,
             embedding: self.embedding,
             results: self.results,
+            candidates: self.candidates,
             filter: Some(filter),
+            reranker: self.reranker,
+            text_query: self.text_query,
+            fusion: self.fusion,
             phantom: std::marker::PhantomData,
         }
     }
+
+    /// Sort the results with a [`Reranker`] like [`BertReranker`]. The search fetches extra candidates, scores each candidate against the query with the reranker and returns the most relevant results with [`EmbeddingIndexedTableSearchResult::relevance`] set.
+    ///
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # use surrealdb::{engine::local::RocksDb, Surreal};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// # let db = Surreal::new::<RocksDb>("./db/temp.db").await.unwrap();
+    /// # db.use_ns("rag").use_db("rag").await.unwrap();
+    /// # let document_table = db
+    /// #     .document_table_builder("documents")
+    /// #     .build::<Document>()
+    /// #     .await
+    /// #     .unwrap();
+    /// let reranker = BertReranker::new().await.unwrap();
+    /// let best = document_table
+    ///     .search("What is Kalosm?")
+    ///     .with_reranker(&reranker)
+    ///     .with_results(3)
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn with_reranker(mut self, reranker: &'a dyn Reranker) -> Self
+    where
+        E: ToString,
+        Doc: AsRef<Document>,
+    {
+        self.reranker = Some(SearchReranker {
+            reranker,
+            query: self.embedding.to_string(),
+            text: EmbeddingIndexedTableSearchResult::text,
+        });
+        self
+    }
 }
 
 /// A builder for creating a new document table.
@@ -352,7 +471,7 @@ impl<C: Connection> DocumentTableBuilder<C, Bert, ChunkStrategy> {
 }
 
 impl<C: Connection, E, K: Chunker> DocumentTableBuilder<C, E, K> {
-    /// Set the location of the vector database.
+    /// Set the location of the vector database. This is only used by [`Self::build`].
     pub fn at(mut self, location: impl AsRef<std::path::Path>) -> Self {
         self.location = Some(location.as_ref().to_path_buf());
         self
@@ -387,22 +506,49 @@ impl<C: Connection, E, K: Chunker> DocumentTableBuilder<C, E, K> {
         }
     }
 
-    /// Build the document table.
+    /// Build the document table with a [`VectorDB`].
     pub async fn build<R: Serialize + DeserializeOwned>(
-        self,
+        mut self,
     ) -> anyhow::Result<DocumentTable<C, R, E, K>>
     where
         E: Embedder,
     {
-        let vector_db = if let Some(location) = self.location {
+        let vector_db = if let Some(location) = self.location.take() {
             VectorDB::new_at(location)?
         } else {
             VectorDB::new()?
         };
+        self.build_with_vector_store(vector_db).await
+    }
+
+    /// Build the document table with a custom [`VectorStore`]. [`BruteForceVectorStore`] is useful for tests and small collections because it doesn't create any files.
+    ///
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # use surrealdb::{engine::local::RocksDb, Surreal};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let db = Surreal::new::<RocksDb>("./db/temp.db").await.unwrap();
+    /// db.use_ns("rag").use_db("rag").await.unwrap();
+    /// let document_table = db
+    ///     .document_table_builder("documents")
+    ///     .build_with_vector_store::<Document, _>(BruteForceVectorStore::new())
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub async fn build_with_vector_store<R: Serialize + DeserializeOwned, V>(
+        self,
+        vector_store: V,
+    ) -> anyhow::Result<DocumentTable<C, R, E, K, V>>
+    where
+        E: Embedder,
+        V: VectorStore<E::VectorSpace>,
+    {
         let table = EmbeddingIndexedTable {
             table: self.table.to_string(),
             db: self.db,
-            vector_db,
+            vector_db: vector_store,
             phantom: std::marker::PhantomData,
         };
         let embedding_model = match self.embedding_model {
@@ -28,6 +28,17 @@ pub enum EmbeddedIndexedTableError {
     /// An error from querying an embedding id that does not exist.
     #[error("Embedding {0:?} not found")]
     EmbeddingNotFound(EmbeddingId),
+    /// An error from adding or searching for an embedding with a different number of dimensions than the other embeddings.
+    #[error("Expected an embedding with {expected} dimensions, but found {found} dimensions")]
+    DimensionMismatch {
+        /// The number of dimensions of the other embeddings.
+        expected: usize,
+        /// The number of dimensions of the embedding.
+        found: usize,
+    },
+    /// An error from converting a payload to or from JSON.
+    #[error("Payload error: {0}")]
+    Payload(#[from] serde_json::Error),
 }
 
 impl From<heed::Error> for EmbeddedIndexedTableError {
@@ -42,6 +53,10 @@ impl From<VectorDbError> for EmbeddedIndexedTableError {
             VectorDbError::Arroy(err) => Self::Arroy(err),
             VectorDbError::Candle(err) => Self::Candle(err),
             VectorDbError::EmbeddingNotFound(id) => Self::EmbeddingNotFound(id),
+            VectorDbError::DimensionMismatch { expected, found } => {
+                Self::DimensionMismatch { expected, found }
+            }
+            VectorDbError::Payload(err) => Self::Payload(err),
         }
     }
 }
@@ -65,15 +80,15 @@ pub struct ObjectWithEmbeddingIds<T> {
     chunks: Vec<(Range<usize>, Vec<EmbeddingId>)>,
 }
 
-/// A table in a surreal database with a primary key tied to an embedding in a vector database.
-pub struct EmbeddingIndexedTable<C: Connection, R, S = UnknownVectorSpace> {
+/// A table in a surreal database with a primary key tied to an embedding in a [`VectorStore`].
+pub struct EmbeddingIndexedTable<C: Connection, R, S = UnknownVectorSpace, V = VectorDB<S>> {
     table: String,
     db: Surreal<C>,
-    vector_db: VectorDB<S>,
-    phantom: std::marker::PhantomData<R>,
+    vector_db: V,
+    phantom: std::marker::PhantomData<(R, S)>,
 }
 
-impl<C: Connection, R, S: VectorSpace> EmbeddingIndexedTable<C, R, S> {
+impl<C: Connection, R, S: VectorSpace, V: VectorStore<S>> EmbeddingIndexedTable<C, R, S, V> {
     /// Get the name of the table.
     pub fn table(&self) -> &str {
         &self.table
@@ -84,8 +99,8 @@ impl<C: Connection, R, S: VectorSpace> EmbeddingIndexedTable<C, R, S> {
         format!("{}-links", &self.table)
     }
 
-    /// Get the raw vector database.
-    pub fn vector_db(&self) -> &VectorDB<S> {
+    /// Get the raw vector store.
+    pub fn vector_db(&self) -> &V {
         &self.vector_db
     }
 
@@ -119,7 +134,7 @@ impl<C: Connection, R, S: VectorSpace> EmbeddingIndexedTable<C, R, S> {
             }
             documents.push((embedding.object, chunks));
         }
-        self.vector_db.clear().await?;
+        self.vector_db.clear()?;
 
         Ok(documents)
     }
@@ -130,6 +145,31 @@ impl<C: Connection, R, S: VectorSpace> EmbeddingIndexedTable<C, R, S> {
         chunks: impl IntoIterator<Item = Chunk<S>>,
         value: R,
     ) -> Result<Id, EmbeddedIndexedTableError>
+    where
+        R: Serialize + DeserializeOwned,
+    {
+        self.insert_inner(chunks, value, None).await
+    }
+
+    /// Insert a new record into the table with the given embedding and add the text of each chunk to the full text index for [hybrid search](EmbeddingIndexedTableSearchBuilder::with_text_query).
+    pub async fn insert_with_text(
+        &self,
+        chunks: impl IntoIterator<Item = Chunk<S>>,
+        value: R,
+    ) -> Result<Id, EmbeddedIndexedTableError>
+    where
+        R: AsRef<Document> + Serialize + DeserializeOwned,
+    {
+        let text = value.as_ref().body().to_string();
+        self.insert_inner(chunks, value, Some(&text)).await
+    }
+
+    async fn insert_inner(
+        &self,
+        chunks: impl IntoIterator<Item = Chunk<S>>,
+        value: R,
+        text: Option<&str>,
+    ) -> Result<Id, EmbeddedIndexedTableError>
     where
         R: Serialize + DeserializeOwned,
     {
@@ -143,6 +183,14 @@ impl<C: Connection, R, S: VectorSpace> EmbeddingIndexedTable<C, R, S> {
 
         for chunk in chunks {
             let chunk_embedding_ids = self.vector_db.add_embeddings(chunk.embeddings)?;
+            if let Some(chunk_text) = text.and_then(|text| text.get(chunk.byte_range.clone())) {
+                self.vector_db.add_texts(
+                    chunk_embedding_ids
+                        .iter()
+                        .map(|embedding_id| (*embedding_id, chunk_text.to_string()))
+                        .collect(),
+                )?;
+            }
             for embedding_id in &chunk_embedding_ids {
                 let byte_range = chunk.byte_range.clone();
 
@@ -256,12 +304,14 @@ impl<C: Connection, R, S: VectorSpace> EmbeddingIndexedTable<C, R, S> {
     pub fn search<'a>(
         &'a self,
         embedding: &'a Embedding<S>,
-    ) -> EmbeddingIndexedTableSearchBuilder<'a, C, R, S> {
+    ) -> EmbeddingIndexedTableSearchBuilder<'a, C, R, S, Candidates, (), V> {
         EmbeddingIndexedTableSearchBuilder {
             table: self,
             embedding,
             results: None,
             filter: None,
+            text_query: None,
+            fusion: HybridFusion::default(),
             phantom: std::marker::PhantomData,
         }
     }
@@ -270,18 +320,18 @@ impl<C: Connection, R, S: VectorSpace> EmbeddingIndexedTable<C, R, S> {
 /// A trait for anything that can be used to filter the results of an embedded table search.
 pub trait IntoEmbeddingIndexedTableSearchFilter<C: Connection, R, S: VectorSpace, Marker> {
     /// Convert the filter into a set of candidates.
-    fn into_embedding_indexed_table_search_filter(
+    fn into_embedding_indexed_table_search_filter<V: VectorStore<S>>(
         self,
-        db: &EmbeddingIndexedTable<C, R, S>,
+        db: &EmbeddingIndexedTable<C, R, S, V>,
     ) -> impl std::future::Future<Output = Result<Candidates, EmbeddedIndexedTableError>> + Send;
 }
 
 impl<C: Connection, R: Send + Sync, S: VectorSpace>
     IntoEmbeddingIndexedTableSearchFilter<C, R, S, ()> for Candidates
 {
-    async fn into_embedding_indexed_table_search_filter(
+    async fn into_embedding_indexed_table_search_filter<V: VectorStore<S>>(
         self,
-        _: &EmbeddingIndexedTable<C, R, S>,
+        _: &EmbeddingIndexedTable<C, R, S, V>,
     ) -> Result<Candidates, EmbeddedIndexedTableError> {
         Ok(self)
     }
@@ -296,9 +346,9 @@ where
     I: IntoIterator<Item = Id>,
     I::IntoIter: Send + Sync + 'static,
 {
-    fn into_embedding_indexed_table_search_filter(
+    fn into_embedding_indexed_table_search_filter<V: VectorStore<S>>(
         self,
-        table: &EmbeddingIndexedTable<C, R, S>,
+        table: &EmbeddingIndexedTable<C, R, S, V>,
     ) -> impl Future<Output = Result<Candidates, EmbeddedIndexedTableError>> + Send {
         let ids = self.into_iter();
         async move {
@@ -330,11 +380,14 @@ pub struct EmbeddingIndexedTableSearchBuilder<
     S: VectorSpace,
     F = Candidates,
     M = (),
+    V = VectorDB<S>,
 > {
-    table: &'a EmbeddingIndexedTable<C, R, S>,
+    table: &'a EmbeddingIndexedTable<C, R, S, V>,
     embedding: &'a Embedding<S>,
     results: Option<usize>,
     filter: Option<F>,
+    text_query: Option<String>,
+    fusion: HybridFusion,
     phantom: std::marker::PhantomData<M>,
 }
 
@@ -343,9 +396,10 @@ impl<
         C: Connection,
         R: DeserializeOwned,
         S: VectorSpace,
+        V: VectorStore<S>,
         F: IntoEmbeddingIndexedTableSearchFilter<C, R, S, M>,
         M,
-    > EmbeddingIndexedTableSearchBuilder<'a, C, R, S, F, M>
+    > EmbeddingIndexedTableSearchBuilder<'a, C, R, S, F, M, V>
 {
     /// Set the number of results to return. Defaults to 10.
     pub fn with_results(mut self, results: usize) -> Self {
@@ -353,11 +407,26 @@ impl<
         self
     }
 
+    /// Turn the search into a hybrid search that also searches the text of each chunk for the query. Only records inserted with [`EmbeddingIndexedTable::insert_with_text`] have text to search. See [`VectorDBSearchBuilder::with_text_query`] for more details.
+    pub fn with_text_query(mut self, query: impl ToString) -> Self {
+        self.text_query = Some(query.to_string());
+        self
+    }
+
+    /// Set how a hybrid search combines the vector and text results. Defaults to [`HybridFusion::ReciprocalRank`] with `k = 60`.
+    pub fn with_fusion(mut self, fusion: HybridFusion) -> Self {
+        self.fusion = fusion;
+        self
+    }
+
     /// Run the search and return the results.
     pub async fn run(
         self,
     ) -> Result<Vec<EmbeddingIndexedTableSearchResult<R>>, EmbeddedIndexedTableError> {
-        let mut query = self.table.vector_db.search(self.embedding);
+        let mut query = VectorStoreQuery::new(self.embedding).with_fusion(self.fusion);
+        if let Some(text_query) = self.text_query {
+            query = query.with_text_query(text_query);
+        }
         if let Some(filter) = self.filter {
             query = query.with_filter(
                 filter
@@ -368,7 +437,7 @@ impl<
         if let Some(results) = self.results {
             query = query.with_results(results);
         }
-        let ids = query.run()?;
+        let ids = self.table.vector_db.search(query)?;
         let mut records = Vec::new();
         for id in ids {
             let main_table_id = self
@@ -387,6 +456,7 @@ impl<
                 record_id: main_table_id.document_id,
                 byte_range: main_table_id.byte_range,
                 record,
+                relevance: id.relevance,
             });
         }
         Ok(records)
@@ -398,9 +468,10 @@ impl<
         C: Connection + 'a,
         R: DeserializeOwned + Send + Sync + 'a,
         S: VectorSpace + 'a,
+        V: VectorStore<S> + 'a,
         F: IntoEmbeddingIndexedTableSearchFilter<C, R, S, M> + Send + 'a,
         M: Send + 'a,
-    > IntoFuture for EmbeddingIndexedTableSearchBuilder<'a, C, R, S, F, M>
+    > IntoFuture for EmbeddingIndexedTableSearchBuilder<'a, C, R, S, F, M, V>
 {
     type IntoFuture = Pin<Box<dyn Future<Output = Self::Output> + Send + 'a>>;
     type Output = Result<Vec<EmbeddingIndexedTableSearchResult<R>>, EmbeddedIndexedTableError>;
@@ -410,14 +481,14 @@ impl<
     }
 }
 
-impl<'a, C: Connection, R: DeserializeOwned, S: VectorSpace>
-    EmbeddingIndexedTableSearchBuilder<'a, C, R, S>
+impl<'a, C: Connection, R: DeserializeOwned, S: VectorSpace, V: VectorStore<S>>
+    EmbeddingIndexedTableSearchBuilder<'a, C, R, S, Candidates, (), V>
 {
     /// Set a filter to apply to the results. Only vectors that pass the filter will be returned.
     pub fn with_filter<Marker, F>(
         self,
         filter: F,
-    ) -> EmbeddingIndexedTableSearchBuilder<'a, C, R, S, F, Marker>
+    ) -> EmbeddingIndexedTableSearchBuilder<'a, C, R, S, F, Marker, V>
     where
         F: IntoEmbeddingIndexedTableSearchFilter<C, R, S, Marker>,
     {
@@ -426,6 +497,8 @@ impl<'a, C: Connection, R: DeserializeOwned, S: VectorSpace>
             embedding: self.embedding,
             results: self.results,
             filter: Some(filter),
+            text_query: self.text_query,
+            fusion: self.fusion,
             phantom: std::marker::PhantomData,
         }
     }
@@ -444,6 +517,8 @@ pub struct EmbeddingIndexedTableSearchResult<R> {
     pub byte_range: std::ops::Range<usize>,
     /// The record.
     pub record: R,
+    /// The relevance score if the results were reranked or came from a hybrid search. Higher scores are more relevant.
+    pub relevance: Option<f32>,
 }
 
 impl<R> EmbeddingIndexedTableSearchResult<R>
@@ -476,27 +551,38 @@ impl<C: Connection> EmbeddingIndexedTableBuilder<C> {
         }
     }
 
-    /// Set the location of the vector database.
+    /// Set the location of the vector database. This is only used by [`Self::build`].
     pub fn at(mut self, location: impl AsRef<std::path::Path>) -> Self {
         self.location = Some(location.as_ref().to_path_buf());
         self
     }
 
-    /// Build the document table.
+    /// Build the document table with a [`VectorDB`].
     pub fn build<S: VectorSpace, R: Serialize + DeserializeOwned>(
-        self,
+        mut self,
     ) -> Result<EmbeddingIndexedTable<C, R, S>, EmbeddedIndexedTableError> {
-        let vector_db = if let Some(location) = self.location {
+        let vector_db = if let Some(location) = self.location.take() {
             VectorDB::new_at(location)?
         } else {
             VectorDB::new()?
         };
-        Ok(EmbeddingIndexedTable {
+        Ok(self.build_with_vector_store(vector_db))
+    }
+
+    /// Build the document table with a custom [`VectorStore`] like [`BruteForceVectorStore`] or [`HnswVectorStore`].
+    pub fn build_with_vector_store<S: VectorSpace, R: Serialize + DeserializeOwned, V>(
+        self,
+        vector_store: V,
+    ) -> EmbeddingIndexedTable<C, R, S, V>
+    where
+        V: VectorStore<S>,
+    {
+        EmbeddingIndexedTable {
             table: self.table.to_string(),
             db: self.db,
-            vector_db,
+            vector_db: vector_store,
             phantom: std::marker::PhantomData,
-        })
+        }
     }
 }
 
@@ -0,0 +1,120 @@
+use kalosm::language::*;
+use kalosm::{EmbeddingIndexedTable, VectorDbSurrealExt};
+use std::{future::Future, pin::Pin};
+use surrealdb::engine::local::{Db, RocksDb};
+use surrealdb::Surreal;
+
+struct PrinterSpace;
+
+impl VectorSpace for PrinterSpace {}
+
+/// An embedder that only knows if text is about a printer. Every text about printers has the same embedding, so only the text search can tell them apart.
+struct PrinterEmbedder;
+
+impl Embedder for PrinterEmbedder {
+    type VectorSpace = PrinterSpace;
+
+    fn embed_for(
+        &self,
+        input: EmbeddingInput,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<Embedding<PrinterSpace>>> + Send + '_>> {
+        let printer = input.text.to_lowercase().contains("printer");
+        Box::pin(async move { Ok(Embedding::from([if printer { 1.0 } else { 0.0 }, 1.0])) })
+    }
+}
+
+const PAPER: &str = "The printer is out of paper.";
+const ERROR_CODE: &str = "Error E-4012 means the printer is jammed.";
+
+async fn surreal(dir: &tempfile::TempDir) -> Surreal<Db> {
+    let db = Surreal::new::<RocksDb>(dir.path().join("surreal").to_str().unwrap())
+        .await
+        .unwrap();
+    db.use_ns("test").use_db("test").await.unwrap();
+    db
+}
+
+/// Embed the whole document as one chunk.
+async fn chunk(document: &Document) -> Chunk<PrinterSpace> {
+    Chunk {
+        byte_range: 0..document.body().len(),
+        embeddings: vec![PrinterEmbedder.embed(document.body()).await.unwrap()],
+    }
+}
+
+#[tokio::test]
+async fn embedding_indexed_table_hybrid_search() {
+    let dir = tempfile::tempdir().unwrap();
+    let table: EmbeddingIndexedTable<_, Document, PrinterSpace> = surreal(&dir)
+        .await
+        .vector_indexed_table_builder("chunks")
+        .at(dir.path().join("embeddings"))
+        .build()
+        .unwrap();
+    for text in [PAPER, ERROR_CODE] {
+        let document = Document::from_parts("", text);
+        table
+            .insert_with_text([chunk(&document).await], document)
+            .await
+            .unwrap();
+    }
+    // Records inserted without text are only found by the vector search
+    let document = Document::from_parts("", "E-4012 is printed on the printer");
+    table
+        .insert([chunk(&document).await], document)
+        .await
+        .unwrap();
+
+    let query = PrinterEmbedder.embed_query("printer E-4012").await.unwrap();
+    let results = table
+        .search(&query)
+        .with_text_query("printer E-4012")
+        .with_fusion(HybridFusion::Weighted { text_weight: 0.7 })
+        .with_results(1)
+        .await
+        .unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].text(), ERROR_CODE);
+    assert!(results[0].relevance.is_some());
+
+    // Removing a record removes its text
+    table.delete(results[0].record_id.clone()).await.unwrap();
+    let results = table
+        .search(&query)
+        .with_text_query("E-4012")
+        .await
+        .unwrap();
+    assert!(results.iter().all(|result| result.text() != ERROR_CODE));
+}
+
+#[tokio::test]
+async fn document_table_hybrid_search() {
+    let dir = tempfile::tempdir().unwrap();
+    let table = surreal(&dir)
+        .await
+        .document_table_builder("documents")
+        .with_embedding_model(PrinterEmbedder)
+        .at(dir.path().join("embeddings"))
+        .build::<Document>()
+        .await
+        .unwrap();
+    table.insert(Document::from_parts("", PAPER)).await.unwrap();
+    // Documents with pre-computed chunks are added to the text index too
+    let document = Document::from_parts("", ERROR_CODE);
+    let chunks = [chunk(&document).await];
+    table.insert_with_chunks(document, chunks).await.unwrap();
+
+    let results = table
+        .search("What does E-4012 mean?")
+        .with_text_query("What does E-4012 mean?")
+        .with_results(1)
+        .await
+        .unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].text(), ERROR_CODE);
+
+    // Only hybrid searches score the relevance of each result
+    let results = table.search("printer").with_results(2).await.unwrap();
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().all(|result| result.relevance.is_none()));
+}
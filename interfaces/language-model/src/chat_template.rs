@@ -0,0 +1,195 @@
+use std::sync::Arc;
+
+use minijinja::{context, Environment, Error, ErrorKind, Value};
+
+/// The type of a chat message
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageType {
+    /// A system prompt.
+    SystemPrompt,
+    /// A user message.
+    UserMessage,
+    /// A model answer.
+    ModelAnswer,
+}
+
+impl MessageType {
+    /// Returns the role chat templates use for this type of message.
+    pub fn role(&self) -> &'static str {
+        match self {
+            MessageType::SystemPrompt => "system",
+            MessageType::UserMessage => "user",
+            MessageType::ModelAnswer => "assistant",
+        }
+    }
+}
+
+/// A single item in the chat history.
+#[derive(Clone, Debug)]
+pub struct ChatHistoryItem {
+    ty: MessageType,
+    contents: String,
+}
+
+impl ChatHistoryItem {
+    /// Creates a new chat history item.
+    pub fn new(ty: MessageType, contents: impl Into<String>) -> Self {
+        Self {
+            ty,
+            contents: contents.into(),
+        }
+    }
+
+    /// Returns the type of the item.
+    pub fn ty(&self) -> MessageType {
+        self.ty
+    }
+
+    /// Returns the contents of the item.
+    pub fn contents(&self) -> &str {
+        &self.contents
+    }
+}
+
+const TEMPLATE_NAME: &str = "chat";
+
+/// A chat template in the jinja format HuggingFace tokenizers use in the `chat_template` field. Chat templates turn a list of messages into a prompt for the model.
+///
+/// ```rust
+/// use kalosm_language_model::*;
+///
+/// let template = ChatTemplate::new(
+///     "{% for message in messages %}<|im_start|>{{ message['role'] }}\n{{ message['content'] }}<|im_end|>\n{% endfor %}{% if add_generation_prompt %}<|im_start|>assistant\n{% endif %}",
+///     "",
+///     "<|endoftext|>",
+/// )
+/// .unwrap();
+/// let prompt = template
+///     .render(&[ChatHistoryItem::new(MessageType::UserMessage, "Hello!")], true)
+///     .unwrap();
+/// assert_eq!(prompt, "<|im_start|>user\nHello!<|im_end|>\n<|im_start|>assistant\n");
+/// ```
+#[derive(Clone, Debug)]
+pub struct ChatTemplate {
+    environment: Arc<Environment<'static>>,
+    bos_token: String,
+    eos_token: String,
+}
+
+impl ChatTemplate {
+    /// Compile a chat template. The `bos_token` and `eos_token` are passed to the template as variables.
+    pub fn new(
+        template: impl Into<String>,
+        bos_token: impl Into<String>,
+        eos_token: impl Into<String>,
+    ) -> anyhow::Result<Self> {
+        // Match the settings transformers renders chat templates with
+        let mut environment = Environment::new();
+        environment.set_trim_blocks(true);
+        environment.set_lstrip_blocks(true);
+        // Templates are written for python jinja, so they use python string methods like `strip` and `startswith`
+        environment
+            .set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+        environment.add_function(
+            "raise_exception",
+            |message: String| -> Result<Value, Error> {
+                Err(Error::new(ErrorKind::InvalidOperation, message))
+            },
+        );
+        environment
+            .add_template_owned(TEMPLATE_NAME, template.into())
+            .map_err(|err| anyhow::anyhow!("Failed to compile the chat template: {err}"))?;
+
+        Ok(Self {
+            environment: Arc::new(environment),
+            bos_token: bos_token.into(),
+            eos_token: eos_token.into(),
+        })
+    }
+
+    /// Render the chat history into a prompt. If `add_generation_prompt` is true, the prompt ends with the text that starts a new assistant message.
+    pub fn render(
+        &self,
+        history: &[ChatHistoryItem],
+        add_generation_prompt: bool,
+    ) -> anyhow::Result<String> {
+        let messages: Vec<_> = history
+            .iter()
+            .map(|item| {
+                context! {
+                    role => item.ty().role(),
+                    content => item.contents(),
+                }
+            })
+            .collect();
+        let template = self.environment.get_template(TEMPLATE_NAME)?;
+        let rendered = template
+            .render(context! {
+                messages => messages,
+                add_generation_prompt => add_generation_prompt,
+                bos_token => self.bos_token,
+                eos_token => self.eos_token,
+            })
+            .map_err(|err| anyhow::anyhow!("Failed to render the chat template: {err}"))?;
+        Ok(rendered)
+    }
+
+    /// Find the text the template adds after each assistant message. Generation should stop once the model generates this text.
+    pub fn end_assistant_marker(&self) -> anyhow::Result<String> {
+        const SENTINEL: &str = "\u{0}ASSISTANT MESSAGE\u{0}";
+        let rendered = self.render(
+            &[
+                ChatHistoryItem::new(MessageType::UserMessage, "Hello"),
+                ChatHistoryItem::new(MessageType::ModelAnswer, SENTINEL),
+            ],
+            false,
+        )?;
+        let marker = rendered
+            .rsplit_once(SENTINEL)
+            .map(|(_, after)| after.trim())
+            .unwrap_or_default();
+        // If the template doesn't close assistant messages, the model ends them with the eos token
+        if marker.is_empty() {
+            Ok(self.eos_token.clone())
+        } else {
+            Ok(marker.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LLAMA3_TEMPLATE: &str = "{% set loop_messages = messages %}{% for message in loop_messages %}{% set content = '<|start_header_id|>' + message['role'] + '<|end_header_id|>\n\n'+ message['content'] | trim + '<|eot_id|>' %}{% if loop.index0 == 0 %}{% set content = bos_token + content %}{% endif %}{{ content }}{% endfor %}{% if add_generation_prompt %}{{ '<|start_header_id|>assistant<|end_header_id|>\n\n' }}{% endif %}";
+
+    #[test]
+    fn renders_llama3_template() {
+        let template =
+            ChatTemplate::new(LLAMA3_TEMPLATE, "<|begin_of_text|>", "<|end_of_text|>").unwrap();
+        let history = [
+            ChatHistoryItem::new(MessageType::SystemPrompt, "Be brief."),
+            ChatHistoryItem::new(MessageType::UserMessage, " Hi! "),
+        ];
+        assert_eq!(
+            template.render(&history, true).unwrap(),
+            "<|begin_of_text|><|start_header_id|>system<|end_header_id|>\n\nBe brief.<|eot_id|><|start_header_id|>user<|end_header_id|>\n\nHi!<|eot_id|><|start_header_id|>assistant<|end_header_id|>\n\n"
+        );
+        assert_eq!(template.end_assistant_marker().unwrap(), "<|eot_id|>");
+    }
+
+    #[test]
+    fn templates_can_raise_exceptions() {
+        let template = ChatTemplate::new(
+            "{% if messages[0]['role'] == 'system' %}{{ raise_exception('System role not supported') }}{% endif %}{{ messages[0]['content'].strip() }}",
+            "",
+            "</s>",
+        )
+        .unwrap();
+        let system = [ChatHistoryItem::new(MessageType::SystemPrompt, "Hi")];
+        assert!(template.render(&system, false).is_err());
+        let user = [ChatHistoryItem::new(MessageType::UserMessage, " Hi ")];
+        assert_eq!(template.render(&user, false).unwrap(), "Hi");
+        assert_eq!(template.end_assistant_marker().unwrap(), "</s>");
+    }
+}
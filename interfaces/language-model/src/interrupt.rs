@@ -0,0 +1,321 @@
+use futures_util::future::Either;
+use futures_util::{Future, Stream, StreamExt};
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+pub use tokio_util::sync::CancellationToken;
+
+/// The reason a generation stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The model finished the generation on its own. It generated the stop token, the stop string, or the constraints were satisfied.
+    Finished,
+    /// The generation hit the maximum number of tokens.
+    MaxLength,
+    /// The generation was cancelled.
+    Cancelled,
+    /// The generation ran past its deadline.
+    TimedOut,
+    /// The model failed with an error before the generation finished.
+    Error,
+}
+
+/// When to interrupt a generation before it finishes on its own.
+///
+/// ```rust, no_run
+/// use kalosm::language::*;
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let mut chat = Chat::new(Llama::new_chat().await.unwrap());
+///     let cancel = CancellationToken::new();
+///     let interrupt = Interrupt::new()
+///         .with_cancellation(cancel.clone())
+///         .with_timeout(Duration::from_secs(10));
+///     let mut response = chat.add_message_with_interrupt("Write a long story", interrupt);
+///     response.to_std_out().await.unwrap();
+///     println!("\nStopped because: {:?}", response.result().await);
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Interrupt {
+    cancellation: Option<CancellationToken>,
+    deadline: Option<Instant>,
+}
+
+impl Interrupt {
+    /// Create a new interrupt that never fires.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop the generation when the token is cancelled.
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = Some(cancellation);
+        self
+    }
+
+    /// Stop the generation once the deadline passes.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Stop the generation once the timeout passes. The timeout starts when this method is called.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.with_deadline(Instant::now() + timeout)
+    }
+
+    /// Get the cancellation token for the generation.
+    pub fn cancellation(&self) -> Option<&CancellationToken> {
+        self.cancellation.as_ref()
+    }
+
+    /// Get the deadline for the generation.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+}
+
+/// The state shared between a running generation and the stream that receives its text.
+///
+/// The model checks [`GenerationControl::interrupted`] before every token and records why it stopped with [`GenerationControl::finish`].
+#[derive(Debug, Clone)]
+pub struct GenerationControl {
+    cancellation: CancellationToken,
+    deadline: Option<Instant>,
+    stop_reason: Arc<OnceLock<StopReason>>,
+}
+
+impl Default for GenerationControl {
+    fn default() -> Self {
+        Self::new(Interrupt::default())
+    }
+}
+
+impl PartialEq for GenerationControl {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.stop_reason, &other.stop_reason)
+    }
+}
+
+impl GenerationControl {
+    /// Start controlling a new generation with the given interrupt.
+    pub fn new(interrupt: Interrupt) -> Self {
+        // A child token lets us cancel this generation without cancelling every generation that shares the user's token
+        let cancellation = match interrupt.cancellation {
+            Some(cancellation) => cancellation.child_token(),
+            None => CancellationToken::new(),
+        };
+        Self {
+            cancellation,
+            deadline: interrupt.deadline,
+            stop_reason: Default::default(),
+        }
+    }
+
+    /// Cancel the generation. It stops at the next token boundary.
+    pub fn cancel(&self) {
+        self.cancellation.cancel();
+    }
+
+    /// Check if the generation should stop early and why.
+    pub fn interrupted(&self) -> Option<StopReason> {
+        if self.cancellation.is_cancelled() {
+            Some(StopReason::Cancelled)
+        } else if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            Some(StopReason::TimedOut)
+        } else {
+            None
+        }
+    }
+
+    /// Wait until the generation is cancelled or runs past its deadline.
+    pub async fn wait_for_interrupt(&self) -> StopReason {
+        let cancelled = std::pin::pin!(self.cancellation.cancelled());
+        let timed_out = std::pin::pin!(async {
+            match self.deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+                None => std::future::pending().await,
+            }
+        });
+        match futures_util::future::select(cancelled, timed_out).await {
+            Either::Left(_) => StopReason::Cancelled,
+            Either::Right(_) => StopReason::TimedOut,
+        }
+    }
+
+    /// Record why the generation stopped. Only the first reason is kept.
+    pub fn finish(&self, reason: StopReason) {
+        _ = self.stop_reason.set(reason);
+    }
+
+    /// Get the reason the generation stopped if it has stopped.
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.stop_reason.get().copied()
+    }
+}
+
+/// A stream of generated text that stops when the generation is cancelled or runs past its deadline.
+///
+/// Dropping the stream cancels the generation.
+pub struct TextGeneration<S> {
+    stream: Option<S>,
+    control: GenerationControl,
+    interrupted: Pin<Box<dyn Future<Output = StopReason> + Send>>,
+}
+
+impl<S> std::fmt::Debug for TextGeneration<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TextGeneration")
+            .field("control", &self.control)
+            .finish()
+    }
+}
+
+impl<S> TextGeneration<S> {
+    /// Wrap the stream of a generation that is controlled by `control`. The model should record why it stopped with [`GenerationControl::finish`] before the stream ends. If it doesn't, the generation is reported as [`StopReason::Finished`].
+    pub fn new(stream: S, control: GenerationControl) -> Self {
+        let interrupted = {
+            let control = control.clone();
+            Box::pin(async move { control.wait_for_interrupt().await })
+        };
+        Self {
+            stream: Some(stream),
+            control,
+            interrupted,
+        }
+    }
+
+    /// Cancel the generation. The stream ends at the next token boundary.
+    pub fn cancel(&self) {
+        self.control.cancel();
+    }
+
+    /// Get the reason the generation stopped or `None` if it is still running.
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.control.stop_reason()
+    }
+
+    /// Get the control of the generation. The control can check why the generation stopped after the stream is moved somewhere else.
+    pub fn control(&self) -> &GenerationControl {
+        &self.control
+    }
+
+    fn finish(&mut self, reason: StopReason) {
+        self.stream = None;
+        self.control.finish(reason);
+    }
+}
+
+impl<S: Stream + Unpin> TextGeneration<S> {
+    /// Wait for the generation to stop and return the reason it stopped. Any text that has not been read from the stream yet is discarded.
+    pub async fn result(mut self) -> StopReason {
+        while self.next().await.is_some() {}
+        self.control
+            .stop_reason()
+            .expect("The generation records a reason when the stream ends")
+    }
+}
+
+impl<S: Stream + Unpin> Stream for TextGeneration<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let Some(stream) = &mut this.stream else {
+            return Poll::Ready(None);
+        };
+        if let Poll::Ready(reason) = this.interrupted.as_mut().poll(cx) {
+            // Dropping the stream closes the channel the model sends text to, which stops models that don't check the interrupt themselves
+            this.finish(reason);
+            return Poll::Ready(None);
+        }
+        match stream.poll_next_unpin(cx) {
+            Poll::Ready(None) => {
+                // The model may have noticed the interrupt before we did
+                let reason = this
+                    .control
+                    .stop_reason()
+                    .or(this.control.interrupted())
+                    .unwrap_or(StopReason::Finished);
+                this.finish(reason);
+                Poll::Ready(None)
+            }
+            poll => poll,
+        }
+    }
+}
+
+impl<S> Drop for TextGeneration<S> {
+    fn drop(&mut self) {
+        self.control.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generation(
+        interrupt: Interrupt,
+    ) -> (
+        tokio::sync::mpsc::UnboundedSender<String>,
+        TextGeneration<kalosm_streams::text_stream::ChannelTextStream>,
+    ) {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let control = GenerationControl::new(interrupt);
+        (sender, TextGeneration::new(receiver.into(), control))
+    }
+
+    #[tokio::test]
+    async fn reports_why_the_generation_stopped() {
+        let (sender, stream) = generation(Interrupt::new());
+        sender.send("Hello".to_string()).unwrap();
+        drop(sender);
+        assert_eq!(stream.result().await, StopReason::Finished);
+
+        // The model reports that it hit the token limit
+        let (sender, stream) = generation(Interrupt::new());
+        sender.send("Hello".to_string()).unwrap();
+        sender.send(" world".to_string()).unwrap();
+        stream.control.finish(StopReason::MaxLength);
+        drop(sender);
+        assert_eq!(stream.result().await, StopReason::MaxLength);
+    }
+
+    #[tokio::test]
+    async fn stops_when_cancelled() {
+        let cancellation = CancellationToken::new();
+        let (sender, mut stream) =
+            generation(Interrupt::new().with_cancellation(cancellation.clone()));
+        sender.send("Hello".to_string()).unwrap();
+        assert_eq!(stream.next().await.as_deref(), Some("Hello"));
+        cancellation.cancel();
+        assert_eq!(stream.next().await, None);
+        assert_eq!(stream.stop_reason(), Some(StopReason::Cancelled));
+        // The model sees the interrupt and the closed channel
+        assert!(sender.send("world".to_string()).is_err());
+    }
+
+    #[tokio::test]
+    async fn stops_at_the_deadline() {
+        let (_sender, stream) =
+            generation(Interrupt::new().with_timeout(Duration::from_millis(10)));
+        assert_eq!(stream.result().await, StopReason::TimedOut);
+    }
+
+    #[test]
+    fn dropping_the_stream_cancels_the_generation() {
+        let (_sender, stream) = generation(Interrupt::new());
+        let control = stream.control.clone();
+        drop(stream);
+        assert_eq!(control.interrupted(), Some(StopReason::Cancelled));
+    }
+}
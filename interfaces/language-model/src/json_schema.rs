@@ -0,0 +1,136 @@
+use kalosm_sample::{
+    CreateParserState, JsonObjectSchema, JsonPropertySchema, LiteralParser, ParseStatus, ParserExt,
+    SchemaType,
+};
+
+/// The property that holds the value when a schema that is not an object is wrapped in an object.
+const WRAPPED_VALUE_PROPERTY: &str = "value";
+
+/// Structured output APIs require the root of the schema to be an object. Wrap any other schema in an object with a single `value` property. Returns the schema and whether it was wrapped.
+pub(crate) fn object_schema(schema: SchemaType) -> (SchemaType, bool) {
+    match schema {
+        SchemaType::Object(_) => (schema, false),
+        schema => (
+            SchemaType::Object(JsonObjectSchema::new([JsonPropertySchema::new(
+                WRAPPED_VALUE_PROPERTY,
+                schema,
+            )
+            .with_required(true)])),
+            true,
+        ),
+    }
+}
+
+/// Rewrite JSON with the whitespace the kalosm-sample parsers expect: `{ "key": value, "other": value }` and `[value, value]`.
+fn normalize_json(json: &str) -> String {
+    let mut normalized = String::with_capacity(json.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    for char in json.chars() {
+        if in_string {
+            normalized.push(char);
+            if escaped {
+                escaped = false;
+            } else if char == '\\' {
+                escaped = true;
+            } else if char == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+        match char {
+            '"' => {
+                in_string = true;
+                normalized.push(char);
+            }
+            ':' => normalized.push_str(": "),
+            ',' => normalized.push_str(", "),
+            '{' => normalized.push_str("{ "),
+            '}' => {
+                if normalized.ends_with("{ ") {
+                    normalized.pop();
+                    normalized.push('}');
+                } else {
+                    normalized.push_str(" }");
+                }
+            }
+            char if char.is_whitespace() => {}
+            char => normalized.push(char),
+        }
+    }
+    normalized
+}
+
+/// Validate JSON a model generated from the schema of a type and parse it with the parser of that type. If the schema was wrapped with [`object_schema`], the value is unwrapped first.
+pub(crate) fn parse_json<P: CreateParserState>(
+    parser: P,
+    wrapped: bool,
+    json: &str,
+) -> anyhow::Result<P::Output> {
+    if wrapped {
+        let parser = LiteralParser::new(format!("{{ \"{WRAPPED_VALUE_PROPERTY}\": "))
+            .ignore_output_then(parser)
+            .then_literal(" }");
+        parse_normalized(parser, json)
+    } else {
+        parse_normalized(parser, json)
+    }
+}
+
+fn parse_normalized<P: CreateParserState>(parser: P, json: &str) -> anyhow::Result<P::Output> {
+    let mut normalized = normalize_json(json);
+    // Numbers only finish parsing once the parser sees the character after them
+    normalized.push(' ');
+    let state = parser.create_parser_state();
+    match parser.parse(&state, normalized.as_bytes()) {
+        Ok(ParseStatus::Finished { result, remaining })
+            if remaining.iter().all(u8::is_ascii_whitespace) =>
+        {
+            Ok(result)
+        }
+        Ok(ParseStatus::Finished { remaining, .. }) => Err(anyhow::anyhow!(
+            "Unexpected text after the value in the response: {}",
+            String::from_utf8_lossy(remaining)
+        )),
+        Ok(ParseStatus::Incomplete { .. }) => Err(anyhow::anyhow!(
+            "The response ended before it matched the schema: {json}"
+        )),
+        Err(err) => Err(anyhow::anyhow!(
+            "The response does not match the schema: {}. Response: {json}",
+            &*err
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kalosm_sample::{Parse, Schema};
+
+    #[test]
+    fn normalizes_whitespace() {
+        assert_eq!(
+            normalize_json("{\n  \"name\":\"A, b: { c }\",\n  \"tags\": [ 1,2 ],\"empty\": {}\n}"),
+            "{ \"name\": \"A, b: { c }\", \"tags\": [1, 2], \"empty\": {} }"
+        );
+        assert_eq!(
+            normalize_json(r#""escaped \" quote""#),
+            r#""escaped \" quote""#
+        );
+    }
+
+    #[test]
+    fn parses_wrapped_values() {
+        let (schema, wrapped) = object_schema(<Vec<String>>::schema());
+        assert!(wrapped);
+        assert!(matches!(schema, SchemaType::Object(_)));
+        let parsed = parse_json(
+            <Vec<String>>::new_parser(),
+            wrapped,
+            "{\"value\":[\"a\", \"b\"]}",
+        )
+        .unwrap();
+        assert_eq!(parsed, ["a", "b"]);
+        assert!(parse_json(<Vec<String>>::new_parser(), wrapped, "{\"value\": [1]}").is_err());
+    }
+}
@@ -30,9 +30,24 @@ mod remote;
 #[cfg(feature = "remote")]
 pub use remote::*;
 
+mod chat_template;
+pub use chat_template::*;
+mod interrupt;
+pub use interrupt::*;
+mod json_schema;
+mod log_probs;
+pub use log_probs::*;
+#[cfg(test)]
+mod mock;
+mod sampling;
+pub use sampling::*;
+mod speculative;
 mod structured;
+mod text_generation;
+pub use text_generation::*;
 mod token_stream;
 pub use token_stream::*;
+mod token_trie;
 
 mod embedding;
 pub use embedding::*;
@@ -0,0 +1,239 @@
+use tokenizers::tokenizer::Tokenizer;
+
+/// The log probability of a single token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenLogProb {
+    /// The id of the token. Remote models that do not expose their tokenizer will not have a token id.
+    pub token_id: Option<u32>,
+    /// The text of the token.
+    pub text: String,
+    /// The natural log of the probability of the token.
+    pub log_prob: f32,
+}
+
+impl TokenLogProb {
+    /// Get the probability of the token between 0 and 1.
+    pub fn probability(&self) -> f32 {
+        self.log_prob.exp()
+    }
+}
+
+/// A token generated by a model along with its log probability and the most likely alternatives the model could have picked instead.
+///
+/// The log probabilities are from the distribution of the model before any sampler adjustments like temperature or repetition penalties are applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedToken {
+    /// The text the token added to the output. This may be empty if the token only contains part of a multi-byte character.
+    pub text: String,
+    /// The id of the token. Remote models that do not expose their tokenizer will not have a token id.
+    pub token_id: Option<u32>,
+    /// The natural log of the probability of the token if the model reported it.
+    pub log_prob: Option<f32>,
+    /// The most likely tokens at this position sorted from most to least likely. This may include the chosen token.
+    pub top_alternatives: Vec<TokenLogProb>,
+}
+
+impl GeneratedToken {
+    /// Create a new generated token from the raw logits the token was sampled from.
+    pub fn from_logits(
+        token_id: u32,
+        text: String,
+        logits: &[f32],
+        top_n: usize,
+        tokenizer: &Tokenizer,
+    ) -> anyhow::Result<Self> {
+        let log_sum_exp = log_sum_exp(logits);
+        let log_prob = logits
+            .get(token_id as usize)
+            .map(|logit| logit - log_sum_exp);
+
+        let mut indexed: Vec<_> = logits.iter().copied().enumerate().collect();
+        let top_n = top_n.min(indexed.len());
+        if top_n > 0 && top_n < indexed.len() {
+            indexed.select_nth_unstable_by(top_n - 1, |a, b| b.1.total_cmp(&a.1));
+        }
+        indexed.truncate(top_n);
+        indexed.sort_unstable_by(|a, b| b.1.total_cmp(&a.1));
+        let top_alternatives = indexed
+            .into_iter()
+            .map(|(id, logit)| {
+                let text = tokenizer
+                    .decode(&[id as u32], false)
+                    .map_err(anyhow::Error::msg)?;
+                Ok(TokenLogProb {
+                    token_id: Some(id as u32),
+                    text,
+                    log_prob: logit - log_sum_exp,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            text,
+            token_id: Some(token_id),
+            log_prob,
+            top_alternatives,
+        })
+    }
+
+    /// Get the probability of the token between 0 and 1 if the model reported it.
+    pub fn probability(&self) -> Option<f32> {
+        self.log_prob.map(f32::exp)
+    }
+}
+
+impl AsRef<str> for GeneratedToken {
+    fn as_ref(&self) -> &str {
+        &self.text
+    }
+}
+
+fn log_sum_exp(logits: &[f32]) -> f32 {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    if max == f32::NEG_INFINITY {
+        return max;
+    }
+    let sum: f32 = logits.iter().map(|logit| (logit - max).exp()).sum();
+    max + sum.ln()
+}
+
+/// Holds back generated tokens that could be the start of the stop string so they are never sent if the stop string is generated.
+pub(crate) struct StopOnBuffer {
+    stop_on: Option<String>,
+    held: Vec<GeneratedToken>,
+    held_text: String,
+}
+
+impl StopOnBuffer {
+    pub(crate) fn new(stop_on: Option<&str>) -> Self {
+        Self {
+            stop_on: stop_on.map(|stop_on| lowercase_with_offsets(stop_on).0),
+            held: Vec::new(),
+            held_text: String::new(),
+        }
+    }
+
+    /// Add a token to the buffer and return the tokens that are safe to send. Returns true if the stop string was found.
+    pub(crate) fn push(&mut self, token: GeneratedToken, ready: &mut Vec<GeneratedToken>) -> bool {
+        self.held_text += &token.text;
+        self.held.push(token);
+        let Some(stop_on) = &self.stop_on else {
+            ready.append(&mut self.held);
+            self.held_text.clear();
+            return false;
+        };
+
+        let (lowercase, offsets) = lowercase_with_offsets(&self.held_text);
+        if let Some(index) = lowercase.find(stop_on.as_str()) {
+            // Send everything before the stop string
+            let mut remaining = offsets[index];
+            for mut token in self.held.drain(..) {
+                if remaining == 0 {
+                    break;
+                }
+                if token.text.len() > remaining {
+                    let mut end = remaining;
+                    while !token.text.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    token.text.truncate(end);
+                    remaining = 0;
+                } else {
+                    remaining -= token.text.len();
+                }
+                ready.push(token);
+            }
+            self.held_text.clear();
+            return true;
+        }
+
+        // Keep holding tokens if the end of the text could be the start of the stop string
+        let could_stop = lowercase
+            .char_indices()
+            .any(|(i, _)| stop_on.starts_with(&lowercase[i..]));
+        if !could_stop {
+            ready.append(&mut self.held);
+            self.held_text.clear();
+        }
+        false
+    }
+
+    /// Release any tokens that are still held.
+    pub(crate) fn flush(&mut self, ready: &mut Vec<GeneratedToken>) {
+        ready.append(&mut self.held);
+        self.held_text.clear();
+    }
+}
+
+/// Lowercase text along with the byte index in the original text of each byte of the lowercase text. Lowercasing can change the length of a character, like `İ` which lowercases to `i̇`.
+fn lowercase_with_offsets(text: &str) -> (String, Vec<usize>) {
+    let mut lowercase = String::with_capacity(text.len());
+    let mut offsets = Vec::with_capacity(text.len());
+    for (index, char) in text.char_indices() {
+        lowercase.extend(char.to_lowercase());
+        offsets.resize(lowercase.len(), index);
+    }
+    (lowercase, offsets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(text: &str) -> GeneratedToken {
+        GeneratedToken {
+            text: text.to_string(),
+            token_id: None,
+            log_prob: None,
+            top_alternatives: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn log_sum_exp_matches_softmax() {
+        let logits = [1.0, 2.0, 3.0];
+        let log_sum_exp = log_sum_exp(&logits);
+        let total: f32 = logits.iter().map(|l| (l - log_sum_exp).exp()).sum();
+        assert!((total - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn stop_on_buffer_holds_partial_matches() {
+        let mut buffer = StopOnBuffer::new(Some("</s>"));
+        let mut ready = Vec::new();
+        assert!(!buffer.push(token("Hello"), &mut ready));
+        assert_eq!(ready, vec![token("Hello")]);
+        ready.clear();
+
+        assert!(!buffer.push(token(" world<"), &mut ready));
+        assert!(ready.is_empty());
+
+        assert!(buffer.push(token("/s>"), &mut ready));
+        assert_eq!(ready, vec![token(" world")]);
+    }
+
+    #[test]
+    fn stop_on_buffer_matches_case_insensitively() {
+        let mut buffer = StopOnBuffer::new(Some("</S>"));
+        let mut ready = Vec::new();
+        // `İ` is two bytes, but lowercases to three
+        assert!(buffer.push(token("İx</s>"), &mut ready));
+        assert_eq!(ready, vec![token("İx")]);
+
+        let mut buffer = StopOnBuffer::new(Some("end"));
+        let mut ready = Vec::new();
+        assert!(!buffer.push(token("İİ E"), &mut ready));
+        assert!(buffer.push(token("ND"), &mut ready));
+        assert_eq!(ready, vec![token("İİ ")]);
+    }
+
+    #[test]
+    fn stop_on_buffer_releases_false_matches() {
+        let mut buffer = StopOnBuffer::new(Some("</s>"));
+        let mut ready = Vec::new();
+        assert!(!buffer.push(token("a <"), &mut ready));
+        assert!(ready.is_empty());
+        assert!(!buffer.push(token("b"), &mut ready));
+        assert_eq!(ready, vec![token("a <"), token("b")]);
+    }
+}
@@ -0,0 +1,170 @@
+//! A tiny scripted model for testing generation without loading model weights.
+
+use std::sync::{Arc, Mutex};
+
+use tokenizers::decoders::fuse::Fuse;
+use tokenizers::models::bpe::BPE;
+use tokenizers::Tokenizer;
+
+use crate::{Session, SyncModel};
+
+type LogitsFn = Box<dyn Fn(&[u32]) -> Vec<f32> + Send + Sync>;
+type DraftFn = Box<dyn Fn(&[u32]) -> Vec<u32> + Send + Sync>;
+
+/// A model that predicts the next token with a function of the tokens in the session.
+pub(crate) struct MockModel {
+    tokenizer: Arc<Tokenizer>,
+    logits: LogitsFn,
+    draft: Option<DraftFn>,
+    stop_token: u32,
+    /// The number of times the model was run over a batch of tokens.
+    forward_passes: Mutex<usize>,
+}
+
+impl MockModel {
+    /// Create a model with one token for each string in the vocabulary. The last token is the stop token.
+    ///
+    /// Text is encoded one character at a time, so every character in a prompt must be in the vocabulary.
+    pub(crate) fn new(
+        vocab: &[&str],
+        logits: impl Fn(&[u32]) -> Vec<f32> + Send + Sync + 'static,
+    ) -> Self {
+        let model = BPE::builder()
+            .vocab_and_merges(
+                vocab
+                    .iter()
+                    .enumerate()
+                    .map(|(id, text)| (text.to_string(), id as u32))
+                    .collect(),
+                Vec::new(),
+            )
+            .build()
+            .unwrap();
+        let mut tokenizer = Tokenizer::new(model);
+        tokenizer.with_decoder(Fuse::new());
+        Self {
+            tokenizer: Arc::new(tokenizer),
+            logits: Box::new(logits),
+            draft: None,
+            stop_token: vocab.len() as u32 - 1,
+            forward_passes: Mutex::new(0),
+        }
+    }
+
+    /// Guess the next tokens after the given tokens with a draft function.
+    pub(crate) fn with_draft(
+        mut self,
+        draft: impl Fn(&[u32]) -> Vec<u32> + Send + Sync + 'static,
+    ) -> Self {
+        self.draft = Some(Box::new(draft));
+        self
+    }
+
+    /// Get the number of forward passes so far.
+    pub(crate) fn forward_passes(&self) -> usize {
+        *self.forward_passes.lock().unwrap()
+    }
+}
+
+/// Logits that make one token much more likely than the rest.
+pub(crate) fn one_hot(vocab_size: usize, token: u32) -> Vec<f32> {
+    let mut logits = vec![0.0; vocab_size];
+    logits[token as usize] = 10.0;
+    logits
+}
+
+/// The session of a [`MockModel`].
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MockSession {
+    pub(crate) tokens: Vec<u32>,
+    /// The number of tokens removed by each call to [`Session::rewind`].
+    pub(crate) rewinds: Vec<usize>,
+}
+
+impl Session for MockSession {
+    fn tokens(&self) -> &[u32] {
+        &self.tokens
+    }
+
+    fn try_clone(&self) -> anyhow::Result<Self> {
+        Ok(self.clone())
+    }
+
+    fn rewind(&mut self, tokens: usize) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            tokens <= self.tokens.len(),
+            "Cannot rewind {tokens} tokens from a session with {} tokens",
+            self.tokens.len()
+        );
+        self.tokens.truncate(self.tokens.len() - tokens);
+        self.rewinds.push(tokens);
+        Ok(())
+    }
+
+    fn supports_rewind(&self) -> bool {
+        true
+    }
+}
+
+impl SyncModel for MockModel {
+    type Session = MockSession;
+
+    fn new_session(&self) -> anyhow::Result<Self::Session> {
+        Ok(MockSession::default())
+    }
+
+    fn feed_text(
+        &self,
+        session: &mut Self::Session,
+        prompt: &str,
+        into: &mut Vec<f32>,
+    ) -> anyhow::Result<()> {
+        let tokens = self
+            .tokenizer
+            .encode(prompt, false)
+            .map_err(anyhow::Error::msg)?;
+        self.feed_tokens(session, tokens.get_ids(), into)
+    }
+
+    fn feed_tokens(
+        &self,
+        session: &mut Self::Session,
+        tokens: &[u32],
+        into: &mut Vec<f32>,
+    ) -> anyhow::Result<()> {
+        *self.forward_passes.lock().unwrap() += 1;
+        session.tokens.extend_from_slice(tokens);
+        *into = (self.logits)(&session.tokens);
+        Ok(())
+    }
+
+    fn feed_tokens_all_logits(
+        &self,
+        session: &mut Self::Session,
+        tokens: &[u32],
+        into: &mut Vec<Vec<f32>>,
+    ) -> anyhow::Result<()> {
+        *self.forward_passes.lock().unwrap() += 1;
+        into.clear();
+        for &token in tokens {
+            session.tokens.push(token);
+            into.push((self.logits)(&session.tokens));
+        }
+        Ok(())
+    }
+
+    fn draft_tokens(&self, session: &mut Self::Session, next: &[u32]) -> anyhow::Result<Vec<u32>> {
+        let Some(draft) = &self.draft else {
+            return Ok(Vec::new());
+        };
+        Ok(draft(&[session.tokens.as_slice(), next].concat()))
+    }
+
+    fn stop_token(&self) -> anyhow::Result<u32> {
+        Ok(self.stop_token)
+    }
+
+    fn tokenizer(&self) -> Arc<Tokenizer> {
+        self.tokenizer.clone()
+    }
+}
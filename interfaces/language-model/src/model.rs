@@ -1,11 +1,19 @@
+use crate::json_schema::{object_schema, parse_json};
+use crate::sampling::LazyMirostat1;
+use crate::speculative::SpeculativeFeeder;
 use crate::structured::generate_structured;
-use crate::TokenOutputStream;
+use crate::ChatTemplate;
+use crate::GeneratedToken;
+use crate::SamplingStrategy;
+use crate::SeededSampler;
+use crate::TextGenerationState;
+use crate::{CancellationToken, GenerationControl, Interrupt, StopReason, TextGeneration};
 use futures_util::{Future, FutureExt};
 use futures_util::{Stream, StreamExt};
 use kalosm_common::*;
 use kalosm_sample::StopOn;
-use kalosm_sample::{CreateParserState, Parse};
-use kalosm_sample::{LiteralParser, Parser};
+use kalosm_sample::{CreateParserState, ObjectFormat, Parse, Schema, SchemaType};
+use kalosm_sample::{LiteralParser, ParseStatus, Parser};
 use kalosm_streams::text_stream::ChannelTextStream;
 use llm_samplers::configure::SamplerChainBuilder;
 use llm_samplers::prelude::*;
@@ -16,6 +24,7 @@ use std::path::Path;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use tokenizers::tokenizer::Tokenizer;
 
 /// A builder that can create a model asynchronously.
@@ -147,6 +156,67 @@ impl<'a, M: Model> StreamTextBuilder<'a, M> {
         self.parameters.stop_on = stop_on.into();
         self
     }
+
+    /// Set the strategy used to pick each token when generating text.
+    pub fn with_sampling_strategy(mut self, strategy: SamplingStrategy) -> Self {
+        self.parameters.strategy = strategy;
+        self
+    }
+
+    /// Set the seed to use when generating text. With a seed, the same prompt and model will always generate the same text.
+    pub fn with_seed(mut self, seed: impl Into<Option<u64>>) -> Self {
+        self.parameters.seed = seed.into();
+        self
+    }
+}
+
+impl<'a, M: Model> StreamTextBuilder<'a, M> {
+    /// Stream every generated token with its log probability and the `top_n` most likely alternatives instead of only the text.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut model = Llama::new().await.unwrap();
+    ///     let prompt = "The capital of France is";
+    ///     let mut result = model.stream_text(prompt).with_max_length(300).with_log_probs(5).await.unwrap();
+    ///
+    ///     while let Some(token) = result.next().await {
+    ///         println!("{:?} ({:?})", token.text, token.probability());
+    ///     }
+    /// }
+    /// ```
+    pub fn with_log_probs(self, top_n: usize) -> StreamTextWithLogProbsBuilder<'a, M> {
+        StreamTextWithLogProbsBuilder {
+            self_: self.self_,
+            prompt: self.prompt,
+            parameters: self.parameters,
+            top_n,
+        }
+    }
+
+    /// Stop generating text at the next token once the interrupt fires. The stream reports why the generation stopped with [`TextGeneration::result`].
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut model = Llama::new().await.unwrap();
+    ///     let interrupt = Interrupt::new().with_timeout(Duration::from_secs(10));
+    ///     let mut stream = model.stream_text("Write a long story").with_interrupt(interrupt).await.unwrap();
+    ///     stream.to_std_out().await.unwrap();
+    ///     println!("\nStopped because: {:?}", stream.result().await);
+    /// }
+    /// ```
+    pub fn with_interrupt(self, interrupt: Interrupt) -> StreamTextWithInterruptBuilder<'a, M> {
+        StreamTextWithInterruptBuilder {
+            builder: self,
+            interrupt,
+        }
+    }
 }
 
 impl<'a, M: Model> IntoFuture for StreamTextBuilder<'a, M> {
@@ -164,12 +234,60 @@ impl<'a, M: Model> IntoFuture for StreamTextBuilder<'a, M> {
     }
 }
 
+/// A builder for the [`StreamTextBuilder::with_interrupt`] method.
+pub struct StreamTextWithInterruptBuilder<'a, M: Model> {
+    builder: StreamTextBuilder<'a, M>,
+    interrupt: Interrupt,
+}
+
+impl<'a, M: Model> IntoFuture for StreamTextWithInterruptBuilder<'a, M> {
+    type Output = anyhow::Result<TextGeneration<M::TextStream>>;
+    type IntoFuture = Pin<Box<dyn std::future::Future<Output = Self::Output> + Send + 'a>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        let Self {
+            mut builder,
+            interrupt,
+        } = self;
+        let control = GenerationControl::new(interrupt);
+        builder.parameters.control = Some(control.clone());
+        let stream = builder.into_future();
+        Box::pin(async move { Ok(TextGeneration::new(stream.await?, control)) })
+    }
+}
+
+/// A builder for the [`StreamTextBuilder::with_log_probs`] method.
+pub struct StreamTextWithLogProbsBuilder<'a, M: Model> {
+    self_: &'a M,
+    prompt: &'a str,
+    parameters: GenerationParameters,
+    top_n: usize,
+}
+
+impl<'a, M: Model> IntoFuture for StreamTextWithLogProbsBuilder<'a, M> {
+    type Output = anyhow::Result<ChannelTextStream<GeneratedToken>>;
+    type IntoFuture = Pin<Box<dyn std::future::Future<Output = Self::Output> + Send + 'a>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        let Self {
+            self_,
+            prompt,
+            parameters,
+            top_n,
+        } = self;
+        self_.stream_text_with_log_probs_inner(prompt, parameters, top_n)
+    }
+}
+
 /// A builder for the [`ModelExt::generate_text`] method.
+///
+/// Awaiting the builder only returns the text, so text that was cut off by the maximum length or an interrupt looks the same as text the model finished on its own. Use [`GenerateTextBuilder::text_with_stop_reason`] to get the reason the generation stopped along with the text, or [`ModelExt::stream_text`] to check the [`TextGeneration`] while the text streams in.
 #[allow(clippy::type_complexity)]
 pub struct GenerateTextBuilder<'a, M: Model> {
     self_: &'a M,
     prompt: &'a str,
     parameters: GenerationParameters,
+    interrupt: Interrupt,
     future: fn(
         &'a M,
         &'a str,
@@ -196,6 +314,7 @@ impl<'a, M: Model> GenerateTextBuilder<'a, M> {
             self_,
             prompt,
             parameters: GenerationParameters::default(),
+            interrupt: Interrupt::default(),
             future,
         }
     }
@@ -253,6 +372,71 @@ impl<'a, M: Model> GenerateTextBuilder<'a, M> {
         self.parameters.stop_on = stop_on.into();
         self
     }
+
+    /// Set the strategy used to pick each token when generating text.
+    pub fn with_sampling_strategy(mut self, strategy: SamplingStrategy) -> Self {
+        self.parameters.strategy = strategy;
+        self
+    }
+
+    /// Set the seed to use when generating text. With a seed, the same prompt and model will always generate the same text.
+    pub fn with_seed(mut self, seed: impl Into<Option<u64>>) -> Self {
+        self.parameters.seed = seed.into();
+        self
+    }
+
+    /// Stop generating text at the next token once the token is cancelled.
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.interrupt = self.interrupt.with_cancellation(cancellation);
+        self
+    }
+
+    /// Stop generating text at the next token once the deadline passes.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.interrupt = self.interrupt.with_deadline(deadline);
+        self
+    }
+
+    /// Stop generating text at the next token once the timeout passes. The timeout starts when this method is called.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.interrupt = self.interrupt.with_timeout(timeout);
+        self
+    }
+
+    /// Generate the text and return it along with the reason the generation stopped.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let model = Llama::new().await.unwrap();
+    ///     let (text, stop_reason) = model
+    ///         .generate_text("The capital of France is")
+    ///         .with_max_length(10)
+    ///         .text_with_stop_reason()
+    ///         .await
+    ///         .unwrap();
+    ///     if stop_reason == StopReason::MaxLength {
+    ///         println!("The text was cut off: {text}");
+    ///     }
+    /// }
+    /// ```
+    pub async fn text_with_stop_reason(self) -> anyhow::Result<(String, StopReason)> {
+        let Self {
+            self_,
+            prompt,
+            mut parameters,
+            interrupt,
+            future,
+        } = self;
+        let control = GenerationControl::new(interrupt);
+        parameters.control = Some(control.clone());
+        let text = future(self_, prompt, parameters).await?;
+        // Models that don't record a reason finished on their own
+        let stop_reason = control.stop_reason().unwrap_or(StopReason::Finished);
+        Ok((text, stop_reason))
+    }
 }
 
 impl<'a, M: Model> IntoFuture for GenerateTextBuilder<'a, M> {
@@ -263,9 +447,11 @@ impl<'a, M: Model> IntoFuture for GenerateTextBuilder<'a, M> {
         let Self {
             self_,
             prompt,
-            parameters,
+            mut parameters,
+            interrupt,
             future,
         } = self;
+        parameters.control = Some(GenerationControl::new(interrupt));
         future(self_, prompt, parameters)
     }
 }
@@ -403,6 +589,90 @@ pub trait ModelExt: Model + Send + Sync + 'static {
         self.stream_structured_text(prompt, P::new_parser())
     }
 
+    /// Generate a type that implements [`Parse`] and [`Schema`] with the given prompt.
+    ///
+    /// Local models constrain every token to the parser of the type like [`ModelExt::generate_parsed`]. Models that can't constrain tokens, like remote OpenAI compatible models, send [`Schema::schema`] to their structured output API instead. The JSON they respond with is validated and parsed with the parser of the type, so the same code works with both kinds of models. Types that are written in a format other than JSON are always generated with their parser.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    ///
+    /// #[derive(Parse, Schema, Clone, Debug)]
+    /// struct Account {
+    ///     username: String,
+    ///     age: u8,
+    /// }
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// // This could also be a local model like `Llama::new_chat().await?`
+    /// let llm = RemoteOpenAICompatibleModel::builder()
+    ///     .with_model("gpt-4o-mini")
+    ///     .build();
+    /// let account: Account = llm
+    ///     .generate_with_schema("An account with a realistic username and age: ")
+    ///     .await?;
+    /// println!("{:#?}", account);
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn generate_with_schema<P: Parse + Schema + 'static>(
+        &self,
+        prompt: &str,
+    ) -> StructureParserResult<Self::TextStream, P>
+    where
+        Self::TextStream: From<tokio::sync::mpsc::UnboundedReceiver<String>>,
+    {
+        self.generate_with_schema_and_parameters(prompt, GenerationParameters::default())
+    }
+
+    /// Generate a type that implements [`Parse`] and [`Schema`] with the given prompt and generation parameters. See [`ModelExt::generate_with_schema`] for more information.
+    ///
+    /// Structured output APIs stop after [`GenerationParameters::with_max_length`] tokens. If the response is cut off, it won't match the schema and the result is an error.
+    fn generate_with_schema_and_parameters<P: Parse + Schema + 'static>(
+        &self,
+        prompt: &str,
+        parameters: GenerationParameters,
+    ) -> StructureParserResult<Self::TextStream, P>
+    where
+        Self::TextStream: From<tokio::sync::mpsc::UnboundedReceiver<String>>,
+    {
+        let schema = P::schema();
+        // Structured output APIs only generate JSON
+        let json = if schema.format() == ObjectFormat::Json {
+            let (schema, wrapped) = object_schema(schema);
+            self.stream_json_with_schema_inner(prompt, schema, parameters.clone())
+                .map(|json| (json, wrapped))
+        } else {
+            None
+        };
+        let Some((mut json, wrapped)) = json else {
+            let sampler = Arc::new(Mutex::new(parameters.seeded_sampler()));
+            let parser = P::new_parser();
+            let parser_state = parser.create_parser_state();
+            return self.stream_structured_text_with_sampler(prompt, parser, parser_state, sampler);
+        };
+
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (result_sender, result_receiver) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let mut text = String::new();
+            let result = loop {
+                match json.recv().await {
+                    Some(Ok(chunk)) => {
+                        text += &chunk;
+                        _ = sender.send(chunk);
+                    }
+                    Some(Err(err)) => break Err(err),
+                    None => break parse_json(P::new_parser(), wrapped, &text),
+                }
+            };
+            _ = result_sender.send(result);
+        });
+
+        StructureParserResult::new(Self::TextStream::from(receiver), result_receiver)
+    }
+
     /// Generate structured text with the given prompt and constraints.
     ///
     /// # Example
@@ -445,6 +715,49 @@ pub trait ModelExt: Model + Send + Sync + 'static {
         parser_state: P::PartialState,
         sampler: Arc<Mutex<dyn Sampler>>,
     ) -> StructureParserResult<Self::TextStream, P::Output>
+    where
+        Self::TextStream: From<tokio::sync::mpsc::UnboundedReceiver<String>>,
+        P: CreateParserState<PartialState: Send, Output: Send> + Send + 'static,
+    {
+        self.stream_structured_text_with_backtracking(prompt, parser, parser_state, sampler, 0)
+    }
+
+    /// Generate structured text with the given prompt and sampler, backtracking up to `max_backtrack` tokens when the parser rejects every possible next token. See [`SyncModelExt::generate_structured_with_backtracking`] for more information.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # use std::sync::{Arc, Mutex};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let llm = Llama::new().await.unwrap();
+    ///
+    /// // Only accept IDs that exist
+    /// let known_ids = [12, 24, 48];
+    /// let parser = U32Parser::new().validate(move |id| {
+    ///     if known_ids.contains(id) {
+    ///         Ok(())
+    ///     } else {
+    ///         Err(ParserError::msg(format!("Unknown ID {id}")))
+    ///     }
+    /// }).then_literal("\n");
+    /// let state = parser.create_parser_state();
+    /// let sampler = Arc::new(Mutex::new(GenerationParameters::default().sampler()));
+    /// let id = llm
+    ///     .stream_structured_text_with_backtracking("The ID of the order is ", parser, state, sampler, 4)
+    ///     .await
+    ///     .unwrap();
+    /// println!("{id}");
+    /// # }
+    /// ```
+    fn stream_structured_text_with_backtracking<P>(
+        &self,
+        prompt: &str,
+        parser: P,
+        parser_state: P::PartialState,
+        sampler: Arc<Mutex<dyn Sampler>>,
+        max_backtrack: usize,
+    ) -> StructureParserResult<Self::TextStream, P::Output>
     where
         Self::TextStream: From<tokio::sync::mpsc::UnboundedReceiver<String>>,
         P: CreateParserState<PartialState: Send, Output: Send> + Send + 'static,
@@ -458,7 +771,7 @@ pub trait ModelExt: Model + Send + Sync + 'static {
         if let Err(err) = self.run_sync(move |llm: &mut Self::SyncModel| {
             let mut session = llm.new_session().unwrap();
             Box::pin(async move {
-                let result = llm.generate_structured(
+                let result = llm.generate_structured_with_backtracking(
                     &mut session,
                     prompt,
                     parser,
@@ -466,6 +779,7 @@ pub trait ModelExt: Model + Send + Sync + 'static {
                     sampler,
                     |token| Ok(sender.send(token)?),
                     Some(64),
+                    max_backtrack,
                 );
                 if let Some(sender) = result_sender.lock().unwrap().take() {
                     _ = sender.send(result);
@@ -528,6 +842,110 @@ impl<S: Stream<Item = String> + Send + Unpin + 'static, O> StructureParserResult
     }
 }
 
+impl<S: Stream<Item = String> + Send + Unpin + 'static, O: Parse> StructureParserResult<S, O> {
+    /// Stream the value generated so far as JSON every time it changes. The value is read from the state of the parser for the type, so fields that are still being generated have incomplete values. See [`Parser::partial_value`] for what the values contain.
+    ///
+    /// The stream borrows the text stream, so you can still await the final result once the partial values are finished.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    ///
+    /// #[derive(Parse, Clone, Debug)]
+    /// struct Story {
+    ///     title: String,
+    ///     body: String,
+    /// }
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let llm = Llama::new().await?;
+    /// let mut story = llm.generate_parsed::<Story>("A short story in JSON format: ");
+    /// let mut partial = story.partial_values();
+    /// while let Some(value) = partial.next().await {
+    ///     println!("{value}");
+    /// }
+    /// let story = story.await?;
+    /// println!("{:#?}", story);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn partial_values(
+        &mut self,
+    ) -> PartialValueStream<&mut S, impl CreateParserState<Output = O>> {
+        PartialValueStream::new(&mut self.stream, O::new_parser())
+    }
+}
+
+/// A stream of the value a parser has read from a stream of text so far. Created with [`StructureParserResult::partial_values`].
+///
+/// Each chunk of text is parsed once from the state the last chunk left the parser in. The stream yields the value every time it changes and ends once the parser finishes or the text doesn't match the parser.
+pub struct PartialValueStream<S, P: Parser> {
+    stream: S,
+    parser: P,
+    // The state is taken once the parser finishes or fails
+    state: Option<P::PartialState>,
+    last: Option<kalosm_sample::serde_json::Value>,
+}
+
+impl<S, P: CreateParserState> PartialValueStream<S, P> {
+    /// Create a stream of partial values from a stream of text and the parser that generated it.
+    pub fn new(stream: S, parser: P) -> Self {
+        let state = parser.create_parser_state();
+        Self {
+            stream,
+            parser,
+            state: Some(state),
+            last: None,
+        }
+    }
+}
+
+impl<S: Stream<Item = String> + Unpin, P: Parser + Unpin> Stream for PartialValueStream<S, P>
+where
+    P::PartialState: Unpin,
+{
+    type Item = kalosm_sample::serde_json::Value;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let Some(state) = &this.state else {
+                return std::task::Poll::Ready(None);
+            };
+            match this.stream.poll_next_unpin(cx) {
+                std::task::Poll::Ready(Some(text)) => {
+                    let value = match this.parser.parse(state, text.as_bytes()) {
+                        Ok(ParseStatus::Incomplete { new_state, .. }) => {
+                            let value = this.parser.partial_value(&new_state);
+                            this.state = Some(new_state);
+                            value
+                        }
+                        Ok(ParseStatus::Finished { result, .. }) => {
+                            this.state = None;
+                            this.parser.output_value(&result)
+                        }
+                        Err(_) => {
+                            this.state = None;
+                            None
+                        }
+                    };
+                    // Only yield values that changed. Some tokens only add whitespace or punctuation
+                    if value.is_some() && value != this.last {
+                        this.last.clone_from(&value);
+                        return std::task::Poll::Ready(value);
+                    }
+                }
+                std::task::Poll::Ready(None) => return std::task::Poll::Ready(None),
+                std::task::Poll::Pending => return std::task::Poll::Pending,
+            }
+        }
+    }
+}
+
 impl<S: Stream<Item = String> + Send + Unpin + 'static, O> Future for StructureParserResult<S, O> {
     type Output = anyhow::Result<O>;
 
@@ -607,11 +1025,70 @@ pub trait SyncModel {
         into: &mut Vec<f32>,
     ) -> anyhow::Result<()>;
 
+    /// Run the model synchronously with a pre-tokenized input and return the logits for every input token instead of only the last one.
+    ///
+    /// This is used to verify many draft tokens in a single forward pass during speculative decoding. The default implementation feeds the tokens one at a time.
+    fn feed_tokens_all_logits(
+        &self,
+        session: &mut Self::Session,
+        tokens: &[u32],
+        into: &mut Vec<Vec<f32>>,
+    ) -> anyhow::Result<()> {
+        into.clear();
+        for &token in tokens {
+            let mut logits = Vec::new();
+            self.feed_tokens(session, &[token], &mut logits)?;
+            into.push(logits);
+        }
+        Ok(())
+    }
+
+    /// Feed one token into each session and write the logits for the next token of every session into `into`.
+    ///
+    /// Models that support batching run every session in a single forward pass which is much faster than running each session on its own. The default implementation feeds each session one at a time.
+    fn feed_batch(
+        &self,
+        sessions: &mut [&mut Self::Session],
+        tokens: &[u32],
+        into: &mut Vec<Vec<f32>>,
+    ) -> anyhow::Result<()> {
+        if sessions.len() != tokens.len() {
+            anyhow::bail!(
+                "Expected one token per session, found {} sessions and {} tokens",
+                sessions.len(),
+                tokens.len()
+            );
+        }
+        into.clear();
+        for (session, &token) in sessions.iter_mut().zip(tokens) {
+            let mut logits = Vec::new();
+            self.feed_tokens(session, &[token], &mut logits)?;
+            into.push(logits);
+        }
+        Ok(())
+    }
+
+    /// Guess the tokens that will follow the session after the `next` tokens are fed into it.
+    ///
+    /// Models that support speculative decoding return a cheap guess from a smaller draft model. The guess is verified with [`SyncModel::feed_tokens_all_logits`] so the output of the model does not change. The default implementation returns no tokens which disables speculative decoding.
+    fn draft_tokens(
+        &self,
+        _session: &mut Self::Session,
+        _next: &[u32],
+    ) -> anyhow::Result<Vec<u32>> {
+        Ok(Vec::new())
+    }
+
     /// Get the token ID that represents the end of a sequence.
     fn stop_token(&self) -> anyhow::Result<u32>;
 
     /// Return the tokenizer associated with this model.
     fn tokenizer(&self) -> Arc<Tokenizer>;
+
+    /// Get the maximum number of tokens a session can hold, if the model has a fixed context window.
+    fn context_length(&self) -> Option<usize> {
+        None
+    }
 }
 
 /// A session for a model.
@@ -641,6 +1118,16 @@ pub trait Session {
     {
         Err(anyhow::Error::msg("Not implemented"))
     }
+
+    /// Remove the last `tokens` tokens from the session. This is used to throw away rejected draft tokens during speculative decoding.
+    fn rewind(&mut self, _tokens: usize) -> anyhow::Result<()> {
+        Err(anyhow::Error::msg("Not implemented"))
+    }
+
+    /// Check if the session implements [`Session::rewind`].
+    fn supports_rewind(&self) -> bool {
+        false
+    }
 }
 
 impl Session for () {
@@ -676,6 +1163,37 @@ pub trait SyncModelExt: SyncModel {
             sampler,
             on_token,
             top_k,
+            0,
+        )
+    }
+
+    /// Generate new text with the given prompt that conforms to the given parser like [`SyncModelExt::generate_structured`], but backtrack when the parser rejects every possible next token.
+    ///
+    /// Validation functions added with [`kalosm_sample::ParserExt::validate`] can lead generation into a state where no token is valid. Instead of returning an error, generation returns to the state before one of the last `max_backtrack` tokens and samples the next best token from there. The text of those tokens is only passed to `on_token` once generation can no longer backtrack over them.
+    ///
+    /// Backtracking requires a session that supports [`Session::rewind`]. If `max_backtrack` is not zero and the session can't rewind, generation returns an error before it feeds the prompt.
+    #[allow(clippy::too_many_arguments)]
+    fn generate_structured_with_backtracking<P: Parser>(
+        &self,
+        session: &mut Self::Session,
+        prompt: impl Display,
+        parser: P,
+        parser_state: P::PartialState,
+        sampler: Arc<Mutex<dyn Sampler>>,
+        on_token: impl FnMut(String) -> anyhow::Result<()>,
+        top_k: Option<usize>,
+        max_backtrack: usize,
+    ) -> anyhow::Result<P::Output> {
+        generate_structured(
+            prompt,
+            self,
+            session,
+            parser,
+            parser_state,
+            sampler,
+            on_token,
+            top_k,
+            max_backtrack,
         )
     }
 
@@ -687,7 +1205,24 @@ pub trait SyncModelExt: SyncModel {
         prompt: &str,
         max_tokens: Option<u32>,
         stop_on: Option<&str>,
-        mut sampler: Arc<Mutex<dyn Sampler>>,
+        sampler: Arc<Mutex<dyn Sampler>>,
+        on_token: impl FnMut(String) -> anyhow::Result<ModelFeedback>,
+    ) -> anyhow::Result<()> {
+        self.stream_text_with_control(
+            session, prompt, max_tokens, stop_on, sampler, None, on_token,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    /// Stream text like [`SyncModel::stream_text_with_sampler`], but stop at the next token once the control is interrupted. The control records why the generation stopped.
+    fn stream_text_with_control(
+        &self,
+        session: &mut Self::Session,
+        prompt: &str,
+        max_tokens: Option<u32>,
+        stop_on: Option<&str>,
+        sampler: Arc<Mutex<dyn Sampler>>,
+        control: Option<GenerationControl>,
         mut on_token: impl FnMut(String) -> anyhow::Result<ModelFeedback>,
     ) -> anyhow::Result<()> {
         let tokens = self
@@ -695,99 +1230,66 @@ pub trait SyncModelExt: SyncModel {
             .encode(prompt, false)
             .map_err(|e| anyhow::anyhow!(e))?;
         let tokens = tokens.get_ids();
-        let mut text_stream = TokenOutputStream::new(self.tokenizer());
-        for &token in tokens {
-            text_stream.next_token(token)?;
-        }
-
-        let mut logit_probs = Vec::new();
-        self.feed_tokens(session, tokens, &mut logit_probs)?;
-        let mut logits = Logits::try_from_iter_top_k(logit_probs, 512)?;
-        let mut tokens_generated = 0;
-        // This stores a buffer of text that has been generated to check against the stop_on string. It should never be longer than the stop_on string.
-        let mut queued_text_matching_stop_on = String::new();
-        let stop_on_lowercase = stop_on.map(|s| s.to_lowercase());
-        let stop_on_lowercase = stop_on_lowercase.as_deref();
-        let stop_token = self.stop_token()?;
-        let mut logit_probs = Vec::new();
-
-        'generate: loop {
-            let new_token = text_stream.sample_token(&mut sampler, logits, stop_on)?;
-            if new_token == stop_token {
-                tracing::trace!("Stopping on stop token");
-                break;
-            }
-            if let Some(mut new_text) = text_stream.next_token(new_token)? {
-                if let Some(stop_on) = stop_on_lowercase {
-                    let lowercase = new_text.to_lowercase();
-
-                    // Check if the string ends with the start of the stop_on string
-                    let mut before_stop_on = None;
-                    let remaining_stop_on = stop_on
-                        .strip_prefix(&queued_text_matching_stop_on)
-                        .unwrap_or(stop_on);
-
-                    // If the remaining stop_on string is empty, we have found a match
-                    if remaining_stop_on.is_empty() {
-                        break;
-                    }
-
-                    for (i, _) in lowercase.char_indices() {
-                        let end_of_new_text = &lowercase[i..];
-                        if end_of_new_text.is_empty() {
-                            break;
-                        }
-
-                        // Check if we have matched all of the stop_on string
-                        if end_of_new_text.starts_with(remaining_stop_on) {
-                            queued_text_matching_stop_on += end_of_new_text;
-                            break 'generate;
-                        }
-
-                        // Check if the string ends with the start of the stop_on string
-                        if remaining_stop_on.starts_with(end_of_new_text) {
-                            before_stop_on = Some(lowercase[..i].to_string());
-                            queued_text_matching_stop_on += end_of_new_text;
-                            break;
-                        }
-                    }
-
-                    match before_stop_on {
-                        Some(before_stop_on) => {
-                            if let ModelFeedback::Stop = on_token(before_stop_on)? {
-                                break;
-                            }
-                        }
-                        None => {
-                            new_text =
-                                std::mem::take(&mut queued_text_matching_stop_on) + &new_text;
-                            if let ModelFeedback::Stop = on_token(new_text)? {
-                                break;
-                            }
-                        }
-                    }
-                } else if let ModelFeedback::Stop = on_token(new_text)? {
-                    break;
-                }
-            }
-            tokens_generated += 1;
-            if let Some(max_tokens) = max_tokens {
-                if tokens_generated >= max_tokens {
-                    break;
-                }
+        let mut state = TextGenerationState::new(
+            self.tokenizer(),
+            tokens,
+            max_tokens,
+            stop_on,
+            sampler,
+            self.stop_token()?,
+        )?
+        .with_control(control);
+
+        let result = SpeculativeFeeder::run(session, |feeder, session| {
+            let mut logit_probs = Vec::new();
+            feeder.feed(self, session, tokens, &mut logit_probs)?;
+            while let Some(new_token) = state.step(&logit_probs, &mut on_token)? {
+                feeder.feed(self, session, &[new_token], &mut logit_probs)?;
             }
-            self.feed_tokens(session, &[new_token], &mut logit_probs)?;
-            logits = Logits::try_from_iter_top_k(logit_probs.iter().copied(), 512)?;
+            Ok(())
+        });
+        if result.is_err() {
+            state.fail();
         }
+        result
+    }
 
-        // Flush the queued text
-        if let Some(stop_string) = stop_on_lowercase {
-            if !queued_text_matching_stop_on.starts_with(stop_string) {
-                on_token(queued_text_matching_stop_on)?;
+    #[allow(clippy::too_many_arguments)]
+    /// Stream text, calling the on_token callback with the log probability and the `top_n` most likely alternatives every time a new token is generated.
+    fn stream_text_with_log_probs(
+        &self,
+        session: &mut Self::Session,
+        prompt: &str,
+        max_tokens: Option<u32>,
+        stop_on: Option<&str>,
+        sampler: Arc<Mutex<dyn Sampler>>,
+        top_n: usize,
+        mut on_token: impl FnMut(GeneratedToken) -> anyhow::Result<ModelFeedback>,
+    ) -> anyhow::Result<()> {
+        let tokens = self
+            .tokenizer()
+            .encode(prompt, false)
+            .map_err(|e| anyhow::anyhow!(e))?;
+        let tokens = tokens.get_ids();
+        let mut state = TextGenerationState::new(
+            self.tokenizer(),
+            tokens,
+            max_tokens,
+            stop_on,
+            sampler,
+            self.stop_token()?,
+        )?;
+
+        SpeculativeFeeder::run(session, |feeder, session| {
+            let mut logit_probs = Vec::new();
+            feeder.feed(self, session, tokens, &mut logit_probs)?;
+            while let Some(new_token) =
+                state.step_with_log_probs(&logit_probs, top_n, &mut on_token)?
+            {
+                feeder.feed(self, session, &[new_token], &mut logit_probs)?;
             }
-        }
-
-        Ok(())
+            Ok(())
+        })
     }
 }
 
@@ -891,7 +1393,10 @@ pub trait Model: Send + Sync + 'static {
     ) -> anyhow::Result<String> {
         let mut text = String::new();
 
-        let mut stream = self.stream_text_inner(prompt, parameters).await?;
+        let control = parameters.control.clone().unwrap_or_default();
+        let stream = self.stream_text_inner(prompt, parameters).await?;
+        // If the generation is interrupted, return the text generated so far
+        let mut stream = TextGeneration::new(stream, control);
         while let Some(new) = stream.next().await {
             text.push_str(&new);
         }
@@ -918,10 +1423,68 @@ pub trait Model: Send + Sync + 'static {
         parameters: GenerationParameters,
     ) -> anyhow::Result<Self::TextStream>;
 
+    /// Generate text with the given prompt and stream every token with its log probability and the `top_n` most likely alternatives.
+    ///
+    /// See [`StreamTextBuilder::with_log_probs`] for nicer API with an example.
+    async fn stream_text_with_log_probs_inner(
+        &self,
+        prompt: &str,
+        parameters: GenerationParameters,
+        top_n: usize,
+    ) -> anyhow::Result<ChannelTextStream<GeneratedToken>> {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let prompt = prompt.to_string();
+        self.run_sync_raw(Box::new(move |llm: &mut Self::SyncModel| {
+            Box::pin(async move {
+                let result = llm.new_session().and_then(|mut session| {
+                    let max_length = parameters.max_length();
+                    let stop_on = parameters.stop_on().map(|s| s.to_string());
+                    llm.stream_text_with_log_probs(
+                        &mut session,
+                        &prompt,
+                        Some(max_length),
+                        stop_on.as_deref(),
+                        Arc::new(Mutex::new(parameters.seeded_sampler())),
+                        top_n,
+                        |token| {
+                            sender
+                                .send(token)
+                                .map_err(|_| {
+                                    anyhow::anyhow!("Failed to send token to output channel")
+                                })
+                                .map(|_| ModelFeedback::Continue)
+                        },
+                    )
+                });
+                if let Err(err) = result {
+                    tracing::error!("Error generating text with log probabilities: {err}");
+                }
+            })
+        }))?;
+        Ok(receiver.into())
+    }
+
+    /// Stream JSON that matches the schema for the prompt. Models that can't constrain generation token by token, like remote models, implement this with a structured output API. Returns `None` if the model doesn't support generating JSON from a schema.
+    ///
+    /// The root of the schema is always an object. See [`ModelExt::generate_with_schema`] for nicer API with an example.
+    fn stream_json_with_schema_inner(
+        &self,
+        _prompt: &str,
+        _schema: SchemaType,
+        _parameters: GenerationParameters,
+    ) -> Option<tokio::sync::mpsc::UnboundedReceiver<anyhow::Result<String>>> {
+        None
+    }
+
     /// Returns the chat markers to use for the model if this is a chat model.
     fn chat_markers(&self) -> Option<ChatMarkers> {
         None
     }
+
+    /// Returns the chat template to use for the model if this model ships with one. If the model has a chat template, chats use it instead of the [`ChatMarkers`].
+    fn chat_template(&self) -> Option<ChatTemplate> {
+        None
+    }
 }
 
 /// An extension trait for models that can be converted into a trait object.
@@ -978,6 +1541,30 @@ impl Model for DynModel {
               + Send) = self.as_ref();
         self_ref.stream_text_inner(prompt, parameters).await
     }
+
+    async fn stream_text_with_log_probs_inner(
+        &self,
+        prompt: &str,
+        parameters: GenerationParameters,
+        top_n: usize,
+    ) -> anyhow::Result<ChannelTextStream<GeneratedToken>> {
+        let self_ref: &(dyn Model<TextStream = ChannelTextStream, SyncModel = BoxedSyncModel>
+              + Send) = self.as_ref();
+        self_ref
+            .stream_text_with_log_probs_inner(prompt, parameters, top_n)
+            .await
+    }
+
+    fn stream_json_with_schema_inner(
+        &self,
+        prompt: &str,
+        schema: SchemaType,
+        parameters: GenerationParameters,
+    ) -> Option<tokio::sync::mpsc::UnboundedReceiver<anyhow::Result<String>>> {
+        let self_ref: &(dyn Model<TextStream = ChannelTextStream, SyncModel = BoxedSyncModel>
+              + Send) = self.as_ref();
+        self_ref.stream_json_with_schema_inner(prompt, schema, parameters)
+    }
 }
 
 /// A trait object for a sync model.
@@ -985,12 +1572,24 @@ pub type BoxedSyncModel = Box<dyn SyncModel<Session = AnySession>>;
 
 trait AnySessionTrait {
     fn save_to(&self, path: &Path) -> anyhow::Result<()>;
+
+    fn rewind(&mut self, tokens: usize) -> anyhow::Result<()>;
+
+    fn supports_rewind(&self) -> bool;
 }
 
 impl<S: Any + Session> AnySessionTrait for S {
     fn save_to(&self, path: &Path) -> anyhow::Result<()> {
         Session::save_to(self, path)
     }
+
+    fn rewind(&mut self, tokens: usize) -> anyhow::Result<()> {
+        Session::rewind(self, tokens)
+    }
+
+    fn supports_rewind(&self) -> bool {
+        Session::supports_rewind(self)
+    }
 }
 
 /// A type-erased session.
@@ -1004,6 +1603,14 @@ impl Session for AnySession {
     fn save_to(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
         self.session.save_to(path.as_ref())
     }
+
+    fn rewind(&mut self, tokens: usize) -> anyhow::Result<()> {
+        self.session.rewind(tokens)
+    }
+
+    fn supports_rewind(&self) -> bool {
+        self.session.supports_rewind()
+    }
 }
 
 impl SyncModel for BoxedSyncModel {
@@ -1034,6 +1641,31 @@ impl SyncModel for BoxedSyncModel {
         self_ref.feed_tokens(session, tokens, into)
     }
 
+    fn feed_tokens_all_logits(
+        &self,
+        session: &mut Self::Session,
+        tokens: &[u32],
+        into: &mut Vec<Vec<f32>>,
+    ) -> anyhow::Result<()> {
+        let self_ref: &(dyn SyncModel<Session = AnySession>) = self.as_ref();
+        self_ref.feed_tokens_all_logits(session, tokens, into)
+    }
+
+    fn draft_tokens(&self, session: &mut Self::Session, next: &[u32]) -> anyhow::Result<Vec<u32>> {
+        let self_ref: &(dyn SyncModel<Session = AnySession>) = self.as_ref();
+        self_ref.draft_tokens(session, next)
+    }
+
+    fn feed_batch(
+        &self,
+        sessions: &mut [&mut Self::Session],
+        tokens: &[u32],
+        into: &mut Vec<Vec<f32>>,
+    ) -> anyhow::Result<()> {
+        let self_ref: &(dyn SyncModel<Session = AnySession>) = self.as_ref();
+        self_ref.feed_batch(sessions, tokens, into)
+    }
+
     fn stop_token(&self) -> anyhow::Result<u32> {
         let self_ref: &(dyn SyncModel<Session = AnySession>) = self.as_ref();
         self_ref.stop_token()
@@ -1043,6 +1675,11 @@ impl SyncModel for BoxedSyncModel {
         let self_ref: &(dyn SyncModel<Session = AnySession>) = self.as_ref();
         self_ref.tokenizer()
     }
+
+    fn context_length(&self) -> Option<usize> {
+        let self_ref: &(dyn SyncModel<Session = AnySession>) = self.as_ref();
+        self_ref.context_length()
+    }
 }
 
 struct AnyModel<M>(M);
@@ -1067,6 +1704,17 @@ where
         self.0.stream_text_inner(prompt, params).await
     }
 
+    async fn stream_text_with_log_probs_inner(
+        &self,
+        prompt: &str,
+        params: GenerationParameters,
+        top_n: usize,
+    ) -> anyhow::Result<ChannelTextStream<GeneratedToken>> {
+        self.0
+            .stream_text_with_log_probs_inner(prompt, params, top_n)
+            .await
+    }
+
     async fn stream_text_with_sampler(
         &self,
         prompt: &str,
@@ -1078,6 +1726,15 @@ where
             .stream_text_with_sampler(prompt, max_tokens, stop_on, sampler)
             .await
     }
+
+    fn stream_json_with_schema_inner(
+        &self,
+        prompt: &str,
+        schema: SchemaType,
+        params: GenerationParameters,
+    ) -> Option<tokio::sync::mpsc::UnboundedReceiver<anyhow::Result<String>>> {
+        self.0.stream_json_with_schema_inner(prompt, schema, params)
+    }
 }
 
 /// Parameters to use when generating text.
@@ -1091,6 +1748,9 @@ pub struct GenerationParameters {
     pub(crate) repetition_penalty_range: u32,
     pub(crate) max_length: u32,
     pub(crate) stop_on: Option<String>,
+    pub(crate) strategy: SamplingStrategy,
+    pub(crate) seed: Option<u64>,
+    pub(crate) control: Option<GenerationControl>,
 }
 
 impl Default for GenerationParameters {
@@ -1104,14 +1764,16 @@ impl Default for GenerationParameters {
             repetition_penalty_range: 64,
             max_length: 128,
             stop_on: None,
+            strategy: SamplingStrategy::default(),
+            seed: None,
+            control: None,
         }
     }
 }
 
 impl crate::model::GenerationParameters {
-    /// Create a sampler chain from the generation parameters.
+    /// Create a sampler chain from the generation parameters. The chain uses the random number generator of the caller, so the seed is ignored. Use [`Self::seeded_sampler`] to respect the seed.
     pub fn sampler(self) -> SamplerChain {
-        use llm_samplers::configure::SamplerSlot;
         let GenerationParameters {
             temperature,
             tau,
@@ -1121,40 +1783,49 @@ impl crate::model::GenerationParameters {
             repetition_penalty_range,
             max_length: _,
             stop_on: _,
+            strategy,
+            seed: _,
+            control: _,
         } = self;
-        SamplerChainBuilder::from([
-            (
-                "repetition",
-                SamplerSlot::new_static(move || {
-                    Box::new(
-                        SampleRepetition::default()
-                            .penalty(repetition_penalty)
-                            .last_n(repetition_penalty_range as usize),
-                    )
-                }),
-            ),
-            (
-                "freqpresence",
-                SamplerSlot::new_static(move || Box::new(SampleFreqPresence::default().last_n(64))),
-            ),
-            (
-                "seqrepetition",
-                SamplerSlot::new_static(move || Box::<SampleSeqRepetition>::default()),
-            ),
-            (
-                "temperature",
-                SamplerSlot::new_static(move || {
-                    Box::new(SampleTemperature::default().temperature(temperature))
-                }),
-            ),
-            (
-                "mirostat2",
-                SamplerSlot::new_static(move || {
-                    Box::new(SampleMirostat2::default().tau(tau).eta(eta).mu(mu))
-                }),
-            ),
-        ])
-        .into_chain()
+        let mut chain = SamplerChain::new()
+            + SampleRepetition::default()
+                .penalty(repetition_penalty)
+                .last_n(repetition_penalty_range as usize)
+            + SampleFreqPresence::default().last_n(64)
+            + SampleSeqRepetition::default();
+        if strategy != SamplingStrategy::Greedy {
+            chain += SampleTemperature::default().temperature(temperature);
+        }
+        match strategy {
+            SamplingStrategy::Greedy => chain += SampleGreedy::default(),
+            SamplingStrategy::TopK(k) => {
+                chain += SampleTopK::default().k(k);
+                chain += SampleRandDistrib::default();
+            }
+            SamplingStrategy::TopP(p) => {
+                chain += SampleTopP::default().p(p);
+                chain += SampleRandDistrib::default();
+            }
+            SamplingStrategy::MinP(p) => {
+                chain += SampleMinP::default().p(p);
+                chain += SampleRandDistrib::default();
+            }
+            SamplingStrategy::Typical(p) => {
+                chain += SampleLocallyTypical::default().p(p);
+                chain += SampleRandDistrib::default();
+            }
+            SamplingStrategy::Mirostat => chain += LazyMirostat1::new(tau, eta, mu),
+            SamplingStrategy::Mirostat2 => {
+                chain += SampleMirostat2::default().tau(tau).eta(eta).mu(mu)
+            }
+        }
+        chain
+    }
+
+    /// Create a sampler from the generation parameters. If a seed is set, the sampler uses a random number generator seeded with it.
+    pub fn seeded_sampler(self) -> SeededSampler {
+        let seed = self.seed;
+        SeededSampler::new(self.sampler(), seed)
     }
 
     /// Get the mirostat2 sampler from the generation parameters.
@@ -1251,6 +1922,18 @@ impl crate::model::GenerationParameters {
         self
     }
 
+    /// Set the strategy used to pick each token when generating text.
+    pub fn with_sampling_strategy(mut self, strategy: SamplingStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Set the seed to use when generating text. With a seed, the same prompt and model will always generate the same text.
+    pub fn with_seed(mut self, seed: impl Into<Option<u64>>) -> Self {
+        self.seed = seed.into();
+        self
+    }
+
     /// Get the temperature to use when generating text.
     pub fn temperature(&self) -> f32 {
         self.temperature
@@ -1290,4 +1973,19 @@ impl crate::model::GenerationParameters {
     pub fn stop_on(&self) -> Option<&str> {
         self.stop_on.as_deref()
     }
+
+    /// Get the strategy used to pick each token when generating text.
+    pub fn sampling_strategy(&self) -> SamplingStrategy {
+        self.strategy
+    }
+
+    /// Get the seed to use when generating text.
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Get the control for the running generation. Models should check [`GenerationControl::interrupted`] before generating each token and report why they stopped with [`GenerationControl::finish`].
+    pub fn control(&self) -> Option<&GenerationControl> {
+        self.control.as_ref()
+    }
 }
@@ -1,13 +1,21 @@
-use async_openai::types::CreateEmbeddingRequestArgs;
+use async_openai::types::{
+    ChatCompletionRequestUserMessageArgs, CompletionFinishReason, CreateChatCompletionRequest,
+    CreateChatCompletionRequestArgs, CreateEmbeddingRequestArgs, Logprobs, ResponseFormat,
+    ResponseFormatJsonSchema,
+};
 use async_openai::{types::CreateCompletionRequestArgs, Client};
 use futures_util::{Future, StreamExt};
 use kalosm_common::*;
+use kalosm_sample::SchemaType;
 use kalosm_streams::text_stream::ChannelTextStream;
 use std::pin::Pin;
 use std::sync::Arc;
 use tokenizers::tokenizer::Tokenizer;
 
-use crate::{Embedder, Embedding, GenerationParameters, ModelBuilder, VectorSpace};
+use crate::{
+    Embedder, Embedding, GeneratedToken, GenerationControl, GenerationParameters, ModelBuilder,
+    SamplingStrategy, StopReason, TokenLogProb, VectorSpace,
+};
 
 /// A model that uses OpenAI's API.
 pub struct RemoteOpenAICompatibleModel {
@@ -75,6 +83,43 @@ impl RemoteOpenAICompatibleModel {
     pub fn builder() -> RemoteOpenAICompatibleModelBuilder<false> {
         RemoteOpenAICompatibleModelBuilder::new()
     }
+
+    fn json_schema_request(
+        &self,
+        prompt: &str,
+        schema: &SchemaType,
+        generation_parameters: GenerationParameters,
+    ) -> anyhow::Result<CreateChatCompletionRequest> {
+        let mut schema: serde_json::Value = serde_json::from_str(&schema.to_string())?;
+        strict_schema(&mut schema);
+        let mut builder = CreateChatCompletionRequestArgs::default();
+        builder
+            .model(&self.model)
+            .n(1)
+            .messages([ChatCompletionRequestUserMessageArgs::default()
+                .content(prompt)
+                .build()?
+                .into()])
+            .stream(true)
+            .temperature(generation_parameters.temperature)
+            .max_tokens(generation_parameters.max_length)
+            .response_format(ResponseFormat::JsonSchema {
+                json_schema: ResponseFormatJsonSchema {
+                    description: None,
+                    name: "response".to_string(),
+                    schema: Some(schema),
+                    // The API only guarantees that the response matches the schema in strict mode
+                    strict: Some(true),
+                },
+            });
+        if let Some(seed) = generation_parameters.seed {
+            builder.seed(seed as i64);
+        }
+        if let SamplingStrategy::TopP(top_p) = generation_parameters.strategy {
+            builder.top_p(top_p);
+        }
+        Ok(builder.build()?)
+    }
 }
 
 #[async_trait::async_trait]
@@ -103,23 +148,34 @@ impl crate::model::Model for RemoteOpenAICompatibleModel {
         if let Some(stop_on) = generation_parameters.stop_on {
             builder.stop(stop_on);
         }
+        if let Some(seed) = generation_parameters.seed {
+            builder.seed(seed as i64);
+        }
+        if let SamplingStrategy::TopP(top_p) = generation_parameters.strategy {
+            builder.top_p(top_p);
+        }
         let request = builder.build()?;
 
         let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
 
         let mut stream = self.client.completions().create_stream(request).await?;
+        let control = generation_parameters.control;
 
         tokio::spawn(async move {
             while let Some(response) = stream.next().await {
                 match response {
                     Ok(response) => {
-                        let text = response.choices[0].text.clone();
-                        if tx.send(text).is_err() {
+                        let choice = &response.choices[0];
+                        report_finish_reason(control.as_ref(), choice.finish_reason.as_ref());
+                        if tx.send(choice.text.clone()).is_err() {
                             break;
                         }
                     }
                     Err(e) => {
                         log::error!("Error in OpenAI stream: {}", e);
+                        if let Some(control) = &control {
+                            control.finish(StopReason::Error);
+                        }
                         break;
                     }
                 }
@@ -130,6 +186,213 @@ impl crate::model::Model for RemoteOpenAICompatibleModel {
 
         Ok(rx.into())
     }
+
+    async fn stream_text_with_log_probs_inner(
+        &self,
+        prompt: &str,
+        generation_parameters: GenerationParameters,
+        top_n: usize,
+    ) -> anyhow::Result<ChannelTextStream<GeneratedToken>> {
+        let mut builder = CreateCompletionRequestArgs::default();
+        builder
+            .model(&self.model)
+            .n(1)
+            .prompt(prompt)
+            .stream(true)
+            .frequency_penalty(generation_parameters.repetition_penalty)
+            .temperature(generation_parameters.temperature)
+            .max_tokens(generation_parameters.max_length as u16)
+            // The completions API returns at most 5 alternatives
+            .logprobs(top_n.min(5) as u8);
+        if let Some(stop_on) = generation_parameters.stop_on {
+            builder.stop(stop_on);
+        }
+        if let Some(seed) = generation_parameters.seed {
+            builder.seed(seed as i64);
+        }
+        if let SamplingStrategy::TopP(top_p) = generation_parameters.strategy {
+            builder.top_p(top_p);
+        }
+        let request = builder.build()?;
+
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let mut stream = self.client.completions().create_stream(request).await?;
+        let control = generation_parameters.control;
+
+        tokio::spawn(async move {
+            while let Some(response) = stream.next().await {
+                match response {
+                    Ok(response) => {
+                        let choice = &response.choices[0];
+                        report_finish_reason(control.as_ref(), choice.finish_reason.as_ref());
+                        let tokens = match &choice.logprobs {
+                            Some(logprobs) => generated_tokens_from_logprobs(logprobs),
+                            // If the API doesn't report log probabilities, send the text without them
+                            None => vec![GeneratedToken {
+                                text: choice.text.clone(),
+                                token_id: None,
+                                log_prob: None,
+                                top_alternatives: Vec::new(),
+                            }],
+                        };
+                        for token in tokens {
+                            if tx.send(token).is_err() {
+                                return Ok(());
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        log::error!("Error in OpenAI stream: {}", e);
+                        if let Some(control) = &control {
+                            control.finish(StopReason::Error);
+                        }
+                        break;
+                    }
+                }
+            }
+
+            Ok::<(), anyhow::Error>(())
+        });
+
+        Ok(rx.into())
+    }
+
+    /// Structured output is only supported through the chat completions API, so the prompt is sent as a single user message.
+    fn stream_json_with_schema_inner(
+        &self,
+        prompt: &str,
+        schema: SchemaType,
+        generation_parameters: GenerationParameters,
+    ) -> Option<tokio::sync::mpsc::UnboundedReceiver<anyhow::Result<String>>> {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let request = self.json_schema_request(prompt, &schema, generation_parameters);
+        let client = self.client.clone();
+
+        tokio::spawn(async move {
+            let result = async {
+                let mut stream = client.chat().create_stream(request?).await?;
+                while let Some(response) = stream.next().await {
+                    let response = response?;
+                    let content = response
+                        .choices
+                        .into_iter()
+                        .next()
+                        .and_then(|choice| choice.delta.content);
+                    if let Some(content) = content {
+                        if tx.send(Ok(content)).is_err() {
+                            break;
+                        }
+                    }
+                }
+                Ok::<(), anyhow::Error>(())
+            }
+            .await;
+            if let Err(err) = result {
+                _ = tx.send(Err(err));
+            }
+        });
+
+        Some(rx)
+    }
+}
+
+/// Rewrite a JSON schema for strict mode. Strict mode requires every property to be listed in `required` and doesn't support `oneOf`, so properties that are not required become nullable and `oneOf` becomes `anyOf`.
+fn strict_schema(schema: &mut serde_json::Value) {
+    let serde_json::Value::Object(object) = schema else {
+        return;
+    };
+    if let Some(one_of) = object.remove("oneOf") {
+        object.insert("anyOf".to_string(), one_of);
+    }
+    let required = object
+        .get("required")
+        .and_then(serde_json::Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    let properties = match object.get_mut("properties") {
+        Some(serde_json::Value::Object(properties)) => {
+            for (name, property) in properties.iter_mut() {
+                strict_schema(property);
+                let required = required
+                    .iter()
+                    .any(|required| required.as_str() == Some(name.as_str()));
+                if !required && !accepts_null(property) {
+                    let schema = property.take();
+                    *property = serde_json::json!({ "anyOf": [schema, { "type": "null" }] });
+                }
+            }
+            Some(
+                properties
+                    .keys()
+                    .cloned()
+                    .map(serde_json::Value::String)
+                    .collect(),
+            )
+        }
+        _ => None,
+    };
+    if let Some(properties) = properties {
+        object.insert("required".to_string(), serde_json::Value::Array(properties));
+    }
+    for (key, value) in object.iter_mut() {
+        match (key.as_str(), value) {
+            // Constants and the names of properties are values, not schemas
+            ("properties" | "required" | "const" | "enum", _) => {}
+            (_, serde_json::Value::Array(schemas)) => schemas.iter_mut().for_each(strict_schema),
+            (_, schema) => strict_schema(schema),
+        }
+    }
+}
+
+/// Check if a schema accepts `null` directly or as one of its variants.
+fn accepts_null(schema: &serde_json::Value) -> bool {
+    schema["type"] == "null"
+        || schema["anyOf"]
+            .as_array()
+            .is_some_and(|schemas| schemas.iter().any(accepts_null))
+}
+
+/// Tell the stream that the generation hit the token limit if the API reports it.
+fn report_finish_reason(
+    control: Option<&GenerationControl>,
+    finish_reason: Option<&CompletionFinishReason>,
+) {
+    if let (Some(control), Some(CompletionFinishReason::Length)) = (control, finish_reason) {
+        control.finish(StopReason::MaxLength);
+    }
+}
+
+fn generated_tokens_from_logprobs(logprobs: &Logprobs) -> Vec<GeneratedToken> {
+    logprobs
+        .tokens
+        .iter()
+        .enumerate()
+        .map(|(i, text)| {
+            let log_prob = logprobs.token_logprobs.get(i).copied().flatten();
+            let mut top_alternatives: Vec<_> = logprobs
+                .top_logprobs
+                .get(i)
+                .and_then(|top| top.as_object())
+                .into_iter()
+                .flatten()
+                .filter_map(|(text, log_prob)| {
+                    Some(TokenLogProb {
+                        token_id: None,
+                        text: text.clone(),
+                        log_prob: log_prob.as_f64()? as f32,
+                    })
+                })
+                .collect();
+            top_alternatives.sort_by(|a, b| b.log_prob.total_cmp(&a.log_prob));
+            GeneratedToken {
+                text: text.clone(),
+                token_id: None,
+                log_prob,
+                top_alternatives,
+            }
+        })
+        .collect()
 }
 
 macro_rules! openai_completion_model {
@@ -229,6 +492,27 @@ macro_rules! openai_completion_model {
                     .stream_text_inner(prompt, generation_parameters)
                     .await
             }
+
+            async fn stream_text_with_log_probs_inner(
+                &self,
+                prompt: &str,
+                generation_parameters: GenerationParameters,
+                top_n: usize,
+            ) -> anyhow::Result<ChannelTextStream<GeneratedToken>> {
+                self.inner
+                    .stream_text_with_log_probs_inner(prompt, generation_parameters, top_n)
+                    .await
+            }
+
+            fn stream_json_with_schema_inner(
+                &self,
+                prompt: &str,
+                schema: SchemaType,
+                generation_parameters: GenerationParameters,
+            ) -> Option<tokio::sync::mpsc::UnboundedReceiver<anyhow::Result<String>>> {
+                self.inner
+                    .stream_json_with_schema_inner(prompt, schema, generation_parameters)
+            }
         }
     };
 }
@@ -387,3 +671,70 @@ impl Embedder for AdaEmbedder {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kalosm_sample::{JsonObjectSchema, JsonPropertySchema, Schema};
+
+    #[test]
+    fn json_schema_request_body() {
+        let model = RemoteOpenAICompatibleModel::builder()
+            .with_model("gpt-4o-mini")
+            .build();
+        let (schema, _) = crate::json_schema::object_schema(<Vec<String>>::schema());
+        let request = model
+            .json_schema_request(
+                "A list of names: ",
+                &schema,
+                GenerationParameters::default()
+                    .with_max_length(100)
+                    .with_seed(7),
+            )
+            .unwrap();
+
+        let body = serde_json::to_value(&request).unwrap();
+        assert_eq!(body["model"], "gpt-4o-mini");
+        assert_eq!(body["stream"], true);
+        assert_eq!(body["max_tokens"], 100);
+        assert_eq!(body["seed"], 7);
+        assert_eq!(body["messages"][0]["role"], "user");
+        assert_eq!(body["messages"][0]["content"], "A list of names: ");
+        let response_format = &body["response_format"];
+        assert_eq!(response_format["type"], "json_schema");
+        assert_eq!(response_format["json_schema"]["name"], "response");
+        assert_eq!(response_format["json_schema"]["strict"], true);
+        let expected_schema: serde_json::Value = serde_json::from_str(&schema.to_string()).unwrap();
+        assert_eq!(response_format["json_schema"]["schema"], expected_schema);
+
+        // Strict mode requires every property, so the optional properties are sent as required but nullable
+        let schema = SchemaType::Object(JsonObjectSchema::new([
+            JsonPropertySchema::new("name", String::schema()).with_required(true),
+            JsonPropertySchema::new("nickname", String::schema()),
+            JsonPropertySchema::new("age", <Option<u32>>::schema()).with_required(true),
+        ]));
+        let request = model
+            .json_schema_request("A person: ", &schema, GenerationParameters::default())
+            .unwrap();
+        let body = serde_json::to_value(&request).unwrap();
+        let schema = &body["response_format"]["json_schema"]["schema"];
+        let mut required: Vec<_> = schema["required"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|name| name.as_str().unwrap())
+            .collect();
+        required.sort();
+        assert_eq!(required, ["age", "name", "nickname"]);
+        assert_eq!(schema["properties"]["name"]["type"], "string");
+        assert_eq!(
+            schema["properties"]["nickname"]["anyOf"][0]["type"],
+            "string"
+        );
+        assert_eq!(schema["properties"]["nickname"]["anyOf"][1]["type"], "null");
+        let age = &schema["properties"]["age"];
+        assert!(age.get("oneOf").is_none());
+        assert_eq!(age["anyOf"][0]["type"], "null");
+        assert_eq!(age["anyOf"].as_array().unwrap().len(), 2);
+    }
+}
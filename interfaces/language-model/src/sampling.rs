@@ -0,0 +1,187 @@
+use llm_samplers::prelude::*;
+use llm_samplers::types::{HasSamplerResources, Sampler, SamplerError};
+use rand::{rngs::StdRng, SeedableRng};
+
+/// The strategy used to pick the next token from the probabilities the model predicts.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SamplingStrategy {
+    /// Always pick the most likely token. This ignores the temperature.
+    Greedy,
+    /// Sample from the `k` most likely tokens.
+    TopK(usize),
+    /// Sample from the smallest set of tokens whose probabilities add up to at least `p` (nucleus sampling).
+    TopP(f32),
+    /// Sample from the tokens that are at least `p` times as likely as the most likely token.
+    MinP(f32),
+    /// Sample from the tokens whose information content is closest to the expected information content until their probabilities add up to `p` (locally typical sampling).
+    Typical(f32),
+    /// Mirostat v1 sampling with the tau, eta and mu of the [`crate::GenerationParameters`].
+    Mirostat,
+    /// Mirostat v2 sampling with the tau, eta and mu of the [`crate::GenerationParameters`].
+    #[default]
+    Mirostat2,
+}
+
+/// Mirostat v1 needs the size of the vocabulary, which we only know once we see the first logits.
+#[derive(Debug)]
+pub(crate) struct LazyMirostat1 {
+    tau: f32,
+    eta: f32,
+    mu: f32,
+    sampler: Option<SampleMirostat1>,
+}
+
+impl LazyMirostat1 {
+    pub(crate) fn new(tau: f32, eta: f32, mu: f32) -> Self {
+        Self {
+            tau,
+            eta,
+            mu,
+            sampler: None,
+        }
+    }
+}
+
+impl Sampler for LazyMirostat1 {
+    fn sample<'a>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits> {
+        let (tau, eta, mu) = (self.tau, self.eta, self.mu);
+        let n_vocab = logits.len();
+        let sampler = self.sampler.get_or_insert_with(|| {
+            SampleMirostat1::default()
+                .n_vocab(n_vocab)
+                .tau(tau)
+                .eta(eta)
+                .mu(mu)
+        });
+        sampler.sample(res, logits)
+    }
+
+    fn sampled_token_id(&self) -> Option<u32> {
+        self.sampler.as_ref()?.sampled_token_id()
+    }
+}
+
+/// A sampler that uses its own random number generator instead of the one the caller provides. If a seed is set, the same seed, prompt and model will always generate the same tokens.
+#[derive(Debug)]
+pub struct SeededSampler<S = SamplerChain> {
+    sampler: S,
+    rng: Option<StdRng>,
+}
+
+impl<S> SeededSampler<S> {
+    /// Wrap a sampler. If the seed is `None`, the sampler uses the random number generator the caller provides.
+    pub fn new(sampler: S, seed: Option<u64>) -> Self {
+        Self {
+            sampler,
+            rng: seed.map(StdRng::seed_from_u64),
+        }
+    }
+
+    /// Get the inner sampler.
+    pub fn inner(&self) -> &S {
+        &self.sampler
+    }
+
+    /// Get the inner sampler mutably.
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.sampler
+    }
+}
+
+impl<S: Sampler> Sampler for SeededSampler<S> {
+    fn sample<'a>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits> {
+        match &mut self.rng {
+            Some(rng) => self
+                .sampler
+                .sample(&mut SeededResources { res, rng }, logits),
+            None => self.sampler.sample(res, logits),
+        }
+    }
+
+    fn sampled_token_id(&self) -> Option<u32> {
+        self.sampler.sampled_token_id()
+    }
+}
+
+/// Forwards the previous tokens from the caller's resources, but replaces the random number generator.
+struct SeededResources<'a, 'b> {
+    res: &'a mut dyn HasSamplerResources,
+    rng: &'b mut StdRng,
+}
+
+impl std::fmt::Debug for SeededResources<'_, '_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SeededResources")
+            .field("res", &self.res)
+            .finish()
+    }
+}
+
+impl HasSamplerResources for SeededResources<'_, '_> {
+    fn with_rng_mut(
+        &mut self,
+        fun: &mut dyn FnMut(&mut dyn rand::RngCore),
+    ) -> Result<(), SamplerError> {
+        fun(self.rng);
+        Ok(())
+    }
+
+    fn with_last_tokens(&self, fun: &mut dyn FnMut(&[u32])) -> Result<(), SamplerError> {
+        self.res.with_last_tokens(fun)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GenerationParameters;
+
+    fn sample_tokens(parameters: GenerationParameters) -> Vec<u32> {
+        let mut sampler = parameters.seeded_sampler();
+        // Every call uses a different random rng, so only the seed can make the results match
+        (0..16)
+            .map(|_| {
+                let mut resources = SimpleSamplerResources::new(
+                    Some(Box::new(StdRng::from_entropy())),
+                    Some(vec![]),
+                );
+                let mut logits = Logits::try_from_iter([1.0f32, 1.1, 0.9, 1.2, 1.0]).unwrap();
+                logits
+                    .sample_token(&mut resources, &mut sampler)
+                    .unwrap()
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn seeded_sampling_is_reproducible() {
+        for strategy in [
+            SamplingStrategy::TopK(3),
+            SamplingStrategy::TopP(0.9),
+            SamplingStrategy::Mirostat2,
+        ] {
+            let parameters = GenerationParameters::default()
+                .with_repetition_penalty(1.0)
+                .with_sampling_strategy(strategy)
+                .with_seed(42);
+            assert_eq!(sample_tokens(parameters.clone()), sample_tokens(parameters));
+        }
+    }
+
+    #[test]
+    fn greedy_sampling_picks_the_most_likely_token() {
+        let parameters = GenerationParameters::default()
+            .with_repetition_penalty(1.0)
+            .with_sampling_strategy(SamplingStrategy::Greedy);
+        assert!(sample_tokens(parameters).iter().all(|token| *token == 3));
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::VecDeque;
+
+use crate::{Session, SyncModel};
+
+/// Feeds tokens into a model, guessing ahead with the model's draft tokens when they are available.
+///
+/// Every time new tokens are fed, the model is asked for draft tokens that likely come next. The new tokens and the draft are run through the model in one forward pass. If the caller then feeds a token that matches the next draft token, the logits are already known and the model doesn't need to run again. When the tokens diverge, the rejected draft tokens are rewound out of the session.
+///
+/// Because the caller still samples every token from the logits of the full model, speculation never changes the generated text.
+#[derive(Default)]
+pub(crate) struct SpeculativeFeeder {
+    /// Draft tokens that have been fed into the session, but not yet accepted along with the logits after each draft token.
+    speculated: VecDeque<(u32, Vec<f32>)>,
+    all_logits: Vec<Vec<f32>>,
+}
+
+impl SpeculativeFeeder {
+    /// Run a generation with a new feeder. Any draft tokens that were never accepted are removed from the session afterwards, even if the generation fails.
+    pub(crate) fn run<S: Session, T>(
+        session: &mut S,
+        generate: impl FnOnce(&mut Self, &mut S) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let mut feeder = Self::default();
+        let result = generate(&mut feeder, session);
+        let finished = feeder.finish(session);
+        let result = result?;
+        finished?;
+        Ok(result)
+    }
+
+    /// Feed tokens into the session and write the logits for the next token into `into`.
+    pub(crate) fn feed<M: ?Sized + SyncModel>(
+        &mut self,
+        llm: &M,
+        session: &mut M::Session,
+        tokens: &[u32],
+        into: &mut Vec<f32>,
+    ) -> anyhow::Result<()> {
+        // If the token was guessed correctly, we already have the logits for it
+        if let ([token], Some((draft, _))) = (tokens, self.speculated.front()) {
+            if token == draft {
+                let (_, logits) = self.speculated.pop_front().unwrap();
+                *into = logits;
+                return Ok(());
+            }
+        }
+        self.finish(session)?;
+
+        let Some((&last, prefix)) = tokens.split_last() else {
+            return llm.feed_tokens(session, tokens, into);
+        };
+        if !prefix.is_empty() {
+            llm.feed_tokens(session, prefix, into)?;
+        }
+        let draft = llm.draft_tokens(session, &[last])?;
+        if draft.is_empty() {
+            return llm.feed_tokens(session, &[last], into);
+        }
+
+        let mut verify = Vec::with_capacity(draft.len() + 1);
+        verify.push(last);
+        verify.extend_from_slice(&draft);
+        llm.feed_tokens_all_logits(session, &verify, &mut self.all_logits)?;
+        let mut logits = self.all_logits.drain(..);
+        *into = logits
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Model returned no logits"))?;
+        self.speculated.extend(draft.into_iter().zip(logits));
+
+        Ok(())
+    }
+
+    /// Remove any draft tokens that were never accepted from the session.
+    pub(crate) fn finish<S: Session>(&mut self, session: &mut S) -> anyhow::Result<()> {
+        if !self.speculated.is_empty() {
+            session.rewind(self.speculated.len())?;
+            self.speculated.clear();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use kalosm_sample::LiteralParser;
+
+    use crate::mock::{one_hot, MockModel, MockSession};
+    use crate::{GenerationParameters, ModelFeedback, SamplingStrategy, SyncModel, SyncModelExt};
+
+    /// A model that always continues with "abcabc..."
+    fn model() -> MockModel {
+        MockModel::new(&["a", "b", "c", "<eos>"], |tokens| {
+            one_hot(4, tokens.last().map_or(0, |last| (last + 1) % 3))
+        })
+    }
+
+    /// A draft that guesses the next three tokens correctly
+    fn good_draft(tokens: &[u32]) -> Vec<u32> {
+        let last = *tokens.last().unwrap();
+        (1..=3).map(|i| (last + i) % 3).collect()
+    }
+
+    /// A draft that always repeats the last token, which the model never does
+    fn bad_draft(tokens: &[u32]) -> Vec<u32> {
+        vec![*tokens.last().unwrap(); 2]
+    }
+
+    fn generate(
+        model: &MockModel,
+        fail_after: Option<usize>,
+    ) -> (anyhow::Result<()>, String, MockSession) {
+        let mut session = model.new_session().unwrap();
+        let sampler = GenerationParameters::default()
+            .with_repetition_penalty(1.0)
+            .with_sampling_strategy(SamplingStrategy::Greedy)
+            .sampler();
+        let mut text = String::new();
+        let result = model.stream_text_with_sampler(
+            &mut session,
+            "a",
+            Some(8),
+            None,
+            Arc::new(Mutex::new(sampler)),
+            |token| {
+                if Some(text.len()) == fail_after {
+                    anyhow::bail!("The receiver was dropped");
+                }
+                text += &token;
+                Ok(ModelFeedback::Continue)
+            },
+        );
+        (result, text, session)
+    }
+
+    #[test]
+    fn accepted_drafts_skip_forward_passes() {
+        let model_without_draft = model();
+        let (result, text, session) = generate(&model_without_draft, None);
+        result.unwrap();
+        assert_eq!(text, "bcabcabc");
+
+        let model_with_draft = model().with_draft(good_draft);
+        let (result, draft_text, draft_session) = generate(&model_with_draft, None);
+        result.unwrap();
+        assert_eq!(draft_text, text);
+        assert_eq!(draft_session.tokens, session.tokens);
+        assert!(model_with_draft.forward_passes() < model_without_draft.forward_passes());
+    }
+
+    #[test]
+    fn rejected_drafts_are_rewound() {
+        let (result, text, session) = generate(&model(), None);
+        result.unwrap();
+
+        let model_with_draft = model().with_draft(bad_draft);
+        let (result, draft_text, draft_session) = generate(&model_with_draft, None);
+        result.unwrap();
+        assert_eq!(draft_text, text);
+        assert_eq!(draft_session.tokens, session.tokens);
+        // Every draft is rejected at the first token
+        assert!(!draft_session.rewinds.is_empty());
+        assert!(draft_session.rewinds.iter().all(|rewound| *rewound == 2));
+    }
+
+    #[test]
+    fn drafts_are_rewound_when_generation_fails() {
+        let (result, text, session) = generate(&model(), Some(2));
+        assert!(result.is_err());
+        assert_eq!(text, "bc");
+
+        let (result, draft_text, draft_session) =
+            generate(&model().with_draft(good_draft), Some(2));
+        assert!(result.is_err());
+        assert_eq!(draft_text, text);
+        assert_eq!(draft_session.tokens, session.tokens);
+    }
+
+    #[test]
+    fn drafts_are_rewound_when_structured_generation_fails() {
+        let model = model().with_draft(good_draft);
+        let mut session = model.new_session().unwrap();
+        let sampler = GenerationParameters::default()
+            .with_repetition_penalty(1.0)
+            .with_sampling_strategy(SamplingStrategy::Greedy)
+            .sampler();
+        let result = model.generate_structured(
+            &mut session,
+            "ab",
+            LiteralParser::new("cabc"),
+            Default::default(),
+            Arc::new(Mutex::new(sampler)),
+            |_| anyhow::bail!("The receiver was dropped"),
+            None,
+        );
+        assert!(result.is_err());
+        // Only the prompt before the healed token is left in the session
+        assert_eq!(session.tokens, vec![0]);
+    }
+}
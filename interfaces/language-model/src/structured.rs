@@ -1,10 +1,13 @@
 use std::{
+    collections::VecDeque,
     fmt::{Debug, Display, Formatter},
     sync::{Arc, Mutex},
 };
 
-use crate::SyncModel;
+use crate::speculative::SpeculativeFeeder;
+use crate::token_trie::{TokenMaskCache, TokenTrie};
 use crate::TokenOutputStream;
+use crate::{Session, SyncModel};
 use kalosm_sample::CreateParserState;
 use kalosm_sample::{LiteralParser, ParseStatus, Parser, ParserExt};
 use llm_samplers::prelude::{Logit, Logits};
@@ -12,6 +15,22 @@ use llm_samplers::types::{HasSamplerResources, Sampler, SamplerError};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use tokenizers::tokenizer::Tokenizer;
 
+/// The number of times generation can backtrack for each token it can backtrack over before it gives up.
+const BACKTRACKS_PER_TOKEN: usize = 8;
+
+/// A token that was generated while backtracking is enabled. Generation can return to the state before the token and try a different token.
+struct Checkpoint<S> {
+    token: u32,
+    /// The tokens that already led to a dead end from this state.
+    rejected: Vec<u32>,
+    parser_state: S,
+    token_stream: TokenOutputStream,
+    logits: Vec<f32>,
+    strip_required_next: bool,
+    /// The text generated from the token that has not been sent yet.
+    text: String,
+}
+
 #[allow(clippy::too_many_arguments)]
 pub(crate) fn generate_structured<M: ?Sized + SyncModel, P: Parser>(
     prompt: impl Display,
@@ -19,9 +38,45 @@ pub(crate) fn generate_structured<M: ?Sized + SyncModel, P: Parser>(
     session: &mut M::Session,
     parser: P,
     parser_state: P::PartialState,
+    sampler: Arc<Mutex<dyn Sampler>>,
+    on_token: impl FnMut(String) -> anyhow::Result<()>,
+    top_k: Option<usize>,
+    max_backtrack: usize,
+) -> anyhow::Result<P::Output> {
+    // Check before anything is fed so a session that can't rewind fails before it generates text it would need to take back
+    if max_backtrack > 0 && !session.supports_rewind() {
+        return Err(anyhow::anyhow!(
+            "Backtracking requires a session that supports rewinding"
+        ));
+    }
+    SpeculativeFeeder::run(session, |feeder, session| {
+        generate_structured_with_feeder(
+            prompt,
+            llm,
+            session,
+            feeder,
+            parser,
+            parser_state,
+            sampler,
+            on_token,
+            top_k,
+            max_backtrack,
+        )
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn generate_structured_with_feeder<M: ?Sized + SyncModel, P: Parser>(
+    prompt: impl Display,
+    llm: &M,
+    session: &mut M::Session,
+    feeder: &mut SpeculativeFeeder,
+    parser: P,
+    parser_state: P::PartialState,
     mut sampler: Arc<Mutex<dyn Sampler>>,
     mut on_token: impl FnMut(String) -> anyhow::Result<()>,
     top_k: Option<usize>,
+    max_backtrack: usize,
 ) -> anyhow::Result<P::Output> {
     let tokenizer = llm.tokenizer();
 
@@ -57,20 +112,33 @@ pub(crate) fn generate_structured<M: ?Sized + SyncModel, P: Parser>(
     let mut parser_state = parser.create_parser_state();
     let mut strip_required_next = true;
 
+    // Unseeded samplers draw from the thread rng. A [`crate::SeededSampler`] ignores it and samples with its own seeded rng, so generation with a seed is reproducible
     let mut rng = rand::thread_rng();
     let mut state_map = vec![];
     let mut logits_indexed = Vec::new();
     let mut token_cache = DetokenizationCache::new();
     let mut logits = Logits::default();
     let mut logit_probs = Vec::new();
+    // The token trie is only built once a constraint needs it
+    let mut token_trie = None;
+    let mut token_masks = TokenMaskCache::default();
+    // The last `max_backtrack` tokens are held back until generation can no longer return to them
+    let mut checkpoints = VecDeque::new();
+    let mut rejected = Vec::new();
+    let mut backtracks_left = max_backtrack.saturating_mul(BACKTRACKS_PER_TOKEN);
+    let mut restored_logits = false;
 
     loop {
         let tokens = token_stream.tokens();
-        llm.feed_tokens(
-            session,
-            &tokens[tokens.len() - unprocessed_token_count..],
-            &mut logit_probs,
-        )?;
+        // After backtracking, the logits for the state are already known
+        if !std::mem::take(&mut restored_logits) {
+            feeder.feed(
+                llm,
+                session,
+                &tokens[tokens.len() - unprocessed_token_count..],
+                &mut logit_probs,
+            )?;
+        }
         let resources = &mut SamplerResources {
             previous_tokens: tokens,
             rng: &mut rng,
@@ -90,12 +158,30 @@ pub(crate) fn generate_structured<M: ?Sized + SyncModel, P: Parser>(
             state_map.push(None);
         }
 
+        if !rejected.is_empty() {
+            logits_indexed.retain(|logit| !rejected.contains(&logit.token_id));
+        }
+
         let mut valid_tokens = false;
 
+        // If we need to check every token or we already know which tokens are valid, remove the invalid tokens with the token trie before detokenizing anything
+        let mut masked = top_k.is_none() || token_masks.contains(&parser, &parser_state);
+        if masked {
+            let mask = token_masks.get(
+                token_trie.get_or_insert_with(|| TokenTrie::for_tokenizer(&tokenizer)),
+                &parser,
+                &parser_state,
+            );
+            logits_indexed.retain(|logit| mask.allows(logit.token_id));
+        }
+
         // If we don't have a top k, then we can just cache the entire detokenization
         if top_k.is_none() {
             token_cache.expand(
-                &(0..logit_probs.len() as u32).collect::<Vec<_>>(),
+                &logits_indexed
+                    .iter()
+                    .map(|logit| logit.token_id)
+                    .collect::<Vec<_>>(),
                 &token_stream,
             )?;
         }
@@ -110,6 +196,30 @@ pub(crate) fn generate_structured<M: ?Sized + SyncModel, P: Parser>(
         let mut partitioned_logits_index = top_k.map(|_| 0);
 
         for i in 0..logits_indexed.len() {
+            // Masking tokens may remove tokens from the end of the list
+            if i >= logits_indexed.len() {
+                break;
+            }
+
+            // If most of the likely tokens were invalid, the constraint is probably strict. Remove the rest of the invalid tokens with the token trie instead of checking them one by one
+            if !masked && i == DETOKENIZATION_INITIAL_BATCH_SIZE {
+                masked = true;
+                let mask = token_masks.get(
+                    token_trie.get_or_insert_with(|| TokenTrie::for_tokenizer(&tokenizer)),
+                    &parser,
+                    &parser_state,
+                );
+                let rest = logits_indexed.split_off(i);
+                logits_indexed.extend(rest.into_iter().filter(|logit| mask.allows(logit.token_id)));
+                if i >= logits_indexed.len() {
+                    break;
+                }
+                // The rest of the logits need to be partitioned again
+                if let Some(partitioned_index) = &mut partitioned_logits_index {
+                    *partitioned_index = i;
+                }
+            }
+
             // If we have top k enabled, and there are less than top k - committed logits sorted, we need to expand the partitioned logits
             if let (Some(top_k), Some(partitioned_index)) = (top_k, partitioned_logits_index) {
                 // If the remaining logits are less than the top k, no need to partition
@@ -164,9 +274,36 @@ pub(crate) fn generate_structured<M: ?Sized + SyncModel, P: Parser>(
             }
         }
 
-        // If there are no valid tokens, return an error
+        // If there are no valid tokens, go back to the last token and try the next best token instead. If we can't backtrack, return an error
         if !valid_tokens {
-            return Err(anyhow::anyhow!("No valid tokens found"));
+            feeder.finish(session)?;
+            if backtracks_left == 0 {
+                return Err(anyhow::anyhow!("No valid tokens found"));
+            }
+            let Some(checkpoint) = checkpoints.pop_back() else {
+                return Err(anyhow::anyhow!("No valid tokens found"));
+            };
+            backtracks_left -= 1;
+            let Checkpoint {
+                token,
+                rejected: rejected_before,
+                parser_state: state_before,
+                token_stream: token_stream_before,
+                logits,
+                strip_required_next: strip_required_next_before,
+                ..
+            } = checkpoint;
+            tracing::trace!("Backtracking to before token {}", token);
+            session.rewind(token_stream.tokens().len() - token_stream_before.tokens().len())?;
+            rejected = rejected_before;
+            rejected.push(token);
+            parser_state = state_before;
+            token_stream = token_stream_before;
+            logit_probs = logits;
+            strip_required_next = strip_required_next_before;
+            restored_logits = true;
+            unprocessed_token_count = 0;
+            continue;
         }
         let token_id = sampler
             .sample_token(resources, &mut logits)?
@@ -178,6 +315,15 @@ pub(crate) fn generate_structured<M: ?Sized + SyncModel, P: Parser>(
             .unwrap()
             .take()
             .ok_or(anyhow::anyhow!("Token {} not found in state map", token_id))?;
+        let mut checkpoint = (max_backtrack > 0).then(|| Checkpoint {
+            token: token_id,
+            rejected: std::mem::take(&mut rejected),
+            parser_state: parser_state.clone(),
+            token_stream: token_stream.clone(),
+            logits: logit_probs.clone(),
+            strip_required_next,
+            text: String::new(),
+        });
         let mut token = token_stream.next_token(token_id)?.unwrap();
         token.truncate(parsed_bytes);
         tracing::trace!("Adding token {} to parser", token);
@@ -188,19 +334,61 @@ pub(crate) fn generate_structured<M: ?Sized + SyncModel, P: Parser>(
             }
             strip_required_next = false;
         }
-        on_token(token)?;
 
-        if let Some(result) = update_state(
+        // Hold back the text while we can still backtrack over the token
+        let mut emit = |text: String| match &mut checkpoint {
+            Some(checkpoint) => {
+                checkpoint.text += &text;
+                Ok(())
+            }
+            None => on_token(text),
+        };
+        emit(token)?;
+
+        let result = match update_state(
             &parser,
             &mut parser_state,
             result,
             &tokenizer,
             &mut token_stream,
-            &mut on_token,
+            &mut emit,
             &mut unprocessed_token_count,
         )? {
+            StateUpdate::Incomplete => None,
+            StateUpdate::Finished(result) => Some(result),
+            // The text the token forces was rejected, so the token is a dead end just like a token the parser rejects
+            StateUpdate::Rejected => {
+                let Some(checkpoint) = checkpoint.filter(|_| backtracks_left > 0) else {
+                    return Err(anyhow::anyhow!(
+                        "The parser rejected the text required after token {}",
+                        token_id
+                    ));
+                };
+                backtracks_left -= 1;
+                tracing::trace!("Backtracking to before token {}", token_id);
+                // Neither the token nor the text it forces has been fed into the session yet, so only the state needs to be restored
+                rejected = checkpoint.rejected;
+                rejected.push(token_id);
+                parser_state = checkpoint.parser_state;
+                token_stream = checkpoint.token_stream;
+                logit_probs = checkpoint.logits;
+                strip_required_next = checkpoint.strip_required_next;
+                restored_logits = true;
+                unprocessed_token_count = 0;
+                continue;
+            }
+        };
+        checkpoints.extend(checkpoint);
+        if let Some(result) = result {
+            for checkpoint in checkpoints {
+                on_token(checkpoint.text)?;
+            }
             return Ok(result);
         }
+        if checkpoints.len() > max_backtrack {
+            let checkpoint = checkpoints.pop_front().unwrap();
+            on_token(checkpoint.text)?;
+        }
     }
 }
 
@@ -211,6 +399,16 @@ fn cmp_logits(a: &Logit, b: &Logit) -> std::cmp::Ordering {
     unsafe { compare.unwrap_unchecked() }
 }
 
+/// The state of the parser after a token and the text it forces are added.
+enum StateUpdate<O> {
+    /// The parser needs more tokens.
+    Incomplete,
+    /// The parser finished with the output.
+    Finished(O),
+    /// The parser rejected the text the token forces, like a value that fails validation once a closing quote is forced.
+    Rejected,
+}
+
 #[allow(unused, clippy::all)]
 fn update_state<P: Parser>(
     parser: &P,
@@ -220,7 +418,7 @@ fn update_state<P: Parser>(
     token_stream: &mut TokenOutputStream,
     on_token: &mut impl FnMut(String) -> anyhow::Result<()>,
     unprocessed_token_count: &mut usize,
-) -> anyhow::Result<Option<P::Output>> {
+) -> anyhow::Result<StateUpdate<P::Output>> {
     match result {
         kalosm_sample::ParseStatus::Incomplete {
             new_state,
@@ -228,17 +426,17 @@ fn update_state<P: Parser>(
         } => {
             *parser_state = new_state;
             if required_next.is_empty() {
-                Ok(None)
+                Ok(StateUpdate::Incomplete)
             } else {
                 // The token may decode to a string that is a valid prefix of the required next token, but in a way that doesn't let us decode the required next tokens
                 let Some(mut extra_tokens) = token_stream.encode_after(&required_next)? else {
-                    return Ok(None);
+                    return Ok(StateUpdate::Incomplete);
                 };
                 // Remove the last token to avoid influencing the next token
                 extra_tokens.pop();
                 // If there are no new tokens, continue generating tokens normally
                 if extra_tokens.is_empty() {
-                    return Ok(None);
+                    return Ok(StateUpdate::Incomplete);
                 }
 
                 let mut all_required_next = String::new();
@@ -248,16 +446,15 @@ fn update_state<P: Parser>(
                 // The token may decode to a string that is a valid prefix of the required next token, but in a way that doesn't encode the same way.
                 // Make sure the final text we are adding is actually valid
                 if !required_next.starts_with(&all_required_next) {
-                    return Ok(None);
+                    return Ok(StateUpdate::Incomplete);
                 }
+                // The required text always continues the parser, but a parser like [`kalosm_sample::ValidateParser`] can still reject the output it finishes
+                let Ok(result) = parser.parse(parser_state, all_required_next.as_bytes()) else {
+                    return Ok(StateUpdate::Rejected);
+                };
                 token_stream.next_tokens(&extra_tokens)?;
                 *unprocessed_token_count += extra_tokens.len();
                 on_token(all_required_next.clone())?;
-                let mut result = parser
-                    .parse(parser_state, all_required_next.as_bytes())
-                    .unwrap_or_else(|_| {
-                        unreachable!("Required next should always be valid attempted to add {:?} but got error", all_required_next)
-                });
                 update_state(
                     parser,
                     parser_state,
@@ -269,7 +466,7 @@ fn update_state<P: Parser>(
                 )
             }
         }
-        kalosm_sample::ParseStatus::Finished { result, .. } => Ok(Some(result)),
+        kalosm_sample::ParseStatus::Finished { result, .. } => Ok(StateUpdate::Finished(result)),
     }
 }
 
@@ -379,3 +576,152 @@ impl DetokenizationCache {
         self.vec.clear();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockModel, MockSession};
+    use crate::{GenerationParameters, SamplingStrategy, SyncModelExt};
+    use kalosm_sample::{IntegerParser, ParserError, RegexParser, StringParser};
+
+    fn generate_string(seed: u64) -> String {
+        let vocab = ["a", "b", "c", "d", "\"", "<eos>"];
+        let model = MockModel::new(&vocab, |_| vec![0.0; 6]);
+        let mut session = model.new_session().unwrap();
+        let sampler = GenerationParameters::default()
+            .with_sampling_strategy(SamplingStrategy::TopK(4))
+            .with_seed(seed)
+            .seeded_sampler();
+        let parser = StringParser::new(8..=8);
+        let state = parser.create_parser_state();
+        let mut text = String::new();
+        model
+            .generate_structured(
+                &mut session,
+                "a",
+                parser,
+                state,
+                Arc::new(Mutex::new(sampler)),
+                |token| {
+                    text += &token;
+                    Ok(())
+                },
+                None,
+            )
+            .unwrap();
+        text
+    }
+
+    #[test]
+    fn seeded_structured_generation_is_reproducible() {
+        let generations = (0..4).map(generate_string).collect::<Vec<_>>();
+        for (seed, text) in generations.iter().enumerate() {
+            assert_eq!(text.len(), 10);
+            assert_eq!(text, &generate_string(seed as u64));
+        }
+        // Different seeds should pick different strings
+        assert!(generations.iter().any(|text| text != &generations[0]));
+    }
+
+    fn generate_range(max_backtrack: usize) -> (anyhow::Result<(i128, i128)>, String, MockSession) {
+        let vocab = ["1", "2", "3", "-", "!", "<eos>"];
+        let model = MockModel::new(&vocab, |_| vec![1.0, 2.0, 3.0, 0.0, 0.0, 0.0]);
+        let mut session = model.new_session().unwrap();
+        let sampler = GenerationParameters::default()
+            .with_repetition_penalty(1.0)
+            .with_sampling_strategy(SamplingStrategy::Greedy)
+            .sampler();
+        // The model prefers 3, but no end is after a start of 3
+        let parser = IntegerParser::new(1..=3)
+            .then_literal("-")
+            .then(IntegerParser::new(1..=3))
+            .validate(|(start, end)| {
+                if end > start {
+                    Ok(())
+                } else {
+                    Err(ParserError::msg("The end must be after the start"))
+                }
+            });
+        let state = parser.create_parser_state();
+        let mut text = String::new();
+        let result = model.generate_structured_with_backtracking(
+            &mut session,
+            "!",
+            parser,
+            state,
+            Arc::new(Mutex::new(sampler)),
+            |token| {
+                text += &token;
+                Ok(())
+            },
+            None,
+            max_backtrack,
+        );
+        (result, text, session)
+    }
+
+    #[test]
+    fn backtracking_rewinds_dead_ends() {
+        let (result, _, session) = generate_range(0);
+        assert!(result.is_err());
+        assert_eq!(session.tokens, [4, 2, 3]);
+        assert!(session.rewinds.is_empty());
+
+        let (result, text, session) = generate_range(2);
+        assert_eq!(result.unwrap(), (2, 3));
+        // The text of the rejected tokens is never sent
+        assert_eq!(text, "2-3");
+        // Both the dash and the rejected 3 before it are rewound before the next best start is sampled
+        assert_eq!(session.rewinds, [1, 1]);
+        assert_eq!(session.tokens, [4, 1, 3]);
+    }
+
+    fn generate_forced(max_backtrack: usize) -> (anyhow::Result<String>, String, MockSession) {
+        let vocab = ["a", "b", "!", "<eos>"];
+        let model = MockModel::new(&vocab, |_| vec![2.0, 1.0, 0.0, 0.0]);
+        let mut session = model.new_session().unwrap();
+        let sampler = GenerationParameters::default()
+            .with_repetition_penalty(1.0)
+            .with_sampling_strategy(SamplingStrategy::Greedy)
+            .sampler();
+        // The model prefers a, but the b that a forces finishes a value the validator rejects
+        let parser = RegexParser::new("[ab]b")
+            .unwrap()
+            .validate(|value: &String| {
+                if value.starts_with('a') {
+                    Err(ParserError::msg("The value must not start with a"))
+                } else {
+                    Ok(())
+                }
+            });
+        let state = parser.create_parser_state();
+        let mut text = String::new();
+        let result = model.generate_structured_with_backtracking(
+            &mut session,
+            "!",
+            parser,
+            state,
+            Arc::new(Mutex::new(sampler)),
+            |token| {
+                text += &token;
+                Ok(())
+            },
+            None,
+            max_backtrack,
+        );
+        (result, text, session)
+    }
+
+    #[test]
+    fn backtracking_rejects_forced_text() {
+        let (result, text, _) = generate_forced(0);
+        assert!(result.is_err());
+        // Without backtracking, the token is sent before the text it forces is rejected
+        assert_eq!(text, "a");
+
+        let (result, text, _) = generate_forced(1);
+        assert_eq!(result.unwrap(), "bb");
+        // The text of the rejected token is never sent
+        assert_eq!(text, "bb");
+    }
+}
@@ -0,0 +1,311 @@
+use std::sync::{Arc, Mutex};
+
+use llm_samplers::prelude::{Logits, Sampler};
+use tokenizers::tokenizer::Tokenizer;
+
+use crate::log_probs::StopOnBuffer;
+use crate::{GeneratedToken, GenerationControl, ModelFeedback, StopReason, TokenOutputStream};
+
+/// The state of a text generation that samples one token at a time.
+///
+/// The state does not run the model itself. Instead, every call to [`TextGenerationState::step`] takes the logits for the next token and returns the token that needs to be fed into the model next. This makes it possible to drive many generations with a single batched forward pass with [`crate::SyncModel::feed_batch`].
+pub struct TextGenerationState {
+    tokenizer: Arc<Tokenizer>,
+    text_stream: TokenOutputStream,
+    sampler: Arc<Mutex<dyn Sampler>>,
+    max_tokens: Option<u32>,
+    tokens_generated: u32,
+    stop_on: Option<String>,
+    stop_on_buffer: StopOnBuffer,
+    ready: Vec<GeneratedToken>,
+    stop_token: u32,
+    finished: bool,
+    control: Option<GenerationControl>,
+    stop_reason: Option<StopReason>,
+}
+
+impl TextGenerationState {
+    /// Create a new generation that continues after the prompt tokens.
+    pub fn new(
+        tokenizer: Arc<Tokenizer>,
+        prompt_tokens: &[u32],
+        max_tokens: Option<u32>,
+        stop_on: Option<&str>,
+        sampler: Arc<Mutex<dyn Sampler>>,
+        stop_token: u32,
+    ) -> anyhow::Result<Self> {
+        let mut text_stream = TokenOutputStream::new(tokenizer.clone());
+        for &token in prompt_tokens {
+            text_stream.next_token(token)?;
+        }
+
+        Ok(Self {
+            tokenizer,
+            text_stream,
+            sampler,
+            max_tokens,
+            tokens_generated: 0,
+            stop_on: stop_on.map(|s| s.to_string()),
+            stop_on_buffer: StopOnBuffer::new(stop_on),
+            ready: Vec::new(),
+            stop_token,
+            finished: false,
+            control: None,
+            stop_reason: None,
+        })
+    }
+
+    /// Stop the generation at the next token once the control is interrupted, and report why the generation stopped to the control.
+    pub fn with_control(mut self, control: Option<GenerationControl>) -> Self {
+        self.control = control;
+        self
+    }
+
+    /// Check if the generation has finished.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Get the reason the generation stopped or `None` if it is still running.
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.stop_reason
+    }
+
+    /// Sample the next token from the logits and send any new text to the on_token callback.
+    ///
+    /// Returns the token that needs to be fed into the model to get the logits for the next step, or `None` if the generation is finished.
+    pub fn step(
+        &mut self,
+        logits: &[f32],
+        mut on_token: impl FnMut(String) -> anyhow::Result<ModelFeedback>,
+    ) -> anyhow::Result<Option<u32>> {
+        self.step_tokens(logits, None, |token| {
+            // Tokens that only contain part of a character don't add any text yet
+            if token.text.is_empty() {
+                return Ok(ModelFeedback::Continue);
+            }
+            on_token(token.text)
+        })
+    }
+
+    /// Sample the next token from the logits like [`TextGenerationState::step`], but send each token to the on_token callback along with its log probability and the `top_n` most likely alternatives.
+    pub fn step_with_log_probs(
+        &mut self,
+        logits: &[f32],
+        top_n: usize,
+        on_token: impl FnMut(GeneratedToken) -> anyhow::Result<ModelFeedback>,
+    ) -> anyhow::Result<Option<u32>> {
+        self.step_tokens(logits, Some(top_n), on_token)
+    }
+
+    fn step_tokens(
+        &mut self,
+        logits: &[f32],
+        top_n: Option<usize>,
+        mut on_token: impl FnMut(GeneratedToken) -> anyhow::Result<ModelFeedback>,
+    ) -> anyhow::Result<Option<u32>> {
+        if self.finished {
+            return Ok(None);
+        }
+        if let Some(reason) = self
+            .control
+            .as_ref()
+            .and_then(|control| control.interrupted())
+        {
+            self.stop_reason = Some(reason);
+            self.finish(&mut on_token)?;
+            return Ok(None);
+        }
+        let result = self.step_inner(logits, top_n, &mut on_token);
+        match &result {
+            Ok(Some(_)) => {}
+            Ok(None) => self.finish(&mut on_token)?,
+            Err(_) => self.fail(),
+        }
+        result
+    }
+
+    /// Stop the generation because the model failed. The control records [`StopReason::Error`] unless the generation was already interrupted. This does nothing if the generation already finished.
+    pub fn fail(&mut self) {
+        if std::mem::replace(&mut self.finished, true) {
+            return;
+        }
+        let interrupted = self
+            .control
+            .as_ref()
+            .and_then(|control| control.interrupted());
+        let reason = *self
+            .stop_reason
+            .insert(interrupted.unwrap_or(StopReason::Error));
+        if let Some(control) = &self.control {
+            control.finish(reason);
+        }
+    }
+
+    fn step_inner(
+        &mut self,
+        logits: &[f32],
+        top_n: Option<usize>,
+        on_token: &mut impl FnMut(GeneratedToken) -> anyhow::Result<ModelFeedback>,
+    ) -> anyhow::Result<Option<u32>> {
+        let sampled_logits = Logits::try_from_iter_top_k(logits.iter().copied(), 512)?;
+        let new_token = self.text_stream.sample_token(
+            &mut self.sampler,
+            sampled_logits,
+            self.stop_on.as_deref(),
+        )?;
+        if new_token == self.stop_token {
+            tracing::trace!("Stopping on stop token");
+            return Ok(None);
+        }
+        let text = self.text_stream.next_token(new_token)?.unwrap_or_default();
+        let token = match top_n {
+            Some(top_n) => {
+                GeneratedToken::from_logits(new_token, text, logits, top_n, &self.tokenizer)?
+            }
+            None => GeneratedToken {
+                text,
+                token_id: Some(new_token),
+                log_prob: None,
+                top_alternatives: Vec::new(),
+            },
+        };
+        // Tokens that could be the start of the stop string are held back until we know if the stop string was generated
+        let found_stop_on = self.stop_on_buffer.push(token, &mut self.ready);
+        for token in self.ready.drain(..) {
+            if let ModelFeedback::Stop = on_token(token)? {
+                return Ok(None);
+            }
+        }
+        if found_stop_on {
+            return Ok(None);
+        }
+        self.tokens_generated += 1;
+        if let Some(max_tokens) = self.max_tokens {
+            if self.tokens_generated >= max_tokens {
+                self.stop_reason = Some(StopReason::MaxLength);
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(new_token))
+    }
+
+    fn finish(
+        &mut self,
+        on_token: &mut impl FnMut(GeneratedToken) -> anyhow::Result<ModelFeedback>,
+    ) -> anyhow::Result<()> {
+        self.finished = true;
+        // Every other way to stop means the model finished on its own, unless the output stream was dropped
+        let interrupted = self
+            .control
+            .as_ref()
+            .and_then(|control| control.interrupted());
+        let reason = *self
+            .stop_reason
+            .get_or_insert(interrupted.unwrap_or(StopReason::Finished));
+        if let Some(control) = &self.control {
+            control.finish(reason);
+        }
+
+        // Flush any tokens that were held back while checking for the stop string
+        self.stop_on_buffer.flush(&mut self.ready);
+        for token in self.ready.drain(..) {
+            if let ModelFeedback::Stop = on_token(token)? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::{one_hot, MockModel};
+    use crate::{GenerationParameters, ModelFeedback, SamplingStrategy, SyncModel, SyncModelExt};
+
+    use super::*;
+
+    /// A model that always continues with "abcabc..."
+    fn model() -> MockModel {
+        MockModel::new(&["a", "b", "c", "<eos>"], |tokens| {
+            one_hot(4, tokens.last().map_or(0, |last| (last + 1) % 3))
+        })
+    }
+
+    fn sampler() -> Arc<Mutex<dyn Sampler>> {
+        Arc::new(Mutex::new(
+            GenerationParameters::default()
+                .with_repetition_penalty(1.0)
+                .with_sampling_strategy(SamplingStrategy::Greedy)
+                .sampler(),
+        ))
+    }
+
+    #[test]
+    fn log_probs_follow_the_text_generation() {
+        let model = model();
+        for (stop_on, expected) in [(None, "bcabca"), (Some("CA"), "b")] {
+            let mut text = String::new();
+            let mut session = model.new_session().unwrap();
+            model
+                .stream_text_with_sampler(&mut session, "a", Some(6), stop_on, sampler(), |token| {
+                    text += &token;
+                    Ok(ModelFeedback::Continue)
+                })
+                .unwrap();
+            assert_eq!(text, expected);
+
+            let mut tokens = Vec::new();
+            let mut session = model.new_session().unwrap();
+            model
+                .stream_text_with_log_probs(
+                    &mut session,
+                    "a",
+                    Some(6),
+                    stop_on,
+                    sampler(),
+                    2,
+                    |token| {
+                        tokens.push(token);
+                        Ok(ModelFeedback::Continue)
+                    },
+                )
+                .unwrap();
+            let log_prob_text: String = tokens.iter().map(|token| token.text.as_str()).collect();
+            assert_eq!(log_prob_text, expected);
+            for token in tokens {
+                assert!(token.probability().unwrap() > 0.99);
+                assert_eq!(token.top_alternatives.len(), 2);
+                assert_eq!(token.top_alternatives[0].token_id, token.token_id);
+            }
+        }
+    }
+
+    #[test]
+    fn generations_report_why_they_stopped() {
+        let model = model();
+        let generate = |stop_on: Option<&str>, fail: bool| {
+            let control = GenerationControl::default();
+            let mut session = model.new_session().unwrap();
+            let result = model.stream_text_with_control(
+                &mut session,
+                "a",
+                Some(6),
+                stop_on,
+                sampler(),
+                Some(control.clone()),
+                |_| {
+                    anyhow::ensure!(!fail, "The output was closed");
+                    Ok(ModelFeedback::Continue)
+                },
+            );
+            assert_eq!(result.is_err(), fail);
+            control.stop_reason()
+        };
+        assert_eq!(generate(None, false), Some(StopReason::MaxLength));
+        assert_eq!(generate(Some("ca"), false), Some(StopReason::Finished));
+        assert_eq!(generate(None, true), Some(StopReason::Error));
+    }
+}
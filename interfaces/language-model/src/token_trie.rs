@@ -0,0 +1,356 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock, Weak},
+};
+
+use kalosm_sample::{ParseStatus, Parser};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use tokenizers::tokenizer::Tokenizer;
+
+/// A prefix tree of the text of every token in a vocabulary.
+///
+/// Walking a parser state over the tree finds every token the parser accepts with one parse per edge instead of one parse per token. Any subtree the parser rejects is skipped entirely, which makes constraints that reject most tokens cheap to check.
+pub(crate) struct TokenTrie {
+    nodes: Vec<TrieNode>,
+    vocab_size: usize,
+}
+
+struct TrieNode {
+    /// The text on the edge from the parent node to this node. Edges always end on a character boundary.
+    text: String,
+    children: Vec<usize>,
+    /// The tokens whose text ends at this node.
+    tokens: Vec<u32>,
+}
+
+/// The trie for each tokenizer, paired with a weak reference to the tokenizer.
+type TokenizerTries = Vec<(Weak<Tokenizer>, Arc<TokenTrie>)>;
+
+/// The tries for each tokenizer that is still alive.
+static TOKEN_TRIES: OnceLock<Mutex<TokenizerTries>> = OnceLock::new();
+
+impl TokenTrie {
+    /// Get the trie for a tokenizer. The trie is built the first time it is requested and shared until the tokenizer is dropped.
+    pub(crate) fn for_tokenizer(tokenizer: &Arc<Tokenizer>) -> Arc<Self> {
+        let mut tries = TOKEN_TRIES.get_or_init(Default::default).lock().unwrap();
+        tries.retain(|(tokenizer, _)| tokenizer.strong_count() > 0);
+        if let Some((_, trie)) = tries
+            .iter()
+            .find(|(cached, _)| std::ptr::eq(cached.as_ptr(), Arc::as_ptr(tokenizer)))
+        {
+            return trie.clone();
+        }
+        let trie = Arc::new(Self::new(tokenizer));
+        tries.push((Arc::downgrade(tokenizer), trie.clone()));
+        trie
+    }
+
+    fn new(tokenizer: &Tokenizer) -> Self {
+        // Tokens decode differently at the start of the text, so decode every token after an anchor token the same way the token stream does
+        let anchor = tokenizer
+            .encode("a", false)
+            .ok()
+            .and_then(|encoding| encoding.get_ids().last().copied());
+        let anchor_text = anchor
+            .and_then(|anchor| tokenizer.decode(&[anchor], false).ok())
+            .unwrap_or_default();
+        let texts = (0..tokenizer.get_vocab_size(true) as u32)
+            .into_par_iter()
+            .map(|token| {
+                let tokens: Vec<u32> = anchor.into_iter().chain([token]).collect();
+                let text = tokenizer.decode(&tokens, false).ok()?;
+                let text = text.strip_prefix(&anchor_text)?;
+                // The token stream only accepts tokens that end in a complete ascii character
+                text.chars()
+                    .last()
+                    .is_some_and(|char| char.is_ascii())
+                    .then(|| text.to_string())
+            })
+            .collect();
+        Self::from_texts(texts)
+    }
+
+    /// Build a trie from the text of each token. Tokens without text are never valid.
+    fn from_texts(texts: Vec<Option<String>>) -> Self {
+        let vocab_size = texts.len();
+        let mut entries: Vec<(&str, u32)> = texts
+            .iter()
+            .enumerate()
+            .filter_map(|(token, text)| Some((text.as_deref()?, token as u32)))
+            .collect();
+        entries.sort_unstable();
+        let mut trie = Self {
+            nodes: Vec::new(),
+            vocab_size,
+        };
+        trie.build(String::new(), &entries, 0);
+        trie
+    }
+
+    /// Add a node for the entries that all share the first `depth` bytes. Returns the index of the node.
+    fn build(&mut self, text: String, entries: &[(&str, u32)], depth: usize) -> usize {
+        let index = self.nodes.len();
+        let ends_here = entries
+            .iter()
+            .take_while(|(text, _)| text.len() == depth)
+            .count();
+        self.nodes.push(TrieNode {
+            text,
+            children: Vec::new(),
+            tokens: entries[..ends_here]
+                .iter()
+                .map(|(_, token)| *token)
+                .collect(),
+        });
+
+        // Group the rest of the entries by their next character
+        let mut rest = &entries[ends_here..];
+        while let Some((first, _)) = rest.first() {
+            let next_char = first[depth..].chars().next().unwrap();
+            let group_len = rest
+                .iter()
+                .take_while(|(text, _)| text[depth..].starts_with(next_char))
+                .count();
+            let (group, remaining) = rest.split_at(group_len);
+            rest = remaining;
+
+            // The entries are sorted, so the prefix shared by the group is the prefix shared by the first and last entry
+            let last = group[group.len() - 1].0;
+            let mut shared = depth
+                + first[depth..]
+                    .bytes()
+                    .zip(last[depth..].bytes())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+            while !first.is_char_boundary(shared) {
+                shared -= 1;
+            }
+            let child = self.build(first[depth..shared].to_string(), group, shared);
+            self.nodes[index].children.push(child);
+        }
+
+        index
+    }
+
+    /// Find every token the parser accepts from the state. Tokens outside of the vocabulary of the tokenizer are always allowed so the caller can check them another way.
+    pub(crate) fn valid_tokens<P: Parser>(&self, parser: &P, state: &P::PartialState) -> TokenMask {
+        let mut mask = vec![false; self.vocab_size];
+        self.walk(0, parser, state, &mut mask);
+        TokenMask(mask.into())
+    }
+
+    fn walk<P: Parser>(&self, node: usize, parser: &P, state: &P::PartialState, mask: &mut [bool]) {
+        for &child in &self.nodes[node].children {
+            match parser.parse(state, self.nodes[child].text.as_bytes()) {
+                Err(_) => {}
+                // Once the parser finishes, it ignores any text after that point
+                Ok(ParseStatus::Finished { .. }) => self.allow_subtree(child, mask),
+                Ok(ParseStatus::Incomplete { new_state, .. }) => {
+                    for &token in &self.nodes[child].tokens {
+                        mask[token as usize] = true;
+                    }
+                    self.walk(child, parser, &new_state, mask);
+                }
+            }
+        }
+    }
+
+    fn allow_subtree(&self, node: usize, mask: &mut [bool]) {
+        for &token in &self.nodes[node].tokens {
+            mask[token as usize] = true;
+        }
+        for &child in &self.nodes[node].children {
+            self.allow_subtree(child, mask);
+        }
+    }
+}
+
+/// The set of tokens a parser accepts from one state.
+#[derive(Clone)]
+pub(crate) struct TokenMask(Arc<[bool]>);
+
+impl TokenMask {
+    /// Check if the token may be valid.
+    pub(crate) fn allows(&self, token: u32) -> bool {
+        self.0.get(token as usize).copied().unwrap_or(true)
+    }
+}
+
+/// A cache of the token masks for parser states that have a [`Parser::state_key`].
+#[derive(Default)]
+pub(crate) struct TokenMaskCache {
+    masks: HashMap<u64, TokenMask>,
+}
+
+impl TokenMaskCache {
+    /// Check if the mask for the state is already cached.
+    pub(crate) fn contains<P: Parser>(&self, parser: &P, state: &P::PartialState) -> bool {
+        parser
+            .state_key(state)
+            .is_some_and(|key| self.masks.contains_key(&key))
+    }
+
+    /// Get the mask for the state, walking the trie if it isn't cached.
+    pub(crate) fn get<P: Parser>(
+        &mut self,
+        trie: &TokenTrie,
+        parser: &P,
+        state: &P::PartialState,
+    ) -> TokenMask {
+        let Some(key) = parser.state_key(state) else {
+            return trie.valid_tokens(parser, state);
+        };
+        self.masks
+            .entry(key)
+            .or_insert_with(|| trie.valid_tokens(parser, state))
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TokenOutputStream;
+    use kalosm_sample::{CreateParserState, LiteralParser, ParserExt, RegexParser, StringParser};
+    use tokenizers::decoders::byte_fallback::ByteFallback;
+    use tokenizers::decoders::fuse::Fuse;
+    use tokenizers::decoders::sequence::Sequence as DecoderSequence;
+    use tokenizers::decoders::strip::Strip;
+    use tokenizers::models::bpe::BPE;
+    use tokenizers::normalizers::{Prepend, Replace, Sequence as NormalizerSequence};
+    use tokenizers::DecoderWrapper;
+
+    fn trie(texts: &[&str]) -> TokenTrie {
+        TokenTrie::from_texts(texts.iter().map(|text| Some(text.to_string())).collect())
+    }
+
+    fn allowed(mask: &TokenMask, len: usize) -> Vec<u32> {
+        (0..len as u32)
+            .filter(|token| mask.allows(*token))
+            .collect()
+    }
+
+    #[test]
+    fn masks_invalid_tokens() {
+        let texts = ["a", "ab", "abc", "b", "\"", "\"a", "1", "12", "é1"];
+        let trie = trie(&texts);
+        let parser = RegexParser::new(r#""[ab]+""#).unwrap();
+        let state = parser.create_parser_state();
+        let mask = trie.valid_tokens(&parser, &state);
+        assert_eq!(allowed(&mask, texts.len()), [4, 5]);
+
+        let state = parser.parse(&state, b"\"").unwrap().unwrap_incomplete().0;
+        let mask = trie.valid_tokens(&parser, &state);
+        assert_eq!(allowed(&mask, texts.len()), [0, 1, 3]);
+    }
+
+    #[test]
+    fn finished_parsers_accept_any_remaining_text() {
+        let texts = ["1", "12", "123", "2", "x"];
+        let trie = trie(&texts);
+        let parser = LiteralParser::new("1").then(LiteralParser::new("2"));
+        let state = parser.create_parser_state();
+        let mask = trie.valid_tokens(&parser, &state);
+        assert_eq!(allowed(&mask, texts.len()), [0, 1, 2]);
+        // Tokens outside of the trie are left for the caller to check
+        assert!(mask.allows(texts.len() as u32));
+    }
+
+    /// A sentencepiece style tokenizer set up like the llama tokenizers. The decoder strips the space from the start of the text, and characters outside the vocabulary fall back to byte tokens.
+    fn sentencepiece_tokenizer() -> Tokenizer {
+        let mut vocab: Vec<String> = vec!["<unk>".into(), "<s>".into()];
+        vocab.extend((0..=255u8).map(|byte| format!("<0x{byte:02X}>")));
+        vocab.extend(
+            [
+                "▁", "a", "b", "c", "\"", "1", "2", "{", "}", ":", ",", "é", "▁a", "▁\"", "ab",
+                "abc", "\"a", "\"ab", "▁{", "12", "\":", "\",", "▁\"a", "b\"", "ca",
+            ]
+            .map(String::from),
+        );
+        let bpe = BPE::builder()
+            .vocab_and_merges(
+                vocab
+                    .into_iter()
+                    .enumerate()
+                    .map(|(id, text)| (text, id as u32))
+                    .collect(),
+                Vec::new(),
+            )
+            .unk_token("<unk>".into())
+            .byte_fallback(true)
+            .build()
+            .unwrap();
+        let mut tokenizer = Tokenizer::new(bpe);
+        tokenizer.with_normalizer(NormalizerSequence::new(vec![
+            Prepend::new("▁".to_string()).into(),
+            Replace::new(" ", "▁").unwrap().into(),
+        ]));
+        tokenizer.with_decoder(DecoderSequence::new(vec![
+            DecoderWrapper::Replace(Replace::new("▁", " ").unwrap()),
+            ByteFallback::new().into(),
+            Fuse::new().into(),
+            Strip::new(' ', 1, 0).into(),
+        ]));
+        tokenizer
+    }
+
+    #[test]
+    fn masks_match_checking_each_token() {
+        let tokenizer = Arc::new(sentencepiece_tokenizer());
+        let trie = TokenTrie::for_tokenizer(&tokenizer);
+        let vocab_size = tokenizer.get_vocab_size(true) as u32;
+
+        // Check each token the way generation does without a mask: decode it after the text so far and parse it
+        let mut stream = TokenOutputStream::new(tokenizer.clone());
+        let prompt = tokenizer.encode("a", false).unwrap();
+        stream.next_tokens(prompt.get_ids()).unwrap();
+        let check_each_token = |parser: &dyn Fn(&str) -> bool| -> Vec<u32> {
+            (0..vocab_size)
+                .filter(|token| {
+                    stream
+                        .peek_token(*token)
+                        .unwrap()
+                        .is_some_and(|text| parser(&text))
+                })
+                .collect()
+        };
+
+        let parser = RegexParser::new(r#"\{"[a-c]+": [12]+\}"#).unwrap();
+        let mut state = parser.create_parser_state();
+        for text in ["", "{", "{\"", "{\"ab", "{\"ab\": 1"] {
+            if !text.is_empty() {
+                state = parser
+                    .parse(&parser.create_parser_state(), text.as_bytes())
+                    .unwrap()
+                    .unwrap_incomplete()
+                    .0;
+            }
+            let mask = trie.valid_tokens(&parser, &state);
+            assert_eq!(
+                allowed(&mask, vocab_size as usize),
+                check_each_token(&|token| parser.parse(&state, token.as_bytes()).is_ok()),
+                "after {text:?}"
+            );
+        }
+
+        let parser = StringParser::new(1..=3);
+        let state = parser.create_parser_state();
+        let mask = trie.valid_tokens(&parser, &state);
+        assert_eq!(
+            allowed(&mask, vocab_size as usize),
+            check_each_token(&|token| parser.parse(&state, token.as_bytes()).is_ok())
+        );
+    }
+
+    #[test]
+    fn caches_masks_by_state_key() {
+        let trie = trie(&["a", "b"]);
+        let parser = LiteralParser::new("ab");
+        let state = parser.create_parser_state();
+        let mut cache = TokenMaskCache::default();
+        assert!(!cache.contains(&parser, &state));
+        let mask = cache.get(&trie, &parser, &state);
+        assert_eq!(allowed(&mask, 2), [0]);
+        assert!(cache.contains(&parser, &state));
+    }
+}
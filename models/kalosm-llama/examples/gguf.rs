@@ -0,0 +1,24 @@
+use kalosm::language::*;
+
+#[tokio::main]
+async fn main() {
+    // Any gguf file with an embedded tokenizer and chat template works without a preset or a separate tokenizer file
+    let model = Llama::builder()
+        .with_source(LlamaSource::gguf(FileSource::huggingface(
+            "bartowski/Llama-3.2-1B-Instruct-GGUF".to_string(),
+            "main".to_string(),
+            "Llama-3.2-1B-Instruct-Q4_K_M.gguf".to_string(),
+        )))
+        .build()
+        .await
+        .unwrap();
+
+    let mut chat = Chat::builder(model)
+        .with_system_prompt("The assistant will act like a pirate")
+        .build();
+
+    chat.add_message("What is the capital of France?")
+        .to_std_out()
+        .await
+        .unwrap();
+}
@@ -0,0 +1,183 @@
+use crate::raw::cache::BatchCache;
+use crate::{InferenceSettings, LlamaModel, LlamaSession};
+use kalosm_language_model::{ModelFeedback, SyncModel, TextGenerationState};
+use llm_samplers::types::Sampler;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// A set of generations that are running at the same time. Every step feeds the next token of all running generations into the model in a single batched forward pass.
+#[derive(Default)]
+pub(crate) struct GenerationBatch {
+    generations: Vec<BatchedGeneration>,
+    /// The padded keys and values of every running generation. This is written back to the sessions and cleared whenever generations join or leave the batch.
+    cache: Option<BatchCache>,
+    logits: Vec<Vec<f32>>,
+}
+
+struct BatchedGeneration {
+    session: LlamaSession,
+    state: TextGenerationState,
+    sender: UnboundedSender<String>,
+    next_token: u32,
+}
+
+fn send_token(
+    sender: &UnboundedSender<String>,
+) -> impl FnMut(String) -> anyhow::Result<ModelFeedback> + '_ {
+    |token| {
+        sender
+            .send(token)
+            .map_err(|_| anyhow::anyhow!("Failed to send token to output channel"))
+            .map(|_| ModelFeedback::Continue)
+    }
+}
+
+impl GenerationBatch {
+    /// Check if there are no running generations.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.generations.is_empty()
+    }
+
+    /// Feed the prompt of a new generation into the model and add it to the batch.
+    pub(crate) fn start(
+        &mut self,
+        model: &LlamaModel,
+        settings: InferenceSettings,
+        sampler: Arc<Mutex<dyn Sampler>>,
+        sender: UnboundedSender<String>,
+    ) -> anyhow::Result<()> {
+        let InferenceSettings {
+            prompt,
+            sample_len,
+            stop_on,
+            control,
+        } = settings;
+
+        let tokenizer = model.tokenizer();
+        let tokens = tokenizer
+            .encode(prompt.as_str(), false)
+            .map_err(anyhow::Error::msg)?;
+        let tokens = tokens.get_ids();
+
+        let mut session = model.new_session()?;
+        let mut logits = Vec::new();
+        model.feed_tokens(&mut session, tokens, &mut logits)?;
+
+        let mut state = TextGenerationState::new(
+            tokenizer,
+            tokens,
+            Some(sample_len as u32),
+            stop_on.as_deref(),
+            sampler,
+            model.stop_token()?,
+        )?
+        .with_control(control);
+        if let Some(next_token) = state.step(&logits, send_token(&sender))? {
+            self.write_back_cache();
+            self.generations.push(BatchedGeneration {
+                session,
+                state,
+                sender,
+                next_token,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Generate the next token for every running generation. Generations that finish are removed from the batch.
+    ///
+    /// If a generation fails, only that generation is stopped and its control records the error.
+    pub(crate) fn step(&mut self, model: &LlamaModel) {
+        if self.generations.is_empty() {
+            return;
+        }
+
+        let tokens: Vec<_> = self
+            .generations
+            .iter()
+            .map(|generation| generation.next_token)
+            .collect();
+        let mut sessions: Vec<_> = self
+            .generations
+            .iter_mut()
+            .map(|generation| &mut generation.session)
+            .collect();
+        let logits: Vec<anyhow::Result<Vec<f32>>> = match model.feed_batch_cached(
+            &mut self.cache,
+            &mut sessions,
+            &tokens,
+            &mut self.logits,
+        ) {
+            Ok(()) => self.logits.drain(..).map(Ok).collect(),
+            Err(err) => {
+                // The batched forward pass doesn't tell us which generation failed. Run each generation on its own instead so only the generations that fail are stopped
+                tracing::error!(
+                    "Error running the batch, retrying each generation on its own: {err}"
+                );
+                if let Err(err) = LlamaModel::write_back_batch(&mut self.cache, &mut sessions) {
+                    self.fail_all(err);
+                    return;
+                }
+                sessions
+                    .iter_mut()
+                    .zip(&tokens)
+                    .map(|(session, &token)| {
+                        let mut logits = Vec::new();
+                        model.feed_tokens(session, &[token], &mut logits)?;
+                        Ok(logits)
+                    })
+                    .collect()
+            }
+        };
+
+        let mut running = Vec::with_capacity(self.generations.len());
+        for (generation, logits) in self.generations.iter_mut().zip(logits) {
+            let next_token = logits.and_then(|logits| {
+                generation
+                    .state
+                    .step(&logits, send_token(&generation.sender))
+            });
+            match next_token {
+                Ok(Some(next_token)) => {
+                    generation.next_token = next_token;
+                    running.push(true);
+                }
+                Ok(None) => running.push(false),
+                Err(err) => {
+                    tracing::error!("Error generating text: {err}");
+                    generation.state.fail();
+                    running.push(false);
+                }
+            }
+        }
+
+        // The generations that keep running need their own keys and values before the batch changes
+        if running.contains(&false) {
+            self.write_back_cache();
+            let mut running = running.into_iter();
+            self.generations.retain(|_| running.next().unwrap_or(false));
+        }
+    }
+
+    /// Copy the keys and values of the batch back into the session of each generation. If that fails, every running generation is stopped.
+    fn write_back_cache(&mut self) {
+        let mut sessions: Vec<_> = self
+            .generations
+            .iter_mut()
+            .map(|generation| &mut generation.session)
+            .collect();
+        if let Err(err) = LlamaModel::write_back_batch(&mut self.cache, &mut sessions) {
+            self.fail_all(err);
+        }
+    }
+
+    /// Stop every running generation with an error.
+    fn fail_all(&mut self, err: anyhow::Error) {
+        tracing::error!("Error running the batch, stopping every generation: {err}");
+        self.cache = None;
+        for mut generation in self.generations.drain(..) {
+            generation.state.fail();
+        }
+    }
+}
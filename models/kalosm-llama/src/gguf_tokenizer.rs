@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Context};
+use candle_core::quantized::gguf_file::Value;
+use kalosm_language_model::ChatMarkers;
+use tokenizers::decoders::byte_fallback::ByteFallback;
+use tokenizers::decoders::fuse::Fuse;
+use tokenizers::decoders::sequence::Sequence as DecoderSequence;
+use tokenizers::decoders::strip::Strip;
+use tokenizers::models::bpe::BPE;
+use tokenizers::normalizers::{Prepend, Replace, Sequence as NormalizerSequence};
+use tokenizers::pre_tokenizers::byte_level::ByteLevel;
+use tokenizers::pre_tokenizers::sequence::Sequence as PreTokenizerSequence;
+use tokenizers::pre_tokenizers::split::{Split, SplitPattern};
+use tokenizers::processors::template::TemplateProcessing;
+use tokenizers::{AddedToken, DecoderWrapper, SplitDelimiterBehavior, Tokenizer};
+
+/// The split pattern used by the llama 3 tokenizer.
+const LLAMA3_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+
+/// The split pattern used by the qwen 2 tokenizer.
+const QWEN2_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+
+// The token types gguf files store in `tokenizer.ggml.token_type`
+const CONTROL_TOKEN: i32 = 3;
+const USER_DEFINED_TOKEN: i32 = 4;
+
+/// Build a tokenizer from the `tokenizer.ggml.*` metadata in a gguf file.
+pub(crate) fn tokenizer_from_gguf(metadata: &HashMap<String, Value>) -> anyhow::Result<Tokenizer> {
+    let get = |key: &str| {
+        metadata.get(key).ok_or_else(|| {
+            anyhow!("The gguf file does not contain a tokenizer: cannot find {key} in metadata")
+        })
+    };
+
+    let tokens = get("tokenizer.ggml.tokens")?
+        .to_vec()?
+        .iter()
+        .map(|token| token.to_string().cloned())
+        .collect::<candle_core::Result<Vec<_>>>()?;
+    let token_types = match metadata.get("tokenizer.ggml.token_type") {
+        Some(types) => types
+            .to_vec()?
+            .iter()
+            .map(|ty| ty.to_i32())
+            .collect::<candle_core::Result<Vec<_>>>()?,
+        None => Vec::new(),
+    };
+    let vocab: HashMap<String, u32> = tokens
+        .iter()
+        .enumerate()
+        .map(|(id, token)| (token.clone(), id as u32))
+        .collect();
+
+    let tokenizer_model = get("tokenizer.ggml.model")?.to_string()?;
+    let mut tokenizer = match tokenizer_model.as_str() {
+        // Sentencepiece tokenizers used by llama 1 and 2, mistral and many other models
+        "llama" => {
+            let scores = match metadata.get("tokenizer.ggml.scores") {
+                Some(scores) => scores
+                    .to_vec()?
+                    .iter()
+                    .map(|score| score.to_f32())
+                    .collect::<candle_core::Result<Vec<_>>>()?,
+                None => vec![0.; tokens.len()],
+            };
+            let merges = merges_from_scores(&tokens, &scores, &vocab);
+            let mut bpe = BPE::builder()
+                .vocab_and_merges(vocab.clone(), merges)
+                .fuse_unk(true)
+                .byte_fallback(true);
+            if let Some(unknown) = metadata
+                .get("tokenizer.ggml.unknown_token_id")
+                .and_then(|id| id.to_u32().ok())
+                .and_then(|id| tokens.get(id as usize))
+            {
+                bpe = bpe.unk_token(unknown.clone());
+            }
+            let bpe = bpe.build().map_err(anyhow::Error::msg)?;
+
+            let mut tokenizer = Tokenizer::new(bpe);
+            tokenizer.with_normalizer(NormalizerSequence::new(vec![
+                Prepend::new("▁".to_string()).into(),
+                Replace::new(" ", "▁").map_err(anyhow::Error::msg)?.into(),
+            ]));
+            tokenizer.with_decoder(DecoderSequence::new(vec![
+                DecoderWrapper::Replace(Replace::new("▁", " ").map_err(anyhow::Error::msg)?),
+                ByteFallback::new().into(),
+                Fuse::new().into(),
+                Strip::new(' ', 1, 0).into(),
+            ]));
+            tokenizer
+        }
+        // Byte level BPE tokenizers used by llama 3, qwen and other models
+        "gpt2" => {
+            let merges = get("tokenizer.ggml.merges")?
+                .to_vec()?
+                .iter()
+                .map(|merge| {
+                    let merge = merge.to_string()?;
+                    merge
+                        .split_once(' ')
+                        .map(|(left, right)| (left.to_string(), right.to_string()))
+                        .ok_or_else(|| anyhow!("Invalid merge in gguf tokenizer: {merge}"))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let bpe = BPE::builder()
+                .vocab_and_merges(vocab.clone(), merges)
+                .build()
+                .map_err(anyhow::Error::msg)?;
+
+            let mut tokenizer = Tokenizer::new(bpe);
+            let pattern = match metadata
+                .get("tokenizer.ggml.pre")
+                .and_then(|pre| pre.to_string().ok())
+                .map(|pre| pre.as_str())
+            {
+                Some("llama3" | "llama-bpe" | "smaug-bpe") => Some(LLAMA3_PATTERN),
+                Some("qwen2") => Some(QWEN2_PATTERN),
+                Some("default" | "gpt-2") | None => None,
+                Some(pre) => {
+                    tracing::warn!("Unknown gguf pre-tokenizer {pre}, falling back to the gpt2 split pattern. Text may be split into different tokens than the model was trained on");
+                    None
+                }
+            };
+            match pattern {
+                Some(pattern) => {
+                    let split = Split::new(
+                        SplitPattern::Regex(pattern.to_string()),
+                        SplitDelimiterBehavior::Isolated,
+                        false,
+                    )
+                    .map_err(anyhow::Error::msg)?;
+                    tokenizer.with_pre_tokenizer(PreTokenizerSequence::new(vec![
+                        split.into(),
+                        ByteLevel::new(false, true, false).into(),
+                    ]));
+                }
+                // Fall back to the original gpt2 split pattern
+                None => {
+                    tokenizer.with_pre_tokenizer(ByteLevel::new(false, true, true));
+                }
+            }
+            tokenizer.with_decoder(ByteLevel::default());
+            tokenizer
+        }
+        other => bail!("Unsupported gguf tokenizer model: {other}"),
+    };
+
+    // Control tokens like <s> or <|im_start|> are never split by the tokenizer
+    let (special, added): (Vec<_>, Vec<_>) = token_types
+        .iter()
+        .zip(&tokens)
+        .filter(|(ty, _)| matches!(**ty, CONTROL_TOKEN | USER_DEFINED_TOKEN))
+        .partition(|(ty, _)| **ty == CONTROL_TOKEN);
+    let special: Vec<_> = special
+        .into_iter()
+        .map(|(_, token)| AddedToken::from(token.clone(), true))
+        .collect();
+    let added: Vec<_> = added
+        .into_iter()
+        .map(|(_, token)| AddedToken::from(token.clone(), false))
+        .collect();
+    tokenizer.add_special_tokens(&special);
+    tokenizer.add_tokens(&added);
+
+    // Sentencepiece tokenizers start every sequence with the bos token unless the gguf file says otherwise
+    let add_bos_token = metadata
+        .get("tokenizer.ggml.add_bos_token")
+        .and_then(|add| add.to_bool().ok())
+        .unwrap_or(tokenizer_model == "llama");
+    let bos_token = metadata
+        .get("tokenizer.ggml.bos_token_id")
+        .and_then(|id| id.to_u32().ok())
+        .and_then(|id| Some((tokens.get(id as usize)?, id)));
+    if let (true, Some((bos, bos_id))) = (add_bos_token, bos_token) {
+        let post_processor = TemplateProcessing::builder()
+            .try_single(vec![format!("{bos}:0"), "$A:0".to_string()])
+            .map_err(anyhow::Error::msg)?
+            .try_pair(vec![
+                format!("{bos}:0"),
+                "$A:0".to_string(),
+                format!("{bos}:1"),
+                "$B:1".to_string(),
+            ])
+            .map_err(anyhow::Error::msg)?
+            .special_tokens(vec![(bos.clone(), bos_id)])
+            .build()?;
+        tokenizer.with_post_processor(post_processor);
+    }
+
+    Ok(tokenizer)
+}
+
+/// Sentencepiece gguf files store a score for each token instead of merges. Recreate the merges by merging the pair of tokens that make up each token in order of the score of the merged token.
+fn merges_from_scores(
+    tokens: &[String],
+    scores: &[f32],
+    vocab: &HashMap<String, u32>,
+) -> Vec<(String, String)> {
+    let mut merges = Vec::new();
+    for (token, score) in tokens.iter().zip(scores) {
+        let mut local = Vec::new();
+        for (split, _) in token.char_indices().skip(1) {
+            let (left, right) = token.split_at(split);
+            if let (Some(left_id), Some(right_id)) = (vocab.get(left), vocab.get(right)) {
+                local.push((*left_id, *right_id, left, right, *score));
+            }
+        }
+        local.sort_by_key(|(left_id, right_id, ..)| (*left_id, *right_id));
+        merges.extend(local);
+    }
+    // Higher scores are merged first. The sort is stable so ties keep the order of the vocab
+    merges.sort_by(|a, b| b.4.total_cmp(&a.4));
+    merges
+        .into_iter()
+        .map(|(_, _, left, right, _)| (left.to_string(), right.to_string()))
+        .collect()
+}
+
+/// A jinja chat template embedded in a gguf file.
+pub(crate) struct GgufChatTemplate {
+    pub(crate) source: String,
+    pub(crate) bos_token: String,
+    pub(crate) eos_token: String,
+}
+
+/// Read the jinja chat template from the `tokenizer.chat_template` metadata in a gguf file.
+pub(crate) fn chat_template_from_gguf(
+    metadata: &HashMap<String, Value>,
+) -> Option<GgufChatTemplate> {
+    let source = metadata
+        .get("tokenizer.chat_template")?
+        .to_string()
+        .ok()?
+        .clone();
+    let special_token = |key: &str| {
+        let tokens = metadata.get("tokenizer.ggml.tokens")?.to_vec().ok()?;
+        let id = metadata.get(key)?.to_u32().ok()?;
+        tokens.get(id as usize)?.to_string().ok().cloned()
+    };
+    Some(GgufChatTemplate {
+        source,
+        bos_token: special_token("tokenizer.ggml.bos_token_id").unwrap_or_default(),
+        eos_token: special_token("tokenizer.ggml.eos_token_id").unwrap_or_default(),
+    })
+}
+
+/// Read the id of the token the model generates when it is done from the `tokenizer.ggml.eos_token_id` metadata in a gguf file.
+pub(crate) fn eos_token_id_from_gguf(metadata: &HashMap<String, Value>) -> Option<u32> {
+    metadata.get("tokenizer.ggml.eos_token_id")?.to_u32().ok()
+}
+
+/// Find the chat markers for a chat template from one of the common chat formats.
+pub(crate) fn chat_markers_from_template(template: &str) -> Option<ChatMarkers> {
+    let markers = if template.contains("<|im_start|>") {
+        ChatMarkers {
+            system_prompt_marker: "<|im_start|>system\n",
+            end_system_prompt_marker: "<|im_end|>",
+            user_marker: "<|im_start|>user\n",
+            end_user_marker: "<|im_end|>",
+            assistant_marker: "<|im_start|>assistant\n",
+            end_assistant_marker: "<|im_end|>",
+        }
+    } else if template.contains("<|start_header_id|>") {
+        ChatMarkers {
+            system_prompt_marker: "<|begin_of_text|><|start_header_id|>system<|end_header_id|>\n\n",
+            end_system_prompt_marker: "<|eot_id|>",
+            user_marker: "<|start_header_id|>user<|end_header_id|>\n\n",
+            end_user_marker: "<|eot_id|>",
+            assistant_marker: "<|start_header_id|>assistant<|end_header_id|>\n\n",
+            end_assistant_marker: "<|eot_id|>",
+        }
+    } else if template.contains("<start_of_turn>") {
+        // Gemma doesn't have a system role, so the system prompt is sent as a user message
+        ChatMarkers {
+            system_prompt_marker: "<start_of_turn>user\n",
+            end_system_prompt_marker: "<end_of_turn>",
+            user_marker: "<start_of_turn>user\n",
+            end_user_marker: "<end_of_turn>",
+            assistant_marker: "<start_of_turn>model\n",
+            end_assistant_marker: "<end_of_turn>",
+        }
+    } else if template.contains("<|user|>") && template.contains("<|end|>") {
+        ChatMarkers {
+            system_prompt_marker: "<|system|>\n",
+            end_system_prompt_marker: "<|end|>",
+            user_marker: "<|user|>\n",
+            end_user_marker: "<|end|>",
+            assistant_marker: "<|assistant|>\n",
+            end_assistant_marker: "<|end|>",
+        }
+    } else if template.contains("<|user|>") {
+        ChatMarkers {
+            system_prompt_marker: "<|system|>\n",
+            end_system_prompt_marker: "</s>",
+            user_marker: "<|user|>\n",
+            end_user_marker: "</s>",
+            assistant_marker: "<|assistant|>\n",
+            end_assistant_marker: "</s>",
+        }
+    } else if template.contains("[INST]") {
+        ChatMarkers {
+            system_prompt_marker: "<s>[INST] ",
+            end_system_prompt_marker: " [/INST]",
+            user_marker: "[INST] ",
+            end_user_marker: " [/INST]",
+            assistant_marker: "",
+            end_assistant_marker: "</s>",
+        }
+    } else {
+        return None;
+    };
+    Some(markers)
+}
+
+/// Read the metadata of a gguf file without loading any of the tensors.
+pub(crate) fn read_gguf_metadata(path: &std::path::Path) -> anyhow::Result<HashMap<String, Value>> {
+    let mut file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open the model file {}", path.display()))?;
+    let content = candle_core::quantized::gguf_file::Content::read(&mut file)?;
+    Ok(content.metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_follow_the_scores() {
+        let tokens: Vec<String> = ["a", "b", "c", "ab", "bc", "abc"]
+            .iter()
+            .map(|token| token.to_string())
+            .collect();
+        let scores = [0., 0., 0., -2., -1., -3.];
+        let vocab = tokens
+            .iter()
+            .enumerate()
+            .map(|(id, token)| (token.clone(), id as u32))
+            .collect();
+        let merges = merges_from_scores(&tokens, &scores, &vocab);
+        let merges: Vec<_> = merges
+            .iter()
+            .map(|(left, right)| (left.as_str(), right.as_str()))
+            .collect();
+        assert_eq!(merges, [("b", "c"), ("a", "b"), ("a", "bc"), ("ab", "c")]);
+    }
+
+    #[test]
+    fn chat_markers_match_common_templates() {
+        let end_assistant_marker =
+            |template: &str| chat_markers_from_template(template).map(|m| m.end_assistant_marker);
+        assert_eq!(
+            end_assistant_marker("{{ '<|im_start|>' + message['role'] }}"),
+            Some("<|im_end|>")
+        );
+        assert_eq!(
+            end_assistant_marker("{{ '<|start_header_id|>' + message['role'] }}"),
+            Some("<|eot_id|>")
+        );
+        assert_eq!(
+            end_assistant_marker("{{ '<start_of_turn>' + role }}"),
+            Some("<end_of_turn>")
+        );
+        assert_eq!(
+            end_assistant_marker("{{ '<|user|>' + content + '<|end|>' }}"),
+            Some("<|end|>")
+        );
+        assert_eq!(
+            end_assistant_marker("{{ '<|user|>\n' + content + eos_token }}"),
+            Some("</s>")
+        );
+        assert_eq!(
+            end_assistant_marker("{{ bos_token + '[INST] ' + content + ' [/INST]' }}"),
+            Some("</s>")
+        );
+        assert!(chat_markers_from_template("{{ content }}").is_none());
+    }
+
+    #[test]
+    fn sentencepiece_tokenizers_add_the_bos_token() {
+        let strings = |values: &[&str]| {
+            Value::Array(
+                values
+                    .iter()
+                    .map(|value| Value::String(value.to_string()))
+                    .collect(),
+            )
+        };
+        let mut metadata = HashMap::new();
+        metadata.insert(
+            "tokenizer.ggml.model".to_string(),
+            Value::String("llama".to_string()),
+        );
+        metadata.insert(
+            "tokenizer.ggml.tokens".to_string(),
+            strings(&["<unk>", "<s>", "</s>", "▁", "a", "▁a"]),
+        );
+        metadata.insert(
+            "tokenizer.ggml.token_type".to_string(),
+            Value::Array(vec![
+                Value::I32(2),
+                Value::I32(CONTROL_TOKEN),
+                Value::I32(CONTROL_TOKEN),
+                Value::I32(1),
+                Value::I32(1),
+                Value::I32(1),
+            ]),
+        );
+        metadata.insert("tokenizer.ggml.bos_token_id".to_string(), Value::U32(1));
+
+        let tokenizer = tokenizer_from_gguf(&metadata).unwrap();
+        let encode = |add_special_tokens| {
+            tokenizer
+                .encode("a", add_special_tokens)
+                .unwrap()
+                .get_ids()
+                .to_vec()
+        };
+        assert_eq!(encode(true), [1, 5]);
+        assert_eq!(encode(false), [5]);
+
+        metadata.insert(
+            "tokenizer.ggml.add_bos_token".to_string(),
+            Value::Bool(false),
+        );
+        let tokenizer = tokenizer_from_gguf(&metadata).unwrap();
+        assert_eq!(tokenizer.encode("a", true).unwrap().get_ids(), [5]);
+    }
+
+    #[test]
+    fn eos_token_is_read_from_metadata() {
+        let mut metadata = HashMap::new();
+        assert_eq!(eos_token_id_from_gguf(&metadata), None);
+        metadata.insert("tokenizer.ggml.eos_token_id".to_string(), Value::U32(7));
+        assert_eq!(eos_token_id_from_gguf(&metadata), Some(7));
+    }
+}
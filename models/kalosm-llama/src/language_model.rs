@@ -5,7 +5,7 @@ pub use crate::Llama;
 use crate::{InferenceSettings, Task};
 use crate::{LlamaBuilder, LlamaModel};
 use kalosm_common::ModelLoadingProgress;
-use kalosm_language_model::ChatMarkers;
+use kalosm_language_model::{ChatMarkers, ChatTemplate};
 use kalosm_language_model::{GenerationParameters, Model, ModelBuilder};
 use kalosm_streams::text_stream::ChannelTextStream;
 use tokenizers::Tokenizer;
@@ -22,7 +22,11 @@ impl ModelBuilder for LlamaBuilder {
     }
 
     fn requires_download(&self) -> bool {
-        !self.source.model.downloaded() || !self.source.tokenizer.downloaded()
+        !self.source.downloaded()
+            || self
+                .draft_source
+                .as_ref()
+                .is_some_and(|draft| !draft.downloaded())
     }
 }
 
@@ -60,8 +64,9 @@ impl Model for Llama {
         self.run(
             InferenceSettings::new(prompt)
                 .with_sample_len(max_length as usize)
-                .with_stop_on(generation_parameters.stop_on().map(|s| s.to_string())),
-            Arc::new(Mutex::new(generation_parameters.sampler())),
+                .with_stop_on(generation_parameters.stop_on().map(|s| s.to_string()))
+                .with_control(generation_parameters.control().cloned()),
+            Arc::new(Mutex::new(generation_parameters.seeded_sampler())),
         )
         .map(Into::into)
     }
@@ -86,4 +91,8 @@ impl Model for Llama {
     fn chat_markers(&self) -> Option<ChatMarkers> {
         self.chat_markers.deref().clone()
     }
+
+    fn chat_template(&self) -> Option<ChatTemplate> {
+        self.chat_template.clone()
+    }
 }
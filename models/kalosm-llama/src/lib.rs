@@ -32,22 +32,25 @@ extern crate intel_mkl_src;
 #[cfg(feature = "accelerate")]
 extern crate accelerate_src;
 
+mod batch;
+mod gguf_tokenizer;
 mod language_model;
 mod model;
+mod prefix_cache;
 mod raw;
 mod session;
 mod source;
 
+use crate::batch::GenerationBatch;
+use crate::model::DraftModel;
 pub use crate::model::LlamaModel;
 pub use crate::raw::cache::*;
 use crate::raw::Model;
 pub use crate::session::LlamaSession;
-use candle_core::{
-    quantized::{ggml_file, gguf_file},
-    Device,
-};
+use crate::source::LoadedTokenizer;
+use candle_core::Device;
 pub use kalosm_common::*;
-use kalosm_language_model::ChatMarkers;
+use kalosm_language_model::{ChatMarkers, ChatTemplate, GenerationControl, StopReason};
 use llm_samplers::types::Sampler;
 pub use source::*;
 use std::sync::{Arc, Mutex};
@@ -80,11 +83,14 @@ type SyncCallback = Box<
 >;
 
 /// A quantized Llama language model with support for streaming generation.
+///
+/// Generations that run at the same time are batched together so the model only needs one forward pass per token for all of them.
 #[derive(Clone)]
 pub struct Llama {
     task_sender: tokio::sync::mpsc::UnboundedSender<Task>,
     tokenizer: Arc<Tokenizer>,
     chat_markers: Arc<Option<ChatMarkers>>,
+    chat_template: Option<ChatTemplate>,
 }
 
 impl Drop for Llama {
@@ -132,6 +138,10 @@ impl Llama {
         device: Device,
         cache: LlamaCache,
         chat_markers: Option<ChatMarkers>,
+        chat_template: Option<ChatTemplate>,
+        draft: Option<DraftModel>,
+        prefix_cache_size: Option<usize>,
+        stop_token: Option<u32>,
     ) -> Self {
         let (task_sender, mut task_receiver) = tokio::sync::mpsc::unbounded_channel();
         let arc_tokenizer = Arc::new(tokenizer);
@@ -139,28 +149,63 @@ impl Llama {
         std::thread::spawn({
             let arc_tokenizer = arc_tokenizer.clone();
             move || {
-                let mut inner = LlamaModel::new(model, arc_tokenizer, device, cache);
+                let mut inner = LlamaModel::new(
+                    model,
+                    arc_tokenizer,
+                    device,
+                    cache,
+                    draft,
+                    prefix_cache_size,
+                    stop_token,
+                );
                 tokio::runtime::Builder::new_current_thread()
                     .enable_all()
                     .build()
                     .unwrap()
                     .block_on(async move {
-                        while let Some(task) = task_receiver.recv().await {
-                            match task {
-                                Task::Kill => break,
-                                Task::Infer {
-                                    settings,
-                                    sender,
-                                    sampler,
-                                } => {
-                                    if let Err(err) = inner._infer(settings, sampler, sender) {
-                                        eprintln!("Error: {}", err);
-                                    }
+                        let mut batch = GenerationBatch::default();
+                        'run: loop {
+                            // Wait for new work if nothing is running, otherwise pick up any tasks that arrived since the last step
+                            let mut tasks = Vec::new();
+                            if batch.is_empty() {
+                                match task_receiver.recv().await {
+                                    Some(task) => tasks.push(task),
+                                    None => break,
                                 }
-                                Task::RunSync { callback } => {
-                                    callback(&mut inner).await;
+                            }
+                            while let Ok(task) = task_receiver.try_recv() {
+                                tasks.push(task);
+                            }
+
+                            for task in tasks {
+                                match task {
+                                    Task::Kill => break 'run,
+                                    Task::Infer {
+                                        settings,
+                                        sender,
+                                        sampler,
+                                    } => {
+                                        let control = settings.control.clone();
+                                        // Speculative decoding runs each generation on its own
+                                        let result = if inner.has_draft() {
+                                            inner._infer(settings, sampler, sender)
+                                        } else {
+                                            batch.start(&inner, settings, sampler, sender)
+                                        };
+                                        if let Err(err) = result {
+                                            tracing::error!("Error starting generation: {err}");
+                                            if let Some(control) = control {
+                                                control.finish(StopReason::Error);
+                                            }
+                                        }
+                                    }
+                                    Task::RunSync { callback } => {
+                                        callback(&mut inner).await;
+                                    }
                                 }
                             }
+
+                            batch.step(&inner);
                         }
                     })
             }
@@ -169,6 +214,7 @@ impl Llama {
             task_sender,
             tokenizer: arc_tokenizer,
             chat_markers: chat_markers.into(),
+            chat_template,
         }
     }
 
@@ -198,8 +244,11 @@ impl Llama {
 #[derive(Default)]
 pub struct LlamaBuilder {
     source: source::LlamaSource,
+    draft_source: Option<source::LlamaSource>,
+    draft_tokens: Option<usize>,
     device: Option<Device>,
     flash_attn: bool,
+    prefix_cache_size: Option<usize>,
 }
 
 impl LlamaBuilder {
@@ -209,6 +258,33 @@ impl LlamaBuilder {
         self
     }
 
+    /// Set a smaller draft model to speed up generation with speculative decoding.
+    ///
+    /// The draft model guesses a few tokens ahead and the main model checks all of the guesses in a single forward pass. Every token is still sampled from the main model, so the output is the same as without a draft model. The draft model must use the same tokenizer as the main model.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder()
+    ///     .with_source(LlamaSource::qwen_2_5_7b_instruct())
+    ///     .with_draft_source(LlamaSource::qwen_2_5_0_5b_instruct())
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_draft_source(mut self, source: source::LlamaSource) -> Self {
+        self.draft_source = Some(source);
+        self
+    }
+
+    /// Set the number of tokens the draft model guesses before the main model checks them. (Defaults to 4)
+    pub fn with_draft_tokens(mut self, tokens: usize) -> Self {
+        self.draft_tokens = Some(tokens);
+        self
+    }
+
     /// Set whether to use Flash Attention.
     pub fn with_flash_attn(mut self, use_flash_attn: bool) -> Self {
         self.flash_attn = use_flash_attn;
@@ -221,6 +297,27 @@ impl LlamaBuilder {
         self
     }
 
+    /// Cache the attention state of up to `max_bytes` bytes of prompts and reuse it for new sessions that start with the same tokens. (Defaults to no cache)
+    ///
+    /// Every task run and new chat feeds its system prompt into a fresh session. With a prefix cache, the model only reads the shared start of those prompts once. The least recently used prompts are evicted when the cache is full.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder()
+    ///     // Keep up to 2GB of cached prompts
+    ///     .with_prefix_cache(2 * 1024 * 1024 * 1024)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_prefix_cache(mut self, max_bytes: usize) -> Self {
+        self.prefix_cache_size = Some(max_bytes);
+        self
+    }
+
     /// Get the device or the default device if not set.
     pub(crate) fn get_device(&self) -> anyhow::Result<Device> {
         match self.device.clone() {
@@ -229,6 +326,50 @@ impl LlamaBuilder {
         }
     }
 
+    /// Download and load the draft model if one is set.
+    pub(crate) async fn load_draft(
+        &self,
+        tokenizer: &Tokenizer,
+        device: &Device,
+        mut handler: impl FnMut(ModelLoadingProgress),
+    ) -> anyhow::Result<Option<DraftModel>> {
+        let Some(source) = &self.draft_source else {
+            return Ok(None);
+        };
+
+        let draft_tokenizer = match &source.tokenizer {
+            Some(tokenizer_source) => {
+                let tokenizer_source = format!("Draft Tokenizer ({})", tokenizer_source);
+                let mut create_progress =
+                    ModelLoadingProgress::downloading_progress(tokenizer_source);
+                source
+                    .tokenizer(|progress| handler(create_progress(progress)))
+                    .await?
+            }
+            None => None,
+        };
+
+        let model_source = format!("Draft Model ({})", source.model);
+        let mut create_progress = ModelLoadingProgress::downloading_progress(model_source);
+        let filename = source
+            .model(|progress| handler(create_progress(progress)))
+            .await?;
+        let draft_tokenizer = source.load_embedded(&filename, draft_tokenizer)?.tokenizer;
+        if draft_tokenizer.get_vocab(true) != tokenizer.get_vocab(true) {
+            anyhow::bail!(
+                "The draft model ({}) must use the same tokenizer as the main model ({})",
+                source.model,
+                self.source.model
+            );
+        }
+        let model = Model::from_file(&filename, source, device)?;
+
+        Ok(Some(DraftModel::new(
+            model,
+            self.draft_tokens.unwrap_or(4).max(1),
+        )))
+    }
+
     /// Build the model with a handler for progress as the download and loading progresses.
     ///
     /// ```rust, no_run
@@ -275,27 +416,31 @@ impl LlamaBuilder {
                     .await
             }
         });
-        let tokenizer = {
-            let source = format!("Tokenizer ({})", self.source.tokenizer);
-            let mut create_progress = ModelLoadingProgress::downloading_progress(source);
-            self.source
-                .tokenizer(|progress| (handler.lock().unwrap())(create_progress(progress)))
-                .await?
+        let tokenizer = match &self.source.tokenizer {
+            Some(tokenizer_source) => {
+                let source = format!("Tokenizer ({})", tokenizer_source);
+                let mut create_progress = ModelLoadingProgress::downloading_progress(source);
+                self.source
+                    .tokenizer(|progress| (handler.lock().unwrap())(create_progress(progress)))
+                    .await?
+            }
+            None => None,
         };
         let filename = filename.await??;
 
-        let mut file = std::fs::File::open(&filename)?;
-        let model = match filename.extension().and_then(|v| v.to_str()) {
-            Some("gguf") => {
-                let model = gguf_file::Content::read(&mut file)?;
-                Model::from_gguf(model, &mut file, &device)?
-            }
-            Some("ggml" | "bin") | Some(_) | None => {
-                let model = ggml_file::Content::read(&mut file, &device)?;
-                let gqa = self.source.group_query_attention;
-                Model::from_ggml(model, gqa as usize, &device)?
-            }
-        };
+        // Bare gguf files carry their own tokenizer and chat template
+        let LoadedTokenizer {
+            tokenizer,
+            chat_markers,
+            chat_template,
+            stop_token,
+        } = self.source.load_embedded(&filename, tokenizer)?;
+        let model = Model::from_file(&filename, &self.source, &device)?;
+        let draft = self
+            .load_draft(&tokenizer, &device, |progress| {
+                (handler.lock().unwrap())(progress)
+            })
+            .await?;
 
         let cache = LlamaCache::new(&model.config);
 
@@ -304,7 +449,11 @@ impl LlamaBuilder {
             tokenizer,
             device,
             cache,
-            self.source.markers,
+            chat_markers,
+            chat_template,
+            draft,
+            self.prefix_cache_size,
+            stop_token,
         ))
     }
 
@@ -324,6 +473,9 @@ pub(crate) struct InferenceSettings {
 
     /// The token to stop on.
     stop_on: Option<String>,
+
+    /// The control that can interrupt the generation.
+    control: Option<GenerationControl>,
 }
 
 impl InferenceSettings {
@@ -332,6 +484,7 @@ impl InferenceSettings {
             prompt: prompt.into(),
             sample_len: 100,
             stop_on: None,
+            control: None,
         }
     }
 
@@ -344,4 +497,9 @@ impl InferenceSettings {
         self.stop_on = stop_on.into();
         self
     }
+
+    pub fn with_control(mut self, control: Option<GenerationControl>) -> Self {
+        self.control = control;
+        self
+    }
 }
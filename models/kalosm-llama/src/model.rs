@@ -1,17 +1,16 @@
-use crate::raw::cache::LlamaCache;
+use crate::prefix_cache::PrefixCache;
+use crate::raw::cache::{BatchCache, LlamaCache};
 use crate::{raw::Model, session::LlamaSession};
 use anyhow::{Error as E, Result};
 use kalosm_common::*;
 use kalosm_language_model::SyncModelExt;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
-use candle_core::{
-    quantized::{ggml_file, gguf_file},
-    DType, Device,
-};
+use candle_core::{DType, Device};
 use kalosm_language_model::SyncModel;
 use tokenizers::Tokenizer;
 
+use crate::source::LoadedTokenizer;
 use crate::InferenceSettings;
 
 /// The inner, synchronous Llama model.
@@ -20,6 +19,28 @@ pub struct LlamaModel {
     device: Device,
     tokenizer: Arc<Tokenizer>,
     cache: LlamaCache,
+    draft: Option<DraftModel>,
+    prefix_cache: Option<Mutex<PrefixCache>>,
+    /// The end of sequence token from the model file, if it has one
+    stop_token: Option<u32>,
+}
+
+/// A smaller model that shares a tokenizer with the main model and guesses tokens for speculative decoding.
+pub(crate) struct DraftModel {
+    model: Model,
+    cache: LlamaCache,
+    tokens: usize,
+}
+
+impl DraftModel {
+    pub(crate) fn new(model: Model, tokens: usize) -> Self {
+        let cache = LlamaCache::new(&model.config);
+        Self {
+            model,
+            cache,
+            tokens,
+        }
+    }
 }
 
 impl SyncModel for LlamaModel {
@@ -27,7 +48,10 @@ impl SyncModel for LlamaModel {
 
     fn new_session(&self) -> anyhow::Result<Self::Session> {
         let cache = self.cache.clone();
-        Ok(Self::Session { cache })
+        Ok(Self::Session {
+            cache,
+            draft_cache: None,
+        })
     }
 
     fn feed_text(
@@ -47,16 +71,113 @@ impl SyncModel for LlamaModel {
         tokens: &[u32],
         logits: &mut Vec<f32>,
     ) -> anyhow::Result<()> {
+        let Some(prefix_cache) = self
+            .prefix_cache
+            .as_ref()
+            .filter(|_| session.cache.tokens.is_empty() && !tokens.is_empty())
+        else {
+            return Self::forward(
+                &self.model,
+                &self.device,
+                tokens,
+                Some(&mut session.cache),
+                logits,
+            );
+        };
+
+        // A new session starts from the longest prompt prefix the model already read. The last token is always fed to get the logits
+        let cached_tokens = prefix_cache
+            .lock()
+            .unwrap()
+            .get(&tokens[..tokens.len() - 1], &mut session.cache)?;
         Self::forward(
             &self.model,
             &self.device,
-            tokens,
+            &tokens[cached_tokens..],
             Some(&mut session.cache),
             logits,
-        )
+        )?;
+        prefix_cache.lock().unwrap().insert(&session.cache)?;
+
+        Ok(())
+    }
+
+    fn feed_tokens_all_logits(
+        &self,
+        session: &mut Self::Session,
+        tokens: &[u32],
+        into: &mut Vec<Vec<f32>>,
+    ) -> anyhow::Result<()> {
+        if tokens.is_empty() {
+            return Err(anyhow::anyhow!("Cannot run model on empty input"));
+        }
+
+        let logits =
+            self.model
+                .forward_all_logits(tokens, &self.device, Some(&mut session.cache))?;
+        *into = logits.squeeze(0)?.to_dtype(DType::F32)?.to_vec2()?;
+
+        Ok(())
+    }
+
+    fn feed_batch(
+        &self,
+        sessions: &mut [&mut Self::Session],
+        tokens: &[u32],
+        into: &mut Vec<Vec<f32>>,
+    ) -> anyhow::Result<()> {
+        // Without a batch that lives across steps, the keys and values are copied into a new batch for this step only
+        let mut batch = None;
+        let result = self.feed_batch_cached(&mut batch, sessions, tokens, into);
+        Self::write_back_batch(&mut batch, sessions)?;
+        result
+    }
+
+    fn draft_tokens(&self, session: &mut Self::Session, next: &[u32]) -> anyhow::Result<Vec<u32>> {
+        let Some(draft) = &self.draft else {
+            return Ok(Vec::new());
+        };
+        let mut tokens = session.cache.tokens.clone();
+        tokens.extend_from_slice(next);
+        if tokens.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Bring the draft cache up to date with the main session. The draft model needs at least one new token to produce logits
+        let draft_cache = session
+            .draft_cache
+            .get_or_insert_with(|| draft.cache.clone());
+        let shared = draft_cache
+            .tokens
+            .iter()
+            .zip(&tokens)
+            .take_while(|(a, b)| a == b)
+            .count()
+            .min(tokens.len() - 1);
+        draft_cache.truncate(shared)?;
+
+        let stop_token = self.stop_token().ok();
+        let mut new_tokens = tokens[shared..].to_vec();
+        let mut guessed = Vec::with_capacity(draft.tokens);
+        while guessed.len() < draft.tokens {
+            let logits = draft
+                .model
+                .forward(&new_tokens, &self.device, Some(draft_cache))?;
+            let token = logits.squeeze(0)?.argmax(0)?.to_scalar::<u32>()?;
+            guessed.push(token);
+            if Some(token) == stop_token {
+                break;
+            }
+            new_tokens = vec![token];
+        }
+
+        Ok(guessed)
     }
 
     fn stop_token(&self) -> anyhow::Result<u32> {
+        if let Some(stop_token) = self.stop_token {
+            return Ok(stop_token);
+        }
         let vocab = self.tokenizer.get_vocab(true);
         let eos_token = match vocab.get("</s>").or(vocab.get("<|end_of_text|>")) {
             Some(token) => *token,
@@ -68,6 +189,10 @@ impl SyncModel for LlamaModel {
     fn tokenizer(&self) -> Arc<Tokenizer> {
         self.tokenizer.clone()
     }
+
+    fn context_length(&self) -> Option<usize> {
+        Some(self.model.config.context_length)
+    }
 }
 
 impl LlamaModel {
@@ -97,12 +222,18 @@ impl LlamaModel {
     ) -> anyhow::Result<Self> {
         let device = builder.get_device()?;
 
-        let tokenizer_source = format!("Tokenizer ({})", builder.source.tokenizer);
-        let mut create_progress = ModelLoadingProgress::downloading_progress(tokenizer_source);
-        let tokenizer = builder
-            .source
-            .tokenizer(|progress| handler(create_progress(progress)))
-            .await?;
+        let tokenizer = match &builder.source.tokenizer {
+            Some(tokenizer_source) => {
+                let tokenizer_source = format!("Tokenizer ({})", tokenizer_source);
+                let mut create_progress =
+                    ModelLoadingProgress::downloading_progress(tokenizer_source);
+                builder
+                    .source
+                    .tokenizer(|progress| handler(create_progress(progress)))
+                    .await?
+            }
+            None => None,
+        };
 
         let source = format!("Model ({})", builder.source.model);
         let mut create_progress = ModelLoadingProgress::downloading_progress(source);
@@ -110,25 +241,28 @@ impl LlamaModel {
             .source
             .model(|progress| handler(create_progress(progress)))
             .await?;
-        let mut file = std::fs::File::open(&filename)?;
-        let model = match filename.extension().and_then(|v| v.to_str()) {
-            Some("gguf") => {
-                let model = gguf_file::Content::read(&mut file)?;
-                Model::from_gguf(model, &mut file, &device)?
-            }
-            Some("ggml" | "bin") | Some(_) | None => {
-                let model = ggml_file::Content::read(&mut file, &device)?;
-                let gqa = builder.source.group_query_attention;
-                Model::from_ggml(model, gqa as usize, &device)?
-            }
-        };
+        let LoadedTokenizer {
+            tokenizer,
+            stop_token,
+            ..
+        } = builder.source.load_embedded(&filename, tokenizer)?;
+        let model = Model::from_file(&filename, &builder.source, &device)?;
+        let draft = builder
+            .load_draft(&tokenizer, &device, &mut handler)
+            .await?;
 
         let cache = LlamaCache::new(&model.config);
+        let prefix_cache = builder
+            .prefix_cache_size
+            .map(|max_bytes| Mutex::new(PrefixCache::new(max_bytes)));
         Ok(Self {
             model,
             tokenizer: Arc::new(tokenizer),
             device,
             cache,
+            draft,
+            prefix_cache,
+            stop_token,
         })
     }
 
@@ -138,15 +272,92 @@ impl LlamaModel {
         tokenizer: Arc<Tokenizer>,
         device: Device,
         cache: LlamaCache,
+        draft: Option<DraftModel>,
+        prefix_cache_size: Option<usize>,
+        stop_token: Option<u32>,
     ) -> Self {
         Self {
             cache,
             model,
             device,
             tokenizer,
+            draft,
+            prefix_cache: prefix_cache_size
+                .map(|max_bytes| Mutex::new(PrefixCache::new(max_bytes))),
+            stop_token,
         }
     }
 
+    /// Feed one token into each session like [`SyncModel::feed_batch`], but keep the keys and values of the batch in `batch` so the next step can append to them.
+    ///
+    /// The sessions must be the same sessions in the same order every step. Before the sessions are used on their own or the batch changes, the keys and values need to be copied back into the sessions with [`LlamaModel::write_back_batch`]. If the forward pass fails, the batch is left as it was before the step.
+    pub(crate) fn feed_batch_cached(
+        &self,
+        batch: &mut Option<BatchCache>,
+        sessions: &mut [&mut LlamaSession],
+        tokens: &[u32],
+        into: &mut Vec<Vec<f32>>,
+    ) -> anyhow::Result<()> {
+        if sessions.len() != tokens.len() {
+            anyhow::bail!(
+                "Expected one token per session, found {} sessions and {} tokens",
+                sessions.len(),
+                tokens.len()
+            );
+        }
+        // Sequences that are about to overflow the context window need to be shifted one at a time
+        let context_length = self.model.config.context_length;
+        if sessions.len() < 2
+            || sessions
+                .iter()
+                .any(|session| session.cache.tokens.len() >= context_length)
+        {
+            Self::write_back_batch(batch, sessions)?;
+            into.clear();
+            for (session, &token) in sessions.iter_mut().zip(tokens) {
+                let mut logits = Vec::new();
+                self.feed_tokens(session, &[token], &mut logits)?;
+                into.push(logits);
+            }
+            return Ok(());
+        }
+
+        let mut caches: Vec<_> = sessions
+            .iter_mut()
+            .map(|session| &mut session.cache)
+            .collect();
+        if batch.is_none() {
+            *batch = Some(BatchCache::new(&caches)?);
+        }
+        let batch = batch.as_mut().unwrap();
+        let logits = self
+            .model
+            .forward_batch(tokens, &self.device, &mut caches, batch)?;
+        *into = logits.to_dtype(DType::F32)?.to_vec2()?;
+
+        Ok(())
+    }
+
+    /// Copy the keys and values of a batch created by [`LlamaModel::feed_batch_cached`] back into the sessions and clear the batch.
+    pub(crate) fn write_back_batch(
+        batch: &mut Option<BatchCache>,
+        sessions: &mut [&mut LlamaSession],
+    ) -> anyhow::Result<()> {
+        if let Some(batch) = batch.take() {
+            let mut caches: Vec<_> = sessions
+                .iter_mut()
+                .map(|session| &mut session.cache)
+                .collect();
+            batch.write_back(&mut caches)?;
+        }
+        Ok(())
+    }
+
+    /// Check if the model has a draft model for speculative decoding.
+    pub(crate) fn has_draft(&self) -> bool {
+        self.draft.is_some()
+    }
+
     pub(crate) fn _infer(
         &mut self,
         settings: InferenceSettings,
@@ -157,16 +368,19 @@ impl LlamaModel {
             prompt,
             sample_len,
             stop_on,
+            control,
         } = settings;
 
         let mut session = self.new_session()?;
 
-        self.stream_text_with_sampler(
+        // The generation records why it stopped in the control, so the stream can report it
+        self.stream_text_with_control(
             &mut session,
             prompt.as_str(),
             Some(sample_len as u32),
             stop_on.as_deref(),
             sampler,
+            control,
             |token| {
                 out.send(token)
                     .map_err(|_| anyhow::anyhow!("Failed to send token to output channel"))
@@ -0,0 +1,286 @@
+use crate::raw::cache::{KvSegment, LlamaCache};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+/// A cache of the attention state of prompts the model has already read, shared between sessions.
+///
+/// The prompts are stored in a radix tree keyed on token ids. Each node holds the keys and values for just the tokens on the edge leading to it, so prompts that share a prefix share the memory for it. A new session can start from any cached prompt that shares a prefix with its own prompt, so a long system prompt or set of few-shot examples is only read once. The cache is bounded by memory and evicts the least recently used prompts first.
+pub(crate) struct PrefixCache {
+    root: Node,
+    max_bytes: usize,
+    used_bytes: usize,
+    clock: u64,
+}
+
+impl PrefixCache {
+    /// Create a cache that holds up to `max_bytes` bytes of attention state.
+    pub(crate) fn new(max_bytes: usize) -> Self {
+        Self {
+            root: Node::default(),
+            max_bytes,
+            used_bytes: 0,
+            clock: 0,
+        }
+    }
+
+    /// Copy the cached state that shares the longest prefix with the tokens into an empty cache. Returns the number of tokens that were copied.
+    pub(crate) fn get(
+        &mut self,
+        tokens: &[u32],
+        into: &mut LlamaCache,
+    ) -> candle_core::Result<usize> {
+        self.clock += 1;
+        let mut node = &mut self.root;
+        let mut matched = 0;
+        while let Some(child) = tokens
+            .get(matched)
+            .and_then(|token| node.children.get_mut(token))
+        {
+            let shared = common_prefix(&child.edge, &tokens[matched..]);
+            child.last_used = self.clock;
+            into.append_segment(&child.edge[..shared], &child.segment)?;
+            matched += shared;
+            if shared < child.edge.len() {
+                break;
+            }
+            node = child;
+        }
+        Ok(matched)
+    }
+
+    /// Store the state of the tokens in the cache that are not cached yet. The least recently used prompts are evicted until the cache fits in memory again.
+    pub(crate) fn insert(&mut self, cache: &LlamaCache) -> candle_core::Result<()> {
+        self.clock += 1;
+        let tokens = &cache.tokens;
+        let mut node = &mut self.root;
+        let mut matched = 0;
+        while matched < tokens.len() {
+            let child = match node.children.entry(tokens[matched]) {
+                Entry::Occupied(child) => child.into_mut(),
+                Entry::Vacant(entry) => {
+                    let segment = cache.copy_segment(matched, tokens.len() - matched)?;
+                    self.used_bytes += segment.memory_usage();
+                    entry.insert(Node {
+                        edge: tokens[matched..].to_vec(),
+                        segment,
+                        last_used: self.clock,
+                        children: HashMap::new(),
+                    });
+                    break;
+                }
+            };
+            let shared = common_prefix(&child.edge, &tokens[matched..]);
+            child.last_used = self.clock;
+            matched += shared;
+            // Split the edge where the tokens diverge from it. Tokens that end partway along an edge are already cached
+            if shared < child.edge.len() && matched < tokens.len() {
+                child.split(shared)?;
+            }
+            node = child;
+        }
+
+        while self.used_bytes > self.max_bytes {
+            let mut oldest = None;
+            self.root.least_recently_used(&mut Vec::new(), &mut oldest);
+            let Some((_, tokens)) = oldest else {
+                break;
+            };
+            self.used_bytes -= self.root.remove(&tokens)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Node {
+    /// The tokens on the edge from the parent node to this node.
+    edge: Vec<u32>,
+    /// The keys and values for the tokens on the edge.
+    segment: KvSegment,
+    last_used: u64,
+    children: HashMap<u32, Node>,
+}
+
+impl Node {
+    /// Split the edge at `at`, moving the rest of the edge and the children into a new child.
+    fn split(&mut self, at: usize) -> candle_core::Result<()> {
+        let child = Node {
+            edge: self.edge.split_off(at),
+            segment: self.segment.split_off(at)?,
+            last_used: self.last_used,
+            children: std::mem::take(&mut self.children),
+        };
+        self.children.insert(child.edge[0], child);
+        Ok(())
+    }
+
+    /// Find the least recently used leaf and the tokens on the path to it. Parents are always used at least as recently as their children, so only leaves are evicted.
+    fn least_recently_used(&self, path: &mut Vec<u32>, oldest: &mut Option<(u64, Vec<u32>)>) {
+        path.extend_from_slice(&self.edge);
+        let older = match oldest {
+            Some((last_used, _)) => self.last_used < *last_used,
+            None => true,
+        };
+        if self.children.is_empty() && !path.is_empty() && older {
+            *oldest = Some((self.last_used, path.clone()));
+        }
+        for child in self.children.values() {
+            child.least_recently_used(path, oldest);
+        }
+        path.truncate(path.len() - self.edge.len());
+    }
+
+    /// Remove the leaf at the end of the tokens. Returns the number of bytes freed.
+    fn remove(&mut self, tokens: &[u32]) -> candle_core::Result<usize> {
+        let Some(&first) = tokens.first() else {
+            return Ok(0);
+        };
+        let Some(child) = self.children.get_mut(&first) else {
+            return Ok(0);
+        };
+        let Some(rest) = tokens.strip_prefix(child.edge.as_slice()) else {
+            return Ok(0);
+        };
+        if rest.is_empty() {
+            if !child.children.is_empty() {
+                return Ok(0);
+            }
+            let child = self.children.remove(&first).unwrap();
+            return Ok(child.segment.memory_usage());
+        }
+        let freed = child.remove(rest)?;
+        // Merge the child with its only child to keep the tree compressed
+        if child.children.len() == 1 {
+            let (_, grandchild) = child.children.drain().next().unwrap();
+            child.edge.extend(grandchild.edge);
+            child.segment.extend(&grandchild.segment)?;
+            child.last_used = child.last_used.max(grandchild.last_used);
+            child.children = grandchild.children;
+        }
+        Ok(freed)
+    }
+}
+
+fn common_prefix(a: &[u32], b: &[u32]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raw::Model;
+    use candle_core::Device;
+
+    /// The bytes of attention state per token of the random model: 2 layers with keys and values for 2 heads of 4 floats.
+    const TOKEN_BYTES: usize = 2 * 2 * 2 * 4 * 4;
+
+    /// Read the tokens with a random model and return the cache.
+    fn read(model: &Model, tokens: &[u32]) -> LlamaCache {
+        let mut cache = LlamaCache::new(&model.config);
+        model
+            .forward(tokens, &Device::Cpu, Some(&mut cache))
+            .unwrap();
+        cache
+    }
+
+    /// Check that two caches hold the same keys. Reading a different number of tokens at once can change the results slightly.
+    fn assert_same_keys(a: &LlamaCache, b: &LlamaCache) {
+        assert_eq!(a.tokens, b.tokens);
+        for (a, b) in a.blocks.iter().zip(&b.blocks) {
+            let a = a.cache().k().unwrap().unwrap();
+            let b = b.cache().k().unwrap().unwrap();
+            let difference: f32 = (a - b)
+                .unwrap()
+                .abs()
+                .unwrap()
+                .max_all()
+                .unwrap()
+                .to_scalar()
+                .unwrap();
+            assert!(difference < 1e-4);
+        }
+    }
+
+    fn get(prefix_cache: &mut PrefixCache, model: &Model, tokens: &[u32]) -> LlamaCache {
+        let mut cache = LlamaCache::new(&model.config);
+        let matched = prefix_cache.get(tokens, &mut cache).unwrap();
+        assert_eq!(cache.tokens, tokens[..matched]);
+        cache
+    }
+
+    /// The edges of the tree in depth first order, sorted by the first token.
+    fn edges(node: &Node) -> Vec<Vec<u32>> {
+        let mut children: Vec<_> = node.children.values().collect();
+        children.sort_by_key(|child| child.edge[0]);
+        children
+            .into_iter()
+            .flat_map(|child| std::iter::once(child.edge.clone()).chain(edges(child)))
+            .collect()
+    }
+
+    #[test]
+    fn cached_prompts_match_the_longest_prefix() {
+        let model = Model::random(16, 64, &Device::Cpu).unwrap();
+        let mut prefix_cache = PrefixCache::new(usize::MAX);
+        assert_eq!(get(&mut prefix_cache, &model, &[1, 2, 3]).tokens, [0u32; 0]);
+
+        prefix_cache.insert(&read(&model, &[1, 2, 3, 4])).unwrap();
+        let cached = get(&mut prefix_cache, &model, &[1, 2, 3, 4, 5]);
+        assert_eq!(cached.tokens, [1, 2, 3, 4]);
+        assert_same_keys(&cached, &read(&model, &[1, 2, 3, 4]));
+        // A prompt that diverges partway along an edge only gets the shared tokens
+        let cached = get(&mut prefix_cache, &model, &[1, 2, 7]);
+        assert_eq!(cached.tokens, [1, 2]);
+        assert_same_keys(&cached, &read(&model, &[1, 2]));
+        assert_eq!(get(&mut prefix_cache, &model, &[7, 1]).tokens, [0u32; 0]);
+    }
+
+    #[test]
+    fn shared_prefixes_are_stored_once() {
+        let model = Model::random(16, 64, &Device::Cpu).unwrap();
+        let mut prefix_cache = PrefixCache::new(usize::MAX);
+        prefix_cache.insert(&read(&model, &[1, 2, 3, 4])).unwrap();
+        prefix_cache.insert(&read(&model, &[1, 2, 5])).unwrap();
+        assert_eq!(edges(&prefix_cache.root), [vec![1, 2], vec![3, 4], vec![5]]);
+        assert_eq!(prefix_cache.used_bytes, 5 * TOKEN_BYTES);
+
+        // Prompts that are already cached don't use more memory
+        prefix_cache.insert(&read(&model, &[1, 2, 3])).unwrap();
+        prefix_cache.insert(&read(&model, &[1, 2, 5])).unwrap();
+        assert_eq!(prefix_cache.used_bytes, 5 * TOKEN_BYTES);
+
+        let cached = get(&mut prefix_cache, &model, &[1, 2, 5, 6]);
+        assert_eq!(cached.tokens, [1, 2, 5]);
+        assert_same_keys(&cached, &read(&model, &[1, 2, 5]));
+    }
+
+    #[test]
+    fn least_recently_used_prompts_are_evicted() {
+        let model = Model::random(16, 64, &Device::Cpu).unwrap();
+        let mut prefix_cache = PrefixCache::new(6 * TOKEN_BYTES);
+        prefix_cache.insert(&read(&model, &[1, 2, 3])).unwrap();
+        prefix_cache.insert(&read(&model, &[1, 2, 4])).unwrap();
+        prefix_cache.insert(&read(&model, &[5, 6])).unwrap();
+        assert_eq!(prefix_cache.used_bytes, 6 * TOKEN_BYTES);
+
+        // Using [1, 2, 3] makes [1, 2, 4] the least recently used leaf
+        get(&mut prefix_cache, &model, &[1, 2, 3]);
+        get(&mut prefix_cache, &model, &[5, 6]);
+        prefix_cache.insert(&read(&model, &[7])).unwrap();
+        // The only child left is merged back into its parent
+        assert_eq!(
+            edges(&prefix_cache.root),
+            [vec![1, 2, 3], vec![5, 6], vec![7]]
+        );
+        assert_eq!(prefix_cache.used_bytes, 6 * TOKEN_BYTES);
+        let cached = get(&mut prefix_cache, &model, &[1, 2, 3]);
+        assert_same_keys(&cached, &read(&model, &[1, 2, 3]));
+        assert_eq!(get(&mut prefix_cache, &model, &[1, 2, 4]).tokens, [1, 2]);
+
+        // Prompts that don't fit at all are not kept
+        prefix_cache.insert(&read(&model, &[8; 7])).unwrap();
+        assert!(prefix_cache.used_bytes <= 6 * TOKEN_BYTES);
+        assert_eq!(get(&mut prefix_cache, &model, &[8]).tokens, [0u32; 0]);
+    }
+}
@@ -102,7 +102,7 @@ impl SeparateAttention {
         num_key_value_heads: usize,
         hidden_states: &Tensor,
         rope_cache: &RopeCache,
-        start_pos: usize,
+        start_positions: &[usize],
     ) -> candle_core::Result<(Tensor, Tensor, Tensor)> {
         let b_sz = hidden_states.dims()[0];
         let seq_len = hidden_states.dims()[1];
@@ -151,11 +151,12 @@ impl SeparateAttention {
                     candle_core::Error::Msg("failed to join key states".to_string())
                 })??;
 
-                let (query_states, key_states) = if self.interleaved_rope {
-                    rope_cache.forward_i(&query_states, &key_states, start_pos)?
-                } else {
-                    rope_cache.forward(&query_states, &key_states, start_pos)?
-                };
+                let (query_states, key_states) = rope_cache.forward_batch(
+                    &query_states,
+                    &key_states,
+                    start_positions,
+                    self.interleaved_rope,
+                )?;
 
                 let value_states = value_states.join().map_err(|_| {
                     candle_core::Error::Msg("failed to join value states".to_string())
@@ -198,11 +199,12 @@ impl SeparateAttention {
                     .transpose(1, 2)?
             };
 
-            let (query_states, key_states) = if self.interleaved_rope {
-                rope_cache.forward_i(&query_states, &key_states, start_pos)?
-            } else {
-                rope_cache.forward(&query_states, &key_states, start_pos)?
-            };
+            let (query_states, key_states) = rope_cache.forward_batch(
+                &query_states,
+                &key_states,
+                start_positions,
+                self.interleaved_rope,
+            )?;
 
             Ok((query_states, key_states, value_states))
         }
@@ -221,7 +223,7 @@ impl GroupedAttention {
         num_key_value_heads: usize,
         x: &Tensor,
         rope_cache: &RopeCache,
-        start_pos: usize,
+        start_positions: &[usize],
     ) -> candle_core::Result<(Tensor, Tensor, Tensor)> {
         let b_sz = x.dims()[0];
         let seq_len = x.dims()[1];
@@ -247,7 +249,7 @@ impl GroupedAttention {
             .transpose(1, 2)?;
 
         let (query_states, key_states) =
-            rope_cache.forward(&query_states, &key_states, start_pos)?;
+            rope_cache.forward_batch(&query_states, &key_states, start_positions, false)?;
 
         Ok((query_states, key_states, value_states))
     }
@@ -274,34 +276,12 @@ impl LlamaAttention {
         start_pos: usize,
         cache: Option<&mut KvCache>,
     ) -> candle_core::Result<Tensor> {
-        let bsz = hidden_states.dims()[0];
-        let q_len = hidden_states.dims()[1];
-        let hidden_size = self.hidden_size;
-        let num_heads = self.n_head;
-        let head_dim = self.head_dim;
-        let num_key_value_heads = self.n_kv_head;
-        let num_key_value_groups = num_heads / num_key_value_heads;
+        let num_key_value_groups = self.n_head / self.n_kv_head;
 
-        let (query_states, key_states, value_states) = match self.attention_variant {
-            AttentionVariant::Separate(ref attention) => attention.forward(
-                num_heads,
-                head_dim,
-                num_key_value_heads,
-                hidden_states,
-                &self.rope_cache,
-                start_pos,
-            )?,
-            AttentionVariant::Grouped(ref attention) => attention.forward(
-                num_heads,
-                head_dim,
-                num_key_value_heads,
-                hidden_states,
-                &self.rope_cache,
-                start_pos,
-            )?,
-        };
+        let (query_states, key_states, value_states) =
+            self.query_key_value(hidden_states, &[start_pos])?;
 
-        let key_states = repeat_kv(key_states.clone(), num_key_value_groups)?;
+        let key_states = repeat_kv(key_states, num_key_value_groups)?;
         let value_states = repeat_kv(value_states, num_key_value_groups)?;
 
         let (key_states, value_states) = match cache {
@@ -309,6 +289,81 @@ impl LlamaAttention {
             Some(cache) => cache.append(&key_states, &value_states)?,
         };
 
+        self.attend(
+            hidden_states,
+            &query_states,
+            &key_states,
+            &value_states,
+            attention_mask,
+        )
+    }
+
+    /// Run attention for a batch of single token sequences that each start at a different position. The keys and values of the whole batch are appended to the padded batch cache.
+    pub(crate) fn forward_batch(
+        &self,
+        hidden_states: &Tensor,
+        attention_mask: &AttentionMask,
+        start_positions: &[usize],
+        cache: &mut KvCache,
+    ) -> candle_core::Result<Tensor> {
+        let num_key_value_groups = self.n_head / self.n_kv_head;
+
+        let (query_states, key_states, value_states) =
+            self.query_key_value(hidden_states, start_positions)?;
+
+        let key_states = repeat_kv(key_states, num_key_value_groups)?;
+        let value_states = repeat_kv(value_states, num_key_value_groups)?;
+
+        let (key_states, value_states) = cache.append(&key_states, &value_states)?;
+
+        self.attend(
+            hidden_states,
+            &query_states,
+            &key_states,
+            &value_states,
+            Some(attention_mask),
+        )
+    }
+
+    fn query_key_value(
+        &self,
+        hidden_states: &Tensor,
+        start_positions: &[usize],
+    ) -> candle_core::Result<(Tensor, Tensor, Tensor)> {
+        match self.attention_variant {
+            AttentionVariant::Separate(ref attention) => attention.forward(
+                self.n_head,
+                self.head_dim,
+                self.n_kv_head,
+                hidden_states,
+                &self.rope_cache,
+                start_positions,
+            ),
+            AttentionVariant::Grouped(ref attention) => attention.forward(
+                self.n_head,
+                self.head_dim,
+                self.n_kv_head,
+                hidden_states,
+                &self.rope_cache,
+                start_positions,
+            ),
+        }
+    }
+
+    fn attend(
+        &self,
+        hidden_states: &Tensor,
+        query_states: &Tensor,
+        key_states: &Tensor,
+        value_states: &Tensor,
+        attention_mask: Option<&AttentionMask>,
+    ) -> candle_core::Result<Tensor> {
+        let bsz = hidden_states.dims()[0];
+        let q_len = hidden_states.dims()[1];
+        let hidden_size = self.hidden_size;
+        let num_heads = self.n_head;
+        let head_dim = self.head_dim;
+
         let mut attn_weights = (query_states.matmul(&key_states.t()?)? / (head_dim as f64).sqrt())?;
 
         if let Some(attention_mask) = attention_mask {
@@ -317,7 +372,7 @@ impl LlamaAttention {
 
         attn_weights = candle_nn::ops::softmax_last_dim(&attn_weights)?;
 
-        let mut attn_output = attn_weights.matmul(&value_states)?;
+        let mut attn_output = attn_weights.matmul(value_states)?;
 
         if attn_output.dims() != [bsz, num_heads, q_len, head_dim] {
             return Err(candle_core::Error::Msg(format!(
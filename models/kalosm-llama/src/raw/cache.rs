@@ -38,6 +38,52 @@ impl LlamaCache {
         }
     }
 
+    /// Remove the last `tokens` tokens from the cache.
+    pub fn rewind(&mut self, tokens: usize) -> candle_core::Result<()> {
+        let len = self.tokens.len().saturating_sub(tokens);
+        self.truncate(len)
+    }
+
+    /// Shorten the cache to the first `len` tokens.
+    pub fn truncate(&mut self, len: usize) -> candle_core::Result<()> {
+        for block in &mut self.blocks {
+            block.truncate(len)?;
+        }
+        self.tokens.truncate(len);
+        Ok(())
+    }
+
+    /// Copy the keys and values of the `len` tokens starting at `start` into a segment that doesn't share memory with the cache.
+    pub(crate) fn copy_segment(&self, start: usize, len: usize) -> candle_core::Result<KvSegment> {
+        let mut layers = Vec::with_capacity(self.blocks.len());
+        for block in &self.blocks {
+            let (Some(k), Some(v)) = (block.cache().k()?, block.cache().v()?) else {
+                candle_core::bail!("Cannot copy tokens from an empty cache")
+            };
+            layers.push((
+                k.narrow(CONCAT_DIMENSION, start, len)?.force_contiguous()?,
+                v.narrow(CONCAT_DIMENSION, start, len)?.force_contiguous()?,
+            ));
+        }
+        Ok(KvSegment { len, layers })
+    }
+
+    /// Append the keys and values of the first `tokens.len()` tokens in the segment to the cache.
+    pub(crate) fn append_segment(
+        &mut self,
+        tokens: &[u32],
+        segment: &KvSegment,
+    ) -> candle_core::Result<()> {
+        for (block, (k, v)) in self.blocks.iter_mut().zip(&segment.layers) {
+            block.append(
+                &k.narrow(CONCAT_DIMENSION, 0, tokens.len())?,
+                &v.narrow(CONCAT_DIMENSION, 0, tokens.len())?,
+            )?;
+        }
+        self.tokens.extend_from_slice(tokens);
+        Ok(())
+    }
+
     /// Get the tensor map for this cache. This can be used to save the cache to disk.
     pub fn get_tensor_map(&self, device: &Device) -> HashMap<String, Tensor> {
         let mut map = HashMap::with_capacity(self.blocks.len());
@@ -122,3 +168,158 @@ impl LlamaCache {
         })
     }
 }
+
+/// A copy of the keys and values of every layer for a run of tokens, detached from any cache.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct KvSegment {
+    len: usize,
+    layers: Vec<(Tensor, Tensor)>,
+}
+
+impl KvSegment {
+    /// Get the number of bytes the keys and values use.
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.layers
+            .iter()
+            .map(|(k, v)| (k.elem_count() + v.elem_count()) * k.dtype().size_in_bytes())
+            .sum()
+    }
+
+    /// Split the segment in two at `at`. This segment keeps the tokens before `at` and the rest are returned.
+    pub(crate) fn split_off(&mut self, at: usize) -> candle_core::Result<KvSegment> {
+        let mut head = Vec::with_capacity(self.layers.len());
+        let mut tail = Vec::with_capacity(self.layers.len());
+        for (k, v) in &self.layers {
+            head.push((
+                k.narrow(CONCAT_DIMENSION, 0, at)?.force_contiguous()?,
+                v.narrow(CONCAT_DIMENSION, 0, at)?.force_contiguous()?,
+            ));
+            tail.push((
+                k.narrow(CONCAT_DIMENSION, at, self.len - at)?
+                    .force_contiguous()?,
+                v.narrow(CONCAT_DIMENSION, at, self.len - at)?
+                    .force_contiguous()?,
+            ));
+        }
+        let tail = KvSegment {
+            len: self.len - at,
+            layers: tail,
+        };
+        self.len = at;
+        self.layers = head;
+        Ok(tail)
+    }
+
+    /// Add the tokens of another segment to the end of this segment.
+    pub(crate) fn extend(&mut self, other: &KvSegment) -> candle_core::Result<()> {
+        for ((k, v), (other_k, other_v)) in self.layers.iter_mut().zip(&other.layers) {
+            *k = Tensor::cat(&[&*k, other_k], CONCAT_DIMENSION)?;
+            *v = Tensor::cat(&[&*v, other_v], CONCAT_DIMENSION)?;
+        }
+        self.len += other.len;
+        Ok(())
+    }
+}
+
+/// The keys and values of a batch of sequences that generate one token each per step.
+///
+/// The keys and values of every sequence are padded at the start to the length of the longest sequence. The newest token of every sequence is always in the last column, so each step appends the new tokens of the whole batch at once instead of copying every sequence into a new padded tensor.
+#[derive(Debug, Clone)]
+pub(crate) struct BatchCache {
+    /// The number of tokens in each sequence.
+    lengths: Vec<usize>,
+    blocks: Vec<KvCache>,
+}
+
+impl BatchCache {
+    /// Copy the keys and values of each cache into one padded batch.
+    pub(crate) fn new(caches: &[&mut LlamaCache]) -> candle_core::Result<Self> {
+        let Some(first) = caches.first() else {
+            candle_core::bail!("Cannot create a batch cache without any sequences");
+        };
+        let lengths: Vec<_> = caches.iter().map(|cache| cache.tokens.len()).collect();
+        let max_len = lengths.iter().copied().max().unwrap_or_default();
+        let mut blocks = Vec::with_capacity(first.blocks.len());
+        for layer in 0..first.blocks.len() {
+            let mut keys = Vec::with_capacity(caches.len());
+            let mut values = Vec::with_capacity(caches.len());
+            for (cache, &len) in caches.iter().zip(&lengths) {
+                let block = &cache.blocks[layer];
+                let (Some(k), Some(v)) = (block.cache().k()?, block.cache().v()?) else {
+                    candle_core::bail!("Cannot batch a sequence without any tokens");
+                };
+                if k.dim(CONCAT_DIMENSION)? != len {
+                    candle_core::bail!(
+                        "The cache holds {} tokens, but has keys and values for {} tokens",
+                        len,
+                        k.dim(CONCAT_DIMENSION)?
+                    );
+                }
+                keys.push(k.pad_with_zeros(CONCAT_DIMENSION, max_len - len, 0)?);
+                values.push(v.pad_with_zeros(CONCAT_DIMENSION, max_len - len, 0)?);
+            }
+            let mut block = KvCache::new(CONCAT_DIMENSION, first.max_seq_len);
+            block.append(&Tensor::cat(&keys, 0)?, &Tensor::cat(&values, 0)?)?;
+            blocks.push(block);
+        }
+        Ok(Self { lengths, blocks })
+    }
+
+    /// Get the number of tokens in each sequence.
+    pub(crate) fn lengths(&self) -> &[usize] {
+        &self.lengths
+    }
+
+    /// Get the cache for one layer of the model.
+    pub(crate) fn block_mut(&mut self, layer: usize) -> &mut KvCache {
+        &mut self.blocks[layer]
+    }
+
+    /// Record that one new token was added to every sequence.
+    pub(crate) fn push_tokens(&mut self) {
+        for len in &mut self.lengths {
+            *len += 1;
+        }
+    }
+
+    /// Remove the last token of every sequence. This undoes a step that failed part way through.
+    pub(crate) fn pop_tokens(&mut self) -> candle_core::Result<()> {
+        for len in &mut self.lengths {
+            *len -= 1;
+        }
+        let max_len = self.lengths.iter().copied().max().unwrap_or_default();
+        for block in &mut self.blocks {
+            block.truncate(max_len)?;
+        }
+        Ok(())
+    }
+
+    /// Copy the keys and values of each sequence back into its own cache.
+    pub(crate) fn write_back(&self, caches: &mut [&mut LlamaCache]) -> candle_core::Result<()> {
+        if caches.len() != self.lengths.len() {
+            candle_core::bail!(
+                "Expected {} caches, found {}",
+                self.lengths.len(),
+                caches.len()
+            );
+        }
+        let max_len = self.lengths.iter().copied().max().unwrap_or_default();
+        for (layer, block) in self.blocks.iter().enumerate() {
+            let (Some(k), Some(v)) = (block.cache().k()?, block.cache().v()?) else {
+                continue;
+            };
+            for (i, (cache, &len)) in caches.iter_mut().zip(&self.lengths).enumerate() {
+                let k = k
+                    .narrow(0, i, 1)?
+                    .narrow(CONCAT_DIMENSION, max_len - len, len)?;
+                let v = v
+                    .narrow(0, i, 1)?
+                    .narrow(CONCAT_DIMENSION, max_len - len, len)?;
+                let mut copy = KvCache::new(CONCAT_DIMENSION, cache.max_seq_len);
+                copy.append(&k, &v)?;
+                cache.blocks[layer] = copy;
+            }
+        }
+        Ok(())
+    }
+}
@@ -20,7 +20,7 @@ pub mod cache;
 mod rope;
 mod silu;
 
-use cache::LlamaCache;
+use cache::{BatchCache, LlamaCache};
 
 fn decode_norm(tensor: QTensor, eps: f64) -> candle_core::Result<RmsNorm> {
     RmsNorm::from_qtensor(tensor, eps)
@@ -52,6 +52,27 @@ pub struct Model {
 }
 
 impl Model {
+    /// Load the model from a ggml or gguf file.
+    pub fn from_file(
+        filename: &std::path::Path,
+        source: &crate::LlamaSource,
+        device: &Device,
+    ) -> anyhow::Result<Self> {
+        let mut file = std::fs::File::open(filename)?;
+        let model = match filename.extension().and_then(|v| v.to_str()) {
+            Some("gguf") => {
+                let model = gguf_file::Content::read(&mut file)?;
+                Model::from_gguf(model, &mut file, device)?
+            }
+            Some("ggml" | "bin") | Some(_) | None => {
+                let model = ggml_file::Content::read(&mut file, device)?;
+                let gqa = source.group_query_attention;
+                Model::from_ggml(model, gqa as usize, device)?
+            }
+        };
+        Ok(model)
+    }
+
     pub fn from_ggml(
         mut ct: ggml_file::Content,
         gqa: usize,
@@ -273,6 +294,107 @@ impl Model {
     }
 
     pub fn forward(
+        &self,
+        tokens: &[u32],
+        device: &Device,
+        cache: Option<&mut LlamaCache>,
+    ) -> Result<Tensor> {
+        let seq_len = tokens.len();
+        let x = self.forward_hidden(tokens, device, cache)?;
+        let x = x.i((.., seq_len - 1, ..))?;
+        self.output.forward(&x)
+    }
+
+    /// Run the model and return the logits for every token in the input instead of only the last one.
+    pub fn forward_all_logits(
+        &self,
+        tokens: &[u32],
+        device: &Device,
+        cache: Option<&mut LlamaCache>,
+    ) -> Result<Tensor> {
+        let seq_len = tokens.len();
+        let x = self.forward_hidden(tokens, device, cache)?;
+        let x = x.narrow(1, x.dim(1)? - seq_len, seq_len)?;
+        self.output.forward(&x)
+    }
+
+    /// Run the model for a batch of sequences with one new token each. The keys and values of every sequence are kept in the batch cache, and the token of each sequence is added to its own cache. Returns the logits for the next token of every sequence.
+    ///
+    /// If the forward pass fails, the batch cache and the tokens of every cache are left as they were before the call.
+    pub fn forward_batch(
+        &self,
+        tokens: &[u32],
+        device: &Device,
+        caches: &mut [&mut LlamaCache],
+        batch: &mut BatchCache,
+    ) -> Result<Tensor> {
+        if tokens.len() != caches.len() || tokens.len() != batch.lengths().len() {
+            candle_core::bail!(
+                "Expected one token per cache, found {} tokens, {} caches and {} batched sequences",
+                tokens.len(),
+                caches.len(),
+                batch.lengths().len()
+            );
+        }
+        if let Some(len) = batch
+            .lengths()
+            .iter()
+            .find(|len| **len >= self.config.context_length)
+        {
+            candle_core::bail!(
+                "A sequence with {} tokens does not fit in the context length of {}",
+                len + 1,
+                self.config.context_length
+            );
+        }
+
+        let start_positions = batch.lengths().to_vec();
+        batch.push_tokens();
+        let result = self.forward_batch_inner(tokens, device, &start_positions, batch);
+        match result {
+            Ok(logits) => {
+                for (cache, &token) in caches.iter_mut().zip(tokens) {
+                    cache.tokens.push(token);
+                }
+                Ok(logits)
+            }
+            Err(err) => {
+                batch.pop_tokens()?;
+                Err(err)
+            }
+        }
+    }
+
+    fn forward_batch_inner(
+        &self,
+        tokens: &[u32],
+        device: &Device,
+        start_positions: &[usize],
+        batch: &mut BatchCache,
+    ) -> Result<Tensor> {
+        let mask = self.masks.get_padding_mask(batch.lengths(), device)?;
+
+        let x = Tensor::new(tokens, device)?.unsqueeze(1)?;
+        let mut layer_in = self.tok_embeddings.forward(&x)?;
+        for (i, layer) in self.layers.iter().enumerate() {
+            let x = layer_in;
+            let residual = &x;
+            let x = layer.attention_norm.forward(&x)?;
+            let attn = layer.forward_batch(&x, &mask, start_positions, batch.block_mut(i))?;
+            let x = (attn + residual)?;
+
+            // MLP
+            let residual = &x;
+            let x = layer.ffn_norm.forward(&x)?;
+
+            layer_in = (&layer.feed_forward_variant.forward(&x)? + residual)?;
+        }
+        let x = self.norm.forward(&layer_in)?;
+        let x = x.i((.., 0, ..))?;
+        self.output.forward(&x)
+    }
+
+    fn forward_hidden(
         &self,
         tokens: &[u32],
         device: &Device,
@@ -325,8 +447,159 @@ impl Model {
 
             layer_in = (&layer.feed_forward_variant.forward(&x)? + residual)?;
         }
-        let x = self.norm.forward(&layer_in)?;
-        let x = x.i((.., seq_len - 1, ..))?;
-        self.output.forward(&x)
+        self.norm.forward(&layer_in)
+    }
+}
+
+#[cfg(test)]
+impl Model {
+    /// Create a tiny model with random weights.
+    pub(crate) fn random(
+        vocab_size: usize,
+        context_length: usize,
+        device: &Device,
+    ) -> Result<Self> {
+        let head_dim = 4;
+        let n_head = 2;
+        let n_kv_head = 1;
+        let hidden_size = head_dim * n_head;
+        let n_layer = 2;
+        let config = LlamaConfig {
+            rope_freq_weight: None,
+            rope_theta: 10000.,
+            context_length,
+            head_dimension: head_dim,
+            n_head,
+            n_layer,
+        };
+        let weight = |shape: (usize, usize)| -> Result<QMatMul> {
+            let tensor = Tensor::randn(0f32, 0.5, shape, device)?;
+            QMatMul::from_qtensor(QTensor::quantize(&tensor, GgmlDType::F32)?)
+        };
+        let norm = || -> Result<RmsNorm> {
+            let ones = Tensor::ones(hidden_size, DType::F32, device)?;
+            decode_norm(QTensor::quantize(&ones, GgmlDType::F32)?, 1e-5)
+        };
+        let rope = RopeCache::new(&config, DType::F32, device)?;
+        let mut layers = Vec::with_capacity(n_layer);
+        for _ in 0..n_layer {
+            layers.push(LlamaAttention {
+                attention_variant: AttentionVariant::Separate(SeparateAttention {
+                    attention_wq: weight((hidden_size, hidden_size))?,
+                    attention_wk: weight((n_kv_head * head_dim, hidden_size))?,
+                    attention_wv: weight((n_kv_head * head_dim, hidden_size))?,
+                    bias: None,
+                    interleaved_rope: false,
+                }),
+                attention_wo: weight((hidden_size, hidden_size))?,
+                attention_norm: norm()?,
+                feed_forward_variant: FeedForwardVariant::Llama(LlamaFeedForward {
+                    feed_forward_w1: weight((4 * hidden_size, hidden_size))?,
+                    feed_forward_w2: weight((hidden_size, 4 * hidden_size))?,
+                    feed_forward_w3: weight((4 * hidden_size, hidden_size))?,
+                }),
+                ffn_norm: norm()?,
+                n_head,
+                n_kv_head,
+                head_dim,
+                hidden_size,
+                rope_cache: rope.clone(),
+            });
+        }
+        Ok(Self {
+            config,
+            tok_embeddings: Embedding::new(
+                Tensor::randn(0f32, 1., (vocab_size, hidden_size), device)?,
+                hidden_size,
+            ),
+            layers,
+            norm: norm()?,
+            output: weight((vocab_size, hidden_size))?,
+            masks: Default::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn max_difference(a: &Tensor, b: &Tensor) -> f32 {
+        (a - b)
+            .unwrap()
+            .abs()
+            .unwrap()
+            .max_all()
+            .unwrap()
+            .to_scalar()
+            .unwrap()
+    }
+
+    #[test]
+    fn forward_batch_matches_forward() {
+        let device = Device::Cpu;
+        let model = Model::random(16, 64, &device).unwrap();
+        let prompts: [&[u32]; 3] = [&[1, 2, 3], &[4, 5, 6, 7, 8], &[9]];
+
+        let mut sequential = Vec::new();
+        let mut batched = Vec::new();
+        for prompt in prompts {
+            let mut cache = LlamaCache::new(&model.config);
+            model.forward(prompt, &device, Some(&mut cache)).unwrap();
+            sequential.push(cache.clone());
+            batched.push(cache);
+        }
+
+        let mut caches: Vec<_> = batched.iter_mut().collect();
+        let mut batch = BatchCache::new(&caches).unwrap();
+        for step in 0..4 {
+            let tokens: Vec<u32> = (0..3).map(|i| (i * 5 + step) % 16).collect();
+            let batch_logits = model
+                .forward_batch(&tokens, &device, &mut caches, &mut batch)
+                .unwrap();
+            for (i, cache) in sequential.iter_mut().enumerate() {
+                let logits = model.forward(&tokens[i..=i], &device, Some(cache)).unwrap();
+                let difference =
+                    max_difference(&logits.squeeze(0).unwrap(), &batch_logits.get(i).unwrap());
+                assert!(difference < 1e-4, "step {step}, sequence {i}: {difference}");
+            }
+        }
+        assert_eq!(batch.lengths(), [7, 9, 5]);
+
+        // Once the keys and values are copied back, each sequence can continue on its own
+        batch.write_back(&mut caches).unwrap();
+        for (cache, expected) in batched.iter_mut().zip(&mut sequential) {
+            assert_eq!(cache.tokens, expected.tokens);
+            let logits = model.forward(&[10], &device, Some(cache)).unwrap();
+            let expected = model.forward(&[10], &device, Some(expected)).unwrap();
+            assert!(max_difference(&logits, &expected) < 1e-4);
+        }
+    }
+
+    #[test]
+    fn failed_batch_steps_are_undone() {
+        let device = Device::Cpu;
+        let model = Model::random(16, 64, &device).unwrap();
+        let mut caches = Vec::new();
+        for prompt in [&[1, 2][..], &[3]] {
+            let mut cache = LlamaCache::new(&model.config);
+            model.forward(prompt, &device, Some(&mut cache)).unwrap();
+            caches.push(cache);
+        }
+        let mut caches: Vec<_> = caches.iter_mut().collect();
+        let mut batch = BatchCache::new(&caches).unwrap();
+
+        // A token outside of the vocabulary fails the embedding lookup
+        assert!(model
+            .forward_batch(&[4, 100], &device, &mut caches, &mut batch)
+            .is_err());
+        assert_eq!(batch.lengths(), [2, 1]);
+        assert_eq!(caches[0].tokens, [1, 2]);
+        assert_eq!(caches[1].tokens, [3]);
+
+        model
+            .forward_batch(&[4, 5], &device, &mut caches, &mut batch)
+            .unwrap();
+        assert_eq!(batch.lengths(), [3, 2]);
     }
 }
@@ -88,6 +88,40 @@ impl RopeCache {
     ) -> candle_core::Result<(Tensor, Tensor)> {
         self.forward_with_embed(q, k, start_pos, candle_nn::rotary_emb::rope_i)
     }
+
+    /// Apply the rotary embedding to a batch of sequences that each start at a different position.
+    pub fn forward_batch(
+        &self,
+        q: &Tensor,
+        k: &Tensor,
+        start_positions: &[usize],
+        interleaved: bool,
+    ) -> candle_core::Result<(Tensor, Tensor)> {
+        let forward = |q: &Tensor, k: &Tensor, start_pos| {
+            if interleaved {
+                self.forward_i(q, k, start_pos)
+            } else {
+                self.forward(q, k, start_pos)
+            }
+        };
+        // If every sequence starts at the same position, we can apply the embedding to the whole batch at once
+        match start_positions {
+            [first, rest @ ..] if rest.iter().all(|pos| pos == first) => {
+                return forward(q, k, *first);
+            }
+            _ => {}
+        }
+
+        let mut queries = Vec::with_capacity(start_positions.len());
+        let mut keys = Vec::with_capacity(start_positions.len());
+        for (i, &start_pos) in start_positions.iter().enumerate() {
+            let (q, k) = forward(&q.narrow(0, i, 1)?, &k.narrow(0, i, 1)?, start_pos)?;
+            queries.push(q);
+            keys.push(k);
+        }
+
+        Ok((Tensor::cat(&queries, 0)?, Tensor::cat(&keys, 0)?))
+    }
 }
 
 #[test]
@@ -8,6 +8,8 @@ use std::collections::HashMap;
 #[derive(Debug, Clone)]
 pub struct LlamaSession {
     pub(crate) cache: LlamaCache,
+    /// The cache of the draft model if the model uses speculative decoding.
+    pub(crate) draft_cache: Option<LlamaCache>,
 }
 
 impl Session for LlamaSession {
@@ -37,6 +39,14 @@ impl Session for LlamaSession {
     {
         Ok(self.clone())
     }
+
+    fn rewind(&mut self, tokens: usize) -> anyhow::Result<()> {
+        Ok(self.cache.rewind(tokens)?)
+    }
+
+    fn supports_rewind(&self) -> bool {
+        true
+    }
 }
 
 impl LlamaSession {
@@ -48,6 +58,7 @@ impl LlamaSession {
     /// Import a cache tensor map.
     pub fn set_tensor_map(&mut self, map: HashMap<String, Tensor>) -> candle_core::Result<()> {
         self.cache = LlamaCache::from_tensor_map(map)?;
+        self.draft_cache = None;
         Ok(())
     }
 
@@ -55,6 +66,7 @@ impl LlamaSession {
     pub fn from_tensor_map(map: HashMap<String, Tensor>) -> candle_core::Result<Self> {
         Ok(Self {
             cache: LlamaCache::from_tensor_map(map)?,
+            draft_cache: None,
         })
     }
 }
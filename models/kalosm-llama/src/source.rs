@@ -1,7 +1,13 @@
 use kalosm_common::{FileLoadingProgress, FileSource};
-use kalosm_language_model::ChatMarkers;
+use kalosm_language_model::{ChatMarkers, ChatTemplate};
+use std::path::Path;
 use tokenizers::Tokenizer;
 
+use crate::gguf_tokenizer::{
+    chat_markers_from_template, chat_template_from_gguf, eos_token_id_from_gguf,
+    read_gguf_metadata, tokenizer_from_gguf,
+};
+
 fn llama_tokenizer() -> FileSource {
     FileSource::huggingface(
         "hf-internal-testing/llama-tokenizer".to_string(),
@@ -45,11 +51,20 @@ fn qwen_chat_markers() -> Option<ChatMarkers> {
     })
 }
 
+/// The tokenizer and chat format for a model.
+pub(crate) struct LoadedTokenizer {
+    pub(crate) tokenizer: Tokenizer,
+    pub(crate) chat_markers: Option<ChatMarkers>,
+    pub(crate) chat_template: Option<ChatTemplate>,
+    /// The end of sequence token from the model file
+    pub(crate) stop_token: Option<u32>,
+}
+
 /// A source for the Llama model.
 #[derive(Clone, Debug)]
 pub struct LlamaSource {
     pub(crate) model: FileSource,
-    pub(crate) tokenizer: FileSource,
+    pub(crate) tokenizer: Option<FileSource>,
     pub(crate) group_query_attention: u8,
     pub(crate) markers: Option<ChatMarkers>,
     pub(crate) cache: kalosm_common::Cache,
@@ -60,13 +75,47 @@ impl LlamaSource {
     pub fn new(model: FileSource, tokenizer: FileSource) -> Self {
         Self {
             model,
-            tokenizer,
+            tokenizer: Some(tokenizer),
+            group_query_attention: 1,
+            markers: Default::default(),
+            cache: Default::default(),
+        }
+    }
+
+    /// Create a new source for the Llama model from a single gguf file. The tokenizer is built from the `tokenizer.ggml.*` metadata in the file and chats are formatted with the jinja template in the `tokenizer.chat_template` metadata.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder()
+    ///     .with_source(LlamaSource::gguf(FileSource::huggingface(
+    ///         "bartowski/Llama-3.2-1B-Instruct-GGUF".to_string(),
+    ///         "main".to_string(),
+    ///         "Llama-3.2-1B-Instruct-Q4_K_M.gguf".to_string(),
+    ///     )))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn gguf(model: FileSource) -> Self {
+        Self {
+            model,
+            tokenizer: None,
             group_query_attention: 1,
             markers: Default::default(),
             cache: Default::default(),
         }
     }
 
+    /// Set the tokenizer file to use for the model instead of the tokenizer embedded in the gguf file
+    pub fn with_tokenizer(mut self, tokenizer: FileSource) -> Self {
+        self.tokenizer = Some(tokenizer);
+
+        self
+    }
+
     /// Set the cache location to use for the model (defaults DATA_DIR/kalosm/cache)
     pub fn with_cache(mut self, cache: kalosm_common::Cache) -> Self {
         self.cache = cache;
@@ -90,12 +139,91 @@ impl LlamaSource {
         self
     }
 
+    /// Load the tokenizer file if the source has one.
     pub(crate) async fn tokenizer(
         &self,
         progress: impl FnMut(FileLoadingProgress),
-    ) -> anyhow::Result<Tokenizer> {
-        let tokenizer_path = self.cache.get(&self.tokenizer, progress).await?;
-        Tokenizer::from_file(tokenizer_path).map_err(anyhow::Error::msg)
+    ) -> anyhow::Result<Option<Tokenizer>> {
+        let Some(tokenizer) = &self.tokenizer else {
+            return Ok(None);
+        };
+        let tokenizer_path = self.cache.get(tokenizer, progress).await?;
+        Tokenizer::from_file(tokenizer_path)
+            .map(Some)
+            .map_err(anyhow::Error::msg)
+    }
+
+    /// Fill in the tokenizer and chat format that are missing from the source with the metadata embedded in the model file, and read the stop token from the model file.
+    pub(crate) fn load_embedded(
+        &self,
+        model_path: &Path,
+        tokenizer: Option<Tokenizer>,
+    ) -> anyhow::Result<LoadedTokenizer> {
+        let is_gguf = model_path.extension().and_then(|ext| ext.to_str()) == Some("gguf");
+        let metadata = if is_gguf {
+            Some(read_gguf_metadata(model_path)?)
+        } else {
+            None
+        };
+
+        let tokenizer = match (tokenizer, &metadata) {
+            (Some(tokenizer), _) => tokenizer,
+            (None, Some(metadata)) => tokenizer_from_gguf(metadata)?,
+            (None, None) => anyhow::bail!(
+                "The model {} does not contain a tokenizer. Set a tokenizer file with `LlamaSource::with_tokenizer`",
+                self.model
+            ),
+        };
+        let stop_token = metadata.as_ref().and_then(eos_token_id_from_gguf);
+
+        // Chat markers set on the source take priority over the chat template in the model file
+        if self.markers.is_some() {
+            return Ok(LoadedTokenizer {
+                tokenizer,
+                chat_markers: self.markers.clone(),
+                chat_template: None,
+                stop_token,
+            });
+        }
+        let Some(template) = metadata.as_ref().and_then(chat_template_from_gguf) else {
+            return Ok(LoadedTokenizer {
+                tokenizer,
+                chat_markers: None,
+                chat_template: None,
+                stop_token,
+            });
+        };
+        let chat_markers = chat_markers_from_template(&template.source);
+        let chat_template =
+            match ChatTemplate::new(template.source, template.bos_token, template.eos_token) {
+                Ok(template) => Some(template),
+                Err(err) => {
+                    tracing::warn!("Failed to load the chat template in {}: {err}", self.model);
+                    None
+                }
+            };
+        if chat_markers.is_none() && chat_template.is_none() {
+            tracing::warn!(
+                "Unknown chat template in {}. Set the chat markers with `LlamaSource::with_chat_markers` to use the model for chat",
+                self.model
+            );
+        }
+
+        Ok(LoadedTokenizer {
+            tokenizer,
+            chat_markers,
+            chat_template,
+            stop_token,
+        })
+    }
+
+    /// Check if the model and tokenizer files are already downloaded.
+    pub(crate) fn downloaded(&self) -> bool {
+        self.model.downloaded()
+            && self
+                .tokenizer
+                .as_ref()
+                .map_or(true, |tokenizer| tokenizer.downloaded())
     }
 
     pub(crate) async fn model(
@@ -113,7 +241,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "mistral-7b-v0.1.Q4_K_M.gguf".to_string(),
             ),
-            tokenizer: mistral_tokenizer(),
+            tokenizer: Some(mistral_tokenizer()),
             group_query_attention: 8,
             ..Default::default()
         }
@@ -127,7 +255,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "mistral-7b-instruct-v0.1.Q4_K_M.gguf".to_string(),
             ),
-            tokenizer: mistral_tokenizer(),
+            tokenizer: Some(mistral_tokenizer()),
             group_query_attention: 8,
             markers: Some(ChatMarkers {
                 system_prompt_marker: "<s>[INST] ",
@@ -149,7 +277,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "mistral-7b-instruct-v0.2.Q4_K_M.gguf".to_string(),
             ),
-            tokenizer: mistral_tokenizer(),
+            tokenizer: Some(mistral_tokenizer()),
             group_query_attention: 8,
             markers: Some(ChatMarkers {
                 system_prompt_marker: "<s>[INST] ",
@@ -171,7 +299,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "neuralhermes-2.5-mistral-7b.Q4_0.gguf".to_string(),
             ),
-            tokenizer: mistral_tokenizer(),
+            tokenizer: Some(mistral_tokenizer()),
             group_query_attention: 8,
             markers: Some(ChatMarkers {
                 system_prompt_marker: "<|im_start|>system\n",
@@ -193,11 +321,11 @@ impl LlamaSource {
                 "main".to_string(),
                 "neural-chat-7b-v3-3.Q4_0.gguf".to_string(),
             ),
-            tokenizer: FileSource::huggingface(
+            tokenizer: Some(FileSource::huggingface(
                 "Intel/neural-chat-7b-v3-3".to_string(),
                 "main".to_string(),
                 "tokenizer.json".to_string(),
-            ),
+            )),
             group_query_attention: 8,
             markers: Some(ChatMarkers {
                 system_prompt_marker: "### System:\n",
@@ -219,7 +347,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "zephyr-7b-alpha.Q4_K_M.gguf".to_string(),
             ),
-            tokenizer: mistral_tokenizer(),
+            tokenizer: Some(mistral_tokenizer()),
             group_query_attention: 8,
             markers: Some(ChatMarkers {
                 system_prompt_marker: "<|system|>",
@@ -241,7 +369,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "zephyr-7b-beta.Q4_K_M.gguf".to_string(),
             ),
-            tokenizer: mistral_tokenizer(),
+            tokenizer: Some(mistral_tokenizer()),
             group_query_attention: 8,
             markers: Some(ChatMarkers {
                 system_prompt_marker: "<|system|>",
@@ -263,11 +391,11 @@ impl LlamaSource {
                 "main".to_string(),
                 "openchat-3.5-0106.Q4_K_M.gguf".to_string(),
             ),
-            tokenizer: FileSource::huggingface(
+            tokenizer: Some(FileSource::huggingface(
                 "openchat/openchat-3.5-0106".to_string(),
                 "main".to_string(),
                 "tokenizer.json".to_string(),
-            ),
+            )),
             group_query_attention: 8,
             markers: Some(ChatMarkers {
                 system_prompt_marker: "",
@@ -289,11 +417,11 @@ impl LlamaSource {
                 "main".to_string(),
                 "starling-lm-7b-alpha.Q4_K_M.gguf".to_string(),
             ),
-            tokenizer: FileSource::huggingface(
+            tokenizer: Some(FileSource::huggingface(
                 "berkeley-nest/Starling-LM-7B-alpha".to_string(),
                 "main".to_string(),
                 "tokenizer.json".to_string(),
-            ),
+            )),
             group_query_attention: 8,
             markers: Some(ChatMarkers {
                 system_prompt_marker: "",
@@ -315,11 +443,11 @@ impl LlamaSource {
                 "main".to_string(),
                 "Starling-LM-7B-beta-Q4_K_M.gguf".to_string(),
             ),
-            tokenizer: FileSource::huggingface(
+            tokenizer: Some(FileSource::huggingface(
                 "Nexusflow/Starling-LM-7B-beta".to_string(),
                 "main".to_string(),
                 "tokenizer.json".to_string(),
-            ),
+            )),
             group_query_attention: 8,
             markers: Some(ChatMarkers {
                 system_prompt_marker: "",
@@ -341,7 +469,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "WizardLM-2-7B-Q4_K_M.gguf".to_string(),
             ),
-            tokenizer: mistral_tokenizer(),
+            tokenizer: Some(mistral_tokenizer()),
             group_query_attention: 8,
             markers: Some(ChatMarkers {
                 system_prompt_marker: "",
@@ -363,11 +491,11 @@ impl LlamaSource {
                 "main".to_string(),
                 "tinyllama-1.1b-chat-v1.0.Q4_K_M.gguf".to_string(),
             ),
-            tokenizer: FileSource::huggingface(
+            tokenizer: Some(FileSource::huggingface(
                 "TinyLlama/TinyLlama-1.1B-Chat-v1.0".to_string(),
                 "main".to_string(),
                 "tokenizer.json".to_string(),
-            ),
+            )),
             group_query_attention: 4,
             markers: Some(ChatMarkers {
                 system_prompt_marker: "<|system|>\n",
@@ -389,11 +517,11 @@ impl LlamaSource {
                 "main".to_string(),
                 "tinyllama-1.1b-intermediate-step-1431k-3t.Q4_K_M.gguf".to_string(),
             ),
-            tokenizer: FileSource::huggingface(
+            tokenizer: Some(FileSource::huggingface(
                 "TinyLlama/TinyLlama-1.1B-intermediate-step-1431k-3T".to_string(),
                 "main".to_string(),
                 "tokenizer.json".to_string(),
-            ),
+            )),
             group_query_attention: 4,
             ..Default::default()
         }
@@ -407,11 +535,11 @@ impl LlamaSource {
                 "5eef2ce24766d31909c0b269fe90c817a8f263fb".to_string(),
                 "Phi-3-mini-4k-instruct-q4.gguf".to_string(),
             ),
-            tokenizer: FileSource::huggingface(
+            tokenizer: Some(FileSource::huggingface(
                 "microsoft/Phi-3-mini-4k-instruct".to_string(),
                 "main".to_string(),
                 "tokenizer.json".to_string(),
-            ),
+            )),
             group_query_attention: 1,
             markers: Some(ChatMarkers {
                 system_prompt_marker: "<|system|>\n",
@@ -434,11 +562,11 @@ impl LlamaSource {
                 "main".to_string(),
                 "Phi-3.1-mini-4k-instruct-Q4_K_M.gguf".to_string(),
             ),
-            tokenizer: FileSource::huggingface(
+            tokenizer: Some(FileSource::huggingface(
                 "microsoft/Phi-3-mini-4k-instruct".to_string(),
                 "main".to_string(),
                 "tokenizer.json".to_string(),
-            ),
+            )),
             group_query_attention: 1,
             markers: Some(ChatMarkers {
                 system_prompt_marker: "<|system|>\n",
@@ -460,11 +588,11 @@ impl LlamaSource {
                 "main".to_string(),
                 "Phi-3.5-mini-instruct-Q4_K_M.gguf".to_string(),
             ),
-            tokenizer: FileSource::huggingface(
+            tokenizer: Some(FileSource::huggingface(
                 "microsoft/Phi-3.5-mini-instruct".to_string(),
                 "main".to_string(),
                 "tokenizer.json".to_string(),
-            ),
+            )),
             group_query_attention: 1,
             markers: Some(ChatMarkers {
                 system_prompt_marker: "<|system|>\n",
@@ -486,7 +614,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "llama-2-7b.ggmlv3.q4_0.bin".to_string(),
             ),
-            tokenizer: llama_tokenizer(),
+            tokenizer: Some(llama_tokenizer()),
             group_query_attention: 1,
             ..Default::default()
         }
@@ -500,7 +628,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "Meta-Llama-3-8B-Q4_K_M.gguf".to_string(),
             ),
-            tokenizer: llama_v3_tokenizer(),
+            tokenizer: Some(llama_v3_tokenizer()),
             group_query_attention: 1,
             ..Default::default()
         }
@@ -514,7 +642,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "Meta-Llama-3-8B-Instruct-Q5_K_M.gguf".to_string(),
             ),
-            tokenizer: llama_v3_tokenizer(),
+            tokenizer: Some(llama_v3_tokenizer()),
             group_query_attention: 1,
             markers: Some(ChatMarkers {
                 system_prompt_marker: "<|begin_of_text|><|start_header_id|>system<|end_header_id|>",
@@ -536,7 +664,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "Meta-Llama-3.1-8B-Instruct-Q4_K_M.gguf".to_string(),
             ),
-            tokenizer: llama_v3_tokenizer(),
+            tokenizer: Some(llama_v3_tokenizer()),
             group_query_attention: 1,
             markers: Some(ChatMarkers {
                 system_prompt_marker:
@@ -559,7 +687,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "Meta-Llama-3-8B-Instruct-Q8_0.gguf".to_string(),
             ),
-            tokenizer: llama_v3_tokenizer(),
+            tokenizer: Some(llama_v3_tokenizer()),
             group_query_attention: 1,
             markers: Some(ChatMarkers {
                 system_prompt_marker: "<|begin_of_text|><|start_header_id|>system<|end_header_id|>",
@@ -581,7 +709,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "Llama-3-Instruct-8B-SPPO-Iter3-Q4_K_M.gguf".to_string(),
             ),
-            tokenizer: llama_v3_tokenizer(),
+            tokenizer: Some(llama_v3_tokenizer()),
             group_query_attention: 1,
             markers: Some(ChatMarkers {
                 system_prompt_marker: "<|begin_of_text|><|start_header_id|>system<|end_header_id|>",
@@ -603,7 +731,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "Llama-3.2-1B-Instruct-Q4_K_M.gguf".to_string(),
             ),
-            tokenizer: llama_v3_tokenizer(),
+            tokenizer: Some(llama_v3_tokenizer()),
             group_query_attention: 1,
             markers: Some(ChatMarkers {
                 system_prompt_marker:
@@ -626,7 +754,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "Llama-3.2-3B-Instruct-Q4_K_M.gguf".to_string(),
             ),
-            tokenizer: llama_v3_tokenizer(),
+            tokenizer: Some(llama_v3_tokenizer()),
             group_query_attention: 1,
             markers: Some(ChatMarkers {
                 system_prompt_marker:
@@ -649,7 +777,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "llama-2-13b.ggmlv3.q4_0.bin".to_string(),
             ),
-            tokenizer: llama_tokenizer(),
+            tokenizer: Some(llama_tokenizer()),
             group_query_attention: 1,
             markers: Default::default(),
             cache: Default::default(),
@@ -664,7 +792,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "llama-2-70b.ggmlv3.q4_0.bin".to_string(),
             ),
-            tokenizer: llama_tokenizer(),
+            tokenizer: Some(llama_tokenizer()),
             group_query_attention: 8,
             ..Default::default()
         }
@@ -678,7 +806,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "llama-2-7b-chat.ggmlv3.q4_0.bin".to_string(),
             ),
-            tokenizer: llama_tokenizer(),
+            tokenizer: Some(llama_tokenizer()),
             group_query_attention: 1,
             markers: Some(ChatMarkers {
                 system_prompt_marker: "<<SYS>>\n",
@@ -700,7 +828,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "llama-2-13b-chat.ggmlv3.q4_0.bin".to_string(),
             ),
-            tokenizer: llama_tokenizer(),
+            tokenizer: Some(llama_tokenizer()),
             group_query_attention: 1,
             markers: Some(ChatMarkers {
                 system_prompt_marker: "<<SYS>>\n",
@@ -722,7 +850,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "llama-2-70b-chat.ggmlv3.q4_0.bin".to_string(),
             ),
-            tokenizer: llama_tokenizer(),
+            tokenizer: Some(llama_tokenizer()),
             group_query_attention: 8,
             markers: Some(ChatMarkers {
                 system_prompt_marker: "<<SYS>>\n",
@@ -744,7 +872,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "codellama-7b.Q8_0.gguf".to_string(),
             ),
-            tokenizer: llama_tokenizer(),
+            tokenizer: Some(llama_tokenizer()),
             group_query_attention: 1,
             ..Default::default()
         }
@@ -758,7 +886,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "codellama-13b.Q8_0.gguf".to_string(),
             ),
-            tokenizer: llama_tokenizer(),
+            tokenizer: Some(llama_tokenizer()),
             group_query_attention: 1,
             ..Default::default()
         }
@@ -772,7 +900,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "codellama-34b.Q8_0.gguf".to_string(),
             ),
-            tokenizer: llama_tokenizer(),
+            tokenizer: Some(llama_tokenizer()),
             group_query_attention: 1,
             ..Default::default()
         }
@@ -786,11 +914,11 @@ impl LlamaSource {
                 "main".to_string(),
                 "solar-10.7b-v1.0.Q4_K_M.gguf".to_string(),
             ),
-            tokenizer: FileSource::huggingface(
+            tokenizer: Some(FileSource::huggingface(
                 "upstage/SOLAR-10.7B-v1.0".to_string(),
                 "main".to_string(),
                 "tokenizer.json".to_string(),
-            ),
+            )),
             ..Default::default()
         }
     }
@@ -803,11 +931,11 @@ impl LlamaSource {
                 "main".to_string(),
                 "solar-10.7b-instruct-v1.0.Q4_K_M.gguf".to_string(),
             ),
-            tokenizer: FileSource::huggingface(
+            tokenizer: Some(FileSource::huggingface(
                 "upstage/SOLAR-10.7B-Instruct-v1.0".to_string(),
                 "main".to_string(),
                 "tokenizer.json".to_string(),
-            ),
+            )),
             markers: Some(ChatMarkers {
                 system_prompt_marker: "<s>### System:\n",
                 end_system_prompt_marker: "",
@@ -828,7 +956,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "qwen2.5-0.5b-instruct-q4_k_m.gguf".to_string(),
             ),
-            tokenizer: qwen_tokenizer(),
+            tokenizer: Some(qwen_tokenizer()),
             group_query_attention: 7,
             markers: qwen_chat_markers(),
             cache: Default::default(),
@@ -843,7 +971,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "qwen2.5-1.5b-instruct-q4_k_m.gguf".to_string(),
             ),
-            tokenizer: qwen_tokenizer(),
+            tokenizer: Some(qwen_tokenizer()),
             group_query_attention: 7,
             markers: qwen_chat_markers(),
             cache: Default::default(),
@@ -858,7 +986,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "qwen2.5-3b-instruct-q4_k_m.gguf".to_string(),
             ),
-            tokenizer: qwen_tokenizer(),
+            tokenizer: Some(qwen_tokenizer()),
             group_query_attention: 7,
             markers: qwen_chat_markers(),
             cache: Default::default(),
@@ -873,7 +1001,7 @@ impl LlamaSource {
                 "main".to_string(),
                 "Qwen2.5-7B-Instruct-Q4_K_M.gguf".to_string(),
             ),
-            tokenizer: qwen_tokenizer(),
+            tokenizer: Some(qwen_tokenizer()),
             group_query_attention: 7,
             markers: qwen_chat_markers(),
             cache: Default::default(),
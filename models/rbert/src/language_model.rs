@@ -1,5 +1,6 @@
 pub use crate::Bert;
 use crate::BertBuilder;
+use crate::BertRerankerBuilder;
 use crate::Pooling;
 use kalosm_common::*;
 pub use kalosm_language_model::{
@@ -25,6 +26,22 @@ impl ModelBuilder for BertBuilder {
     }
 }
 
+#[async_trait::async_trait]
+impl ModelBuilder for BertRerankerBuilder {
+    type Model = crate::BertReranker;
+
+    async fn start_with_loading_handler(
+        self,
+        loading_handler: impl FnMut(ModelLoadingProgress) + Send + 'static,
+    ) -> anyhow::Result<Self::Model> {
+        self.build_with_loading_handler(loading_handler).await
+    }
+
+    fn requires_download(&self) -> bool {
+        true
+    }
+}
+
 impl Bert {
     /// Embed a sentence with a specific pooling strategy.
     pub fn embed_with_pooling(
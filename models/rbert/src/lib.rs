@@ -58,11 +58,13 @@ use tokenizers::{Encoding, PaddingParams, Tokenizer};
 
 mod language_model;
 mod raw;
+mod reranker;
 mod source;
 
 pub use crate::language_model::*;
 use crate::raw::DTYPE;
-pub use crate::raw::{BertModel, Config};
+pub use crate::raw::{BertCrossEncoderModel, BertModel, Config};
+pub use crate::reranker::*;
 pub use crate::source::*;
 
 /// A builder for a [`Bert`] model
@@ -17,8 +17,9 @@ use self_output::*;
 mod intermediate_layer;
 use intermediate_layer::*;
 
-use candle_core::{DType, Device, Result, Tensor};
-use candle_nn::VarBuilder;
+use candle_core::{DType, Device, IndexOp, Result, Tensor};
+use candle_nn::{Module, VarBuilder};
+use candle_transformers::models::with_tracing::{linear, Linear};
 use serde::Deserialize;
 
 pub(crate) const DTYPE: DType = DType::F32;
@@ -153,3 +154,61 @@ impl BertModel {
         self.embeddings.embedding_dim()
     }
 }
+
+/// A raw synchronous Bert model with a classification head that scores how well a pair of texts match. You should generally use the [`super::BertReranker`] instead.
+// https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/modeling_bert.py#L1562
+pub struct BertCrossEncoderModel {
+    bert: BertModel,
+    pooler: Linear,
+    classifier: Linear,
+    span: tracing::Span,
+}
+
+impl BertCrossEncoderModel {
+    /// Load a new [`BertCrossEncoderModel`] from [`VarBuilder`] with a [`Config`]. The classifier must have a single output.
+    pub fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
+        let bert = BertModel::load(vb.clone(), config)?;
+        let prefix = config.model_type.as_deref().unwrap_or("bert");
+        let pooler = linear(
+            config.hidden_size,
+            config.hidden_size,
+            vb.pp(format!("{prefix}.pooler.dense")),
+        )?;
+        let classifier = linear(config.hidden_size, 1, vb.pp("classifier"))?;
+        Ok(Self {
+            bert,
+            pooler,
+            classifier,
+            span: tracing::span!(tracing::Level::TRACE, "cross-encoder"),
+        })
+    }
+
+    /// Run the model with a batch of text pairs. Returns the relevance logit for each pair with the shape `(batch, 1)`.
+    ///
+    /// input_ids: The token ids of both texts in each pair.
+    /// token_type_ids: The token type ids of the input. This should be 0 for the tokens in the first text and 1 for the tokens in the second text.
+    /// attention_mask: The attention mask of the input. If you pad the input, you will need to create an attention mask.
+    pub fn forward(
+        &self,
+        input_ids: &Tensor,
+        token_type_ids: &Tensor,
+        attention_mask: Option<&Tensor>,
+    ) -> Result<Tensor> {
+        let _enter = self.span.enter();
+        let sequence_output =
+            self.bert
+                .forward(input_ids, token_type_ids, attention_mask, false)?;
+        // The CLS token summarizes the pair
+        let cls = sequence_output.i((.., 0))?;
+        let pooled = self.pooler.forward(&cls)?.tanh()?;
+        self.classifier.forward(&pooled)
+    }
+
+    pub(crate) fn device(&self) -> &Device {
+        &self.bert.device
+    }
+
+    pub(crate) fn max_seq_len(&self) -> usize {
+        self.bert.max_seq_len()
+    }
+}
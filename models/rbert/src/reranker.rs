@@ -0,0 +1,197 @@
+use std::sync::{Arc, RwLock};
+
+use candle_core::Tensor;
+use candle_nn::VarBuilder;
+use kalosm_common::*;
+use tokenizers::{Encoding, PaddingParams, Tokenizer, TruncationParams};
+
+use crate::raw::{BertCrossEncoderModel, Config, DTYPE};
+use crate::BertRerankerSource;
+
+/// A builder for a [`BertReranker`] model
+#[derive(Default)]
+pub struct BertRerankerBuilder {
+    source: BertRerankerSource,
+    cache: kalosm_common::Cache,
+}
+
+impl BertRerankerBuilder {
+    /// Set the source of the model
+    pub fn with_source(mut self, source: BertRerankerSource) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Set the cache location to use for the model (defaults DATA_DIR/kalosm/cache)
+    pub fn with_cache(mut self, cache: kalosm_common::Cache) -> Self {
+        self.cache = cache;
+
+        self
+    }
+
+    /// Build the model
+    pub async fn build(self) -> anyhow::Result<BertReranker> {
+        self.build_with_loading_handler(ModelLoadingProgress::multi_bar_loading_indicator())
+            .await
+    }
+
+    /// Build the model with a loading handler
+    pub async fn build_with_loading_handler(
+        self,
+        loading_handler: impl FnMut(ModelLoadingProgress) + Send + 'static,
+    ) -> anyhow::Result<BertReranker> {
+        BertReranker::from_builder(self, loading_handler).await
+    }
+}
+
+/// A bert cross-encoder that scores how relevant documents are to a query.
+///
+/// Unlike an embedding model, a cross-encoder reads the query and the document together. It is too slow to search a whole database, but much better at sorting the top results of a vector search.
+///
+/// # Example
+/// ```rust, no_run
+/// use rbert::*;
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let reranker = BertReranker::new().await?;
+///     let documents = [
+///         "Napoleon was a great general",
+///         "Kalosm is a library for local AI",
+///         "Cats are cool",
+///     ];
+///     let scores = reranker.score_batch("What is Kalosm?", documents.to_vec())?;
+///     for (score, document) in scores.iter().zip(documents) {
+///         println!("score: {score:.2} '{document}'");
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone)]
+pub struct BertReranker {
+    model: Arc<BertCrossEncoderModel>,
+    tokenizer: Arc<RwLock<Tokenizer>>,
+}
+
+impl BertReranker {
+    /// Create a new [`BertRerankerBuilder`]
+    pub fn builder() -> BertRerankerBuilder {
+        BertRerankerBuilder::default()
+    }
+
+    /// Create a new default bert reranker
+    pub async fn new() -> anyhow::Result<Self> {
+        Self::builder().build().await
+    }
+
+    async fn from_builder(
+        builder: BertRerankerBuilder,
+        mut progress_handler: impl FnMut(ModelLoadingProgress) + Send + 'static,
+    ) -> anyhow::Result<Self> {
+        let BertRerankerBuilder { source, cache } = builder;
+        let BertRerankerSource {
+            config,
+            tokenizer,
+            model,
+        } = source;
+
+        let source = format!("Config ({})", config);
+        let mut create_progress = ModelLoadingProgress::downloading_progress(source);
+        let config_filename = cache
+            .get(&config, |progress| {
+                progress_handler(create_progress(progress))
+            })
+            .await?;
+        let tokenizer_source = format!("Tokenizer ({})", tokenizer);
+        let mut create_progress = ModelLoadingProgress::downloading_progress(tokenizer_source);
+        let tokenizer_filename = cache
+            .get(&tokenizer, |progress| {
+                progress_handler(create_progress(progress))
+            })
+            .await?;
+        let model_source = format!("Model ({})", model);
+        let mut create_progress = ModelLoadingProgress::downloading_progress(model_source);
+        let weights_filename = cache
+            .get(&model, |progress| {
+                progress_handler(create_progress(progress))
+            })
+            .await?;
+
+        let config = std::fs::read_to_string(config_filename)?;
+        let config: Config = serde_json::from_str(&config)?;
+
+        let device = accelerated_device_if_available()?;
+        let vb =
+            unsafe { VarBuilder::from_mmaped_safetensors(&[&weights_filename], DTYPE, &device)? };
+        let model = BertCrossEncoderModel::load(vb, &config)?;
+        let mut tokenizer =
+            Tokenizer::from_file(&tokenizer_filename).map_err(anyhow::Error::msg)?;
+        tokenizer.with_padding(None);
+        // Long documents are cut off so the pair fits in the model
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: model.max_seq_len(),
+                ..Default::default()
+            }))
+            .map_err(anyhow::Error::msg)?;
+
+        Ok(BertReranker {
+            tokenizer: Arc::new(RwLock::new(tokenizer)),
+            model: Arc::new(model),
+        })
+    }
+
+    /// Score how relevant each document is to the query. Returns a score between 0 and 1 for each document in the same order as the documents. Higher scores are more relevant.
+    pub fn score_batch(&self, query: &str, documents: Vec<&str>) -> anyhow::Result<Vec<f32>> {
+        const BATCH_SIZE: usize = 16;
+
+        let mut scores = Vec::with_capacity(documents.len());
+        for documents in documents.chunks(BATCH_SIZE) {
+            let pairs = documents
+                .iter()
+                .map(|document| (query, *document))
+                .collect::<Vec<_>>();
+            let encodings = {
+                let tokenizer_read = self.tokenizer.read().unwrap();
+                tokenizer_read.encode_batch(pairs, true)
+            }
+            .map_err(anyhow::Error::msg)?;
+            scores.extend(maybe_autoreleasepool(|| self.score_encodings(encodings))?);
+        }
+        Ok(scores)
+    }
+
+    fn score_encodings(&self, mut encodings: Vec<Encoding>) -> anyhow::Result<Vec<f32>> {
+        if encodings.is_empty() {
+            return Ok(Vec::new());
+        }
+        let device = self.model.device();
+        let pp = PaddingParams {
+            strategy: tokenizers::PaddingStrategy::BatchLongest,
+            ..Default::default()
+        };
+        tokenizers::pad_encodings(&mut encodings, &pp).map_err(anyhow::Error::msg)?;
+
+        let stack = |values: fn(&Encoding) -> &[u32]| -> anyhow::Result<Tensor> {
+            let rows = encodings
+                .iter()
+                .map(|encoding| Tensor::new(values(encoding), device))
+                .collect::<candle_core::Result<Vec<_>>>()?;
+            Ok(Tensor::stack(&rows, 0)?)
+        };
+        let token_ids = stack(Encoding::get_ids)?;
+        // The token type ids tell the model which tokens are from the query and which are from the document
+        let token_type_ids = stack(Encoding::get_type_ids)?;
+        let attention_mask = stack(Encoding::get_attention_mask)?;
+
+        let logits = self
+            .model
+            .forward(&token_ids, &token_type_ids, Some(&attention_mask))?;
+        let logits = logits.squeeze(1)?.to_vec1::<f32>()?;
+
+        Ok(logits
+            .into_iter()
+            .map(|logit| 1. / (1. + (-logit).exp()))
+            .collect())
+    }
+}
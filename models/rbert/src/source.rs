@@ -244,3 +244,69 @@ impl Default for BertSource {
         Self::bge_small_en()
     }
 }
+
+/// The source of a [`crate::BertReranker`] model
+pub struct BertRerankerSource {
+    pub(crate) config: FileSource,
+    pub(crate) tokenizer: FileSource,
+    pub(crate) model: FileSource,
+}
+
+impl BertRerankerSource {
+    /// Create a new [`BertRerankerSource`] with the default model
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the model to use, check out available cross-encoder models: <https://huggingface.co/cross-encoder>
+    pub fn with_model(mut self, model: FileSource) -> Self {
+        self.model = model;
+        self
+    }
+
+    /// Set the tokenizer to use
+    pub fn with_tokenizer(mut self, tokenizer: FileSource) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+
+    /// Set the config to use
+    pub fn with_config(mut self, config: FileSource) -> Self {
+        self.config = config;
+        self
+    }
+
+    fn cross_encoder(model_id: &str) -> Self {
+        let file = |name: &str| {
+            FileSource::huggingface(model_id.to_string(), "main".to_string(), name.to_string())
+        };
+        Self {
+            config: file("config.json"),
+            tokenizer: file("tokenizer.json"),
+            model: file("model.safetensors"),
+        }
+    }
+
+    /// Create a new [`BertRerankerSource`] with the [ms-marco-TinyBERT-L-2-v2](https://huggingface.co/cross-encoder/ms-marco-TinyBERT-L-2-v2) model
+    pub fn ms_marco_tiny_bert_l2_v2() -> Self {
+        Self::cross_encoder("cross-encoder/ms-marco-TinyBERT-L-2-v2")
+    }
+
+    /// Create a new [`BertRerankerSource`] with the [ms-marco-MiniLM-L-6-v2](https://huggingface.co/cross-encoder/ms-marco-MiniLM-L-6-v2) model
+    pub fn ms_marco_mini_lm_l6_v2() -> Self {
+        Self::cross_encoder("cross-encoder/ms-marco-MiniLM-L-6-v2")
+    }
+
+    /// Create a new [`BertRerankerSource`] with the [ms-marco-MiniLM-L-12-v2](https://huggingface.co/cross-encoder/ms-marco-MiniLM-L-12-v2) model
+    ///
+    /// This model is slower than [`Self::ms_marco_mini_lm_l6_v2`] but slightly more accurate.
+    pub fn ms_marco_mini_lm_l12_v2() -> Self {
+        Self::cross_encoder("cross-encoder/ms-marco-MiniLM-L-12-v2")
+    }
+}
+
+impl Default for BertRerankerSource {
+    fn default() -> Self {
+        Self::ms_marco_mini_lm_l6_v2()
+    }
+}
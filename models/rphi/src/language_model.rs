@@ -61,8 +61,9 @@ impl Model for Phi {
         self.run(
             InferenceSettings::new(prompt)
                 .with_sample_len(max_length as usize)
-                .with_stop_on(generation_parameters.stop_on().map(|s| s.to_string())),
-            Arc::new(Mutex::new(generation_parameters.sampler())),
+                .with_stop_on(generation_parameters.stop_on().map(|s| s.to_string()))
+                .with_control(generation_parameters.control().cloned()),
+            Arc::new(Mutex::new(generation_parameters.seeded_sampler())),
         )
         .map(Into::into)
     }
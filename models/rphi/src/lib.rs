@@ -39,7 +39,7 @@ mod source;
 use kalosm_common::accelerated_device_if_available;
 use kalosm_common::ModelLoadingProgress;
 pub use kalosm_language_model;
-use kalosm_language_model::ChatMarkers;
+use kalosm_language_model::{ChatMarkers, GenerationControl, StopReason};
 use raw::PhiCache;
 pub use source::*;
 
@@ -142,8 +142,12 @@ impl Phi {
                                     sender,
                                     sampler,
                                 } => {
+                                    let control = settings.control.clone();
                                     if let Err(err) = inner._infer(settings, sampler, sender) {
                                         tracing::error!("Error in PhiModel::_infer: {}", err);
+                                        if let Some(control) = control {
+                                            control.finish(StopReason::Error);
+                                        }
                                     }
                                 }
                                 Task::RunSync { callback } => {
@@ -281,6 +285,9 @@ pub(crate) struct InferenceSettings {
 
     /// The token to stop on.
     stop_on: Option<String>,
+
+    /// The control that can interrupt the generation.
+    control: Option<GenerationControl>,
 }
 
 impl InferenceSettings {
@@ -289,6 +296,7 @@ impl InferenceSettings {
             prompt: prompt.into(),
             sample_len: 100,
             stop_on: None,
+            control: None,
         }
     }
 
@@ -301,4 +309,9 @@ impl InferenceSettings {
         self.stop_on = stop_on.into();
         self
     }
+
+    pub fn with_control(mut self, control: Option<GenerationControl>) -> Self {
+        self.control = control;
+        self
+    }
 }
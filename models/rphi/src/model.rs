@@ -48,6 +48,17 @@ impl Session for PhiSession {
     {
         Ok(self.clone())
     }
+
+    fn rewind(&mut self, tokens: usize) -> anyhow::Result<()> {
+        self.cache.rewind(tokens)?;
+        let len = self.current_tokens.len().saturating_sub(tokens);
+        self.current_tokens.truncate(len);
+        Ok(())
+    }
+
+    fn supports_rewind(&self) -> bool {
+        true
+    }
 }
 
 impl PhiSession {
@@ -198,16 +209,19 @@ impl PhiModel {
             prompt,
             sample_len,
             stop_on,
+            control,
         } = settings;
 
         let mut session = self.new_session()?;
 
-        self.stream_text_with_sampler(
+        // The generation records why it stopped in the control, so the stream can report it
+        self.stream_text_with_control(
             &mut session,
             prompt.as_str(),
             Some(sample_len as u32),
             stop_on.as_deref(),
             sampler,
+            control,
             |token| {
                 out.send(token)
                     .map_err(|_| anyhow::anyhow!("Failed to send token to output channel"))
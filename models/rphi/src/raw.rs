@@ -469,6 +469,27 @@ impl PhiCache {
         }
     }
 
+    /// Remove the last `tokens` tokens from the cache.
+    pub fn rewind(&mut self, tokens: usize) -> Result<()> {
+        for ParallelBlockCache(block) in &mut self.blocks {
+            let Some(ParallelBlockCacheValue { key, value }) = block else {
+                continue;
+            };
+            let len = key.dim(1)?.saturating_sub(tokens);
+            if len == 0 {
+                *block = None;
+            } else {
+                *key = key.narrow(1, 0, len)?;
+                *value = value.narrow(1, 0, len)?;
+            }
+        }
+        // The next tokens need a causal mask if the cache is empty again
+        if self.blocks.iter().all(|block| block.0.is_none()) {
+            self.first_token = true;
+        }
+        Ok(())
+    }
+
     /// Get the tensor map for this cache. This can be used to save the cache to disk.
     pub fn get_tensor_map(&self) -> HashMap<String, Tensor> {
         let mut map = HashMap::with_capacity(self.blocks.len());